@@ -0,0 +1,27 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sqlite_starter_rust::{database::Database, integrity::integrity_check};
+
+// Opens the input as a database file, reads every cell of every b-tree page, reassembling
+// payloads from their overflow pages, and checks its integrity. Malformed files must be
+// rejected with an error rather than a panic or an attempt to allocate what they claim.
+fuzz_target!(|data: &[u8]| {
+    let path = std::env::temp_dir().join(format!("fuzz-database-{}.db", std::process::id()));
+    if std::fs::write(&path, data).is_err() {
+        return;
+    }
+    let Ok(db) = Database::open(&path) else {
+        return;
+    };
+
+    for number in 1..=db.page_count() {
+        let Ok(page) = db.page(number) else {
+            continue;
+        };
+        for i in 0..page.btree_header.ncells() {
+            let _ = db.read_cell(&page, i);
+        }
+    }
+    let _ = integrity_check(&db, 100, false);
+});
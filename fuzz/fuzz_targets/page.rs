@@ -0,0 +1,21 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sqlite_starter_rust::{cell::Cell, page::Page};
+
+// Decodes the input as a b-tree page, page 1 (index 0, whose b-tree header follows the
+// database header) when the first byte is odd, and then every cell on it. Malformed input must be rejected with an error rather than a panic.
+fuzz_target!(|data: &[u8]| {
+    let Some((first, bytes)) = data.split_first() else {
+        return;
+    };
+    let idx = 1 - (first & 1) as usize;
+    let Ok(page) = Page::new(idx, None, bytes) else {
+        return;
+    };
+
+    let usable_size = bytes.len().max(480);
+    for &offset in &page.cell_offsets {
+        let _ = Cell::from_bytes(page.page_type(), offset as usize, bytes, usable_size);
+    }
+});
@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sqlite_starter_rust::record::Record;
+
+// Malformed records must be rejected with an error rather than a panic.
+fuzz_target!(|data: &[u8]| {
+    let _ = Record::new(data);
+});
@@ -0,0 +1,500 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{
+    column::{Collated, Collation, SerialValue},
+    error::{bail, Result},
+    expr::{to_numeric, to_text},
+};
+
+/// The built-in aggregate functions.
+///
+/// See <https://www.sqlite.org/lang_aggfunc.html>.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AggregateFunc {
+    Count,
+    Sum,
+    Total,
+    Avg,
+    Min,
+    Max,
+    GroupConcat,
+}
+
+impl AggregateFunc {
+    /// Looks up an aggregate function by name, ignoring case.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name.to_ascii_lowercase().as_str() {
+            "count" => AggregateFunc::Count,
+            "sum" => AggregateFunc::Sum,
+            "total" => AggregateFunc::Total,
+            "avg" => AggregateFunc::Avg,
+            "min" => AggregateFunc::Min,
+            "max" => AggregateFunc::Max,
+            "group_concat" => AggregateFunc::GroupConcat,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AggregateFunc::Count => "count",
+            AggregateFunc::Sum => "sum",
+            AggregateFunc::Total => "total",
+            AggregateFunc::Avg => "avg",
+            AggregateFunc::Min => "min",
+            AggregateFunc::Max => "max",
+            AggregateFunc::GroupConcat => "group_concat",
+        }
+    }
+
+    /// Whether the function can be called with `nargs` arguments.
+    pub fn accepts(self, nargs: usize) -> bool {
+        match self {
+            AggregateFunc::Count => nargs <= 1,
+            AggregateFunc::GroupConcat => nargs == 1 || nargs == 2,
+            _ => nargs == 1,
+        }
+    }
+}
+
+/// The running state of one aggregate function over the rows of one group.
+#[derive(Debug, Clone)]
+pub struct Accumulator {
+    func: AggregateFunc,
+    /// The collation of the argument, which decides the values DISTINCT, MIN and MAX
+    /// consider equal or smaller.
+    collation: Collation,
+    /// The values seen so far, for DISTINCT aggregates.
+    seen: Option<BTreeSet<Collated>>,
+    state: State,
+}
+
+#[derive(Debug, Clone)]
+enum State {
+    Count(i64),
+    Sum(Sum),
+    Extreme(Option<SerialValue>),
+    Concat(Option<String>),
+}
+
+impl Accumulator {
+    pub fn new(func: AggregateFunc, distinct: bool, collation: Collation) -> Self {
+        let state = match func {
+            AggregateFunc::Count => State::Count(0),
+            AggregateFunc::Sum | AggregateFunc::Total | AggregateFunc::Avg => {
+                State::Sum(Sum::default())
+            }
+            AggregateFunc::Min | AggregateFunc::Max => State::Extreme(None),
+            AggregateFunc::GroupConcat => State::Concat(None),
+        };
+
+        Self {
+            func,
+            collation,
+            seen: distinct.then(BTreeSet::new),
+            state,
+        }
+    }
+
+    /// Adds the arguments of one row. Returns whether the result of a MIN or MAX changed,
+    /// which decides the row bare columns are taken from.
+    pub fn step(&mut self, args: &[SerialValue]) -> Result<bool> {
+        let value = match args.first() {
+            // count(*)
+            None => {
+                if let State::Count(count) = &mut self.state {
+                    *count += 1;
+                }
+                return Ok(false);
+            }
+            Some(SerialValue::Null) => return Ok(false),
+            Some(value) => value,
+        };
+
+        if let Some(seen) = &mut self.seen {
+            if !seen.insert(Collated(value.clone(), self.collation)) {
+                return Ok(false);
+            }
+        }
+
+        match &mut self.state {
+            State::Count(count) => *count += 1,
+            State::Sum(sum) => sum.add(value),
+            State::Extreme(extreme) => {
+                let replace = match extreme {
+                    None => true,
+                    Some(current) if self.func == AggregateFunc::Min => {
+                        self.collation.compare(value, current).is_lt()
+                    }
+                    Some(current) => self.collation.compare(value, current).is_gt(),
+                };
+                if replace {
+                    *extreme = Some(value.clone());
+                }
+                return Ok(replace);
+            }
+            State::Concat(concat) => {
+                let value = to_text(value).unwrap_or_default();
+                match concat {
+                    None => *concat = Some(value),
+                    Some(concat) => {
+                        let separator = match args.get(1) {
+                            Some(separator) => to_text(separator).unwrap_or_default(),
+                            None => ",".to_string(),
+                        };
+                        concat.push_str(&separator);
+                        concat.push_str(&value);
+                    }
+                }
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// The result of the aggregate over the rows added so far.
+    pub fn finish(&self) -> Result<SerialValue> {
+        Ok(match &self.state {
+            State::Count(count) => SerialValue::I64(*count),
+            State::Sum(sum) => match self.func {
+                AggregateFunc::Sum => sum.sum()?,
+                AggregateFunc::Total => SerialValue::Float64(sum.real()),
+                _ if sum.count == 0 => SerialValue::Null,
+                _ => SerialValue::Float64(sum.real() / sum.count as f64),
+            },
+            State::Extreme(extreme) => extreme.clone().unwrap_or(SerialValue::Null),
+            State::Concat(concat) => match concat {
+                Some(concat) => SerialValue::String(concat.clone()),
+                None => SerialValue::Null,
+            },
+        })
+    }
+}
+
+/// The groups of an aggregate query, built up one input row at a time: each row is put
+/// in the group of its key with [`Groups::select`], then added to the aggregates of the
+/// group with [`Groups::step`] and offered as the row of its bare columns with
+/// [`Groups::set_row`].
+///
+/// Rows are in the same group if their keys are equal under the collations of the GROUP
+/// BY terms.
+///
+/// Bare columns come from the first row of the group, as in sqlite3, unless the query has
+/// exactly one min() or max() aggregate, in which case they come from the row holding the
+/// minimum or maximum.
+/// See <https://www.sqlite.org/lang_select.html#bare_columns_in_an_aggregate_query>.
+#[derive(Debug, Clone)]
+pub struct Groups {
+    /// The function of each aggregate, whether it is DISTINCT and the collation of its
+    /// argument.
+    calls: Vec<(AggregateFunc, bool, Collation)>,
+    /// The collation of each GROUP BY term.
+    collations: Vec<Collation>,
+    single_extreme: bool,
+    groups: BTreeMap<Vec<Collated>, Group>,
+    /// The key of the group of the current row.
+    current: Vec<Collated>,
+    /// Whether the current row changed the result of a min() or max().
+    extreme_changed: bool,
+}
+
+/// The accumulators of one group, and the row its bare columns are taken from.
+#[derive(Debug, Clone)]
+struct Group {
+    accumulators: Vec<Accumulator>,
+    row: Option<Vec<SerialValue>>,
+}
+
+impl Groups {
+    pub fn new(calls: Vec<(AggregateFunc, bool, Collation)>, collations: Vec<Collation>) -> Self {
+        let extremes = calls
+            .iter()
+            .filter(|(func, ..)| matches!(func, AggregateFunc::Min | AggregateFunc::Max))
+            .count();
+        Self {
+            calls,
+            collations,
+            single_extreme: extremes == 1,
+            groups: BTreeMap::new(),
+            current: Vec::new(),
+            extreme_changed: false,
+        }
+    }
+
+    /// Starts on a row of the group with the values `key` of the GROUP BY terms.
+    pub fn select(&mut self, key: Vec<SerialValue>) {
+        let key: Vec<Collated> = key
+            .into_iter()
+            .zip(&self.collations)
+            .map(|(value, &collation)| Collated(value, collation))
+            .collect();
+        if !self.groups.contains_key(&key) {
+            self.groups.insert(key.clone(), self.new_group());
+        }
+        self.current = key;
+        self.extreme_changed = false;
+    }
+
+    /// Adds the arguments of the `i`-th aggregate for the current row.
+    pub fn step(&mut self, i: usize, args: &[SerialValue]) -> Result<()> {
+        let Some(group) = self.groups.get_mut(&self.current) else {
+            bail!(Sql, "no group selected");
+        };
+        let Some(accumulator) = group.accumulators.get_mut(i) else {
+            bail!(Sql, "no aggregate {}", i);
+        };
+
+        let is_extreme = matches!(self.calls[i].0, AggregateFunc::Min | AggregateFunc::Max);
+        self.extreme_changed |= accumulator.step(args)? && is_extreme;
+        Ok(())
+    }
+
+    /// Offers the current row as the one the bare columns of its group are taken from.
+    pub fn set_row(&mut self, row: Vec<SerialValue>) {
+        let (single_extreme, extreme_changed) = (self.single_extreme, self.extreme_changed);
+        if let Some(group) = self.groups.get_mut(&self.current) {
+            if group.row.is_none() || (single_extreme && extreme_changed) {
+                group.row = Some(row);
+            }
+        }
+    }
+
+    /// One row per group, in the order of the group keys: the row of its bare columns,
+    /// `width` NULLs if it has none, followed by the results of the aggregates. Without
+    /// GROUP BY terms, there is one row even if there were no input rows.
+    pub fn finish(mut self, width: usize, grouped: bool) -> Result<Vec<Vec<SerialValue>>> {
+        if !grouped && self.groups.is_empty() {
+            self.groups.insert(Vec::new(), self.new_group());
+        }
+
+        self.groups
+            .into_values()
+            .map(|group| {
+                let mut row = group.row.unwrap_or_else(|| vec![SerialValue::Null; width]);
+                for accumulator in &group.accumulators {
+                    row.push(accumulator.finish()?);
+                }
+                Ok(row)
+            })
+            .collect()
+    }
+
+    fn new_group(&self) -> Group {
+        Group {
+            accumulators: self
+                .calls
+                .iter()
+                .map(|&(func, distinct, collation)| Accumulator::new(func, distinct, collation))
+                .collect(),
+            row: None,
+        }
+    }
+}
+
+/// A sum that stays exact while every value is an integer, and otherwise uses
+/// Kahan-Babuska-Neumaier summation, as SQLite does.
+#[derive(Debug, Clone, Default)]
+struct Sum {
+    count: i64,
+    integer: i64,
+    /// Set once a real is added or the integer sum overflows.
+    approximate: bool,
+    overflow: bool,
+    real: f64,
+    error: f64,
+}
+
+/// Integers at least this large are added in two parts to keep the low bits.
+const LARGE_INTEGER: i64 = 4503599627370496;
+
+impl Sum {
+    fn add(&mut self, value: &SerialValue) {
+        self.count += 1;
+
+        // Text counts as an integer only if it is entirely an integer literal.
+        let integer = match value {
+            SerialValue::String(txt) => txt.trim().parse().ok(),
+            value => value.as_integer(),
+        };
+
+        match integer {
+            Some(num) if !self.approximate => match self.integer.checked_add(num) {
+                Some(sum) => self.integer = sum,
+                None => {
+                    self.overflow = true;
+                    self.start_approximating();
+                    self.add_integer(num);
+                }
+            },
+            Some(num) => self.add_integer(num),
+            None => {
+                if !self.approximate {
+                    self.start_approximating();
+                }
+                self.add_real(to_numeric(value).map_or(0.0, |num| num.as_f64()));
+            }
+        }
+    }
+
+    fn start_approximating(&mut self) {
+        self.approximate = true;
+        if !(-LARGE_INTEGER + 1..LARGE_INTEGER).contains(&self.integer) {
+            let small = self.integer % 16384;
+            self.real = (self.integer - small) as f64;
+            self.error = small as f64;
+        } else {
+            self.real = self.integer as f64;
+            self.error = 0.0;
+        }
+    }
+
+    fn add_integer(&mut self, num: i64) {
+        if !(-LARGE_INTEGER + 1..LARGE_INTEGER).contains(&num) {
+            let small = num % 16384;
+            self.add_real((num - small) as f64);
+            self.add_real(small as f64);
+        } else {
+            self.add_real(num as f64);
+        }
+    }
+
+    fn add_real(&mut self, num: f64) {
+        let sum = self.real + num;
+        if self.real.abs() > num.abs() {
+            self.error += (self.real - sum) + num;
+        } else {
+            self.error += (num - sum) + self.real;
+        }
+        self.real = sum;
+    }
+
+    /// The result of SUM: NULL if no values were added, and an error if an integer sum
+    /// overflowed.
+    fn sum(&self) -> Result<SerialValue> {
+        Ok(match self.count {
+            0 => SerialValue::Null,
+            _ if self.overflow => bail!(Sql, "integer overflow"),
+            _ if self.approximate => SerialValue::Float64(self.real()),
+            _ => SerialValue::I64(self.integer),
+        })
+    }
+
+    /// The sum as a real, as used by TOTAL and AVG.
+    fn real(&self) -> f64 {
+        match self.approximate {
+            true if self.error.is_finite() => self.real + self.error,
+            true => self.real,
+            false => self.integer as f64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aggregate(func: AggregateFunc, distinct: bool, values: &[SerialValue]) -> SerialValue {
+        let mut accumulator = Accumulator::new(func, distinct, Collation::Binary);
+        for value in values {
+            accumulator.step(std::slice::from_ref(value)).unwrap();
+        }
+        accumulator.finish().unwrap()
+    }
+
+    #[test]
+    fn test_aggregates() {
+        let values = [
+            SerialValue::I8(3),
+            SerialValue::Null,
+            SerialValue::I64(3),
+            SerialValue::String("4".to_string()),
+        ];
+
+        assert_eq!(
+            aggregate(AggregateFunc::Count, false, &values),
+            SerialValue::I64(3)
+        );
+        assert_eq!(
+            aggregate(AggregateFunc::Count, true, &values),
+            SerialValue::I64(2)
+        );
+        assert!(matches!(
+            aggregate(AggregateFunc::Sum, false, &values),
+            SerialValue::I64(10)
+        ));
+        assert!(matches!(
+            aggregate(AggregateFunc::Avg, true, &values),
+            SerialValue::Float64(avg) if avg == 3.5
+        ));
+        assert_eq!(
+            aggregate(AggregateFunc::Max, false, &values),
+            SerialValue::String("4".to_string())
+        );
+        assert_eq!(
+            aggregate(AggregateFunc::GroupConcat, false, &values),
+            SerialValue::String("3,3,4".to_string())
+        );
+    }
+
+    #[test]
+    fn test_aggregates_over_no_rows() {
+        assert_eq!(
+            aggregate(AggregateFunc::Count, false, &[]),
+            SerialValue::Zero
+        );
+        assert_eq!(aggregate(AggregateFunc::Sum, false, &[]), SerialValue::Null);
+        assert!(matches!(
+            aggregate(AggregateFunc::Total, false, &[]),
+            SerialValue::Float64(total) if total == 0.0
+        ));
+        assert_eq!(aggregate(AggregateFunc::Min, false, &[]), SerialValue::Null);
+    }
+
+    #[test]
+    fn test_nocase_groups() {
+        // SELECT a, count(*), min(a), count(DISTINCT a) FROM t GROUP BY a, where a is
+        // declared COLLATE NOCASE.
+        let text = |txt: &str| SerialValue::String(txt.to_string());
+        let mut groups = Groups::new(
+            vec![
+                (AggregateFunc::Count, false, Collation::Binary),
+                (AggregateFunc::Min, false, Collation::NoCase),
+                (AggregateFunc::Count, true, Collation::NoCase),
+            ],
+            vec![Collation::NoCase],
+        );
+        for a in ["x", "X", "y", "Y"] {
+            groups.select(vec![text(a)]);
+            groups.step(0, &[]).unwrap();
+            groups.step(1, &[text(a)]).unwrap();
+            groups.step(2, &[text(a)]).unwrap();
+            groups.set_row(vec![text(a)]);
+        }
+
+        assert_eq!(
+            groups.finish(1, true).unwrap(),
+            [
+                vec![text("x"), SerialValue::I64(2), text("x"), SerialValue::One],
+                vec![text("y"), SerialValue::I64(2), text("y"), SerialValue::One],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sum_overflow() {
+        let values = [SerialValue::I64(i64::MAX), SerialValue::I64(1)];
+
+        let mut sum = Accumulator::new(AggregateFunc::Sum, false, Collation::Binary);
+        let mut total = Accumulator::new(AggregateFunc::Total, false, Collation::Binary);
+        for value in &values {
+            sum.step(std::slice::from_ref(value)).unwrap();
+            total.step(std::slice::from_ref(value)).unwrap();
+        }
+
+        assert!(sum.finish().is_err());
+        assert!(matches!(
+            total.finish().unwrap(),
+            SerialValue::Float64(total) if total == 9223372036854775808.0
+        ));
+    }
+}
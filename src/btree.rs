@@ -0,0 +1,553 @@
+use std::{cmp::Ordering, sync::Arc};
+
+use crate::{
+    column::{Collation, SerialValue},
+    database::Database,
+    decode_varint_at,
+    error::{bail, err, Result},
+    page::Page,
+    page::PageType,
+    record::Record,
+};
+
+/// The deepest a b-tree can be, as in SQLite. A deeper path means a child pointer leads
+/// back to one of its ancestors.
+const MAX_DEPTH: usize = 20;
+
+/// A position within a table or index b-tree.
+///
+/// Table b-trees are walked in rowid order and index b-trees in key order. Every movement
+/// returns `Ok(true)` when the cursor ends up on an entry and `Ok(false)` once it runs off
+/// either end of the tree.
+#[derive(Debug)]
+pub struct BTreeCursor<'a> {
+    db: &'a Database,
+    root: usize,
+    /// Pages from the root down to the current entry. The last page is paired with the cell
+    /// the cursor is on, every other page with the child the cursor descended into.
+    stack: Vec<(Arc<Page>, usize)>,
+}
+
+impl<'a> BTreeCursor<'a> {
+    pub fn new(db: &'a Database, root: usize) -> Self {
+        Self {
+            db,
+            root,
+            stack: Vec::new(),
+        }
+    }
+
+    /// Page number of the root page of the b-tree.
+    pub fn root(&self) -> usize {
+        self.root
+    }
+
+    /// Whether the cursor currently points at an entry.
+    pub fn is_valid(&self) -> bool {
+        !self.stack.is_empty()
+    }
+
+    /// Moves to the smallest entry of the b-tree.
+    pub fn first(&mut self) -> Result<bool> {
+        self.stack.clear();
+        let root = self.db.page(self.root)?;
+        self.descend_leftmost(root)
+    }
+
+    /// Moves to the largest entry of the b-tree.
+    pub fn last(&mut self) -> Result<bool> {
+        self.stack.clear();
+        let root = self.db.page(self.root)?;
+        self.descend_rightmost(root)
+    }
+
+    /// Moves to the entry following the current one.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<bool> {
+        let Some((page, i)) = self.stack.last_mut() else {
+            return Ok(false);
+        };
+
+        *i += 1;
+        let (page, i) = (Arc::clone(page), *i);
+        if page.is_leaf() {
+            if i < page.cell_offsets.len() {
+                return Ok(true);
+            }
+            return self.ascend_forward();
+        }
+
+        // On a cell of an interior index page: continue with the smallest entry to its right.
+        let child = self.child(&page, i)?;
+        self.descend_leftmost(child)
+    }
+
+    /// Moves to the entry preceding the current one.
+    pub fn prev(&mut self) -> Result<bool> {
+        let Some((page, i)) = self.stack.last_mut() else {
+            return Ok(false);
+        };
+
+        if page.is_leaf() {
+            if *i > 0 {
+                *i -= 1;
+                return Ok(true);
+            }
+            return self.ascend_backward();
+        }
+
+        // On a cell of an interior index page: continue with the largest entry to its left.
+        let (page, i) = (Arc::clone(page), *i);
+        let child = self.child(&page, i)?;
+        self.descend_rightmost(child)
+    }
+
+    /// Moves to the first row whose rowid is greater than or equal to `rowid`, returning
+    /// whether a row with exactly that rowid exists.
+    pub fn seek_rowid(&mut self, rowid: i64) -> Result<bool> {
+        self.stack.clear();
+        let mut page = self.db.page(self.root)?;
+        if page.is_index() {
+            bail!(
+                Corrupt(self.root, 0),
+                "seek_rowid on index b-tree rooted at page {}",
+                self.root
+            );
+        }
+
+        loop {
+            let i = partition_point(page.cell_offsets.len(), |i| {
+                Ok(cell_rowid(&page, i)? < rowid)
+            })?;
+
+            if page.is_leaf() {
+                self.stack.push((page, i));
+                break;
+            }
+
+            let child = self.child(&page, i)?;
+            self.stack.push((page, i));
+            page = child;
+        }
+
+        if !self.settle_forward()? {
+            return Ok(false);
+        }
+
+        Ok(self.rowid()? == rowid)
+    }
+
+    /// Moves to the first index entry whose leading columns come at or after `key` in the
+    /// `order` of the index, returning whether those columns are equal to `key`.
+    pub fn seek_key(&mut self, key: &[SerialValue], order: &[KeyOrder]) -> Result<bool> {
+        if !self.seek_index(key, order, false)? {
+            return Ok(false);
+        }
+
+        Ok(compare_prefix(&self.record()?, key, order) == Ordering::Equal)
+    }
+
+    /// Moves to the first index entry whose leading columns come after `key` in the `order`
+    /// of the index, returning whether there is one.
+    pub fn seek_past_key(&mut self, key: &[SerialValue], order: &[KeyOrder]) -> Result<bool> {
+        self.seek_index(key, order, true)
+    }
+
+    /// Moves to the first index entry whose leading columns come at or, if `past_equal`,
+    /// after `key`, returning whether there is one.
+    fn seek_index(
+        &mut self,
+        key: &[SerialValue],
+        order: &[KeyOrder],
+        past_equal: bool,
+    ) -> Result<bool> {
+        self.stack.clear();
+        let mut page = self.db.page(self.root)?;
+        if !page.is_index() {
+            bail!(
+                Corrupt(self.root, 0),
+                "seek_key on table b-tree rooted at page {}",
+                self.root
+            );
+        }
+
+        loop {
+            let i = partition_point(page.cell_offsets.len(), |i| {
+                let record = self.cell_record(&page, i)?;
+                Ok(match compare_prefix(&record, key, order) {
+                    Ordering::Less => true,
+                    Ordering::Equal => past_equal,
+                    Ordering::Greater => false,
+                })
+            })?;
+
+            if page.is_leaf() {
+                self.stack.push((page, i));
+                break;
+            }
+
+            let child = self.child(&page, i)?;
+            self.stack.push((page, i));
+            page = child;
+        }
+
+        self.settle_forward()
+    }
+
+    /// Rowid of the current entry. For index b-trees this is the last column of the key.
+    pub fn rowid(&self) -> Result<i64> {
+        let (page, i) = self.current()?;
+        if !page.is_index() {
+            return cell_rowid(page, *i);
+        }
+
+        let record = self.cell_record(page, *i)?;
+        match record
+            .columns
+            .last()
+            .and_then(|column| column.data().as_integer())
+        {
+            Some(rowid) => Ok(rowid),
+            None => bail!(
+                Corrupt(page.number, page.cell_offsets[*i] as usize),
+                "index entry on page {} has no rowid",
+                page.number
+            ),
+        }
+    }
+
+    /// Record of the current entry: the row of a table b-tree or the key of an index b-tree.
+    pub fn record(&self) -> Result<Record> {
+        let (page, i) = self.current()?;
+        self.cell_record(page, *i)
+    }
+
+    /// Iterates over the entries starting from the first one.
+    pub fn entries(self) -> Entries<'a> {
+        Entries {
+            cursor: self,
+            step: Step::First,
+            reverse: false,
+        }
+    }
+
+    /// Iterates over the entries backwards, starting from the last one.
+    pub fn entries_rev(self) -> Entries<'a> {
+        Entries {
+            cursor: self,
+            step: Step::First,
+            reverse: true,
+        }
+    }
+
+    /// Iterates over the entries starting from the current one, e.g. after a seek.
+    pub fn remaining(self) -> Entries<'a> {
+        self.remaining_from(false)
+    }
+
+    /// Iterates over the entries backwards starting from the current one.
+    pub fn remaining_rev(self) -> Entries<'a> {
+        self.remaining_from(true)
+    }
+
+    fn remaining_from(self, reverse: bool) -> Entries<'a> {
+        let step = if self.is_valid() {
+            Step::Current
+        } else {
+            Step::Done
+        };
+
+        Entries {
+            cursor: self,
+            step,
+            reverse,
+        }
+    }
+
+    fn current(&self) -> Result<&(Arc<Page>, usize)> {
+        match self.stack.last() {
+            Some(entry) => Ok(entry),
+            None => bail!(Sql, "cursor is not positioned on an entry"),
+        }
+    }
+
+    /// The `i`-th child of `page`, which is the deepest page on the stack or the page about
+    /// to be pushed onto it.
+    fn child(&self, page: &Page, i: usize) -> Result<Arc<Page>> {
+        if self.stack.len() + 1 >= MAX_DEPTH {
+            bail!(
+                Corrupt(page.number, 0),
+                "b-tree {} is deeper than {} pages",
+                self.root,
+                MAX_DEPTH
+            );
+        }
+        match page.child_page(i) {
+            Some(page_num) => self.db.page(page_num as usize),
+            None => bail!(
+                Corrupt(page.number, 0),
+                "missing child pointer {} in b-tree {}",
+                i,
+                self.root
+            ),
+        }
+    }
+
+    fn cell_record(&self, page: &Page, i: usize) -> Result<Record> {
+        let cell = self.db.read_cell(page, i as u16)?;
+        cell.record.ok_or_else(|| {
+            err!(
+                Corrupt(page.number, page.cell_offsets[i] as usize),
+                "cell has no payload"
+            )
+        })
+    }
+
+    fn descend_leftmost(&mut self, mut page: Arc<Page>) -> Result<bool> {
+        while !page.is_leaf() {
+            let child = self.child(&page, 0)?;
+            self.stack.push((page, 0));
+            page = child;
+        }
+        self.stack.push((page, 0));
+
+        self.settle_forward()
+    }
+
+    fn descend_rightmost(&mut self, mut page: Arc<Page>) -> Result<bool> {
+        while !page.is_leaf() {
+            let i = page.cell_offsets.len();
+            let child = self.child(&page, i)?;
+            self.stack.push((page, i));
+            page = child;
+        }
+
+        match page.cell_offsets.len() {
+            0 => {
+                self.stack.push((page, 0));
+                self.ascend_backward()
+            }
+            n => {
+                self.stack.push((page, n - 1));
+                Ok(true)
+            }
+        }
+    }
+
+    /// Makes sure the cursor is on an entry, moving forward if it points past the end of a leaf.
+    fn settle_forward(&mut self) -> Result<bool> {
+        match self.stack.last() {
+            Some((page, i)) if *i >= page.cell_offsets.len() => self.ascend_forward(),
+            Some(_) => Ok(true),
+            None => Ok(false),
+        }
+    }
+
+    /// Leaves the exhausted page on top of the stack and moves to the next entry above it.
+    fn ascend_forward(&mut self) -> Result<bool> {
+        self.stack.pop();
+
+        while let Some((page, i)) = self.stack.last_mut() {
+            if *i < page.cell_offsets.len() {
+                if page.is_index() {
+                    // Interior index cells are entries themselves.
+                    return Ok(true);
+                }
+
+                *i += 1;
+                let (page, i) = (Arc::clone(page), *i);
+                let child = self.child(&page, i)?;
+                return self.descend_leftmost(child);
+            }
+            self.stack.pop();
+        }
+
+        Ok(false)
+    }
+
+    /// Leaves the exhausted page on top of the stack and moves to the previous entry above it.
+    fn ascend_backward(&mut self) -> Result<bool> {
+        self.stack.pop();
+
+        while let Some((page, i)) = self.stack.last_mut() {
+            if *i > 0 {
+                *i -= 1;
+                if page.is_index() {
+                    return Ok(true);
+                }
+
+                let (page, i) = (Arc::clone(page), *i);
+                let child = self.child(&page, i)?;
+                return self.descend_rightmost(child);
+            }
+            self.stack.pop();
+        }
+
+        Ok(false)
+    }
+}
+
+/// Iterator over `(rowid, record)` pairs of a b-tree, see [`BTreeCursor::entries`].
+#[derive(Debug)]
+pub struct Entries<'a> {
+    cursor: BTreeCursor<'a>,
+    step: Step,
+    reverse: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step {
+    First,
+    Current,
+    Next,
+    Done,
+}
+
+impl Iterator for Entries<'_> {
+    type Item = Result<(i64, Record)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let moved = match self.step {
+            Step::First if self.reverse => self.cursor.last(),
+            Step::First => self.cursor.first(),
+            Step::Current => Ok(true),
+            Step::Next if self.reverse => self.cursor.prev(),
+            Step::Next => self.cursor.next(),
+            Step::Done => return None,
+        };
+
+        match moved {
+            Ok(true) => {
+                self.step = Step::Next;
+                Some(
+                    self.cursor
+                        .rowid()
+                        .and_then(|rowid| Ok((rowid, self.cursor.record()?))),
+                )
+            }
+            Ok(false) => {
+                self.step = Step::Done;
+                None
+            }
+            Err(err) => {
+                self.step = Step::Done;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+/// Index of the first of `len` cells for which `is_before` returns false. The cells are
+/// probed in the order SQLite probes them, so that pages whose cells are out of order lead
+/// to the same entries.
+fn partition_point(len: usize, mut is_before: impl FnMut(usize) -> Result<bool>) -> Result<usize> {
+    let (mut lo, mut hi) = (0, len);
+    while lo < hi {
+        let mid = lo + (hi - lo - 1) / 2;
+        if is_before(mid)? {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+
+    Ok(lo)
+}
+
+/// Reads the rowid of a table b-tree cell without decoding its payload.
+fn cell_rowid(page: &Page, i: usize) -> Result<i64> {
+    let offset = page.cell_offsets[i] as usize;
+
+    match page.page_type() {
+        PageType::InteriorTable => Ok(decode_varint_at(&page.buffer, offset + 4)
+            .map_err(|err| err.at(page.number, 0))?
+            .0),
+        PageType::LeafTable => {
+            let (_, npayload_len) =
+                decode_varint_at(&page.buffer, offset).map_err(|err| err.at(page.number, 0))?;
+            Ok(decode_varint_at(&page.buffer, offset + npayload_len)
+                .map_err(|err| err.at(page.number, 0))?
+                .0)
+        }
+        page_type => bail!(
+            Corrupt(page.number, 0),
+            "{:?} page has no rowids",
+            page_type
+        ),
+    }
+}
+
+/// How the entries of an index b-tree are ordered by one of their columns.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeyOrder {
+    pub collation: Collation,
+    pub descending: bool,
+}
+
+impl KeyOrder {
+    pub fn compare(self, left: &SerialValue, right: &SerialValue) -> Ordering {
+        let ordering = self.collation.compare(left, right);
+        match self.descending {
+            true => ordering.reverse(),
+            false => ordering,
+        }
+    }
+}
+
+/// Compares the leading columns of an index record with `key`, in the `order` of the
+/// index. Columns without an order are compared with BINARY, ascending.
+pub fn compare_prefix(record: &Record, key: &[SerialValue], order: &[KeyOrder]) -> Ordering {
+    record
+        .columns
+        .iter()
+        .zip(key)
+        .enumerate()
+        .map(|(i, (column, value))| {
+            let order = order.get(i).copied().unwrap_or_default();
+            order.compare(column.data(), value)
+        })
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cursor_walks_table_in_both_directions() {
+        let db = Database::open("sample.db").unwrap();
+        let mut cursor = BTreeCursor::new(&db, 2);
+
+        let mut rowids = Vec::new();
+        let mut more = cursor.first().unwrap();
+        while more {
+            rowids.push(cursor.rowid().unwrap());
+            more = cursor.next().unwrap();
+        }
+        assert_eq!(rowids, vec![1, 2, 3, 4]);
+
+        let mut rowids = Vec::new();
+        let mut more = cursor.last().unwrap();
+        while more {
+            rowids.push(cursor.rowid().unwrap());
+            more = cursor.prev().unwrap();
+        }
+        assert_eq!(rowids, vec![4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn test_cursor_seek_rowid() {
+        let db = Database::open("sample.db").unwrap();
+        let mut cursor = BTreeCursor::new(&db, 2);
+
+        assert!(cursor.seek_rowid(3).unwrap());
+        assert_eq!(
+            cursor.record().unwrap().columns[1].data(),
+            &SerialValue::String("Honeycrisp".to_string())
+        );
+
+        assert!(!cursor.seek_rowid(5).unwrap());
+        assert!(!cursor.is_valid());
+    }
+}
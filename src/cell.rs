@@ -1,69 +1,68 @@
-use anyhow::{bail, Context};
-
-use crate::{decode_varint, page::PageType, record::Record};
+use crate::{
+    decode_varint_at,
+    error::{bail, Result},
+    page::PageType,
+    read_array, read_bytes,
+    record::Record,
+};
+
+/// The largest payload a cell can hold: SQLite's hard limit on the length of a string,
+/// BLOB or row.
+///
+/// See <https://www.sqlite.org/limits.html#max_length>.
+pub const MAX_PAYLOAD: i64 = i32::MAX as i64;
 
 #[derive(Debug, Clone)]
 pub struct Cell {
     pub page_number_left_child: Option<u32>,
     pub npayload: Option<i64>,
     pub rowid: Option<i64>,
+    /// The decoded payload. `None` until the overflow pages of a spilled payload have been read.
     pub record: Option<Record>,
     pub page_number_first_overflow: Option<u32>,
+    /// The payload bytes stored on the b-tree page itself.
+    pub payload: Vec<u8>,
 }
 
 impl Cell {
-    pub fn from_bytes(page_type: &PageType, offset: usize, bytes: &[u8]) -> anyhow::Result<Self> {
+    /// Parses the cell at `offset` of a page whose usable size is `usable_size`. Errors
+    /// are placed at their offset in `bytes`.
+    ///
+    /// When the payload does not fit on the page only its local part is kept in `payload`,
+    /// `record` is left empty and the rest must be read from `page_number_first_overflow`.
+    pub fn from_bytes(
+        page_type: &PageType,
+        offset: usize,
+        bytes: &[u8],
+        usable_size: usize,
+    ) -> Result<Self> {
         let mut idx = offset;
 
         match page_type {
             PageType::LeafTable => {
-                let (npayload, bytes_read) = decode_varint(&bytes[idx..idx + 9])
-                    .context("decode varint for payload size")?;
+                let (npayload, bytes_read) = decode_varint_at(bytes, idx)?;
                 idx += bytes_read;
 
-                let (rowid, bytes_read) = decode_varint(&bytes[idx..idx + 9])
-                    .context("decode varint for payload size")?;
+                let (rowid, bytes_read) = decode_varint_at(bytes, idx)?;
                 idx += bytes_read;
-                //let end = if npayload as usize > bytes.len() {
-                //    bytes.len()
-                //} else {
-                //    idx + npayload as usize
-                //};
-                let end = idx + npayload as usize;
-                let payload = &bytes[idx..end];
-                let record = Record::new(payload)?;
-
-                let page_number_first_overflow = if bytes.len() > end + 4 {
-                    let num = u32::from_be_bytes([
-                        bytes[end],
-                        bytes[end + 1],
-                        bytes[end + 2],
-                        bytes[end + 3],
-                    ]);
-                    Some(num)
-                } else {
-                    None
-                };
+
+                let (payload, page_number_first_overflow) =
+                    read_payload(page_type, npayload, bytes, idx, usable_size)?;
 
                 Ok(Self {
                     page_number_left_child: None,
                     npayload: Some(npayload),
                     rowid: Some(rowid),
-                    record: Some(record),
+                    record: complete_record(&payload, page_number_first_overflow, idx)?,
                     page_number_first_overflow,
+                    payload,
                 })
             }
             PageType::InteriorTable => {
-                let page_number_left_child = Some(u32::from_be_bytes([
-                    bytes[idx],
-                    bytes[idx + 1],
-                    bytes[idx + 2],
-                    bytes[idx + 3],
-                ]));
+                let page_number_left_child = Some(u32::from_be_bytes(read_array(bytes, idx)?));
                 idx += 4;
 
-                let (rowid, _bytes_read) =
-                    decode_varint(&bytes[idx..]).context("decode varint for payload size")?;
+                let (rowid, _bytes_read) = decode_varint_at(bytes, idx)?;
 
                 Ok(Self {
                     page_number_left_child,
@@ -71,69 +70,151 @@ impl Cell {
                     rowid: Some(rowid),
                     record: None,
                     page_number_first_overflow: None,
+                    payload: Vec::new(),
                 })
             }
             PageType::LeafIndex => {
-                let (npayload, bytes_read) =
-                    decode_varint(&bytes[idx..]).context("decode varint for payload size")?;
+                let (npayload, bytes_read) = decode_varint_at(bytes, idx)?;
                 idx += bytes_read;
 
-                let end = idx + npayload as usize;
-                let payload = &bytes[idx..end];
-                let record = Record::new(payload).context("create new record")?;
+                let (payload, page_number_first_overflow) =
+                    read_payload(page_type, npayload, bytes, idx, usable_size)?;
 
                 Ok(Self {
                     page_number_left_child: None,
                     npayload: Some(npayload),
                     rowid: None,
-                    record: Some(record),
-                    page_number_first_overflow: None,
+                    record: complete_record(&payload, page_number_first_overflow, idx)?,
+                    page_number_first_overflow,
+                    payload,
                 })
             }
             PageType::InteriorIndex => {
-                let page_number_left_child = Some(u32::from_be_bytes([
-                    bytes[idx],
-                    bytes[idx + 1],
-                    bytes[idx + 2],
-                    bytes[idx + 3],
-                ]));
+                let page_number_left_child = Some(u32::from_be_bytes(read_array(bytes, idx)?));
                 idx += 4;
 
-                let (npayload, bytes_read) =
-                    decode_varint(&bytes[idx..]).context("decode varint for payload size")?;
+                let (npayload, bytes_read) = decode_varint_at(bytes, idx)?;
                 idx += bytes_read;
 
-                let end = idx + npayload as usize;
-                let payload = &bytes[idx..end];
-                let record = Record::new(payload).context("create new record")?;
-
-                let page_number_first_overflow = if bytes.len() > end + 4 {
-                    let num = u32::from_be_bytes([
-                        bytes[end],
-                        bytes[end + 1],
-                        bytes[end + 2],
-                        bytes[end + 3],
-                    ]);
-                    Some(num)
-                } else {
-                    None
-                };
+                let (payload, page_number_first_overflow) =
+                    read_payload(page_type, npayload, bytes, idx, usable_size)?;
 
                 Ok(Self {
                     page_number_left_child,
                     npayload: Some(npayload),
                     rowid: None,
-                    record: Some(record),
+                    record: complete_record(&payload, page_number_first_overflow, idx)?,
                     page_number_first_overflow,
+                    payload,
                 })
             }
             PageType::PageError => {
-                bail!("can not read cell")
+                bail!(
+                    Corrupt(0, offset),
+                    "cell on a page that is not a b-tree page"
+                )
             }
         }
     }
 
-    // pub fn record(&self) -> &Record {
-    //     &self.record
-    // }
+    /// Whether part of the payload is stored on overflow pages.
+    pub fn has_overflow(&self) -> bool {
+        self.page_number_first_overflow.is_some()
+    }
+}
+
+/// Number of payload bytes stored on a b-tree page of type `page_type` for a payload of
+/// `npayload` bytes. The remainder spills onto overflow pages.
+///
+/// See <https://www.sqlite.org/fileformat.html#cell_payload_size_limits>.
+pub fn local_payload_size(page_type: &PageType, npayload: usize, usable_size: usize) -> usize {
+    let max_local = match page_type {
+        PageType::LeafTable => usable_size - 35,
+        _ => ((usable_size - 12) * 64 / 255) - 23,
+    };
+
+    if npayload <= max_local {
+        return npayload;
+    }
+
+    let min_local = ((usable_size - 12) * 32 / 255) - 23;
+    let local = min_local + ((npayload - min_local) % (usable_size - 4));
+    if local <= max_local {
+        local
+    } else {
+        min_local
+    }
+}
+
+/// Splits the bytes following a cell header at `offset` into the local payload and the
+/// first overflow page number, if any.
+fn read_payload(
+    page_type: &PageType,
+    npayload: i64,
+    bytes: &[u8],
+    offset: usize,
+    usable_size: usize,
+) -> Result<(Vec<u8>, Option<u32>)> {
+    if !(0..=MAX_PAYLOAD).contains(&npayload) {
+        bail!(Corrupt(0, offset), "invalid payload size: {}", npayload);
+    }
+
+    let npayload = npayload as usize;
+    let local = local_payload_size(page_type, npayload, usable_size);
+    let payload = read_bytes(bytes, offset, local)?.to_vec();
+
+    if local == npayload {
+        return Ok((payload, None));
+    }
+
+    let page_number_first_overflow = u32::from_be_bytes(read_array(bytes, offset + local)?);
+
+    Ok((payload, Some(page_number_first_overflow)))
+}
+
+/// Decodes the record of a payload stored at `start` of the page, unless part of it is
+/// stored on overflow pages.
+fn complete_record(
+    payload: &[u8],
+    page_number_first_overflow: Option<u32>,
+    start: usize,
+) -> Result<Option<Record>> {
+    match page_number_first_overflow {
+        Some(_) => Ok(None),
+        None => Ok(Some(Record::new(payload).map_err(|err| err.at(0, start))?)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_local_payload_size() {
+        // Small payloads stay on the page.
+        assert_eq!(local_payload_size(&PageType::LeafTable, 100, 4096), 100);
+        assert_eq!(local_payload_size(&PageType::LeafTable, 4061, 4096), 4061);
+        assert_eq!(local_payload_size(&PageType::LeafIndex, 1002, 4096), 1002);
+
+        // Table leaves spill once the payload exceeds U-35.
+        assert_eq!(local_payload_size(&PageType::LeafTable, 4062, 4096), 489);
+        assert_eq!(local_payload_size(&PageType::LeafTable, 10000, 4096), 1816);
+
+        // Index pages spill earlier.
+        assert_eq!(local_payload_size(&PageType::LeafIndex, 1003, 4096), 489);
+        assert_eq!(
+            local_payload_size(&PageType::InteriorIndex, 5000, 4096),
+            908
+        );
+    }
+
+    #[test]
+    fn test_payload_size_out_of_range() {
+        // A leaf table cell claiming a payload of 2^62 bytes, for rowid 1.
+        let mut bytes = crate::encode_varint(1 << 62);
+        bytes.push(1);
+        bytes.extend_from_slice(&[0; 1000]);
+        let err = Cell::from_bytes(&PageType::LeafTable, 0, &bytes, 4096).unwrap_err();
+        assert!(matches!(err, crate::error::Error::Corrupt { .. }));
+    }
 }
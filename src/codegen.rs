@@ -0,0 +1,1324 @@
+use crate::{
+    btree::KeyOrder,
+    column::{Affinity, Collation, SerialValue},
+    error::{bail, Result},
+    expr::{comparison_collation, BinaryOp, Expr, UnaryOp},
+    query::{Bound, Plan},
+    schema::Table,
+    vdbe::{Function, Insn, Program},
+};
+
+/// Compiles a plan into a program that returns its rows.
+///
+/// Each step of the plan becomes a loop, into which the steps above it are compiled: a
+/// step is given the code that handles one of its rows, and emits it once per row.
+pub(crate) fn compile(plan: &Plan) -> Result<Program> {
+    let mut codegen = Codegen::default();
+    codegen.plan(plan, &[], &mut |codegen, layout, _| {
+        let (start, n) = codegen.row(layout)?;
+        codegen.emit(Insn::ResultRow { start, n });
+        Ok(())
+    })?;
+    Ok(codegen.finish())
+}
+
+/// Where a value of the rows of a step is read from.
+#[derive(Debug, Clone, Copy)]
+enum Location {
+    /// A column of the current entry or row of a cursor. Columns of tables with REAL
+    /// affinity may be stored as integers.
+    Column {
+        cursor: usize,
+        column: usize,
+        real: bool,
+    },
+    Rowid(usize),
+    /// The rowid at the end of the current entry of an index cursor.
+    IdxRowid(usize),
+    Register(usize),
+    Null,
+}
+
+/// Emits the code that handles a row, given where its values are and the label to jump
+/// to for the next row.
+type Body<'b> = &'b mut dyn FnMut(&mut Codegen, &[Location], usize) -> Result<()>;
+
+#[derive(Default)]
+struct Codegen {
+    /// The instructions that open cursors, run once at the start of the program.
+    prologue: Vec<Insn>,
+    /// The instructions of the query, whose jump targets are labels.
+    code: Vec<Insn>,
+    /// The address in `code` of each label, once it is placed.
+    labels: Vec<Option<usize>>,
+    registers: usize,
+    cursors: usize,
+}
+
+impl Codegen {
+    fn emit(&mut self, insn: Insn) {
+        self.code.push(insn);
+    }
+
+    fn register(&mut self) -> usize {
+        self.registers += 1;
+        self.registers
+    }
+
+    /// Allocates `n` consecutive registers and returns the first.
+    fn registers(&mut self, n: usize) -> usize {
+        let start = self.registers + 1;
+        self.registers += n;
+        start
+    }
+
+    fn cursor(&mut self) -> usize {
+        self.cursors += 1;
+        self.cursors - 1
+    }
+
+    fn label(&mut self) -> usize {
+        self.labels.push(None);
+        self.labels.len() - 1
+    }
+
+    /// Places a label at the next instruction.
+    fn place(&mut self, label: usize) {
+        self.labels[label] = Some(self.code.len());
+    }
+
+    /// Lays the program out as SQLite does: Init jumps to the end, which jumps back to the
+    /// start, past which the cursors are opened and the query runs until it halts.
+    fn finish(self) -> Program {
+        let start = 1 + self.prologue.len();
+        let end = start + self.code.len() + 1;
+
+        let mut insns = vec![Insn::Init { target: end }];
+        insns.extend(self.prologue);
+        for mut insn in self.code {
+            for target in insn.targets_mut() {
+                let address = self.labels[*target].expect("label was never placed");
+                *target = start + address;
+            }
+            insns.push(insn);
+        }
+        insns.push(Insn::Halt);
+        insns.push(Insn::Goto { target: 1 });
+
+        Program {
+            insns,
+            registers: self.registers,
+            cursors: self.cursors,
+        }
+    }
+
+    /// Opens a cursor on the b-tree rooted at `root_page`, with the `key` order of an
+    /// index or `None` for a table.
+    fn open(
+        &mut self,
+        root_page: usize,
+        columns: usize,
+        key: Option<Vec<KeyOrder>>,
+        name: &str,
+    ) -> usize {
+        let cursor = self.cursor();
+        self.prologue.push(Insn::OpenRead {
+            cursor,
+            root_page,
+            columns,
+            key,
+            name: name.to_string(),
+        });
+        cursor
+    }
+
+    fn open_table(&mut self, table: &Table) -> usize {
+        self.open(table.root_page, table.columns.len(), None, &table.name)
+    }
+
+    /// Emits the loop over the rows of `plan`, running `body` on each. Lookups are
+    /// evaluated against the row of the tables joined before, laid out as `outer`.
+    fn plan(&mut self, plan: &Plan, outer: &[Location], body: Body) -> Result<()> {
+        let end = self.label();
+
+        match plan {
+            Plan::Row => body(self, &[], end)?,
+            Plan::Scan {
+                table,
+                reverse,
+                lower,
+                upper,
+            } => {
+                let cursor = self.open_table(table);
+                let (first, last) = match reverse {
+                    true => (upper, lower),
+                    false => (lower, upper),
+                };
+                let seek = match first {
+                    Some(bound) => {
+                        let key = self.register();
+                        self.expr(&bound.value, outer, key)?;
+                        Some((bound.inclusive, key))
+                    }
+                    None => None,
+                };
+                // The rowids past the last bound are found by comparing each with it.
+                let stop = match last {
+                    Some(bound) => {
+                        let reg = self.register();
+                        self.expr(&bound.value, outer, reg)?;
+                        self.emit(Insn::Affinity {
+                            reg,
+                            affinity: Affinity::Numeric,
+                        });
+                        Some((bound.inclusive, reg))
+                    }
+                    None => None,
+                };
+
+                self.emit(match (seek, reverse) {
+                    (Some((inclusive, key)), _) => Insn::Seek {
+                        op: match (reverse, inclusive) {
+                            (false, true) => BinaryOp::GtEq,
+                            (false, false) => BinaryOp::Gt,
+                            (true, true) => BinaryOp::LtEq,
+                            (true, false) => BinaryOp::Lt,
+                        },
+                        cursor,
+                        target: end,
+                        key,
+                        nkey: 1,
+                    },
+                    (None, false) => Insn::Rewind {
+                        cursor,
+                        target: end,
+                    },
+                    (None, true) => Insn::Last {
+                        cursor,
+                        target: end,
+                    },
+                });
+
+                let (top, next) = (self.label(), self.label());
+                self.place(top);
+                if let Some((inclusive, rhs)) = stop {
+                    let rowid = self.register();
+                    self.emit(Insn::Rowid {
+                        cursor,
+                        dest: rowid,
+                    });
+                    self.emit(Insn::Compare {
+                        op: match (reverse, inclusive) {
+                            (false, true) => BinaryOp::Gt,
+                            (false, false) => BinaryOp::GtEq,
+                            (true, true) => BinaryOp::Lt,
+                            (true, false) => BinaryOp::LtEq,
+                        },
+                        lhs: rowid,
+                        rhs,
+                        target: end,
+                        collation: Collation::Binary,
+                        jump_if_null: true,
+                    });
+                }
+                body(self, &table_layout(table, cursor), next)?;
+                self.place(next);
+                self.emit(match reverse {
+                    true => Insn::Prev {
+                        cursor,
+                        target: top,
+                    },
+                    false => Insn::Next {
+                        cursor,
+                        target: top,
+                    },
+                });
+            }
+            Plan::RowidSeek { table, rowid } => {
+                let cursor = self.open_table(table);
+                let reg = self.register();
+                self.expr(rowid, outer, reg)?;
+                self.emit(Insn::SeekRowid {
+                    cursor,
+                    target: end,
+                    rowid: reg,
+                });
+                body(self, &table_layout(table, cursor), end)?;
+            }
+            Plan::IndexSeek {
+                table,
+                index,
+                reverse,
+                order,
+                key,
+                lower,
+                upper,
+                covering,
+            } => {
+                let index_cursor = self.open(
+                    index.root_page,
+                    order.len() + 1,
+                    Some(order.clone()),
+                    &index.name,
+                );
+                let table_cursor = match covering {
+                    Some(_) => None,
+                    None => Some(self.open_table(table)),
+                };
+
+                // Nothing is equal to, less than or greater than NULL.
+                let start = self.registers(key.len() + 1);
+                for (i, value) in key.iter().enumerate() {
+                    self.expr(value, outer, start + i)?;
+                    self.emit(Insn::IsNull {
+                        reg: start + i,
+                        target: end,
+                    });
+                }
+                let bound = |codegen: &mut Self, bound: &Option<Bound>| -> Result<_> {
+                    let Some(bound) = bound else {
+                        return Ok(None);
+                    };
+                    let reg = codegen.register();
+                    codegen.expr(&bound.value, outer, reg)?;
+                    codegen.emit(Insn::IsNull { reg, target: end });
+                    Ok(Some((reg, bound.inclusive)))
+                };
+                let mut lower = bound(self, lower)?;
+                let upper = bound(self, upper)?;
+                // NULLs sort before every other value, but are not less than them.
+                if upper.is_some() && lower.is_none() {
+                    let reg = self.register();
+                    self.emit(Insn::Null { dest: reg });
+                    lower = Some((reg, false));
+                }
+
+                // The range in the order the entries are read in, each end following the key.
+                let descending = order.get(key.len()).is_some_and(|order| order.descending);
+                let (first, last) = match descending != *reverse {
+                    true => (upper, lower),
+                    false => (lower, upper),
+                };
+                let nkey = key.len();
+                let seek = match first {
+                    Some((reg, inclusive)) => {
+                        self.emit(Insn::SCopy {
+                            src: reg,
+                            dest: start + nkey,
+                        });
+                        Some((nkey + 1, inclusive))
+                    }
+                    None if nkey > 0 => Some((nkey, true)),
+                    None => None,
+                };
+                let stop = match last {
+                    Some((reg, inclusive)) => {
+                        let stop = self.registers(nkey + 1);
+                        for i in 0..nkey {
+                            self.emit(Insn::SCopy {
+                                src: start + i,
+                                dest: stop + i,
+                            });
+                        }
+                        self.emit(Insn::SCopy {
+                            src: reg,
+                            dest: stop + nkey,
+                        });
+                        Some((stop, nkey + 1, inclusive))
+                    }
+                    None if nkey > 0 => Some((start, nkey, true)),
+                    None => None,
+                };
+
+                self.emit(match (seek, reverse) {
+                    (Some((n, inclusive)), _) => Insn::Seek {
+                        op: match (reverse, inclusive) {
+                            (false, true) => BinaryOp::GtEq,
+                            (false, false) => BinaryOp::Gt,
+                            (true, true) => BinaryOp::LtEq,
+                            (true, false) => BinaryOp::Lt,
+                        },
+                        cursor: index_cursor,
+                        target: end,
+                        key: start,
+                        nkey: n,
+                    },
+                    (None, false) => Insn::Rewind {
+                        cursor: index_cursor,
+                        target: end,
+                    },
+                    (None, true) => Insn::Last {
+                        cursor: index_cursor,
+                        target: end,
+                    },
+                });
+
+                let (top, next) = (self.label(), self.label());
+                self.place(top);
+                if let Some((key, nkey, inclusive)) = stop {
+                    self.emit(Insn::IdxCompare {
+                        op: match (reverse, inclusive) {
+                            (false, true) => BinaryOp::Gt,
+                            (false, false) => BinaryOp::GtEq,
+                            (true, true) => BinaryOp::Lt,
+                            (true, false) => BinaryOp::LtEq,
+                        },
+                        cursor: index_cursor,
+                        target: end,
+                        key,
+                        nkey,
+                    });
+                }
+                let layout = match (covering, table_cursor) {
+                    (Some(positions), _) => {
+                        let mut layout = vec![Location::Null; table.columns.len() + 1];
+                        for (column, position) in positions.iter().enumerate() {
+                            if let Some(i) = *position {
+                                layout[i] = Location::Column {
+                                    cursor: index_cursor,
+                                    column,
+                                    real: is_real(table, i),
+                                };
+                            }
+                        }
+                        if let Some(i) = table.rowid_alias {
+                            layout[i] = Location::IdxRowid(index_cursor);
+                        }
+                        layout[table.columns.len()] = Location::IdxRowid(index_cursor);
+                        layout
+                    }
+                    (None, Some(cursor)) => {
+                        let rowid = self.register();
+                        self.emit(Insn::IdxRowid {
+                            cursor: index_cursor,
+                            dest: rowid,
+                        });
+                        self.emit(Insn::SeekRowid {
+                            cursor,
+                            target: next,
+                            rowid,
+                        });
+                        table_layout(table, cursor)
+                    }
+                    (None, None) => unreachable!("an index seek reads the table or covers it"),
+                };
+                body(self, &layout, next)?;
+                self.place(next);
+                self.emit(match reverse {
+                    true => Insn::Prev {
+                        cursor: index_cursor,
+                        target: top,
+                    },
+                    false => Insn::Next {
+                        cursor: index_cursor,
+                        target: top,
+                    },
+                });
+            }
+            Plan::NestedLoop {
+                outer: outer_plan,
+                inner,
+                condition,
+                left,
+                ..
+            } => {
+                self.plan(
+                    outer_plan,
+                    outer,
+                    &mut |codegen, outer_layout, outer_next| {
+                        // A LEFT join remembers whether the outer row matched, and if not
+                        // runs the body once more with the inner cursors on a row of NULLs.
+                        let matched = left.then(|| codegen.register());
+                        if let Some(reg) = matched {
+                            codegen.emit(Insn::Integer {
+                                value: 0,
+                                dest: reg,
+                            });
+                        }
+                        let (first_cursor, reentry) = (codegen.cursors, codegen.label());
+
+                        codegen.plan(inner, outer_layout, &mut |codegen, inner_layout, next| {
+                            let layout = [outer_layout, inner_layout].concat();
+                            if let Some(condition) = condition {
+                                codegen.if_false(condition, &layout, next)?;
+                            }
+                            codegen.place(reentry);
+                            if let Some(reg) = matched {
+                                codegen.emit(Insn::Integer {
+                                    value: 1,
+                                    dest: reg,
+                                });
+                            }
+                            body(codegen, &layout, next)
+                        })?;
+
+                        if let Some(reg) = matched {
+                            codegen.emit(Insn::IfPos {
+                                reg,
+                                target: outer_next,
+                                decrement: 0,
+                            });
+                            for cursor in first_cursor..codegen.cursors {
+                                codegen.emit(Insn::NullRow { cursor });
+                            }
+                            codegen.emit(Insn::Goto { target: reentry });
+                        }
+                        Ok(())
+                    },
+                )?;
+            }
+            Plan::HashJoin {
+                outer: outer_plan,
+                inner,
+                outer_keys,
+                inner_keys,
+                collations,
+                condition,
+                left,
+                inner_width,
+                build_outer,
+            } => {
+                let hash = self.cursor();
+                self.prologue.push(Insn::HashOpen {
+                    cursor: hash,
+                    collations: collations.clone(),
+                    condition: condition.clone(),
+                    build_first: *build_outer,
+                    null_extend: left.then_some(*inner_width),
+                });
+                let (build, build_keys, probe, probe_keys) = match build_outer {
+                    true => (outer_plan, outer_keys, inner, inner_keys),
+                    false => (inner, inner_keys, outer_plan, outer_keys),
+                };
+
+                let mut build_width = 0;
+                self.plan(build, outer, &mut |codegen, layout, _| {
+                    let key = codegen.exprs(build_keys, layout)?;
+                    let (row, n) = codegen.row(layout)?;
+                    build_width = n;
+                    codegen.emit(Insn::HashBuild {
+                        cursor: hash,
+                        key,
+                        nkey: build_keys.len(),
+                        row,
+                        n,
+                    });
+                    Ok(())
+                })?;
+
+                // The probe rows come from a coroutine, which runs until it has the next
+                // one whenever the join needs more.
+                let (coroutine, start, resume) = (self.register(), self.label(), self.label());
+                let (key, mut probe_row) = (self.registers(probe_keys.len()), (0, 0));
+                self.emit(Insn::InitCoroutine {
+                    reg: coroutine,
+                    target: resume,
+                    start,
+                });
+                self.place(start);
+                self.plan(probe, outer, &mut |codegen, layout, _| {
+                    for (i, value) in probe_keys.iter().enumerate() {
+                        codegen.expr(value, layout, key + i)?;
+                    }
+                    probe_row = codegen.row(layout)?;
+                    codegen.emit(Insn::Yield {
+                        reg: coroutine,
+                        target: 0,
+                    });
+                    Ok(())
+                })?;
+                self.emit(Insn::EndCoroutine { reg: coroutine });
+                self.place(resume);
+
+                // The probe rows are fed to the join whenever it runs out of joined rows,
+                // and the join is finished once the coroutine has no more.
+                let (top, need, finish) = (self.label(), self.label(), self.label());
+                self.emit(Insn::Goto { target: top });
+                self.place(need);
+                self.emit(Insn::Yield {
+                    reg: coroutine,
+                    target: finish,
+                });
+                self.emit(Insn::HashProbe {
+                    cursor: hash,
+                    key,
+                    nkey: probe_keys.len(),
+                    row: probe_row.0,
+                    n: probe_row.1,
+                });
+                self.emit(Insn::Goto { target: top });
+                self.place(finish);
+                self.emit(Insn::HashFinish { cursor: hash });
+                self.place(top);
+                self.emit(Insn::HashNext {
+                    cursor: hash,
+                    target: need,
+                    end,
+                });
+                let layout = cursor_layout(hash, build_width + probe_row.1);
+                body(self, &layout, top)?;
+                self.emit(Insn::Goto { target: top });
+            }
+            Plan::Filter { input, predicate } => {
+                self.plan(input, outer, &mut |codegen, layout, next| {
+                    codegen.if_false(predicate, layout, next)?;
+                    body(codegen, layout, next)
+                })?;
+            }
+            Plan::Aggregate {
+                input,
+                input_width,
+                group_by,
+                aggregates,
+            } => {
+                let calls = aggregates
+                    .iter()
+                    .map(|aggregate| match aggregate {
+                        Expr::Aggregate {
+                            func,
+                            args,
+                            distinct,
+                        } => {
+                            let collation = args.first().and_then(Expr::collation);
+                            Ok(((*func, *distinct, collation.unwrap_or_default()), args))
+                        }
+                        expr => bail!(Sql, "not an aggregate: {:?}", expr),
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                let groups = self.cursor();
+                self.prologue.push(Insn::AggOpen {
+                    cursor: groups,
+                    calls: calls.iter().map(|(call, _)| *call).collect(),
+                    collations: group_by
+                        .iter()
+                        .map(|expr| expr.collation().unwrap_or_default())
+                        .collect(),
+                    width: *input_width,
+                    grouped: !group_by.is_empty(),
+                });
+
+                self.plan(input, outer, &mut |codegen, layout, _| {
+                    let key = codegen.exprs(group_by, layout)?;
+                    codegen.emit(Insn::AggGroup {
+                        cursor: groups,
+                        key,
+                        nkey: group_by.len(),
+                    });
+                    for (call, (_, args)) in calls.iter().enumerate() {
+                        let start = codegen.exprs(args, layout)?;
+                        codegen.emit(Insn::AggStep {
+                            cursor: groups,
+                            call,
+                            args: start,
+                            nargs: args.len(),
+                        });
+                    }
+                    let (row, n) = codegen.row(layout)?;
+                    codegen.emit(Insn::AggRow {
+                        cursor: groups,
+                        row,
+                        n,
+                    });
+                    Ok(())
+                })?;
+
+                self.emit(Insn::AggFinal {
+                    cursor: groups,
+                    target: end,
+                });
+                let (top, next) = (self.label(), self.label());
+                self.place(top);
+                let layout = cursor_layout(groups, input_width + aggregates.len());
+                body(self, &layout, next)?;
+                self.place(next);
+                self.emit(Insn::Next {
+                    cursor: groups,
+                    target: top,
+                });
+            }
+            Plan::Distinct {
+                input,
+                columns,
+                collations,
+                ordered,
+            } => {
+                let set = self.cursor();
+                self.prologue.push(Insn::OpenEphemeral {
+                    cursor: set,
+                    collations: collations.clone(),
+                    last_only: *ordered,
+                });
+                self.plan(input, outer, &mut |codegen, layout, next| {
+                    let key = codegen.exprs(columns, layout)?;
+                    codegen.emit(Insn::Found {
+                        cursor: set,
+                        key,
+                        nkey: columns.len(),
+                        target: next,
+                    });
+                    codegen.emit(Insn::IdxInsert {
+                        cursor: set,
+                        key,
+                        nkey: columns.len(),
+                    });
+                    body(codegen, layout, next)
+                })?;
+            }
+            Plan::Sort { input, keys } => {
+                let sorter = self.cursor();
+                self.prologue.push(Insn::SorterOpen {
+                    cursor: sorter,
+                    keys: keys.clone(),
+                });
+
+                let mut width = 0;
+                self.plan(input, outer, &mut |codegen, layout, _| {
+                    let key = codegen.registers(keys.len());
+                    for (i, sort_key) in keys.iter().enumerate() {
+                        codegen.expr(&sort_key.expr, layout, key + i)?;
+                    }
+                    let (row, n) = codegen.row(layout)?;
+                    width = n;
+                    codegen.emit(Insn::SorterInsert {
+                        cursor: sorter,
+                        key,
+                        nkey: keys.len(),
+                        row,
+                        n,
+                    });
+                    Ok(())
+                })?;
+
+                self.emit(Insn::SorterSort {
+                    cursor: sorter,
+                    target: end,
+                });
+                let (top, next) = (self.label(), self.label());
+                self.place(top);
+                body(self, &cursor_layout(sorter, width), next)?;
+                self.place(next);
+                self.emit(Insn::SorterNext {
+                    cursor: sorter,
+                    target: top,
+                });
+            }
+            Plan::IntegrityCheck { quick, max_errors } => {
+                let cursor = self.cursor();
+                self.emit(Insn::IntegrityCk {
+                    cursor,
+                    max_errors: *max_errors,
+                    quick: *quick,
+                    target: end,
+                });
+                let (top, next) = (self.label(), self.label());
+                self.place(top);
+                body(self, &cursor_layout(cursor, 1), next)?;
+                self.place(next);
+                self.emit(Insn::Next {
+                    cursor,
+                    target: top,
+                });
+            }
+            Plan::Project { input, columns } => {
+                self.plan(input, outer, &mut |codegen, layout, next| {
+                    let start = codegen.exprs(columns, layout)?;
+                    let layout: Vec<_> = (start..start + columns.len())
+                        .map(Location::Register)
+                        .collect();
+                    body(codegen, &layout, next)
+                })?;
+            }
+            Plan::Limit {
+                input,
+                limit,
+                offset,
+            } => {
+                // A negative limit means no limit, and a negative offset no offset.
+                let remaining = self.register();
+                self.expr(limit, &[], remaining)?;
+                self.emit(Insn::MustBeInt { reg: remaining });
+                let skip = match offset {
+                    Some(offset) => {
+                        let reg = self.register();
+                        self.expr(offset, &[], reg)?;
+                        self.emit(Insn::MustBeInt { reg });
+                        Some(reg)
+                    }
+                    None => None,
+                };
+                self.emit(Insn::IfNot {
+                    reg: remaining,
+                    target: end,
+                    jump_if_null: true,
+                });
+
+                // Rows are read from the input only until the limit is reached.
+                self.plan(input, outer, &mut |codegen, layout, next| {
+                    if let Some(reg) = skip {
+                        codegen.emit(Insn::IfPos {
+                            reg,
+                            target: next,
+                            decrement: 1,
+                        });
+                    }
+                    body(codegen, layout, next)?;
+                    codegen.emit(Insn::DecrJumpZero {
+                        reg: remaining,
+                        target: end,
+                    });
+                    Ok(())
+                })?;
+            }
+        }
+
+        self.place(end);
+        Ok(())
+    }
+
+    /// Puts the values at `layout` into consecutive registers, unless they already are.
+    /// Returns the first register and the number of values.
+    fn row(&mut self, layout: &[Location]) -> Result<(usize, usize)> {
+        if let Some(Location::Register(first)) = layout.first() {
+            let consecutive = layout.iter().enumerate().all(
+                |(i, location)| matches!(location, Location::Register(reg) if *reg == first + i),
+            );
+            if consecutive {
+                return Ok((*first, layout.len()));
+            }
+        }
+
+        let start = self.registers(layout.len());
+        for (i, location) in layout.iter().enumerate() {
+            self.load(*location, start + i);
+        }
+        Ok((start, layout.len()))
+    }
+
+    fn load(&mut self, location: Location, dest: usize) {
+        match location {
+            Location::Column {
+                cursor,
+                column,
+                real,
+            } => {
+                self.emit(Insn::Column {
+                    cursor,
+                    column,
+                    dest,
+                });
+                if real {
+                    self.emit(Insn::RealAffinity { reg: dest });
+                }
+            }
+            Location::Rowid(cursor) => self.emit(Insn::Rowid { cursor, dest }),
+            Location::IdxRowid(cursor) => self.emit(Insn::IdxRowid { cursor, dest }),
+            Location::Register(src) => self.emit(Insn::SCopy { src, dest }),
+            Location::Null => self.emit(Insn::Null { dest }),
+        }
+    }
+
+    /// Evaluates expressions into consecutive registers and returns the first.
+    fn exprs(&mut self, exprs: &[Expr], layout: &[Location]) -> Result<usize> {
+        let start = self.registers(exprs.len());
+        for (i, expr) in exprs.iter().enumerate() {
+            self.expr(expr, layout, start + i)?;
+        }
+        Ok(start)
+    }
+
+    /// Emits the code that evaluates `expr` against the row at `layout` into `dest`.
+    fn expr(&mut self, expr: &Expr, layout: &[Location], dest: usize) -> Result<()> {
+        match expr {
+            Expr::Identifier { name, .. } => bail!(Sql, "column {} is not bound", name),
+            Expr::Column(i) => match layout.get(*i) {
+                Some(location) => self.load(*location, dest),
+                None => bail!(Sql, "column {} out of range", i),
+            },
+            Expr::Literal(value) => self.emit(match value {
+                SerialValue::Null => Insn::Null { dest },
+                SerialValue::Float64(value) => Insn::Real {
+                    value: *value,
+                    dest,
+                },
+                SerialValue::String(value) => Insn::String8 {
+                    value: value.clone(),
+                    dest,
+                },
+                SerialValue::Blob(value) => Insn::Blob {
+                    value: value.clone(),
+                    dest,
+                },
+                value => Insn::Integer {
+                    value: value.as_integer().unwrap_or_default(),
+                    dest,
+                },
+            }),
+            Expr::Parameter(index) => self.emit(Insn::Variable {
+                index: *index,
+                dest,
+            }),
+            Expr::Unary { op, expr } => match op {
+                UnaryOp::Not => {
+                    let src = self.operand(expr, layout)?;
+                    self.emit(Insn::Not { src, dest });
+                }
+                UnaryOp::Minus => {
+                    let rhs = self.operand(expr, layout)?;
+                    let lhs = self.register();
+                    self.emit(Insn::Integer {
+                        value: 0,
+                        dest: lhs,
+                    });
+                    self.emit(Insn::Arithmetic {
+                        op: BinaryOp::Subtract,
+                        lhs,
+                        rhs,
+                        dest,
+                    });
+                }
+                UnaryOp::Plus => self.expr(expr, layout, dest)?,
+            },
+            Expr::Binary { left, op, right } => {
+                let (lhs, rhs) = (self.operand(left, layout)?, self.operand(right, layout)?);
+                match op {
+                    BinaryOp::And => self.emit(Insn::And { lhs, rhs, dest }),
+                    BinaryOp::Or => self.emit(Insn::Or { lhs, rhs, dest }),
+                    op if op.is_comparison() => {
+                        let collation = comparison_collation(left, right);
+                        self.comparison(*op, lhs, rhs, collation, dest);
+                    }
+                    op => self.emit(Insn::Arithmetic {
+                        op: *op,
+                        lhs,
+                        rhs,
+                        dest,
+                    }),
+                }
+            }
+            Expr::IsNull { expr, negated } => {
+                let reg = self.operand(expr, layout)?;
+                let done = self.label();
+                self.emit(Insn::Integer { value: 1, dest });
+                self.emit(match negated {
+                    true => Insn::NotNull { reg, target: done },
+                    false => Insn::IsNull { reg, target: done },
+                });
+                self.emit(Insn::Integer { value: 0, dest });
+                self.place(done);
+            }
+            Expr::InList {
+                expr,
+                list,
+                negated,
+            } => {
+                // Only the collation of the left operand applies.
+                let collation = expr.collation().unwrap_or_default();
+                let value = self.operand(expr, layout)?;
+                let (saw_null, found, done) = (self.register(), self.label(), self.label());
+                self.emit(Insn::Null { dest });
+                self.emit(Insn::IsNull {
+                    reg: value,
+                    target: done,
+                });
+                self.emit(Insn::Integer {
+                    value: 0,
+                    dest: saw_null,
+                });
+                for item in list {
+                    let item = self.operand(item, layout)?;
+                    let not_null = self.label();
+                    self.emit(Insn::Compare {
+                        op: BinaryOp::Eq,
+                        lhs: value,
+                        rhs: item,
+                        target: found,
+                        collation,
+                        jump_if_null: false,
+                    });
+                    self.emit(Insn::NotNull {
+                        reg: item,
+                        target: not_null,
+                    });
+                    self.emit(Insn::Integer {
+                        value: 1,
+                        dest: saw_null,
+                    });
+                    self.place(not_null);
+                }
+
+                // Without a match, the result is NULL if the list held a NULL.
+                self.emit(Insn::IfPos {
+                    reg: saw_null,
+                    target: done,
+                    decrement: 0,
+                });
+                self.emit(Insn::Integer {
+                    value: *negated as i64,
+                    dest,
+                });
+                self.emit(Insn::Goto { target: done });
+                self.place(found);
+                self.emit(Insn::Integer {
+                    value: !*negated as i64,
+                    dest,
+                });
+                self.place(done);
+            }
+            Expr::Between {
+                expr,
+                low,
+                high,
+                negated,
+            } => {
+                let value = self.operand(expr, layout)?;
+                let (lhs, rhs) = (self.register(), self.register());
+                let bound = self.operand(low, layout)?;
+                self.comparison(
+                    BinaryOp::GtEq,
+                    value,
+                    bound,
+                    comparison_collation(expr, low),
+                    lhs,
+                );
+                let bound = self.operand(high, layout)?;
+                self.comparison(
+                    BinaryOp::LtEq,
+                    value,
+                    bound,
+                    comparison_collation(expr, high),
+                    rhs,
+                );
+                self.emit(Insn::And { lhs, rhs, dest });
+                if *negated {
+                    self.emit(Insn::Not { src: dest, dest });
+                }
+            }
+            Expr::Like {
+                expr,
+                pattern,
+                escape,
+                negated,
+            } => {
+                let func = Function::Like { escape: *escape };
+                self.function(func, pattern, expr, *negated, layout, dest)?
+            }
+            Expr::Glob {
+                expr,
+                pattern,
+                negated,
+            } => self.function(Function::Glob, pattern, expr, *negated, layout, dest)?,
+            Expr::ApplyAffinity { expr, affinity } => {
+                self.expr(expr, layout, dest)?;
+                self.emit(Insn::Affinity {
+                    reg: dest,
+                    affinity: *affinity,
+                });
+            }
+            Expr::Collate { expr, .. } => self.expr(expr, layout, dest)?,
+            Expr::Aggregate { func, .. } => {
+                bail!(Sql, "misuse of aggregate function {}()", func.name())
+            }
+        }
+        Ok(())
+    }
+
+    /// Evaluates an operand into a register of its own.
+    fn operand(&mut self, expr: &Expr, layout: &[Location]) -> Result<usize> {
+        let reg = self.register();
+        self.expr(expr, layout, reg)?;
+        Ok(reg)
+    }
+
+    /// Sets `dest` to whether `lhs op rhs` holds, or NULL if either is NULL.
+    fn comparison(
+        &mut self,
+        op: BinaryOp,
+        lhs: usize,
+        rhs: usize,
+        collation: Collation,
+        dest: usize,
+    ) {
+        let done = self.label();
+        self.emit(Insn::Integer { value: 1, dest });
+        self.emit(Insn::Compare {
+            op,
+            lhs,
+            rhs,
+            target: done,
+            collation,
+            jump_if_null: false,
+        });
+        self.emit(Insn::ZeroOrNull { lhs, dest, rhs });
+        self.place(done);
+    }
+
+    /// Calls LIKE or GLOB, whose functions take the pattern first.
+    fn function(
+        &mut self,
+        func: Function,
+        pattern: &Expr,
+        value: &Expr,
+        negated: bool,
+        layout: &[Location],
+        dest: usize,
+    ) -> Result<()> {
+        let args = self.registers(2);
+        self.expr(pattern, layout, args)?;
+        self.expr(value, layout, args + 1)?;
+        self.emit(Insn::Function {
+            func,
+            args,
+            nargs: 2,
+            dest,
+        });
+        if negated {
+            self.emit(Insn::Not { src: dest, dest });
+        }
+        Ok(())
+    }
+
+    /// Emits the code that jumps to `target` unless `expr` is true, as a WHERE clause
+    /// treats NULL as false.
+    fn if_false(&mut self, expr: &Expr, layout: &[Location], target: usize) -> Result<()> {
+        match expr {
+            Expr::Binary {
+                left,
+                op: BinaryOp::And,
+                right,
+            } => {
+                self.if_false(left, layout, target)?;
+                self.if_false(right, layout, target)?;
+            }
+            Expr::Binary { left, op, right } if op.is_comparison() => {
+                let (lhs, rhs) = (self.operand(left, layout)?, self.operand(right, layout)?);
+                self.emit(Insn::Compare {
+                    op: match op {
+                        BinaryOp::Eq => BinaryOp::NotEq,
+                        BinaryOp::NotEq => BinaryOp::Eq,
+                        BinaryOp::Lt => BinaryOp::GtEq,
+                        BinaryOp::LtEq => BinaryOp::Gt,
+                        BinaryOp::Gt => BinaryOp::LtEq,
+                        _ => BinaryOp::Lt,
+                    },
+                    lhs,
+                    rhs,
+                    target,
+                    collation: comparison_collation(left, right),
+                    jump_if_null: true,
+                });
+            }
+            Expr::IsNull { expr, negated } => {
+                let reg = self.operand(expr, layout)?;
+                self.emit(match negated {
+                    true => Insn::IsNull { reg, target },
+                    false => Insn::NotNull { reg, target },
+                });
+            }
+            expr => {
+                let reg = self.operand(expr, layout)?;
+                self.emit(Insn::IfNot {
+                    reg,
+                    target,
+                    jump_if_null: true,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Where the rows of `table` are read from through a cursor on it, laid out as described
+/// by [`Table::scope`].
+fn table_layout(table: &Table, cursor: usize) -> Vec<Location> {
+    let mut layout: Vec<_> = (0..table.columns.len())
+        .map(|column| match table.rowid_alias {
+            // The rowid alias is stored as NULL in the record.
+            Some(alias) if alias == column => Location::Rowid(cursor),
+            _ => Location::Column {
+                cursor,
+                column,
+                real: is_real(table, column),
+            },
+        })
+        .collect();
+    layout.push(Location::Rowid(cursor));
+    layout
+}
+
+fn is_real(table: &Table, column: usize) -> bool {
+    table
+        .columns
+        .get(column)
+        .is_some_and(|column| column.affinity() == Affinity::Real)
+}
+
+/// The rows held by a sorter, hash join, aggregate or integrity check cursor.
+fn cursor_layout(cursor: usize, width: usize) -> Vec<Location> {
+    (0..width)
+        .map(|column| Location::Column {
+            cursor,
+            column,
+            real: false,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::Database,
+        query::{Query, Row},
+        sql::Sql,
+    };
+
+    /// Compiles `sql` against indexed.db, returning the program and the rows it returns.
+    fn compile_sql(sql: &str) -> (Program, Vec<Row>) {
+        let db = Database::open("indexed.db").unwrap();
+        let query = Query::prepare(&db, &Sql::from_str(sql).unwrap()).unwrap();
+        let rows = query
+            .rows(&db, Vec::new())
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        (query.program().clone(), rows)
+    }
+
+    #[test]
+    fn test_finish() {
+        let (program, rows) = compile_sql("SELECT id FROM p");
+        let insns = &program.insns;
+        let end = insns.len() - 1;
+
+        // Init jumps to the Goto at the end, which runs the prologue and then the code
+        // after it, which halts before reaching the Goto again.
+        assert!(matches!(insns[0], Insn::Init { target } if target == end));
+        assert!(matches!(insns[1], Insn::OpenRead { cursor: 0, .. }));
+        assert!(matches!(insns[end - 1], Insn::Halt));
+        assert!(matches!(insns[end], Insn::Goto { target: 1 }));
+        for insn in insns {
+            assert!(insn
+                .clone()
+                .targets_mut()
+                .iter()
+                .all(|&&mut target| target <= end));
+        }
+        assert_eq!(rows.len(), 3);
+    }
+
+    #[test]
+    fn test_comparison() {
+        // The literal compared with the TEXT column p.t takes its affinity, and the COLLATE
+        // clause decides how they compare.
+        let (program, rows) = compile_sql("SELECT id FROM p WHERE t < 1 OR t = 'Y' COLLATE NOCASE");
+        let affinities = program
+            .insns
+            .iter()
+            .filter(|insn| {
+                matches!(
+                    insn,
+                    Insn::Affinity {
+                        affinity: Affinity::Text,
+                        ..
+                    }
+                )
+            })
+            .count();
+        assert_eq!(affinities, 2);
+        assert!(program.insns.iter().any(|insn| matches!(
+            insn,
+            Insn::Compare {
+                op: BinaryOp::Eq,
+                collation: Collation::NoCase,
+                ..
+            }
+        )));
+        assert_eq!(rows, [[SerialValue::I64(2)]]);
+    }
+
+    #[test]
+    fn test_index_range() {
+        let seek = |program: &Program| {
+            let insns = &program.insns;
+            let at = insns
+                .iter()
+                .position(|insn| matches!(insn, Insn::Seek { .. }))
+                .unwrap();
+            let Insn::Seek { op, target, .. } = insns[at] else {
+                unreachable!()
+            };
+            let Insn::IdxCompare {
+                op: stop,
+                target: done,
+                ..
+            } = insns[at + 1]
+            else {
+                panic!("no IdxCompare after the Seek");
+            };
+            // Both leave the loop for the Halt.
+            assert!(matches!(insns[target], Insn::Halt));
+            assert_eq!(done, target);
+            (op, stop, insns[target - 1].clone())
+        };
+
+        // Start at (a, 10) and stop at (a, 30), or the other way round.
+        let sql = "SELECT id FROM t WHERE a = 'e' AND b >= 10 AND b < 30";
+        let (program, forward) = compile_sql(sql);
+        let (op, stop, next) = seek(&program);
+        assert_eq!((op, stop), (BinaryOp::GtEq, BinaryOp::GtEq));
+        assert!(matches!(next, Insn::Next { cursor: 0, .. }));
+
+        let (program, mut backward) = compile_sql(&format!("{} ORDER BY b DESC, id DESC", sql));
+        let (op, stop, prev) = seek(&program);
+        assert_eq!((op, stop), (BinaryOp::Lt, BinaryOp::Lt));
+        assert!(matches!(prev, Insn::Prev { cursor: 0, .. }));
+
+        assert_eq!(forward.len(), 40);
+        backward.reverse();
+        assert_eq!(forward, backward);
+    }
+
+    #[test]
+    fn test_limit_offset() {
+        let (program, rows) = compile_sql("SELECT id FROM p LIMIT 1 OFFSET 1");
+        assert!(program
+            .insns
+            .iter()
+            .any(|insn| matches!(insn, Insn::IfPos { decrement: 1, .. })));
+        assert!(program
+            .insns
+            .iter()
+            .any(|insn| matches!(insn, Insn::DecrJumpZero { .. })));
+        assert_eq!(rows, [[SerialValue::I64(2)]]);
+    }
+
+    #[test]
+    fn test_left_join() {
+        let (program, rows) =
+            compile_sql("SELECT p.id, t.d FROM p LEFT JOIN t ON t.id = p.id * 500");
+
+        // Unless a row of t matched, the row of p is output once more with t's cursor on
+        // a row of NULLs.
+        let insns = &program.insns;
+        let at = insns
+            .iter()
+            .position(|insn| matches!(insn, Insn::NullRow { cursor: 1 }))
+            .unwrap();
+        assert!(matches!(insns[at - 1], Insn::IfPos { decrement: 0, .. }));
+        let Insn::Goto { target } = insns[at + 1] else {
+            panic!("no Goto after the NullRow");
+        };
+        assert!(target < at);
+
+        let row = |id, d: Option<&str>| {
+            vec![
+                SerialValue::I64(id),
+                d.map_or(SerialValue::Null, |d| SerialValue::String(d.to_string())),
+            ]
+        };
+        assert_eq!(
+            rows,
+            [row(1, Some("d0500")), row(2, Some("d0000")), row(3, None)]
+        );
+    }
+}
@@ -1,4 +1,10 @@
-use anyhow::anyhow;
+use std::{
+    cmp::Ordering,
+    hash::{Hash, Hasher},
+    io::{self, Write},
+};
+
+use crate::error::{bail, err, Result};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum SerialType {
@@ -40,7 +46,7 @@ pub enum SerialType {
 }
 
 impl SerialType {
-    pub fn read(npayload: i64) -> anyhow::Result<Self> {
+    pub fn read(npayload: i64) -> Result<Self> {
         match npayload {
             0 => Ok(Self::Null),
             1 => Ok(Self::I8),
@@ -59,7 +65,7 @@ impl SerialType {
                     Ok(Self::String(((n - 13) / 2) as usize))
                 }
             }
-            npayload => Err(anyhow!("invalid serial type: {}", npayload)),
+            npayload => Err(err!(Corrupt(0, 0), "invalid serial type: {}", npayload)),
         }
     }
 
@@ -81,7 +87,12 @@ impl SerialType {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+/// A value read from a record.
+///
+/// Values compare the way SQLite compares them with the BINARY collation: NULL sorts first,
+/// then integers and reals ordered numerically, then text ordered bytewise, then blobs.
+/// See <https://www.sqlite.org/datatype3.html#sort_order>.
+#[derive(Debug, Clone)]
 pub enum SerialValue {
     Null,
     I8(i8),
@@ -98,15 +109,547 @@ pub enum SerialValue {
 }
 
 impl SerialValue {
+    /// The value as a 64-bit integer if it is stored as one of the integer serial types.
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            SerialValue::I8(num) => Some(*num as i64),
+            SerialValue::I16(num) => Some(*num as i64),
+            SerialValue::I24(num) | SerialValue::I32(num) => Some(*num as i64),
+            SerialValue::I48(num) | SerialValue::I64(num) => Some(*num),
+            SerialValue::Zero => Some(0),
+            SerialValue::One => Some(1),
+            _ => None,
+        }
+    }
+
+    /// Converts the value as it would be when stored in a column with the given affinity.
+    ///
+    /// See <https://www.sqlite.org/datatype3.html#type_affinity>.
+    pub fn apply_affinity(&self, affinity: Affinity) -> SerialValue {
+        match (affinity, self) {
+            (Affinity::Text, SerialValue::Float64(num)) => {
+                SerialValue::String(format_real(*num, 15))
+            }
+            (Affinity::Text, value) => match value.as_integer() {
+                Some(num) => SerialValue::String(num.to_string()),
+                None => value.clone(),
+            },
+            (Affinity::Numeric | Affinity::Integer, SerialValue::String(txt)) => {
+                match parse_numeric(txt) {
+                    Some(SerialValue::Float64(num)) => real_to_integer(num),
+                    Some(value) => value,
+                    None => self.clone(),
+                }
+            }
+            (Affinity::Numeric | Affinity::Integer, SerialValue::Float64(num)) => {
+                real_to_integer(*num)
+            }
+            (Affinity::Real, SerialValue::String(txt)) => match parse_numeric(txt) {
+                Some(value) => value.apply_affinity(Affinity::Real),
+                None => self.clone(),
+            },
+            (Affinity::Real, value) => match value.as_integer() {
+                Some(num) => SerialValue::Float64(num as f64),
+                None => value.clone(),
+            },
+            (_, value) => value.clone(),
+        }
+    }
+
+    /// 0 for NULL, 1 for numbers, 2 for text and 3 for blobs.
+    fn storage_class(&self) -> u8 {
+        match self {
+            SerialValue::Null => 0,
+            SerialValue::String(_) => 2,
+            SerialValue::Blob(_) => 3,
+            _ => 1,
+        }
+    }
+
+    /// Renders the value as the sqlite3 shell does in its default list mode.
     pub fn display(&self) -> String {
+        self.display_with(&DisplayOptions::default())
+    }
+
+    /// Renders the value as the sqlite3 shell does with the given `.mode` and `.nullvalue`.
+    ///
+    /// Blobs that are not valid UTF-8 are rendered lossily; use [`SerialValue::write`] to
+    /// output their raw bytes.
+    pub fn display_with(&self, options: &DisplayOptions) -> String {
+        let mut bytes = Vec::new();
+        self.write(&mut bytes, options)
+            .expect("writing to a Vec can not fail");
+
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+
+    /// Writes the value to `out` as the sqlite3 shell does with the given `.mode` and
+    /// `.nullvalue`.
+    ///
+    /// Like sqlite3, which prints them as C strings, text and blobs are cut off at the first
+    /// NUL byte.
+    pub fn write(&self, out: &mut impl Write, options: &DisplayOptions) -> io::Result<()> {
+        let until_nul = |bytes: &[u8]| match bytes.iter().position(|byte| *byte == 0) {
+            Some(nul) => bytes[..nul].to_vec(),
+            None => bytes.to_vec(),
+        };
+
+        match (options.mode, self) {
+            (OutputMode::List, SerialValue::Null) => out.write_all(options.null_value.as_bytes()),
+            (OutputMode::Quote, SerialValue::Null) => out.write_all(b"NULL"),
+            (OutputMode::List, SerialValue::String(txt)) => {
+                out.write_all(&until_nul(txt.as_bytes()))
+            }
+            (OutputMode::Quote, SerialValue::String(txt)) => {
+                let txt = String::from_utf8_lossy(&until_nul(txt.as_bytes())).replace('\'', "''");
+                write!(out, "'{}'", txt)
+            }
+            (OutputMode::List, SerialValue::Blob(bytes)) => out.write_all(&until_nul(bytes)),
+            (OutputMode::Quote, SerialValue::Blob(bytes)) => {
+                out.write_all(b"X'")?;
+                for byte in bytes {
+                    write!(out, "{:02x}", byte)?;
+                }
+                out.write_all(b"'")
+            }
+            (OutputMode::List, SerialValue::Float64(num)) => {
+                out.write_all(format_real(*num, 15).as_bytes())
+            }
+            // Infinities are written as literals that read back as them.
+            (OutputMode::Quote, SerialValue::Float64(num)) if num.is_infinite() => {
+                out.write_all(if *num > 0.0 { b"1e999" } else { b"-1e999" })
+            }
+            (OutputMode::Quote, SerialValue::Float64(num)) => {
+                out.write_all(format_real(*num, 20).as_bytes())
+            }
+            (_, value) => write!(out, "{}", value.as_integer().unwrap_or_default()),
+        }
+    }
+}
+
+/// The output modes of the sqlite3 shell, as set by `.mode`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputMode {
+    /// Values as they are, separated by `|`.
+    #[default]
+    List,
+    /// Values as SQL literals, separated by `,`.
+    Quote,
+}
+
+impl OutputMode {
+    /// The string printed between the columns of a row.
+    pub fn separator(self) -> &'static str {
+        match self {
+            OutputMode::List => "|",
+            OutputMode::Quote => ",",
+        }
+    }
+}
+
+/// How to render query results.
+#[derive(Debug, Clone, Default)]
+pub struct DisplayOptions {
+    pub mode: OutputMode,
+    /// The string printed for NULL in list mode, as set by `.nullvalue`. Empty by default.
+    pub null_value: String,
+}
+
+impl DisplayOptions {
+    /// Writes one row of a query result to `out`, followed by a newline.
+    pub fn write_row(&self, out: &mut impl Write, row: &[SerialValue]) -> io::Result<()> {
+        for (i, value) in row.iter().enumerate() {
+            if i > 0 {
+                out.write_all(self.mode.separator().as_bytes())?;
+            }
+            value.write(out, self)?;
+        }
+        writeln!(out)
+    }
+}
+
+impl PartialEq for SerialValue {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for SerialValue {}
+
+impl PartialOrd for SerialValue {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for SerialValue {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (SerialValue::String(a), SerialValue::String(b)) => a.as_bytes().cmp(b.as_bytes()),
+            (SerialValue::Blob(a), SerialValue::Blob(b)) => a.cmp(b),
+            (SerialValue::Float64(a), SerialValue::Float64(b)) => {
+                a.partial_cmp(b).unwrap_or_else(|| a.total_cmp(b))
+            }
+            (SerialValue::Float64(a), b) if b.storage_class() == 1 => {
+                compare_integer_real(b.as_integer().unwrap_or_default(), *a).reverse()
+            }
+            (a, SerialValue::Float64(b)) if a.storage_class() == 1 => {
+                compare_integer_real(a.as_integer().unwrap_or_default(), *b)
+            }
+            (a, b) => match (a.as_integer(), b.as_integer()) {
+                (Some(a), Some(b)) => a.cmp(&b),
+                _ => a.storage_class().cmp(&b.storage_class()),
+            },
+        }
+    }
+}
+
+impl Hash for SerialValue {
+    /// Hashes values that compare equal alike: an integer and a real with the same value,
+    /// or the same integer in different serial types.
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        const TWO_POW_63: f64 = 9223372036854775808.0;
+
+        match self {
+            SerialValue::Null => 0u8.hash(state),
+            SerialValue::Float64(real)
+                if real.fract() == 0.0 && *real >= -TWO_POW_63 && *real < TWO_POW_63 =>
+            {
+                1u8.hash(state);
+                (*real as i64).hash(state);
+            }
+            SerialValue::Float64(real) => {
+                2u8.hash(state);
+                real.to_bits().hash(state);
+            }
+            SerialValue::String(txt) => {
+                3u8.hash(state);
+                txt.hash(state);
+            }
+            SerialValue::Blob(blob) => {
+                4u8.hash(state);
+                blob.hash(state);
+            }
+            integer => {
+                1u8.hash(state);
+                integer.as_integer().unwrap_or_default().hash(state);
+            }
+        }
+    }
+}
+
+/// Compares an integer with a real exactly, without rounding the integer to a double.
+fn compare_integer_real(integer: i64, real: f64) -> Ordering {
+    const TWO_POW_63: f64 = 9223372036854775808.0;
+
+    if real.is_nan() || real < -TWO_POW_63 {
+        return Ordering::Greater;
+    }
+    if real >= TWO_POW_63 {
+        return Ordering::Less;
+    }
+
+    let truncated = real.trunc();
+    integer.cmp(&(truncated as i64)).then_with(|| {
+        0.0.partial_cmp(&(real - truncated))
+            .unwrap_or(Ordering::Equal)
+    })
+}
+
+/// Converts a real to an integer if that loses no information.
+fn real_to_integer(num: f64) -> SerialValue {
+    let integer = num as i64;
+    if integer as f64 == num && integer > i64::MIN && integer < i64::MAX {
+        SerialValue::I64(integer)
+    } else {
+        SerialValue::Float64(num)
+    }
+}
+
+/// Parses text that is, apart from surrounding whitespace, entirely an integer or real
+/// literal. Integers too large for 64 bits become reals.
+fn parse_numeric(txt: &str) -> Option<SerialValue> {
+    let txt = txt.trim();
+    let digits = txt.strip_prefix(['+', '-']).unwrap_or(txt);
+    let (mantissa, exponent) = match digits.find(['e', 'E']) {
+        Some(at) => (&digits[..at], Some(&digits[at + 1..])),
+        None => (digits, None),
+    };
+
+    let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let all_digits = |part: &str| part.bytes().all(|byte| byte.is_ascii_digit());
+    if whole.is_empty() && fraction.is_empty() || !all_digits(whole) || !all_digits(fraction) {
+        return None;
+    }
+    if let Some(exponent) = exponent {
+        let exponent = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+        if exponent.is_empty() || !all_digits(exponent) {
+            return None;
+        }
+    }
+
+    if exponent.is_none() && !mantissa.contains('.') {
+        if let Ok(num) = txt.parse() {
+            return Some(SerialValue::I64(num));
+        }
+    }
+
+    txt.parse().ok().map(SerialValue::Float64)
+}
+
+/// Formats a real the way SQLite's `printf("%!.<precision>g")` does: `precision`
+/// significant digits and always a decimal point. SQLite converts reals to text with a
+/// precision of 15.
+fn format_real(num: f64, precision: usize) -> String {
+    if num.is_infinite() {
+        return if num > 0.0 { "Inf" } else { "-Inf" }.to_string();
+    }
+    if num == 0.0 {
+        return "0.0".to_string();
+    }
+
+    let (digits, decimal_point) = decimal_digits(num.abs(), precision);
+    let exponent = decimal_point - 1;
+    let digit = |i: i32| match usize::try_from(i).ok().and_then(|i| digits.get(i)) {
+        Some(digit) => char::from(b'0' + digit),
+        None => '0',
+    };
+    let fraction = |from: i32| match (from..digits.len() as i32).map(digit).collect::<String>() {
+        fraction if fraction.is_empty() => "0".to_string(),
+        fraction => fraction,
+    };
+
+    let sign = if num < 0.0 { "-" } else { "" };
+    if exponent < -4 || exponent >= precision as i32 {
+        let exponent_sign = if exponent < 0 { '-' } else { '+' };
+        format!(
+            "{}{}.{}e{}{:02}",
+            sign,
+            digit(0),
+            fraction(1),
+            exponent_sign,
+            exponent.abs()
+        )
+    } else if exponent >= 0 {
+        let whole: String = (0..=exponent).map(digit).collect();
+        format!("{}{}.{}", sign, whole, fraction(exponent + 1))
+    } else {
+        let zeros = "0".repeat((-exponent - 1) as usize);
+        format!("{}0.{}{}", sign, zeros, fraction(0))
+    }
+}
+
+/// Decimal digits of a positive real, rounded to at most `precision` significant digits and
+/// without trailing zeros, and the position of the decimal point relative to the first
+/// digit.
+///
+/// This follows `sqlite3FpDecode`, which computes about 19 digits with double-double
+/// arithmetic rather than exactly, so that the digits match sqlite3's output.
+// The constants are copied from SQLite as they are.
+#[allow(clippy::excessive_precision)]
+fn decimal_digits(num: f64, precision: usize) -> (Vec<u8>, i32) {
+    /// Multiplies the double-double `x` by the double-double `(y, yy)`.
+    fn multiply(x: &mut [f64; 2], y: f64, yy: f64) {
+        let high = |value: f64| f64::from_bits(value.to_bits() & 0xffff_ffff_fc00_0000);
+
+        let (hx, hy) = (high(x[0]), high(y));
+        let (tx, ty) = (x[0] - hx, y - hy);
+        let p = hx * hy;
+        let q = hx * ty + tx * hy;
+        let c = p + q;
+        let cc = p - c + q + tx * ty;
+        let cc = x[0] * yy + x[1] * y + cc;
+        x[0] = c + cc;
+        x[1] = c - x[0] + cc;
+    }
+
+    // Scale the number to between about 1e17 and 1e19.
+    let mut rr = [num, 0.0];
+    let mut exponent = 0;
+    if rr[0] > 9.223372036854774784e18 {
+        while rr[0] > 9.223372036854774784e118 {
+            exponent += 100;
+            multiply(&mut rr, 1.0e-100, -1.99918998026028836196e-117);
+        }
+        while rr[0] > 9.223372036854774784e28 {
+            exponent += 10;
+            multiply(&mut rr, 1.0e-10, -3.6432197315497741579e-27);
+        }
+        while rr[0] > 9.223372036854774784e18 {
+            exponent += 1;
+            multiply(&mut rr, 1.0e-01, -5.5511151231257827021e-18);
+        }
+    } else {
+        while rr[0] < 9.223372036854774784e-83 {
+            exponent -= 100;
+            multiply(&mut rr, 1.0e+100, -1.5902891109759918046e+83);
+        }
+        while rr[0] < 9.223372036854774784e07 {
+            exponent -= 10;
+            multiply(&mut rr, 1.0e+10, 0.0);
+        }
+        while rr[0] < 9.22337203685477478e17 {
+            exponent -= 1;
+            multiply(&mut rr, 1.0e+01, 0.0);
+        }
+    }
+    let scaled = if rr[1] < 0.0 {
+        (rr[0] as u64).wrapping_sub((-rr[1]) as u64)
+    } else {
+        (rr[0] as u64).wrapping_add(rr[1] as u64)
+    };
+
+    let mut digits: Vec<u8> = scaled.to_string().bytes().map(|byte| byte - b'0').collect();
+    let mut decimal_point = digits.len() as i32 + exponent;
+
+    if precision < digits.len() {
+        let round_up = digits[precision] >= 5;
+        digits.truncate(precision);
+        if round_up {
+            match digits.iter().rposition(|digit| *digit != 9) {
+                Some(i) => {
+                    digits[i] += 1;
+                    digits.truncate(i + 1);
+                }
+                None => {
+                    digits = vec![1];
+                    decimal_point += 1;
+                }
+            }
+        }
+    }
+    while digits.len() > 1 && digits.last() == Some(&0) {
+        digits.pop();
+    }
+
+    (digits, decimal_point)
+}
+
+/// The type affinity of a column: the storage class its values are converted to when
+/// possible.
+///
+/// See <https://www.sqlite.org/datatype3.html#type_affinity>.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Affinity {
+    Text,
+    Numeric,
+    Integer,
+    Real,
+    /// Also called "no affinity": values are stored as they are.
+    Blob,
+}
+
+impl Affinity {
+    /// Determines the affinity of a column from its declared type.
+    ///
+    /// See <https://www.sqlite.org/datatype3.html#determination_of_column_affinity>.
+    pub fn from_decl_type(decl_type: &str) -> Self {
+        let decl_type = decl_type.to_ascii_uppercase();
+        let contains = |words: &[&str]| words.iter().any(|word| decl_type.contains(word));
+
+        if contains(&["INT"]) {
+            Affinity::Integer
+        } else if contains(&["CHAR", "CLOB", "TEXT"]) {
+            Affinity::Text
+        } else if contains(&["BLOB"]) || decl_type.is_empty() {
+            Affinity::Blob
+        } else if contains(&["REAL", "FLOA", "DOUB"]) {
+            Affinity::Real
+        } else {
+            Affinity::Numeric
+        }
+    }
+
+    /// Whether this is INTEGER, REAL or NUMERIC affinity.
+    pub fn is_numeric(self) -> bool {
+        matches!(self, Affinity::Numeric | Affinity::Integer | Affinity::Real)
+    }
+}
+
+/// A collating sequence, which orders text values. Values of other storage classes are
+/// always compared the same way.
+///
+/// See <https://www.sqlite.org/datatype3.html#collating_sequences>.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Collation {
+    /// Compares bytes with memcmp().
+    #[default]
+    Binary,
+    /// Like BINARY, but folds the 26 upper case ASCII letters to lower case first.
+    NoCase,
+    /// Like BINARY, but ignores trailing spaces.
+    RTrim,
+}
+
+impl Collation {
+    /// Looks up a built-in collating sequence by name, ignoring case.
+    pub fn from_name(name: &str) -> Result<Self> {
+        Ok(match name.to_ascii_uppercase().as_str() {
+            "BINARY" => Collation::Binary,
+            "NOCASE" => Collation::NoCase,
+            "RTRIM" => Collation::RTrim,
+            _ => bail!(Sql, "no such collation sequence: {}", name),
+        })
+    }
+
+    pub fn compare(self, left: &SerialValue, right: &SerialValue) -> Ordering {
+        let (SerialValue::String(left), SerialValue::String(right)) = (left, right) else {
+            return left.cmp(right);
+        };
+
         match self {
-            SerialValue::Null => "".to_string(),
-            SerialValue::String(txt) => txt.to_string(),
-            _ => todo!(),
+            Collation::Binary => left.as_bytes().cmp(right.as_bytes()),
+            Collation::NoCase => left
+                .bytes()
+                .map(|byte| byte.to_ascii_lowercase())
+                .cmp(right.bytes().map(|byte| byte.to_ascii_lowercase())),
+            Collation::RTrim => left
+                .trim_end_matches(' ')
+                .as_bytes()
+                .cmp(right.trim_end_matches(' ').as_bytes()),
         }
     }
+
+    /// The value that stands for every value equal to `value` under this collation, so
+    /// that equal values can be found by hashing.
+    pub fn fold(self, value: SerialValue) -> SerialValue {
+        match (self, value) {
+            (Collation::NoCase, SerialValue::String(txt)) => {
+                SerialValue::String(txt.to_ascii_lowercase())
+            }
+            (Collation::RTrim, SerialValue::String(txt)) => {
+                SerialValue::String(txt.trim_end_matches(' ').to_string())
+            }
+            (_, value) => value,
+        }
+    }
+}
+
+/// A value ordered by a collating sequence, so that values equal under it are the same
+/// key of an ordered map or set.
+#[derive(Debug, Clone)]
+pub struct Collated(pub SerialValue, pub Collation);
+
+impl Ord for Collated {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.1.compare(&self.0, &other.0)
+    }
 }
 
+impl PartialOrd for Collated {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Collated {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Collated {}
+
 /// Each record consists of a key and optional data
 #[derive(Debug, Clone)]
 pub struct Column {
@@ -127,3 +670,137 @@ impl Column {
         &self.data
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serial_value_ordering() {
+        let mut values = vec![
+            SerialValue::Blob(vec![0]),
+            SerialValue::String("a".to_string()),
+            SerialValue::Float64(2.5),
+            SerialValue::I8(2),
+            SerialValue::Null,
+            SerialValue::I64(3),
+        ];
+        values.sort();
+
+        assert_eq!(
+            values,
+            vec![
+                SerialValue::Null,
+                SerialValue::I8(2),
+                SerialValue::Float64(2.5),
+                SerialValue::I64(3),
+                SerialValue::String("a".to_string()),
+                SerialValue::Blob(vec![0]),
+            ]
+        );
+        assert_eq!(SerialValue::One, SerialValue::Float64(1.0));
+        assert!(SerialValue::I64(i64::MAX) < SerialValue::Float64(9223372036854775808.0));
+        assert!(SerialValue::I64(i64::MAX - 1) > SerialValue::Float64(9.223372036854775e18));
+    }
+
+    #[test]
+    fn test_apply_affinity() {
+        let text = |txt: &str| SerialValue::String(txt.to_string());
+
+        assert_eq!(Affinity::from_decl_type("VARCHAR(255)"), Affinity::Text);
+        assert_eq!(Affinity::from_decl_type("BIGINT"), Affinity::Integer);
+        assert_eq!(
+            Affinity::from_decl_type("FLOATING POINT"),
+            Affinity::Integer
+        );
+        assert_eq!(Affinity::from_decl_type("DOUBLE"), Affinity::Real);
+        assert_eq!(Affinity::from_decl_type(""), Affinity::Blob);
+        assert_eq!(
+            Affinity::from_decl_type("DECIMAL(10, 2)"),
+            Affinity::Numeric
+        );
+
+        assert!(matches!(
+            text(" 42 ").apply_affinity(Affinity::Numeric),
+            SerialValue::I64(42)
+        ));
+        assert!(matches!(
+            text("3.0").apply_affinity(Affinity::Integer),
+            SerialValue::I64(3)
+        ));
+        assert!(matches!(
+            text("1.5e1").apply_affinity(Affinity::Real),
+            SerialValue::Float64(num) if num == 15.0
+        ));
+        assert!(matches!(
+            text("12abc").apply_affinity(Affinity::Numeric),
+            SerialValue::String(_)
+        ));
+        assert!(matches!(
+            SerialValue::I16(7).apply_affinity(Affinity::Text),
+            SerialValue::String(txt) if txt == "7"
+        ));
+        assert!(matches!(
+            SerialValue::Float64(1e20).apply_affinity(Affinity::Text),
+            SerialValue::String(txt) if txt == "1.0e+20"
+        ));
+    }
+
+    #[test]
+    fn test_format_real() {
+        assert_eq!(format_real(1.0, 15), "1.0");
+        assert_eq!(format_real(3.5, 15), "3.5");
+        assert_eq!(format_real(0.1, 15), "0.1");
+        assert_eq!(format_real(1e20, 15), "1.0e+20");
+        assert_eq!(format_real(1.5e-5, 15), "1.5e-05");
+        assert_eq!(format_real(123456789012345.0, 15), "123456789012345.0");
+        assert_eq!(format_real(1e15, 15), "1.0e+15");
+        assert_eq!(format_real(1.0 / 3.0, 15), "0.333333333333333");
+        assert_eq!(format_real(1e14, 15), "100000000000000.0");
+        assert_eq!(format_real(-0.05, 15), "-0.05");
+        assert_eq!(format_real(9.999999999999999e14, 15), "1.0e+15");
+        assert_eq!(format_real(0.1, 20), "0.1000000000000000055");
+        assert_eq!(format_real(1.0 / 3.0, 20), "0.3333333333333333148");
+        assert_eq!(format_real(1e-5, 20), "1.000000000000000082e-05");
+        assert_eq!(format_real(1e19, 20), "10000000000000000000.0");
+    }
+
+    #[test]
+    fn test_display() {
+        let quote = DisplayOptions {
+            mode: OutputMode::Quote,
+            ..Default::default()
+        };
+        let null_value = DisplayOptions {
+            null_value: "NIL".to_string(),
+            ..Default::default()
+        };
+
+        assert_eq!(SerialValue::I24(-70000).display(), "-70000");
+        assert_eq!(SerialValue::Zero.display(), "0");
+        assert_eq!(SerialValue::Float64(2.0).display(), "2.0");
+        assert_eq!(SerialValue::Float64(1e300).display(), "1.0e+300");
+        assert_eq!(SerialValue::Blob(b"abc".to_vec()).display(), "abc");
+        assert_eq!(SerialValue::Null.display(), "");
+        assert_eq!(SerialValue::Null.display_with(&null_value), "NIL");
+
+        assert_eq!(SerialValue::Null.display_with(&quote), "NULL");
+        assert_eq!(
+            SerialValue::String("it's".to_string()).display_with(&quote),
+            "'it''s'"
+        );
+        assert_eq!(
+            SerialValue::Blob(vec![0, 255]).display_with(&quote),
+            "X'00ff'"
+        );
+        assert_eq!(
+            SerialValue::Float64(f64::INFINITY).display_with(&quote),
+            "1e999"
+        );
+        assert_eq!(
+            SerialValue::Float64(f64::NEG_INFINITY).display_with(&quote),
+            "-1e999"
+        );
+        assert_eq!(SerialValue::Float64(f64::INFINITY).display(), "Inf");
+    }
+}
@@ -0,0 +1,659 @@
+use std::path::Path;
+
+use serde::de::{Deserialize, DeserializeOwned};
+
+use crate::{
+    column::SerialValue,
+    database::Database,
+    de,
+    error::{bail, err, Result},
+    query::{self, Query},
+    sql::{Explain, Sql},
+    vdbe::Program,
+};
+
+/// An open database file, through which SQL statements are prepared and run.
+///
+/// ```no_run
+/// use sqlite_starter_rust::Connection;
+///
+/// let conn = Connection::open("sample.db")?;
+/// let stmt = conn.prepare("SELECT id, name FROM apples WHERE color = 'Red'")?;
+/// for row in stmt.query(())? {
+///     let row = row?;
+///     let id: i64 = row.get(0)?;
+///     let name: String = row.get("name")?;
+///     println!("{id}: {name}");
+/// }
+/// # Ok::<(), sqlite_starter_rust::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct Connection {
+    db: Database,
+}
+
+/// A statement prepared against a [`Connection`], which can be run any number of times.
+#[derive(Debug)]
+pub struct Statement<'c> {
+    conn: &'c Connection,
+    query: Query,
+    explain: Option<Explain>,
+    column_names: Vec<String>,
+    parameters: Vec<Option<String>>,
+}
+
+/// The rows a [`Statement`] returns, read from the database as they are iterated over.
+pub struct Rows<'s> {
+    rows: query::Rows<'s>,
+    column_names: &'s [String],
+}
+
+/// A row returned by a [`Statement`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row<'s> {
+    values: Vec<SerialValue>,
+    column_names: &'s [String],
+}
+
+impl Connection {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self {
+            db: Database::open(path)?,
+        })
+    }
+
+    /// The database file the connection reads.
+    pub fn database(&self) -> &Database {
+        &self.db
+    }
+
+    /// Parses a SELECT statement, possibly prefixed with EXPLAIN, or `PRAGMA integrity_check`
+    /// or `PRAGMA quick_check`, and plans how to run it.
+    pub fn prepare(&self, sql: &str) -> Result<Statement<'_>> {
+        let sql = Sql::from_str(sql)?;
+        if sql.projection.is_empty() && sql.pragma.is_none() {
+            bail!(
+                Unsupported,
+                "only SELECT and PRAGMA statements can be prepared"
+            );
+        }
+        let query = Query::prepare(&self.db, &sql)?;
+
+        // EXPLAIN returns the same columns as in SQLite.
+        let column_names = match sql.explain {
+            Some(Explain::Program) => ["addr", "opcode", "p1", "p2", "p3", "p4", "p5", "comment"]
+                .map(String::from)
+                .into(),
+            Some(Explain::QueryPlan) => ["id", "parent", "notused", "detail"]
+                .map(String::from)
+                .into(),
+            None => query.column_names.clone(),
+        };
+
+        Ok(Statement {
+            conn: self,
+            query,
+            explain: sql.explain,
+            column_names,
+            parameters: sql.parameters,
+        })
+    }
+}
+
+impl<'c> Statement<'c> {
+    /// The names of the columns of the rows the statement returns.
+    pub fn column_names(&self) -> &[String] {
+        &self.column_names
+    }
+
+    /// The number of parameters of the statement: the largest index of a parameter.
+    pub fn parameter_count(&self) -> usize {
+        self.parameters.len()
+    }
+
+    /// The name of the parameter with index `index`, from 1, including its prefix. `?NNN`
+    /// parameters are named as written, and `?` parameters have no name.
+    pub fn parameter_name(&self, index: usize) -> Option<&str> {
+        self.parameters.get(index.checked_sub(1)?)?.as_deref()
+    }
+
+    /// The index, from 1, of the parameter named `name`, including its prefix.
+    pub fn parameter_index(&self, name: &str) -> Option<usize> {
+        self.parameters
+            .iter()
+            .position(|parameter| parameter.as_deref() == Some(name))
+            .map(|i| i + 1)
+    }
+
+    /// Whether the statement is prefixed with EXPLAIN, and how.
+    pub fn explain(&self) -> Option<Explain> {
+        self.explain
+    }
+
+    /// The program the statement runs, as listed by EXPLAIN.
+    pub fn program(&self) -> &Program {
+        self.query.program()
+    }
+
+    /// The steps the statement takes, as described by EXPLAIN QUERY PLAN.
+    pub fn query_plan(&self) -> Vec<String> {
+        self.query.query_plan()
+    }
+
+    /// Runs the statement with `params` bound to its parameters. Statements without
+    /// parameters take `()`. The statement can be run again with other values, without
+    /// being prepared again. EXPLAIN statements ignore `params`.
+    pub fn query(&self, params: impl Params) -> Result<Rows<'_>> {
+        let rows: query::Rows<'_> = match self.explain {
+            Some(Explain::Program) => Box::new(self.program().rows().into_iter().map(Ok)),
+            Some(Explain::QueryPlan) => {
+                // Each step is a child of the one before if it is nested in it, which the
+                // flat list of steps does not tell, so they are all listed at the top.
+                let rows: Vec<_> = self
+                    .query_plan()
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, detail)| {
+                        Ok(vec![
+                            SerialValue::I64(i as i64 + 1),
+                            SerialValue::I64(0),
+                            SerialValue::I64(0),
+                            SerialValue::String(detail),
+                        ])
+                    })
+                    .collect();
+                Box::new(rows.into_iter())
+            }
+            None => self
+                .query
+                .rows(&self.conn.db, params.bind(&self.parameters)?)?,
+        };
+
+        Ok(Rows {
+            rows,
+            column_names: &self.column_names,
+        })
+    }
+
+    /// Runs the statement like [`Statement::query`], deserializing each row into `T` as
+    /// described by [`de::from_row`].
+    pub fn query_as<T: DeserializeOwned>(
+        &self,
+        params: impl Params,
+    ) -> Result<impl Iterator<Item = Result<T>> + '_> {
+        Ok(self.query(params)?.map(|row| row?.deserialize()))
+    }
+}
+
+impl<'s> Iterator for Rows<'s> {
+    type Item = Result<Row<'s>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let values = match self.rows.next()? {
+            Ok(values) => values,
+            Err(err) => return Some(Err(err)),
+        };
+        Some(Ok(Row {
+            values,
+            column_names: self.column_names,
+        }))
+    }
+}
+
+impl Row<'_> {
+    /// The value of a column, given its index or its name, converted to `T`.
+    pub fn get<T: FromValue>(&self, column: impl ColumnIndex) -> Result<T> {
+        let i = column.index(self.column_names)?;
+        T::from_value(&self.values[i]).map_err(|err| {
+            err!(
+                Conversion,
+                "column {} ({}): {}",
+                i,
+                self.column_names[i],
+                err
+            )
+        })
+    }
+
+    /// Deserializes the row into `T`, as described by [`de::from_row`].
+    pub fn deserialize<'r, T: Deserialize<'r>>(&'r self) -> Result<T> {
+        de::from_row(self)
+    }
+
+    /// The values of the row, in the order of the columns.
+    pub fn values(&self) -> &[SerialValue] {
+        &self.values
+    }
+
+    pub fn into_values(self) -> Vec<SerialValue> {
+        self.values
+    }
+
+    pub fn column_names(&self) -> &[String] {
+        self.column_names
+    }
+}
+
+/// The values to bind to the parameters of a statement, given in order or by name.
+///
+/// Values are given in order as `()`, a tuple, an array or a slice, and bound to the
+/// parameters by index: the first to `?1`, the second to `?2` and so on. There must be
+/// one for each parameter. Values given by name, as pairs such as `(":name", value)`, are
+/// bound to the parameters written with that name, including the prefix; parameters
+/// without a value are NULL.
+pub trait Params {
+    /// The values to bind, by index from 1, to parameters with the given names.
+    fn bind(self, parameters: &[Option<String>]) -> Result<Vec<SerialValue>>;
+}
+
+/// Binds `values` to the parameters by index.
+fn bind_positional(
+    parameters: &[Option<String>],
+    values: impl ExactSizeIterator<Item = SerialValue>,
+) -> Result<Vec<SerialValue>> {
+    if values.len() != parameters.len() {
+        bail!(
+            Sql,
+            "wrong number of parameters: expected {}, got {}",
+            parameters.len(),
+            values.len()
+        );
+    }
+    Ok(values.collect())
+}
+
+/// Binds `values` to the parameters with their names.
+fn bind_named<'v>(
+    parameters: &[Option<String>],
+    values: impl Iterator<Item = (&'v str, SerialValue)>,
+) -> Result<Vec<SerialValue>> {
+    let mut bound = vec![SerialValue::Null; parameters.len()];
+    for (name, value) in values {
+        match parameters
+            .iter()
+            .position(|parameter| parameter.as_deref() == Some(name))
+        {
+            Some(i) => bound[i] = value,
+            None => bail!(Sql, "no such parameter: {}", name),
+        }
+    }
+    Ok(bound)
+}
+
+impl Params for () {
+    fn bind(self, parameters: &[Option<String>]) -> Result<Vec<SerialValue>> {
+        bind_positional(parameters, std::iter::empty())
+    }
+}
+
+impl<T: ToValue, const N: usize> Params for [T; N] {
+    fn bind(self, parameters: &[Option<String>]) -> Result<Vec<SerialValue>> {
+        bind_positional(parameters, self.iter().map(ToValue::to_value))
+    }
+}
+
+impl<T: ToValue> Params for &[T] {
+    fn bind(self, parameters: &[Option<String>]) -> Result<Vec<SerialValue>> {
+        bind_positional(parameters, self.iter().map(ToValue::to_value))
+    }
+}
+
+impl<T: ToValue> Params for Vec<T> {
+    fn bind(self, parameters: &[Option<String>]) -> Result<Vec<SerialValue>> {
+        bind_positional(parameters, self.iter().map(ToValue::to_value))
+    }
+}
+
+impl<T: ToValue, const N: usize> Params for [(&str, T); N] {
+    fn bind(self, parameters: &[Option<String>]) -> Result<Vec<SerialValue>> {
+        bind_named(
+            parameters,
+            self.iter().map(|(name, value)| (*name, value.to_value())),
+        )
+    }
+}
+
+impl<T: ToValue> Params for &[(&str, T)] {
+    fn bind(self, parameters: &[Option<String>]) -> Result<Vec<SerialValue>> {
+        bind_named(
+            parameters,
+            self.iter().map(|(name, value)| (*name, value.to_value())),
+        )
+    }
+}
+
+macro_rules! tuple_params {
+    ($($name:ident),*) => {
+        impl<$($name: ToValue),*> Params for ($($name,)*) {
+            #[allow(non_snake_case)]
+            fn bind(self, parameters: &[Option<String>]) -> Result<Vec<SerialValue>> {
+                let ($($name,)*) = self;
+                bind_positional(parameters, [$($name.to_value()),*].into_iter())
+            }
+        }
+    };
+}
+
+tuple_params!(A);
+tuple_params!(A, B);
+tuple_params!(A, B, C);
+tuple_params!(A, B, C, D);
+tuple_params!(A, B, C, D, E);
+tuple_params!(A, B, C, D, E, F);
+tuple_params!(A, B, C, D, E, F, G);
+tuple_params!(A, B, C, D, E, F, G, H);
+
+/// A type whose values can be bound to a parameter: integers as integers, floats as reals,
+/// strings as text, bytes as blobs and `None` as NULL.
+pub trait ToValue {
+    fn to_value(&self) -> SerialValue;
+}
+
+impl ToValue for SerialValue {
+    fn to_value(&self) -> SerialValue {
+        self.clone()
+    }
+}
+
+impl<T: ToValue + ?Sized> ToValue for &T {
+    fn to_value(&self) -> SerialValue {
+        (**self).to_value()
+    }
+}
+
+impl<T: ToValue> ToValue for Option<T> {
+    fn to_value(&self) -> SerialValue {
+        match self {
+            Some(value) => value.to_value(),
+            None => SerialValue::Null,
+        }
+    }
+}
+
+macro_rules! to_integer {
+    ($($ty:ty),*) => {
+        $(
+            impl ToValue for $ty {
+                fn to_value(&self) -> SerialValue {
+                    SerialValue::I64(*self as i64)
+                }
+            }
+        )*
+    };
+}
+
+to_integer!(i8, i16, i32, i64, u8, u16, u32, bool);
+
+impl ToValue for f64 {
+    fn to_value(&self) -> SerialValue {
+        SerialValue::Float64(*self)
+    }
+}
+
+impl ToValue for f32 {
+    fn to_value(&self) -> SerialValue {
+        SerialValue::Float64(*self as f64)
+    }
+}
+
+impl ToValue for str {
+    fn to_value(&self) -> SerialValue {
+        SerialValue::String(self.to_string())
+    }
+}
+
+impl ToValue for String {
+    fn to_value(&self) -> SerialValue {
+        SerialValue::String(self.clone())
+    }
+}
+
+impl ToValue for [u8] {
+    fn to_value(&self) -> SerialValue {
+        SerialValue::Blob(self.to_vec())
+    }
+}
+
+impl ToValue for Vec<u8> {
+    fn to_value(&self) -> SerialValue {
+        SerialValue::Blob(self.clone())
+    }
+}
+
+/// What a column of a [`Row`] can be found by: its index, from 0, or its name, which is
+/// matched ignoring ASCII case as in SQLite.
+pub trait ColumnIndex {
+    fn index(&self, column_names: &[String]) -> Result<usize>;
+}
+
+impl ColumnIndex for usize {
+    fn index(&self, column_names: &[String]) -> Result<usize> {
+        match *self < column_names.len() {
+            true => Ok(*self),
+            false => bail!(
+                Sql,
+                "column index {} out of range: the row has {} columns",
+                self,
+                column_names.len()
+            ),
+        }
+    }
+}
+
+impl ColumnIndex for &str {
+    fn index(&self, column_names: &[String]) -> Result<usize> {
+        match column_names
+            .iter()
+            .position(|name| name.eq_ignore_ascii_case(self))
+        {
+            Some(i) => Ok(i),
+            None => bail!(Sql, "no such column: {}", self),
+        }
+    }
+}
+
+/// A type a value of a [`Row`] can be read as.
+///
+/// Integers are read as any integer type they fit in, and as reals. Text is read as
+/// strings, and both text and blobs as bytes. NULL is only read as `None`.
+pub trait FromValue: Sized {
+    fn from_value(value: &SerialValue) -> Result<Self>;
+}
+
+impl FromValue for SerialValue {
+    fn from_value(value: &SerialValue) -> Result<Self> {
+        Ok(value.clone())
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: &SerialValue) -> Result<Self> {
+        match value {
+            SerialValue::Null => Ok(None),
+            value => T::from_value(value).map(Some),
+        }
+    }
+}
+
+impl FromValue for i64 {
+    fn from_value(value: &SerialValue) -> Result<Self> {
+        match value.as_integer() {
+            Some(num) => Ok(num),
+            None => bail!(Conversion, "{} is not an integer", type_name(value)),
+        }
+    }
+}
+
+macro_rules! from_integer {
+    ($($ty:ty),*) => {
+        $(
+            impl FromValue for $ty {
+                fn from_value(value: &SerialValue) -> Result<Self> {
+                    let num = i64::from_value(value)?;
+                    match <$ty>::try_from(num) {
+                        Ok(num) => Ok(num),
+                        Err(_) => bail!(Conversion, "{} is out of range", num),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+from_integer!(i8, i16, i32, u8, u16, u32, u64, usize);
+
+impl FromValue for bool {
+    fn from_value(value: &SerialValue) -> Result<Self> {
+        Ok(i64::from_value(value)? != 0)
+    }
+}
+
+impl FromValue for f64 {
+    fn from_value(value: &SerialValue) -> Result<Self> {
+        match value {
+            SerialValue::Float64(num) => Ok(*num),
+            value => match value.as_integer() {
+                Some(num) => Ok(num as f64),
+                None => bail!(Conversion, "{} is not a number", type_name(value)),
+            },
+        }
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: &SerialValue) -> Result<Self> {
+        match value {
+            SerialValue::String(txt) => Ok(txt.clone()),
+            value => bail!(Conversion, "{} is not text", type_name(value)),
+        }
+    }
+}
+
+impl FromValue for Vec<u8> {
+    fn from_value(value: &SerialValue) -> Result<Self> {
+        match value {
+            SerialValue::Blob(bytes) => Ok(bytes.clone()),
+            SerialValue::String(txt) => Ok(txt.clone().into_bytes()),
+            value => bail!(Conversion, "{} is not a blob", type_name(value)),
+        }
+    }
+}
+
+/// The name of the type of a value, as SQLite's typeof() returns it.
+fn type_name(value: &SerialValue) -> &'static str {
+    match value {
+        SerialValue::Null => "null",
+        SerialValue::Float64(_) => "real",
+        SerialValue::String(_) => "text",
+        SerialValue::Blob(_) => "blob",
+        _ => "integer",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_rows() {
+        let conn = Connection::open("sample.db").unwrap();
+        let stmt = conn
+            .prepare("SELECT id, name, color AS colour FROM apples WHERE id > 1 ORDER BY id")
+            .unwrap();
+        assert_eq!(stmt.column_names(), ["id", "name", "colour"]);
+
+        let rows = stmt.query(()).unwrap().collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0].get::<i64>(0).unwrap(), 2);
+        assert_eq!(rows[0].get::<String>("NAME").unwrap(), "Fuji");
+        assert_eq!(rows[0].get::<f64>("id").unwrap(), 2.0);
+        assert_eq!(rows[0].get::<Option<String>>(2).unwrap().unwrap(), "Red");
+
+        assert!(rows[0].get::<i64>("name").is_err());
+        assert!(rows[0].get::<String>("color").is_err());
+        assert!(rows[0].get::<String>(3).is_err());
+        assert!(stmt.query([SerialValue::I64(1)]).is_err());
+
+        // A statement runs again from the start.
+        assert_eq!(stmt.query(()).unwrap().count(), 3);
+
+        let stmt = conn.prepare("PRAGMA quick_check").unwrap();
+        assert_eq!(stmt.column_names(), ["quick_check"]);
+        let row = stmt.query(()).unwrap().next().unwrap().unwrap();
+        assert_eq!(row.get::<String>(0).unwrap(), "ok");
+    }
+
+    #[test]
+    fn test_query_parameters() {
+        let conn = Connection::open("sample.db").unwrap();
+        let stmt = conn
+            .prepare("SELECT name FROM apples WHERE id > ?1 AND color LIKE :color")
+            .unwrap();
+        assert_eq!(stmt.parameter_count(), 2);
+        assert_eq!(stmt.parameter_name(1), Some("?1"));
+        assert_eq!(stmt.parameter_index(":color"), Some(2));
+
+        fn names(stmt: &Statement, params: impl Params) -> Vec<String> {
+            stmt.query(params)
+                .unwrap()
+                .map(|row| row?.get::<String>(0))
+                .collect::<Result<_>>()
+                .unwrap()
+        }
+        assert_eq!(names(&stmt, (1, "%red")), ["Fuji", "Honeycrisp"]);
+        assert_eq!(names(&stmt, (2.5, "%red")), ["Honeycrisp"]);
+        assert_eq!(names(&stmt, (None::<i64>, "%red")), Vec::<String>::new());
+
+        assert_eq!(
+            names(&stmt, [("?1", "3"), (":color", "y%")]),
+            ["Golden Delicious"]
+        );
+
+        assert!(stmt.query((1,)).is_err());
+        assert!(stmt.query([(":colour", "red")]).is_err());
+
+        let stmt = conn.prepare("SELECT ?, ?, ?").unwrap();
+        let row = stmt
+            .query((1.5, b"ab".as_slice(), "x"))
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            row.values(),
+            [
+                SerialValue::Float64(1.5),
+                SerialValue::Blob(b"ab".to_vec()),
+                SerialValue::String("x".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        use crate::Error;
+
+        assert!(matches!(
+            Connection::open("no such file.db"),
+            Err(Error::Io(_))
+        ));
+        let conn = Connection::open("sample.db").unwrap();
+        assert!(matches!(
+            conn.prepare("SELECT FROM WHERE"),
+            Err(Error::Syntax(_))
+        ));
+        assert!(matches!(
+            conn.prepare("DELETE FROM apples"),
+            Err(Error::Unsupported(_))
+        ));
+        assert!(matches!(
+            conn.prepare("PRAGMA journal_mode"),
+            Err(Error::Unsupported(_))
+        ));
+        let err = conn.prepare("SELECT nope FROM apples").unwrap_err();
+        assert_eq!(err.to_string(), "no such column: nope");
+        let stmt = conn.prepare("SELECT name FROM apples").unwrap();
+        let row = stmt.query(()).unwrap().next().unwrap().unwrap();
+        assert!(matches!(row.get::<i64>(0), Err(Error::Conversion(_))));
+    }
+}
@@ -1,287 +1,431 @@
-use std::{
-    collections::HashSet,
-    fs::{self},
-    path::Path,
-};
+use std::{path::Path, sync::Arc};
 
 use crate::{
     cell::Cell,
-    column::SerialValue,
-    page::{Page, PageType},
+    error::{bail, Error, Result},
+    page::Page,
+    pager::{Pager, DEFAULT_CACHE_SIZE},
     record::Record,
-    sql::Sql,
+    schema::Schema,
 };
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct Database {
     /// The first 100 bytes of the database file comprise the database file header.
     pub header: DbHeader,
-    pub pages: Vec<Page>,
+    pager: Pager,
 }
 
 impl Database {
-    pub fn read_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
-        let file = fs::read(path)?;
-
-        // let (header, _rest) = file.split_at(100);
-        let header = DbHeader::new(&file[0..100])?;
-        assert_eq!(file.len() % header.page_size, 0);
-        assert_eq!(header.header_string, "SQLite format 3\0");
-
-        let mut pages = vec![]; // 64023
-        for (page_i, b_tree_page) in file.chunks(header.page_size).enumerate() {
-            let page = if page_i == 0 {
-                Page::new(page_i, Some(header.clone()), b_tree_page)
-            } else {
-                Page::new(page_i, None, b_tree_page)
-            };
-            pages.push(page);
-        }
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::open_with_cache_size(path, DEFAULT_CACHE_SIZE)
+    }
+
+    /// Opens the database keeping at most `cache_size` pages in memory.
+    pub fn open_with_cache_size(path: impl AsRef<Path>, cache_size: usize) -> Result<Self> {
+        let pager = Pager::open(path, cache_size)?;
+        let header = pager.header().clone();
 
-        Ok(Self { header, pages })
+        Ok(Self { header, pager })
     }
 
     pub fn page_size(&self) -> usize {
         self.header.page_size
     }
 
-    pub fn read_index(
-        &self,
-        num: usize,
-        _index_statement: &Sql,
-        select_statement: &Sql,
-        rowids: &mut HashSet<i64>,
-    ) {
-        let mut page_idxes: Vec<usize> = vec![num - 1];
-        let select_query: Vec<&str> = select_statement
-            .selection
-            .values()
-            .map(|val| val.as_str())
-            .collect();
-
-        while let Some(page_idx) = page_idxes.pop() {
-            if let Some(page) = self.pages.get(page_idx) {
-                let cells: Vec<Cell> = page
-                    .cell_offsets
-                    .iter()
-                    .map(|offset| {
-                        Cell::from_bytes(page.page_type(), *offset as usize, &page.buffer)
-                            .expect("construct cell")
-                    })
-                    .collect();
-                match page.page_type() {
-                    PageType::InteriorIndex => {
-                        for cell in cells.iter() {
-                            let page_num_left_child = cell.page_number_left_child.unwrap();
-                            let record = cell.record.clone().unwrap();
-
-                            if let SerialValue::String(country) = record.columns[0].data() {
-                                match country.as_str().cmp(select_query[0]) {
-                                    std::cmp::Ordering::Less => {
-                                        page_idxes.push(page_num_left_child as usize - 1);
-                                    }
-                                    std::cmp::Ordering::Greater => {
-                                        if let Some(num) = page.btree_header.right_most_pointer {
-                                            page_idxes.push(num as usize - 1);
-                                        }
-                                    }
-                                    std::cmp::Ordering::Equal => {
-                                        let num = match record.columns[1].data() {
-                                            SerialValue::I8(num) => *num as i64,
-                                            SerialValue::I16(num) => *num as i64,
-                                            SerialValue::I24(num) => *num as i64,
-                                            SerialValue::I32(num) => *num as i64,
-                                            _ => todo!(),
-                                        };
-
-                                        rowids.insert(num);
-                                    }
-                                }
-                            };
-                        }
-                    }
-
-                    PageType::LeafIndex => {
-                        for cell in cells.iter() {
-                            let record = cell.record.clone().unwrap();
-
-                            if let SerialValue::String(country) = record.columns[0].data() {
-                                if select_query[0] == country {
-                                    let num = match record.columns[1].data() {
-                                        SerialValue::I8(num) => *num as i64,
-                                        SerialValue::I16(num) => *num as i64,
-                                        SerialValue::I24(num) => *num as i64,
-                                        SerialValue::I32(num) => *num as i64,
-                                        _ => todo!(),
-                                    };
-
-                                    rowids.insert(num);
-                                }
-                            };
-                        }
-                    }
-                    _ => {}
-                }
-            }
-        }
+    /// Returns page `page_num`, counting from 1, reading it from disk if it is not cached.
+    pub fn page(&self, page_num: usize) -> Result<Arc<Page>> {
+        self.pager.get(page_num)
     }
 
-    pub fn read_table(
-        &self,
-        num: usize,
-        select_statement: &Sql,
-        fields: Vec<(usize, String)>,
-        row_set: &mut HashSet<String>,
-        rowid_set: &mut HashSet<i64>,
-    ) {
-        let mut page_idxes: Vec<usize> = vec![num - 1];
-        while let Some(page_idx) = page_idxes.pop() {
-            if let Some(page) = self.pages.get(page_idx) {
-                let cells: Vec<Cell> = page
-                    .cell_offsets
-                    .iter()
-                    .map(|offset| {
-                        Cell::from_bytes(page.page_type(), *offset as usize, &page.buffer)
-                            .expect("construct cell")
-                    })
-                    .collect();
-                let cell_len = page.cell_offsets.len();
-
-                if !select_statement.selection.is_empty() {
-                    for i in 0..cell_len {
-                        if let Some(page_num_left_child) = cells[i].page_number_left_child {
-                            page_idxes.push(page_num_left_child as usize - 1);
-                        }
-
-                        if let Some(record) = &cells[i].record {
-                            select_statement.print_rows(
-                                record,
-                                &cells[i].rowid,
-                                &fields,
-                                row_set,
-                                rowid_set,
-                            );
-                        }
-                    }
-
-                    if let Some(num) = page.btree_header.right_most_pointer {
-                        page_idxes.push(num as usize - 1);
-                    }
-                } else {
-                    for i in 0..cell_len {
-                        if let Ok((_, Some(record))) = page.read_cell(i as u16) {
-                            let mut values = Vec::new();
-
-                            for (field_idx, _field_name) in &fields {
-                                values.push(record.columns[*field_idx].data().display());
-                            }
-                            println!("{}", values.join("|"));
-                        }
-                    }
-                }
-            }
-        }
+    /// The number of pages in the database.
+    pub fn page_count(&self) -> usize {
+        self.pager.npages()
+    }
+
+    /// The bytes of page `page_num` as stored, bypassing the cache and the checks of [`Page`].
+    pub(crate) fn raw_page(&self, page_num: usize) -> Result<Vec<u8>> {
+        self.pager.read_raw(page_num)
     }
 
-    pub fn read_ids_from_table(
-        &self,
-        num: usize,
-        select_statement: &Sql,
-        fields: Vec<(usize, String)>,
-        row_set: &mut HashSet<String>,
-        rowid_set: &mut HashSet<i64>,
-        ids: &[i64],
-    ) {
-        let mut page_idxes: Vec<usize> = vec![num - 1];
-        while let Some(page_idx) = page_idxes.pop() {
-            if let Some(page) = self.pages.get(page_idx) {
-                let cells: Vec<Cell> = page
-                    .cell_offsets
-                    .iter()
-                    .map(|offset| {
-                        Cell::from_bytes(page.page_type(), *offset as usize, &page.buffer)
-                            .expect("construct cell")
-                    })
-                    .collect();
-                let cell_len = page.cell_offsets.len();
-
-                if !select_statement.selection.is_empty() {
-                    match page.page_type() {
-                        PageType::InteriorTable => {
-                            let mut ids = ids;
-                            for i in 0..cell_len {
-                                let page_num_left_child = cells[i].page_number_left_child.unwrap();
-                                let key = cells[i].rowid.unwrap();
-
-                                let split_at = ids.split_at(ids.partition_point(|id| *id < key));
-                                let left_ids = split_at.0; // Ids to the left
-                                ids = split_at.1; // Ids to the right
-
-                                if !left_ids.is_empty() {
-                                    page_idxes.push(page_num_left_child as usize - 1);
-                                }
-                            }
-
-                            if ids.is_empty() {
-                                continue;
-                            }
-
-                            if let Some(num) = page.btree_header.right_most_pointer {
-                                page_idxes.push(num as usize - 1);
-                            }
-                        }
-                        PageType::LeafTable => {
-                            let records: Vec<(i64, Record)> = cells
-                                .iter()
-                                .filter(|cell| {
-                                    let rowid = cell.rowid.unwrap();
-                                    ids.binary_search(&rowid).is_ok()
-                                })
-                                .map(|cell| (cell.rowid.unwrap(), cell.record.clone().unwrap()))
-                                .collect();
-
-                            for (rowid, record) in records {
-                                select_statement.print_rows_by_rowid(
-                                    &record,
-                                    &Some(rowid),
-                                    &fields,
-                                    row_set,
-                                    rowid_set,
-                                );
-                            }
-                        }
-                        _ => {}
-                    }
-                } else {
-                    for i in 0..cell_len {
-                        if let Ok((_, Some(record))) = page.read_cell(i as u16) {
-                            let mut values = Vec::new();
-
-                            for (field_idx, _field_name) in &fields {
-                                values.push(record.columns[*field_idx].data().display());
-                            }
-                            println!("{}", values.join("|"));
-                        }
-                    }
-                }
-            }
+    /// Reads the `i`-th cell of `page`, reassembling its payload from overflow pages if needed.
+    pub fn read_cell(&self, page: &Page, i: u16) -> Result<Cell> {
+        if i >= page.btree_header.ncells {
+            bail!(
+                Corrupt(page.number, 0),
+                "cell {} out of range (page has {} cells)",
+                i,
+                page.btree_header.ncells
+            );
+        }
+
+        let offset = page.cell_offsets[i as usize] as usize;
+        let mut cell = Cell::from_bytes(
+            page.page_type(),
+            offset,
+            &page.buffer,
+            self.header.usable_size(),
+        )
+        .map_err(|err| err.at(page.number, 0))?;
+
+        if let (Some(first_overflow), Some(npayload)) =
+            (cell.page_number_first_overflow, cell.npayload)
+        {
+            let remaining = npayload as usize - cell.payload.len();
+            let overflow = self
+                .pager
+                .read_overflow(first_overflow, remaining)
+                .map_err(|err| err.at(page.number, offset))?;
+            cell.payload.extend_from_slice(&overflow);
+            cell.record =
+                Some(Record::new(&cell.payload).map_err(|err| err.at(page.number, offset))?);
         }
+
+        Ok(cell)
+    }
+
+    /// Reads the tables and indexes of the database.
+    pub fn schema(&self) -> Result<Schema> {
+        Schema::read(self)
     }
 }
 
+/// The string every valid database file begins with.
+pub const HEADER_STRING: &str = "SQLite format 3\0";
+
+/// The 100-byte database header at the start of page 1.
+///
+/// See <https://www.sqlite.org/fileformat.html#the_database_header>.
 #[derive(Debug, Clone)]
 pub struct DbHeader {
-    header_string: String,
-    page_size: usize,
+    /// The header string: "SQLite format 3\000"
+    pub header_string: String,
+
+    /// The database page size in bytes. Stored as 1 in the file when the page size is 65536.
+    pub page_size: usize,
+
+    /// File format write version. 1 for legacy; 2 for WAL.
+    pub write_version: u8,
+
+    /// File format read version. 1 for legacy; 2 for WAL.
+    pub read_version: u8,
+
+    /// Bytes of unused "reserved" space at the end of each page.
+    pub reserved_space: u8,
+
+    /// Maximum embedded payload fraction. Must be 64.
+    pub max_payload_fraction: u8,
+
+    /// Minimum embedded payload fraction. Must be 32.
+    pub min_payload_fraction: u8,
+
+    /// Leaf payload fraction. Must be 32.
+    pub leaf_payload_fraction: u8,
+
+    /// File change counter.
+    pub file_change_counter: u32,
+
+    /// Size of the database file in pages. The "in-header database size".
+    pub database_size: u32,
+
+    /// Page number of the first freelist trunk page.
+    pub first_freelist_trunk_page: u32,
+
+    /// Total number of freelist pages.
+    pub freelist_count: u32,
+
+    /// The schema cookie.
+    pub schema_cookie: u32,
+
+    /// The schema format number. Supported schema formats are 1, 2, 3, and 4.
+    pub schema_format: u32,
+
+    /// Default page cache size.
+    pub default_cache_size: u32,
+
+    /// The page number of the largest root b-tree page when in auto-vacuum or
+    /// incremental-vacuum modes, or zero otherwise.
+    pub largest_root_page: u32,
+
+    /// The database text encoding.
+    pub text_encoding: TextEncoding,
+
+    /// The "user version" as read and set by the user_version pragma.
+    pub user_version: u32,
+
+    /// True (non-zero) for incremental-vacuum mode. False (zero) otherwise.
+    pub incremental_vacuum: u32,
+
+    /// The "Application ID" set by PRAGMA application_id.
+    pub application_id: u32,
+
+    /// The version-valid-for number.
+    pub version_valid_for: u32,
+
+    /// SQLITE_VERSION_NUMBER of the library that last wrote the file.
+    pub sqlite_version_number: u32,
 }
 
 impl DbHeader {
-    pub fn new(header: &[u8]) -> anyhow::Result<Self> {
-        let header_string = String::from_utf8(header[0..16].to_vec())?;
+    pub fn new(header: &[u8]) -> Result<Self> {
+        if header.len() < 100 {
+            bail!(
+                Corrupt(1, header.len()),
+                "database header is {} bytes, expected 100",
+                header.len()
+            );
+        }
+
+        if &header[0..16] != HEADER_STRING.as_bytes() {
+            bail!(Corrupt(1, 0), "file is not a database");
+        }
+        let header_string = HEADER_STRING.to_string();
+
+        let page_size = match u16::from_be_bytes([header[16], header[17]]) {
+            1 => 65536,
+            n if (512..=32768).contains(&n) && n.is_power_of_two() => n as usize,
+            n => bail!(Corrupt(1, 16), "invalid page size: {}", n),
+        };
+
+        let read_u32 = |offset: usize| {
+            u32::from_be_bytes([
+                header[offset],
+                header[offset + 1],
+                header[offset + 2],
+                header[offset + 3],
+            ])
+        };
 
-        Ok(Self {
+        let db_header = Self {
             header_string,
-            page_size: u16::from_be_bytes([header[16], header[17]]) as usize,
-        })
+            page_size,
+            write_version: header[18],
+            read_version: header[19],
+            reserved_space: header[20],
+            max_payload_fraction: header[21],
+            min_payload_fraction: header[22],
+            leaf_payload_fraction: header[23],
+            file_change_counter: read_u32(24),
+            database_size: read_u32(28),
+            first_freelist_trunk_page: read_u32(32),
+            freelist_count: read_u32(36),
+            schema_cookie: read_u32(40),
+            schema_format: read_u32(44),
+            default_cache_size: read_u32(48),
+            largest_root_page: read_u32(52),
+            text_encoding: TextEncoding::try_from(read_u32(56)).map_err(|err| err.at(1, 56))?,
+            user_version: read_u32(60),
+            incremental_vacuum: read_u32(64),
+            application_id: read_u32(68),
+            version_valid_for: read_u32(92),
+            sqlite_version_number: read_u32(96),
+        };
+        db_header.validate()?;
+
+        Ok(db_header)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if !matches!(self.write_version, 1 | 2) {
+            bail!(
+                Unsupported,
+                "file format write version {}",
+                self.write_version
+            );
+        }
+        if !matches!(self.read_version, 1 | 2) {
+            bail!(
+                Unsupported,
+                "file format read version {}",
+                self.read_version
+            );
+        }
+        if self.usable_size() < 480 {
+            bail!(
+                Corrupt(1, 20),
+                "usable page size {} is too small (reserved space {})",
+                self.usable_size(),
+                self.reserved_space
+            );
+        }
+        if (
+            self.max_payload_fraction,
+            self.min_payload_fraction,
+            self.leaf_payload_fraction,
+        ) != (64, 32, 32)
+        {
+            bail!(
+                Corrupt(1, 21),
+                "invalid payload fractions: {}/{}/{}",
+                self.max_payload_fraction,
+                self.min_payload_fraction,
+                self.leaf_payload_fraction
+            );
+        }
+        if self.schema_format > 4 {
+            bail!(Unsupported, "schema format {}", self.schema_format);
+        }
+        // Text is only decoded as UTF-8.
+        if !matches!(self.text_encoding, TextEncoding::Unset | TextEncoding::Utf8) {
+            bail!(Unsupported, "text encoding {}", self.text_encoding);
+        }
+
+        Ok(())
+    }
+
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    /// The page size less the reserved space at the end of every page.
+    pub fn usable_size(&self) -> usize {
+        self.page_size - self.reserved_space as usize
+    }
+
+    /// The in-header database size, if it can be trusted. It is only valid when non-zero
+    /// and the file change counter matches the version-valid-for number.
+    pub fn valid_database_size(&self) -> Option<usize> {
+        (self.database_size != 0 && self.file_change_counter == self.version_valid_for)
+            .then_some(self.database_size as usize)
+    }
+}
+
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextEncoding {
+    /// A value of 0 means that no encoding was chosen yet, as in databases that have never
+    /// had a table created. Their text is read as UTF-8.
+    Unset = 0,
+
+    /// A value of 1 means UTF-8.
+    Utf8 = 1,
+
+    /// A value of 2 means UTF-16le.
+    Utf16le = 2,
+
+    /// A value of 3 means UTF-16be.
+    Utf16be = 3,
+}
+
+impl TryFrom<u32> for TextEncoding {
+    type Error = Error;
+
+    fn try_from(value: u32) -> Result<Self> {
+        match value {
+            0 => Ok(Self::Unset),
+            1 => Ok(Self::Utf8),
+            2 => Ok(Self::Utf16le),
+            3 => Ok(Self::Utf16be),
+            n => bail!(Corrupt(0, 0), "invalid text encoding: {}", n),
+        }
+    }
+}
+
+impl std::fmt::Display for TextEncoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unset => write!(f, "0"),
+            Self::Utf8 => write!(f, "1 (utf8)"),
+            Self::Utf16le => write!(f, "2 (utf16le)"),
+            Self::Utf16be => write!(f, "3 (utf16be)"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::column::SerialValue;
+
+    fn sample_header() -> Vec<u8> {
+        std::fs::read("sample.db").unwrap()[0..100].to_vec()
+    }
+
+    #[test]
+    fn test_db_header_fields() {
+        let header = DbHeader::new(&sample_header()).unwrap();
+
+        assert_eq!(header.page_size, 4096);
+        assert_eq!(header.write_version, 1);
+        assert_eq!(header.read_version, 1);
+        assert_eq!(header.file_change_counter, 5);
+        assert_eq!(header.valid_database_size(), Some(4));
+        assert_eq!(header.schema_cookie, 2);
+        assert_eq!(header.schema_format, 4);
+        assert_eq!(header.text_encoding, TextEncoding::Utf8);
+        assert_eq!(header.sqlite_version_number, 3034000);
+    }
+
+    #[test]
+    fn test_db_header_page_size_one_means_65536() {
+        let mut raw = sample_header();
+        raw[16..18].copy_from_slice(&1u16.to_be_bytes());
+
+        assert_eq!(DbHeader::new(&raw).unwrap().page_size, 65536);
+    }
+
+    #[test]
+    fn test_db_header_keeps_unset_text_encoding() {
+        let mut raw = sample_header();
+        raw[56..60].copy_from_slice(&0u32.to_be_bytes());
+
+        let header = DbHeader::new(&raw).unwrap();
+        assert_eq!(header.text_encoding, TextEncoding::Unset);
+        assert_eq!(header.text_encoding.to_string(), "0");
+    }
+
+    #[test]
+    fn test_db_header_rejects_invalid() {
+        let mut raw = sample_header();
+        raw[16..18].copy_from_slice(&1000u16.to_be_bytes());
+        assert!(DbHeader::new(&raw).is_err());
+
+        let mut raw = sample_header();
+        raw[0] = b'X';
+        assert!(DbHeader::new(&raw).is_err());
+
+        let mut raw = sample_header();
+        raw[56..60].copy_from_slice(&2u32.to_be_bytes());
+        assert!(matches!(DbHeader::new(&raw), Err(Error::Unsupported(_))));
+    }
+
+    #[test]
+    fn test_read_overflow_chain() {
+        // overflow.db has 512-byte pages, so this row and its index key spill onto
+        // overflow pages.
+        let body = SerialValue::String("abcdefghij".repeat(200));
+        let db = Database::open("overflow.db").unwrap();
+        let schema = db.schema().unwrap();
+        let roots = [
+            schema.table("notes").unwrap().root_page,
+            schema.indexes("notes").next().unwrap().root_page,
+        ];
+        for root in roots {
+            let cell = db.read_cell(&db.page(root).unwrap(), 0).unwrap();
+            assert!(cell.page_number_first_overflow.is_some());
+            let record = cell.record.unwrap();
+            assert!(record.columns.iter().any(|column| column.data() == &body));
+        }
+
+        let first_overflow = {
+            let cell = db.read_cell(&db.page(roots[0]).unwrap(), 0).unwrap();
+            cell.page_number_first_overflow.unwrap() as usize
+        };
+        let read_with_next_page = |next_page: u32| {
+            let mut bytes = std::fs::read("overflow.db").unwrap();
+            let start = (first_overflow - 1) * 512;
+            bytes[start..start + 4].copy_from_slice(&next_page.to_be_bytes());
+            let path = std::env::temp_dir().join(format!("overflow-{}.db", std::process::id()));
+            std::fs::write(&path, bytes).unwrap();
+            let db = Database::open(&path).unwrap();
+            let result = db.read_cell(&db.page(roots[0]).unwrap(), 0);
+            std::fs::remove_file(&path).unwrap();
+            result
+        };
+        // A chain cut short, and one that leads back to its first page.
+        for next_page in [0, first_overflow as u32] {
+            let err = read_with_next_page(next_page).unwrap_err();
+            assert!(matches!(err, Error::Corrupt { .. }), "{err}");
+        }
     }
 }
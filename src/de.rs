@@ -0,0 +1,452 @@
+use std::fmt;
+
+use serde::de::{
+    self, value::StrDeserializer, DeserializeSeed, Deserializer, IntoDeserializer, MapAccess,
+    SeqAccess, Unexpected, Visitor,
+};
+
+use crate::{
+    column::SerialValue,
+    connection::Row,
+    error::{err, Error},
+};
+
+/// Deserializes `T` from a row: a struct or map from the values by column name, a tuple
+/// or sequence from the values in order, and anything else from the only value of a row
+/// with a single column.
+///
+/// Integers deserialize into any integer type they fit in and into floats, reals into
+/// floats, text into strings, chars and unit enum variants, text and blobs into bytes,
+/// and NULL into `None` or `()`. Any other pairing is an error naming the column.
+pub fn from_row<'r, T: de::Deserialize<'r>>(row: &'r Row) -> Result<T, Error> {
+    T::deserialize(RowDeserializer {
+        values: row.values(),
+        column_names: row.column_names(),
+    })
+}
+
+/// Errors raised while deserializing are [`Error::Conversion`]s.
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error::Conversion(msg.to_string())
+    }
+}
+
+/// The error with the column it was raised for.
+fn in_column(err: Error, i: usize, name: &str) -> Error {
+    err!(Conversion, "column {} ({}): {}", i, name, err)
+}
+
+struct RowDeserializer<'r> {
+    values: &'r [SerialValue],
+    column_names: &'r [String],
+}
+
+impl<'r> RowDeserializer<'r> {
+    /// The deserializer of the only value of the row.
+    fn single(&self) -> Result<ValueDeserializer<'r>, Error> {
+        match self.values {
+            [value] => Ok(ValueDeserializer { value }),
+            values => Err(de::Error::custom(format!(
+                "a row of {} columns cannot be deserialized into a single value",
+                values.len()
+            ))),
+        }
+    }
+}
+
+/// Forwards the deserialization of a single value to the only value of the row.
+macro_rules! forward_to_single {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'r>>(self, visitor: V) -> Result<V::Value, Error> {
+                let (i, name) = (0, self.column_names.first().map_or("", String::as_str));
+                self.single()?
+                    .$method(visitor)
+                    .map_err(|err| in_column(err, i, name))
+            }
+        )*
+    };
+}
+
+impl<'r> Deserializer<'r> for RowDeserializer<'r> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'r>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_map(visitor)
+    }
+
+    forward_to_single! {
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 deserialize_f32
+        deserialize_f64 deserialize_char deserialize_str deserialize_string deserialize_bytes
+        deserialize_byte_buf deserialize_option deserialize_unit deserialize_identifier
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'r>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'r>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'r>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_seq(Columns {
+            values: self.values,
+            column_names: self.column_names,
+            next: 0,
+        })
+    }
+
+    fn deserialize_tuple<V: Visitor<'r>>(self, len: usize, visitor: V) -> Result<V::Value, Error> {
+        if len != self.values.len() {
+            return Err(de::Error::invalid_length(
+                self.values.len(),
+                &format!("a row of {} columns", len).as_str(),
+            ));
+        }
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'r>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'r>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_map(Columns {
+            values: self.values,
+            column_names: self.column_names,
+            next: 0,
+        })
+    }
+
+    fn deserialize_struct<V: Visitor<'r>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'r>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        let column_name = self.column_names.first().map_or("", String::as_str);
+        self.single()?
+            .deserialize_enum(name, variants, visitor)
+            .map_err(|err| in_column(err, 0, column_name))
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'r>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+}
+
+/// The columns of a row, as a sequence of values or a map from names to values.
+struct Columns<'r> {
+    values: &'r [SerialValue],
+    column_names: &'r [String],
+    /// The index of the next column.
+    next: usize,
+}
+
+impl<'r> Columns<'r> {
+    /// Deserializes the value of the column before `next` with `seed`.
+    fn value<T: DeserializeSeed<'r>>(&self, seed: T) -> Result<T::Value, Error> {
+        let i = self.next - 1;
+        seed.deserialize(ValueDeserializer {
+            value: &self.values[i],
+        })
+        .map_err(|err| in_column(err, i, &self.column_names[i]))
+    }
+}
+
+impl<'r> SeqAccess<'r> for Columns<'r> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'r>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Error> {
+        if self.next == self.values.len() {
+            return Ok(None);
+        }
+        self.next += 1;
+        self.value(seed).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.values.len() - self.next)
+    }
+}
+
+impl<'r> MapAccess<'r> for Columns<'r> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'r>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        let Some(name) = self.column_names.get(self.next) else {
+            return Ok(None);
+        };
+        self.next += 1;
+        let name: StrDeserializer<'_, Error> = name.as_str().into_deserializer();
+        seed.deserialize(name).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'r>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        self.value(seed)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.values.len() - self.next)
+    }
+}
+
+struct ValueDeserializer<'r> {
+    value: &'r SerialValue,
+}
+
+impl<'r> ValueDeserializer<'r> {
+    /// The value as serde describes it in errors.
+    fn unexpected(&self) -> Unexpected<'r> {
+        match self.value {
+            SerialValue::Null => Unexpected::Other("NULL"),
+            SerialValue::Float64(num) => Unexpected::Float(*num),
+            SerialValue::String(txt) => Unexpected::Str(txt),
+            SerialValue::Blob(bytes) => Unexpected::Bytes(bytes),
+            value => Unexpected::Signed(value.as_integer().unwrap_or_default()),
+        }
+    }
+
+    fn integer(&self, expected: &dyn de::Expected) -> Result<i64, Error> {
+        self.value
+            .as_integer()
+            .ok_or_else(|| de::Error::invalid_type(self.unexpected(), expected))
+    }
+}
+
+/// Deserializes an integer into the integer types, whose visitors check that it fits.
+macro_rules! deserialize_integer {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'r>>(self, visitor: V) -> Result<V::Value, Error> {
+                let num = self.integer(&visitor)?;
+                visitor.visit_i64(num)
+            }
+        )*
+    };
+}
+
+impl<'r> Deserializer<'r> for ValueDeserializer<'r> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'r>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.value {
+            SerialValue::Null => visitor.visit_unit(),
+            SerialValue::Float64(num) => visitor.visit_f64(*num),
+            SerialValue::String(txt) => visitor.visit_borrowed_str(txt),
+            SerialValue::Blob(bytes) => visitor.visit_borrowed_bytes(bytes),
+            value => visitor.visit_i64(value.as_integer().unwrap_or_default()),
+        }
+    }
+
+    deserialize_integer! {
+        deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64
+    }
+
+    fn deserialize_bool<V: Visitor<'r>>(self, visitor: V) -> Result<V::Value, Error> {
+        let num = self.integer(&visitor)?;
+        visitor.visit_bool(num != 0)
+    }
+
+    fn deserialize_f32<V: Visitor<'r>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_f64(visitor)
+    }
+
+    fn deserialize_f64<V: Visitor<'r>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.value {
+            SerialValue::Float64(num) => visitor.visit_f64(*num),
+            _ => {
+                let num = self.integer(&visitor)?;
+                visitor.visit_f64(num as f64)
+            }
+        }
+    }
+
+    fn deserialize_char<V: Visitor<'r>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_str<V: Visitor<'r>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.value {
+            SerialValue::String(txt) => visitor.visit_borrowed_str(txt),
+            _ => Err(de::Error::invalid_type(self.unexpected(), &visitor)),
+        }
+    }
+
+    fn deserialize_string<V: Visitor<'r>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bytes<V: Visitor<'r>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.value {
+            SerialValue::Blob(bytes) => visitor.visit_borrowed_bytes(bytes),
+            SerialValue::String(txt) => visitor.visit_borrowed_bytes(txt.as_bytes()),
+            _ => Err(de::Error::invalid_type(self.unexpected(), &visitor)),
+        }
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'r>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'r>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.value {
+            SerialValue::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'r>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.value {
+            SerialValue::Null => visitor.visit_unit(),
+            _ => Err(de::Error::invalid_type(self.unexpected(), &visitor)),
+        }
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'r>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'r>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'r>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self.value {
+            SerialValue::String(txt) => {
+                let variant: StrDeserializer<'_, Error> = txt.as_str().into_deserializer();
+                variant.deserialize_enum(name, variants, visitor)
+            }
+            _ => Err(de::Error::invalid_type(self.unexpected(), &visitor)),
+        }
+    }
+
+    fn deserialize_identifier<V: Visitor<'r>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'r>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    serde::forward_to_deserialize_any! {
+        <V: Visitor<'r>>
+        seq tuple tuple_struct map struct
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use crate::connection::Connection;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Apple {
+        id: u32,
+        name: String,
+        color: Option<String>,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    enum Color {
+        Red,
+        Yellow,
+    }
+
+    #[test]
+    fn test_query_as() {
+        let conn = Connection::open("sample.db").unwrap();
+        let stmt = conn
+            .prepare("SELECT name, id FROM apples WHERE id BETWEEN ? AND ?")
+            .unwrap();
+        let apples = stmt
+            .query_as::<Apple>((2, 3))
+            .unwrap()
+            .collect::<crate::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(
+            apples,
+            [
+                Apple {
+                    id: 2,
+                    name: "Fuji".to_string(),
+                    color: None
+                },
+                Apple {
+                    id: 3,
+                    name: "Honeycrisp".to_string(),
+                    color: None
+                }
+            ]
+        );
+
+        let stmt = conn
+            .prepare("SELECT id, color FROM apples WHERE id IN (2, 4)")
+            .unwrap();
+        let rows = stmt
+            .query_as::<(i64, Color)>(())
+            .unwrap()
+            .collect::<crate::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(rows, [(2, Color::Red), (4, Color::Yellow)]);
+
+        let stmt = conn.prepare("SELECT name AS id FROM apples").unwrap();
+        let err = stmt.query_as::<(u8,)>(()).unwrap().next().unwrap();
+        assert_eq!(
+            err.unwrap_err().to_string(),
+            "column 0 (id): invalid type: string \"Granny Smith\", expected u8"
+        );
+        let err = stmt.query_as::<Apple>(()).unwrap().next().unwrap();
+        assert!(err.is_err());
+
+        let stmt = conn.prepare("SELECT count(*) FROM apples").unwrap();
+        let count = stmt.query_as::<u8>(()).unwrap().next().unwrap().unwrap();
+        assert_eq!(count, 4);
+    }
+}
@@ -0,0 +1,84 @@
+use std::io;
+
+use thiserror::Error;
+
+/// An error opening a database, reading it, or preparing or running a statement.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// Reading the database file or a temporary file failed.
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+
+    /// The database file is malformed at byte `offset` of page `page`, counting pages from 1.
+    /// Page 0 stands for the file as a whole.
+    #[error("database disk image is malformed: {message} (page {page}, offset {offset})")]
+    Corrupt {
+        page: usize,
+        offset: usize,
+        message: String,
+    },
+
+    /// The database or the statement uses a feature that is not implemented.
+    #[error("unsupported: {0}")]
+    Unsupported(String),
+
+    /// The statement is not valid SQL.
+    #[error("syntax error: {0}")]
+    Syntax(String),
+
+    /// The statement is valid SQL that cannot be prepared or run, such as one that refers
+    /// to a missing table or adds text to an integer that overflows.
+    #[error("{0}")]
+    Sql(String),
+
+    /// A value cannot be converted to the type it is read as, or a row to the type it is
+    /// deserialized into.
+    #[error("{0}")]
+    Conversion(String),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    /// Places an error found at `offset` of a byte range that starts at `start` of page
+    /// `page`. Corruption found outside any page is moved onto it; other errors are kept.
+    pub(crate) fn at(self, page: usize, start: usize) -> Self {
+        match self {
+            Error::Corrupt {
+                page: 0,
+                offset,
+                message,
+            } => Error::Corrupt {
+                page,
+                offset: start + offset,
+                message,
+            },
+            err => err,
+        }
+    }
+}
+
+/// Builds an [`Error`] of the given kind with a formatted message:
+/// `err!(Sql, "no such table: {}", name)` or `err!(Corrupt(page, offset), "...")`.
+macro_rules! err {
+    (Corrupt($page:expr, $offset:expr), $($arg:tt)+) => {
+        $crate::error::Error::Corrupt {
+            page: $page,
+            offset: $offset,
+            message: format!($($arg)+),
+        }
+    };
+    ($kind:ident, $($arg:tt)+) => {
+        $crate::error::Error::$kind(format!($($arg)+))
+    };
+}
+
+/// Returns early with an [`Error`] built by [`err!`].
+macro_rules! bail {
+    ($($arg:tt)+) => {
+        return Err($crate::error::err!($($arg)+))
+    };
+}
+
+pub(crate) use bail;
+pub(crate) use err;
@@ -0,0 +1,1360 @@
+use std::cmp::Ordering;
+
+use sqlparser::ast::{self, BinaryOperator, UnaryOperator, Value};
+
+use crate::{
+    aggregate::AggregateFunc,
+    column::{Affinity, Collation, SerialValue},
+    error::{bail, err, Error, Result},
+};
+
+/// A scalar SQL expression.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    /// A column referenced by name that has not been bound to a row position yet.
+    Identifier {
+        table: Option<String>,
+        name: String,
+    },
+
+    /// The value at this position of the row being evaluated.
+    Column(usize),
+
+    Literal(SerialValue),
+
+    /// The value bound to the parameter with this index, from 1.
+    Parameter(usize),
+
+    Unary {
+        op: UnaryOp,
+        expr: Box<Expr>,
+    },
+
+    Binary {
+        left: Box<Expr>,
+        op: BinaryOp,
+        right: Box<Expr>,
+    },
+
+    /// `expr IS NULL`, or `expr IS NOT NULL` when negated.
+    IsNull {
+        expr: Box<Expr>,
+        negated: bool,
+    },
+
+    /// `expr [NOT] IN (list)`
+    InList {
+        expr: Box<Expr>,
+        list: Vec<Expr>,
+        negated: bool,
+    },
+
+    /// `expr [NOT] BETWEEN low AND high`
+    Between {
+        expr: Box<Expr>,
+        low: Box<Expr>,
+        high: Box<Expr>,
+        negated: bool,
+    },
+
+    /// `expr [NOT] LIKE pattern [ESCAPE escape]`
+    Like {
+        expr: Box<Expr>,
+        pattern: Box<Expr>,
+        escape: Option<char>,
+        negated: bool,
+    },
+
+    /// `expr [NOT] GLOB pattern`
+    Glob {
+        expr: Box<Expr>,
+        pattern: Box<Expr>,
+        negated: bool,
+    },
+
+    /// Converts the value of `expr` with `affinity`, as SQLite does to the operands of a
+    /// comparison with a column.
+    ApplyAffinity {
+        expr: Box<Expr>,
+        affinity: Affinity,
+    },
+
+    /// `expr COLLATE collation`: the value of `expr`, compared with `collation`. Unless
+    /// `explicit`, the collation is the one a column was declared with.
+    Collate {
+        expr: Box<Expr>,
+        collation: Collation,
+        explicit: bool,
+    },
+
+    /// A call to an aggregate function. `args` is empty for `count(*)`.
+    Aggregate {
+        func: AggregateFunc,
+        args: Vec<Expr>,
+        distinct: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Not,
+    Minus,
+    Plus,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    And,
+    Or,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    Concat,
+}
+
+impl TryFrom<&ast::Expr> for Expr {
+    type Error = Error;
+
+    fn try_from(expr: &ast::Expr) -> Result<Self, Self::Error> {
+        let boxed = |expr: &ast::Expr| Expr::try_from(expr).map(Box::new);
+
+        Ok(match expr {
+            ast::Expr::Identifier(ident) => Expr::Identifier {
+                table: None,
+                name: ident.value.clone(),
+            },
+            ast::Expr::CompoundIdentifier(idents) => match idents.as_slice() {
+                [.., table, column] => Expr::Identifier {
+                    table: Some(table.value.clone()),
+                    name: column.value.clone(),
+                },
+                _ => bail!(Unsupported, "identifier: {}", expr),
+            },
+            // Sql::from_str numbers each parameter as ?NNN.
+            ast::Expr::Value(Value::Placeholder(name)) => {
+                match name.strip_prefix('?').map(str::parse) {
+                    Some(Ok(index)) => Expr::Parameter(index),
+                    _ => bail!(Unsupported, "parameter: {}", name),
+                }
+            }
+            ast::Expr::Value(value) => Expr::Literal(literal(value)?),
+            ast::Expr::Nested(expr) => Expr::try_from(expr.as_ref())?,
+            ast::Expr::Collate { expr, collation } => Expr::Collate {
+                expr: boxed(expr)?,
+                collation: match collation.0.last() {
+                    Some(name) => Collation::from_name(&name.value)?,
+                    None => bail!(Unsupported, "collation: {}", collation),
+                },
+                explicit: true,
+            },
+            ast::Expr::UnaryOp { op, expr } => Expr::Unary {
+                op: match op {
+                    UnaryOperator::Not => UnaryOp::Not,
+                    UnaryOperator::Minus => UnaryOp::Minus,
+                    UnaryOperator::Plus => UnaryOp::Plus,
+                    op => bail!(Unsupported, "operator: {}", op),
+                },
+                expr: boxed(expr)?,
+            },
+            ast::Expr::BinaryOp { left, op, right } => {
+                let op = match op {
+                    BinaryOperator::Eq => BinaryOp::Eq,
+                    BinaryOperator::NotEq => BinaryOp::NotEq,
+                    BinaryOperator::Lt => BinaryOp::Lt,
+                    BinaryOperator::LtEq => BinaryOp::LtEq,
+                    BinaryOperator::Gt => BinaryOp::Gt,
+                    BinaryOperator::GtEq => BinaryOp::GtEq,
+                    BinaryOperator::And => BinaryOp::And,
+                    BinaryOperator::Or => BinaryOp::Or,
+                    BinaryOperator::Plus => BinaryOp::Add,
+                    BinaryOperator::Minus => BinaryOp::Subtract,
+                    BinaryOperator::Multiply => BinaryOp::Multiply,
+                    BinaryOperator::Divide => BinaryOp::Divide,
+                    BinaryOperator::Modulo => BinaryOp::Modulo,
+                    BinaryOperator::StringConcat => BinaryOp::Concat,
+                    BinaryOperator::Custom(op) if op.eq_ignore_ascii_case("glob") => {
+                        return Ok(Expr::Glob {
+                            expr: boxed(left)?,
+                            pattern: boxed(right)?,
+                            negated: false,
+                        });
+                    }
+                    op => bail!(Unsupported, "operator: {}", op),
+                };
+
+                Expr::Binary {
+                    left: boxed(left)?,
+                    op,
+                    right: boxed(right)?,
+                }
+            }
+            ast::Expr::IsNull(expr) => Expr::IsNull {
+                expr: boxed(expr)?,
+                negated: false,
+            },
+            ast::Expr::IsNotNull(expr) => Expr::IsNull {
+                expr: boxed(expr)?,
+                negated: true,
+            },
+            ast::Expr::InList {
+                expr,
+                list,
+                negated,
+            } => Expr::InList {
+                expr: boxed(expr)?,
+                list: list.iter().map(Expr::try_from).collect::<Result<_, _>>()?,
+                negated: *negated,
+            },
+            ast::Expr::Between {
+                expr,
+                negated,
+                low,
+                high,
+            } => Expr::Between {
+                expr: boxed(expr)?,
+                low: boxed(low)?,
+                high: boxed(high)?,
+                negated: *negated,
+            },
+            ast::Expr::Like {
+                negated,
+                expr,
+                pattern,
+                escape_char,
+            } => Expr::Like {
+                expr: boxed(expr)?,
+                pattern: boxed(pattern)?,
+                escape: *escape_char,
+                negated: *negated,
+            },
+            ast::Expr::Function(function) => {
+                let name = function.name.to_string();
+                let Some(func) = AggregateFunc::from_name(&name) else {
+                    bail!(Sql, "no such function: {}", name);
+                };
+
+                let mut args = Vec::new();
+                for arg in &function.args {
+                    match arg {
+                        ast::FunctionArg::Unnamed(ast::FunctionArgExpr::Expr(arg)) => {
+                            args.push(Expr::try_from(arg)?)
+                        }
+                        ast::FunctionArg::Unnamed(ast::FunctionArgExpr::Wildcard)
+                            if func == AggregateFunc::Count && function.args.len() == 1 => {}
+                        arg => bail!(Unsupported, "argument to {}: {}", name, arg),
+                    }
+                }
+                if !func.accepts(args.len()) {
+                    bail!(Sql, "wrong number of arguments to function {}()", name);
+                }
+                if function.distinct && args.len() != 1 {
+                    bail!(Sql, "DISTINCT aggregates must have exactly one argument");
+                }
+
+                Expr::Aggregate {
+                    func,
+                    args,
+                    distinct: function.distinct,
+                }
+            }
+            expr => bail!(Unsupported, "expression: {}", expr),
+        })
+    }
+}
+
+fn literal(value: &Value) -> Result<SerialValue> {
+    Ok(match value {
+        Value::Number(num, _) => match num.parse::<i64>() {
+            Ok(num) => SerialValue::I64(num),
+            Err(_) => SerialValue::Float64(
+                num.parse()
+                    .map_err(|_| err!(Syntax, "invalid number: {}", num))?,
+            ),
+        },
+        Value::SingleQuotedString(txt) => SerialValue::String(txt.clone()),
+        Value::HexStringLiteral(hex) => {
+            if hex.len() % 2 != 0 {
+                bail!(Syntax, "malformed blob literal: X'{}'", hex);
+            }
+            let bytes = (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+                .collect::<Result<_, _>>()
+                .map_err(|_| err!(Syntax, "malformed blob literal: X'{}'", hex))?;
+            SerialValue::Blob(bytes)
+        }
+        Value::Boolean(true) => SerialValue::One,
+        Value::Boolean(false) => SerialValue::Zero,
+        Value::Null => SerialValue::Null,
+        value => bail!(Unsupported, "literal: {}", value),
+    })
+}
+
+/// Names of the columns visible to an expression, in the order they appear in a row.
+#[derive(Debug, Clone, Default)]
+pub struct Scope {
+    columns: Vec<ScopeColumn>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ScopeColumn {
+    pub table: Option<String>,
+    pub name: String,
+    pub affinity: Affinity,
+    pub collation: Collation,
+    /// Hidden columns, like the rowid, can be referenced by name but are not part of `*`.
+    pub hidden: bool,
+    /// Set for the right-hand column of a USING or NATURAL join, which an unqualified name
+    /// does not refer to and `*` does not include.
+    pub qualified_only: bool,
+}
+
+impl Scope {
+    pub fn push(
+        &mut self,
+        table: Option<&str>,
+        name: &str,
+        affinity: Affinity,
+        collation: Collation,
+        hidden: bool,
+    ) {
+        self.columns.push(ScopeColumn {
+            table: table.map(str::to_string),
+            name: name.to_string(),
+            affinity,
+            collation,
+            hidden,
+            qualified_only: false,
+        });
+    }
+
+    /// Appends the columns of `other`, as when joining another table.
+    pub fn extend(&mut self, other: Scope) {
+        self.columns.extend(other.columns);
+    }
+
+    /// Makes the column at position `i` only visible to names qualified with its table.
+    pub fn qualify_only(&mut self, i: usize) {
+        self.columns[i].qualified_only = true;
+    }
+
+    pub fn columns(&self) -> &[ScopeColumn] {
+        &self.columns
+    }
+
+    /// Row position of the column called `name`, optionally qualified with a table name.
+    pub fn resolve(&self, table: Option<&str>, name: &str) -> Result<usize> {
+        let matches_table = |column: &ScopeColumn| match (table, &column.table) {
+            (None, _) => !column.qualified_only,
+            (Some(table), Some(column_table)) => table.eq_ignore_ascii_case(column_table),
+            (Some(_), None) => false,
+        };
+
+        let mut candidates =
+            self.columns.iter().enumerate().filter(|(_, column)| {
+                matches_table(column) && column.name.eq_ignore_ascii_case(name)
+            });
+
+        let qualified_name = || match table {
+            Some(table) => format!("{}.{}", table, name),
+            None => name.to_string(),
+        };
+
+        match (candidates.next(), candidates.next()) {
+            (Some((i, _)), None) => Ok(i),
+            (Some(_), Some(_)) => bail!(Sql, "ambiguous column name: {}", qualified_name()),
+            (None, _) if is_rowid_name(name) && !name.eq_ignore_ascii_case("rowid") => {
+                self.resolve(table, "rowid")
+            }
+            (None, _) => bail!(Sql, "no such column: {}", qualified_name()),
+        }
+    }
+}
+
+fn is_rowid_name(name: &str) -> bool {
+    ["rowid", "oid", "_rowid_"]
+        .iter()
+        .any(|alias| name.eq_ignore_ascii_case(alias))
+}
+
+impl Expr {
+    /// Replaces every column name with its position in rows described by `scope`.
+    pub fn bind(&self, scope: &Scope) -> Result<Expr> {
+        Ok(match self {
+            Expr::Identifier { table, name } => {
+                let i = scope.resolve(table.as_deref(), name)?;
+                match scope.columns[i].collation {
+                    Collation::Binary => Expr::Column(i),
+                    collation => Expr::Collate {
+                        expr: Box::new(Expr::Column(i)),
+                        collation,
+                        explicit: false,
+                    },
+                }
+            }
+            Expr::Binary { left, op, right } if op.is_comparison() => {
+                let (left_affinity, right_affinity) =
+                    comparison_affinities(left.affinity(scope), right.affinity(scope));
+                Expr::Binary {
+                    left: Box::new(left.bind(scope)?.with_affinity(left_affinity)),
+                    op: *op,
+                    right: Box::new(right.bind(scope)?.with_affinity(right_affinity)),
+                }
+            }
+            Expr::InList {
+                expr,
+                list,
+                negated,
+            } => Expr::InList {
+                expr: Box::new(expr.bind(scope)?),
+                list: list
+                    .iter()
+                    .map(|item| {
+                        let (_, affinity) =
+                            comparison_affinities(expr.affinity(scope), item.affinity(scope));
+                        Ok(item.bind(scope)?.with_affinity(affinity))
+                    })
+                    .collect::<Result<_>>()?,
+                negated: *negated,
+            },
+            Expr::Between {
+                expr,
+                low,
+                high,
+                negated,
+            } => {
+                let (expr_affinity, low_affinity) =
+                    comparison_affinities(expr.affinity(scope), low.affinity(scope));
+                let (_, high_affinity) =
+                    comparison_affinities(expr.affinity(scope), high.affinity(scope));
+                Expr::Between {
+                    expr: Box::new(expr.bind(scope)?.with_affinity(expr_affinity)),
+                    low: Box::new(low.bind(scope)?.with_affinity(low_affinity)),
+                    high: Box::new(high.bind(scope)?.with_affinity(high_affinity)),
+                    negated: *negated,
+                }
+            }
+            expr => expr.map_children(|child| child.bind(scope))?,
+        })
+    }
+
+    /// The affinity of the expression: that of the column it refers to, if any.
+    ///
+    /// See <https://www.sqlite.org/datatype3.html#affinity_of_expressions>.
+    fn affinity(&self, scope: &Scope) -> Option<Affinity> {
+        match self {
+            Expr::Identifier { table, name } => scope
+                .resolve(table.as_deref(), name)
+                .ok()
+                .map(|i| scope.columns[i].affinity),
+            Expr::Column(i) => scope.columns.get(*i).map(|column| column.affinity),
+            Expr::ApplyAffinity { affinity, .. } => Some(*affinity),
+            Expr::Collate { expr, .. } => expr.affinity(scope),
+            _ => None,
+        }
+    }
+
+    /// The collating sequence that comparisons with this bound expression use, if it
+    /// names one: through a COLLATE operator or because it is a column, whose collation
+    /// is BINARY unless declared otherwise.
+    ///
+    /// See <https://www.sqlite.org/datatype3.html#assigning_collating_sequences_from_sql>.
+    pub fn collation(&self) -> Option<Collation> {
+        match self {
+            Expr::Collate { collation, .. } => Some(*collation),
+            Expr::Column(_) => Some(Collation::Binary),
+            Expr::ApplyAffinity { expr, .. } => expr.collation(),
+            _ => None,
+        }
+    }
+
+    /// The collating sequence named by a COLLATE operator on this bound expression.
+    fn explicit_collation(&self) -> Option<Collation> {
+        match self {
+            Expr::Collate {
+                collation,
+                explicit: true,
+                ..
+            } => Some(*collation),
+            Expr::Collate { expr, .. } | Expr::ApplyAffinity { expr, .. } => {
+                expr.explicit_collation()
+            }
+            _ => None,
+        }
+    }
+
+    fn with_affinity(self, affinity: Option<Affinity>) -> Expr {
+        match affinity {
+            Some(affinity) => Expr::ApplyAffinity {
+                expr: Box::new(self),
+                affinity,
+            },
+            None => self,
+        }
+    }
+
+    /// The direct subexpressions of this expression.
+    fn children(&self) -> Vec<&Expr> {
+        match self {
+            Expr::Identifier { .. } | Expr::Column(_) | Expr::Literal(_) | Expr::Parameter(_) => {
+                Vec::new()
+            }
+            Expr::Unary { expr, .. }
+            | Expr::IsNull { expr, .. }
+            | Expr::ApplyAffinity { expr, .. }
+            | Expr::Collate { expr, .. } => vec![expr],
+            Expr::Binary { left, right, .. } => vec![left, right],
+            Expr::InList { expr, list, .. } => {
+                let mut children = vec![expr.as_ref()];
+                children.extend(list);
+                children
+            }
+            Expr::Between {
+                expr, low, high, ..
+            } => vec![expr, low, high],
+            Expr::Like { expr, pattern, .. } | Expr::Glob { expr, pattern, .. } => {
+                vec![expr, pattern]
+            }
+            Expr::Aggregate { args, .. } => args.iter().collect(),
+        }
+    }
+
+    /// Whether the expression does not depend on the row it is evaluated against.
+    pub fn is_constant(&self) -> bool {
+        !matches!(
+            self,
+            Expr::Identifier { .. } | Expr::Column(_) | Expr::Aggregate { .. }
+        ) && self.children().into_iter().all(Expr::is_constant)
+    }
+
+    /// The row positions of the columns the bound expression reads.
+    pub fn referenced_columns(&self) -> Vec<usize> {
+        match self {
+            Expr::Column(i) => vec![*i],
+            expr => expr
+                .children()
+                .into_iter()
+                .flat_map(Expr::referenced_columns)
+                .collect(),
+        }
+    }
+
+    /// Rebuilds the bound expression with each column position `i` replaced by `f(i)`.
+    pub fn map_columns(&self, f: &impl Fn(usize) -> usize) -> Expr {
+        match self {
+            Expr::Column(i) => Expr::Column(f(*i)),
+            expr => expr
+                .map_children(|child| Ok(child.map_columns(f)))
+                .expect("mapping columns cannot fail"),
+        }
+    }
+
+    /// Rebuilds the expression with each parameter replaced by the value bound to it in
+    /// `values`, or NULL if none is.
+    pub fn bind_parameters(&self, values: &[SerialValue]) -> Expr {
+        match self {
+            Expr::Parameter(i) => {
+                Expr::Literal(values.get(i - 1).cloned().unwrap_or(SerialValue::Null))
+            }
+            expr => expr
+                .map_children(|child| Ok(child.bind_parameters(values)))
+                .expect("binding parameters cannot fail"),
+        }
+    }
+
+    /// Whether the expression calls an aggregate function.
+    pub fn contains_aggregate(&self) -> bool {
+        matches!(self, Expr::Aggregate { .. })
+            || self.children().into_iter().any(Expr::contains_aggregate)
+    }
+
+    /// Replaces every aggregate call with a reference to its result, which follows the
+    /// `width` columns of the input row. The calls are appended to `aggregates`, once each.
+    pub fn extract_aggregates(&self, width: usize, aggregates: &mut Vec<Expr>) -> Result<Expr> {
+        if let Expr::Aggregate { func, args, .. } = self {
+            if args.iter().any(Expr::contains_aggregate) {
+                bail!(Sql, "misuse of aggregate function {}()", func.name());
+            }
+
+            let i = match aggregates.iter().position(|aggregate| aggregate == self) {
+                Some(i) => i,
+                None => {
+                    aggregates.push(self.clone());
+                    aggregates.len() - 1
+                }
+            };
+            return Ok(Expr::Column(width + i));
+        }
+
+        self.map_children(|child| child.extract_aggregates(width, aggregates))
+    }
+
+    /// Rebuilds the expression with `f` applied to each of its direct subexpressions.
+    pub fn map_children(&self, mut f: impl FnMut(&Expr) -> Result<Expr>) -> Result<Expr> {
+        let mut boxed = |expr: &Expr| f(expr).map(Box::new);
+
+        Ok(match self {
+            Expr::Identifier { .. } | Expr::Column(_) | Expr::Literal(_) | Expr::Parameter(_) => {
+                self.clone()
+            }
+            Expr::Unary { op, expr } => Expr::Unary {
+                op: *op,
+                expr: boxed(expr)?,
+            },
+            Expr::Binary { left, op, right } => Expr::Binary {
+                left: boxed(left)?,
+                op: *op,
+                right: boxed(right)?,
+            },
+            Expr::IsNull { expr, negated } => Expr::IsNull {
+                expr: boxed(expr)?,
+                negated: *negated,
+            },
+            Expr::InList {
+                expr,
+                list,
+                negated,
+            } => Expr::InList {
+                expr: boxed(expr)?,
+                list: list
+                    .iter()
+                    .map(|item| boxed(item).map(|item| *item))
+                    .collect::<Result<_, _>>()?,
+                negated: *negated,
+            },
+            Expr::Between {
+                expr,
+                low,
+                high,
+                negated,
+            } => Expr::Between {
+                expr: boxed(expr)?,
+                low: boxed(low)?,
+                high: boxed(high)?,
+                negated: *negated,
+            },
+            Expr::Like {
+                expr,
+                pattern,
+                escape,
+                negated,
+            } => Expr::Like {
+                expr: boxed(expr)?,
+                pattern: boxed(pattern)?,
+                escape: *escape,
+                negated: *negated,
+            },
+            Expr::Glob {
+                expr,
+                pattern,
+                negated,
+            } => Expr::Glob {
+                expr: boxed(expr)?,
+                pattern: boxed(pattern)?,
+                negated: *negated,
+            },
+            Expr::ApplyAffinity { expr, affinity } => Expr::ApplyAffinity {
+                expr: boxed(expr)?,
+                affinity: *affinity,
+            },
+            Expr::Collate {
+                expr,
+                collation,
+                explicit,
+            } => Expr::Collate {
+                expr: boxed(expr)?,
+                collation: *collation,
+                explicit: *explicit,
+            },
+            Expr::Aggregate {
+                func,
+                args,
+                distinct,
+            } => Expr::Aggregate {
+                func: *func,
+                args: args
+                    .iter()
+                    .map(|arg| boxed(arg).map(|arg| *arg))
+                    .collect::<Result<_, _>>()?,
+                distinct: *distinct,
+            },
+        })
+    }
+
+    /// Evaluates the expression against a row bound with [`Expr::bind`].
+    pub fn eval(&self, row: &[SerialValue]) -> Result<SerialValue> {
+        Ok(match self {
+            Expr::Identifier { name, .. } => bail!(Sql, "column {} is not bound", name),
+            Expr::Column(i) => match row.get(*i) {
+                Some(value) => value.clone(),
+                None => bail!(Sql, "column {} out of range", i),
+            },
+            Expr::Literal(value) => value.clone(),
+            Expr::Parameter(i) => bail!(Sql, "parameter ?{} is not bound", i),
+            Expr::Unary { op, expr } => {
+                let value = expr.eval(row)?;
+                match op {
+                    UnaryOp::Not => from_truth(truth(&value).map(|truth| !truth)),
+                    UnaryOp::Minus => match to_numeric(&value) {
+                        None => SerialValue::Null,
+                        Some(Numeric::Integer(num)) => match num.checked_neg() {
+                            Some(num) => SerialValue::I64(num),
+                            None => SerialValue::Float64(-(num as f64)),
+                        },
+                        Some(Numeric::Real(num)) => SerialValue::Float64(-num),
+                    },
+                    UnaryOp::Plus => value,
+                }
+            }
+            Expr::Binary { left, op, right } => match op {
+                BinaryOp::And => {
+                    let left = truth(&left.eval(row)?);
+                    if left == Some(false) {
+                        return Ok(from_truth(Some(false)));
+                    }
+                    match (left, truth(&right.eval(row)?)) {
+                        (_, Some(false)) => from_truth(Some(false)),
+                        (Some(true), Some(true)) => from_truth(Some(true)),
+                        _ => SerialValue::Null,
+                    }
+                }
+                BinaryOp::Or => {
+                    let left = truth(&left.eval(row)?);
+                    if left == Some(true) {
+                        return Ok(from_truth(Some(true)));
+                    }
+                    match (left, truth(&right.eval(row)?)) {
+                        (_, Some(true)) => from_truth(Some(true)),
+                        (Some(false), Some(false)) => from_truth(Some(false)),
+                        _ => SerialValue::Null,
+                    }
+                }
+                op => {
+                    let collation = comparison_collation(left, right);
+                    binary(*op, &left.eval(row)?, &right.eval(row)?, collation)
+                }
+            },
+            Expr::IsNull { expr, negated } => {
+                from_truth(Some((expr.eval(row)? == SerialValue::Null) != *negated))
+            }
+            Expr::InList {
+                expr,
+                list,
+                negated,
+            } => {
+                let value = expr.eval(row)?;
+                if value == SerialValue::Null {
+                    return Ok(SerialValue::Null);
+                }
+
+                // Only the collation of the left operand applies.
+                let collation = expr.collation().unwrap_or_default();
+                let mut saw_null = false;
+                let mut found = false;
+                for item in list {
+                    match compare(&value, &item.eval(row)?, collation) {
+                        Some(Ordering::Equal) => {
+                            found = true;
+                            break;
+                        }
+                        Some(_) => {}
+                        None => saw_null = true,
+                    }
+                }
+
+                match (found, saw_null) {
+                    (true, _) => from_truth(Some(!*negated)),
+                    (false, true) => SerialValue::Null,
+                    (false, false) => from_truth(Some(*negated)),
+                }
+            }
+            Expr::Between {
+                expr,
+                low,
+                high,
+                negated,
+            } => {
+                let value = expr.eval(row)?;
+                let collation = |bound: &Expr| comparison_collation(expr, bound);
+                let above_low =
+                    compare(&value, &low.eval(row)?, collation(low)).map(Ordering::is_ge);
+                let below_high =
+                    compare(&value, &high.eval(row)?, collation(high)).map(Ordering::is_le);
+                let between = match (above_low, below_high) {
+                    (Some(false), _) | (_, Some(false)) => Some(false),
+                    (Some(true), Some(true)) => Some(true),
+                    _ => None,
+                };
+                from_truth(between.map(|between| between != *negated))
+            }
+            Expr::Like {
+                expr,
+                pattern,
+                escape,
+                negated,
+            } => {
+                let (value, pattern) = (expr.eval(row)?, pattern.eval(row)?);
+                match (to_text(&value), to_text(&pattern)) {
+                    (Some(value), Some(pattern)) => {
+                        from_truth(Some(like(&pattern, &value, *escape) != *negated))
+                    }
+                    _ => SerialValue::Null,
+                }
+            }
+            Expr::Glob {
+                expr,
+                pattern,
+                negated,
+            } => {
+                let (value, pattern) = (expr.eval(row)?, pattern.eval(row)?);
+                match (to_text(&value), to_text(&pattern)) {
+                    (Some(value), Some(pattern)) => {
+                        from_truth(Some(glob(&pattern, &value) != *negated))
+                    }
+                    _ => SerialValue::Null,
+                }
+            }
+            Expr::ApplyAffinity { expr, affinity } => expr.eval(row)?.apply_affinity(*affinity),
+            Expr::Collate { expr, .. } => expr.eval(row)?,
+            Expr::Aggregate { func, .. } => {
+                bail!(Sql, "misuse of aggregate function {}()", func.name())
+            }
+        })
+    }
+
+    /// Evaluates the expression as a WHERE condition: NULL counts as false.
+    pub fn is_true(&self, row: &[SerialValue]) -> Result<bool> {
+        Ok(truth(&self.eval(row)?).unwrap_or(false))
+    }
+
+    /// Splits the expression into the terms of a top-level AND chain.
+    pub fn conjuncts(&self) -> Vec<&Expr> {
+        match self {
+            Expr::Binary {
+                left,
+                op: BinaryOp::And,
+                right,
+            } => {
+                let mut terms = left.conjuncts();
+                terms.extend(right.conjuncts());
+                terms
+            }
+            expr => vec![expr],
+        }
+    }
+}
+
+impl BinaryOp {
+    pub fn is_comparison(self) -> bool {
+        matches!(
+            self,
+            BinaryOp::Eq
+                | BinaryOp::NotEq
+                | BinaryOp::Lt
+                | BinaryOp::LtEq
+                | BinaryOp::Gt
+                | BinaryOp::GtEq
+        )
+    }
+}
+
+/// The affinities to apply to the left and right operands of a comparison, given the
+/// affinities of the operands themselves.
+///
+/// See <https://www.sqlite.org/datatype3.html#type_conversions_prior_to_comparison>.
+fn comparison_affinities(
+    left: Option<Affinity>,
+    right: Option<Affinity>,
+) -> (Option<Affinity>, Option<Affinity>) {
+    // TEXT affinity is only applied to an operand without any affinity: a column declared
+    // without a type has BLOB affinity, which is compared with TEXT unconverted.
+    let is_numeric = |affinity: Option<Affinity>| affinity.is_some_and(Affinity::is_numeric);
+
+    match (left, right) {
+        (left, right) if is_numeric(left) && !is_numeric(right) => (None, Some(Affinity::Numeric)),
+        (left, right) if is_numeric(right) && !is_numeric(left) => (Some(Affinity::Numeric), None),
+        (Some(Affinity::Text), None) => (None, Some(Affinity::Text)),
+        (None, Some(Affinity::Text)) => (Some(Affinity::Text), None),
+        _ => (None, None),
+    }
+}
+
+/// The collating sequence a comparison of `left` with `right` uses. A COLLATE operator
+/// takes precedence over the collation of a column, and the left operand over the right.
+pub fn comparison_collation(left: &Expr, right: &Expr) -> Collation {
+    left.explicit_collation()
+        .or(right.explicit_collation())
+        .or(left.collation())
+        .or(right.collation())
+        .unwrap_or_default()
+}
+
+pub(crate) fn binary(
+    op: BinaryOp,
+    left: &SerialValue,
+    right: &SerialValue,
+    collation: Collation,
+) -> SerialValue {
+    let comparison =
+        |is_match: fn(Ordering) -> bool| from_truth(compare(left, right, collation).map(is_match));
+
+    match op {
+        BinaryOp::Eq => comparison(Ordering::is_eq),
+        BinaryOp::NotEq => comparison(Ordering::is_ne),
+        BinaryOp::Lt => comparison(Ordering::is_lt),
+        BinaryOp::LtEq => comparison(Ordering::is_le),
+        BinaryOp::Gt => comparison(Ordering::is_gt),
+        BinaryOp::GtEq => comparison(Ordering::is_ge),
+        BinaryOp::Concat => match (to_text(left), to_text(right)) {
+            (Some(left), Some(right)) => SerialValue::String(left + &right),
+            _ => SerialValue::Null,
+        },
+        BinaryOp::Add
+        | BinaryOp::Subtract
+        | BinaryOp::Multiply
+        | BinaryOp::Divide
+        | BinaryOp::Modulo => match (to_numeric(left), to_numeric(right)) {
+            (Some(left), Some(right)) => arithmetic(op, left, right),
+            _ => SerialValue::Null,
+        },
+        BinaryOp::And | BinaryOp::Or => unreachable!("logical operators short-circuit"),
+    }
+}
+
+/// Compares two values with `collation`, or returns `None` if either is NULL.
+pub(crate) fn compare(
+    left: &SerialValue,
+    right: &SerialValue,
+    collation: Collation,
+) -> Option<Ordering> {
+    if *left == SerialValue::Null || *right == SerialValue::Null {
+        return None;
+    }
+
+    Some(collation.compare(left, right))
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Numeric {
+    Integer(i64),
+    Real(f64),
+}
+
+fn arithmetic(op: BinaryOp, left: Numeric, right: Numeric) -> SerialValue {
+    use Numeric::*;
+
+    match (left, right) {
+        (Integer(a), Integer(b)) => {
+            let result = match op {
+                BinaryOp::Add => a.checked_add(b),
+                BinaryOp::Subtract => a.checked_sub(b),
+                BinaryOp::Multiply => a.checked_mul(b),
+                BinaryOp::Divide | BinaryOp::Modulo if b == 0 => return SerialValue::Null,
+                BinaryOp::Divide => a.checked_div(b),
+                BinaryOp::Modulo => Some(a.checked_rem(b).unwrap_or(0)),
+                _ => unreachable!("not an arithmetic operator"),
+            };
+
+            match result {
+                Some(num) => SerialValue::I64(num),
+                // Integer overflow falls back to floating point, as SQLite does.
+                None => arithmetic(op, Real(a as f64), Real(b as f64)),
+            }
+        }
+        (a, b) => {
+            let (a, b) = (a.as_f64(), b.as_f64());
+            let result = match op {
+                BinaryOp::Add => a + b,
+                BinaryOp::Subtract => a - b,
+                BinaryOp::Multiply => a * b,
+                BinaryOp::Divide if b == 0.0 => return SerialValue::Null,
+                BinaryOp::Divide => a / b,
+                BinaryOp::Modulo => {
+                    let (a, b) = (a as i64, b as i64);
+                    if b == 0 {
+                        return SerialValue::Null;
+                    }
+                    a.checked_rem(b).unwrap_or(0) as f64
+                }
+                _ => unreachable!("not an arithmetic operator"),
+            };
+
+            if result.is_nan() {
+                SerialValue::Null
+            } else {
+                SerialValue::Float64(result)
+            }
+        }
+    }
+}
+
+impl Numeric {
+    pub(crate) fn as_f64(self) -> f64 {
+        match self {
+            Numeric::Integer(num) => num as f64,
+            Numeric::Real(num) => num,
+        }
+    }
+}
+
+/// Converts a value to a number the way SQLite does for arithmetic: text is read up to the
+/// first character that cannot be part of a number.
+pub(crate) fn to_numeric(value: &SerialValue) -> Option<Numeric> {
+    match value {
+        SerialValue::Null => None,
+        SerialValue::Float64(num) => Some(Numeric::Real(*num)),
+        SerialValue::String(txt) => Some(text_to_numeric(txt)),
+        SerialValue::Blob(bytes) => Some(text_to_numeric(&String::from_utf8_lossy(bytes))),
+        value => value.as_integer().map(Numeric::Integer),
+    }
+}
+
+fn text_to_numeric(txt: &str) -> Numeric {
+    let txt = txt.trim_start();
+    let bytes = txt.as_bytes();
+
+    let mut end = 0;
+    if matches!(bytes.first(), Some(b'+' | b'-')) {
+        end += 1;
+    }
+    let digits_start = end;
+    while bytes.get(end).is_some_and(u8::is_ascii_digit) {
+        end += 1;
+    }
+    let mut is_integer = true;
+    if bytes.get(end) == Some(&b'.') {
+        is_integer = false;
+        end += 1;
+        while bytes.get(end).is_some_and(u8::is_ascii_digit) {
+            end += 1;
+        }
+    }
+    if end > digits_start && matches!(bytes.get(end), Some(b'e' | b'E')) {
+        let mut exponent_end = end + 1;
+        if matches!(bytes.get(exponent_end), Some(b'+' | b'-')) {
+            exponent_end += 1;
+        }
+        if bytes.get(exponent_end).is_some_and(u8::is_ascii_digit) {
+            is_integer = false;
+            end = exponent_end;
+            while bytes.get(end).is_some_and(u8::is_ascii_digit) {
+                end += 1;
+            }
+        }
+    }
+
+    let prefix = &txt[..end];
+    if !prefix.bytes().any(|byte| byte.is_ascii_digit()) {
+        return Numeric::Integer(0);
+    }
+    if is_integer {
+        if let Ok(num) = prefix.parse() {
+            return Numeric::Integer(num);
+        }
+    }
+
+    Numeric::Real(prefix.parse().unwrap_or(0.0))
+}
+
+/// Converts a value to text, or returns `None` for NULL.
+pub(crate) fn to_text(value: &SerialValue) -> Option<String> {
+    match value {
+        SerialValue::Null => None,
+        SerialValue::String(txt) => Some(txt.clone()),
+        SerialValue::Blob(bytes) => Some(String::from_utf8_lossy(bytes).into_owned()),
+        value => Some(value.display()),
+    }
+}
+
+/// Interprets a value as a boolean, or returns `None` for NULL.
+pub(crate) fn truth(value: &SerialValue) -> Option<bool> {
+    match to_numeric(value)? {
+        Numeric::Integer(num) => Some(num != 0),
+        Numeric::Real(num) => Some(num != 0.0),
+    }
+}
+
+pub(crate) fn from_truth(truth: Option<bool>) -> SerialValue {
+    match truth {
+        Some(true) => SerialValue::One,
+        Some(false) => SerialValue::Zero,
+        None => SerialValue::Null,
+    }
+}
+
+/// Matches `value` against a LIKE pattern: `%` matches any sequence, `_` any single
+/// character, and ASCII letters match case-insensitively.
+pub(crate) fn like(pattern: &str, value: &str, escape: Option<char>) -> bool {
+    let mut tokens = Vec::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        tokens.push(match c {
+            // An escape at the end of the pattern matches nothing.
+            _ if Some(c) == escape => match chars.next() {
+                Some(c) => Token::Char(c),
+                None => return false,
+            },
+            '%' => Token::Any,
+            '_' => Token::One,
+            c => Token::Char(c),
+        });
+    }
+
+    wildcard_match(&tokens, value, true)
+}
+
+/// Matches `value` against a GLOB pattern: `*` matches any sequence, `?` any single
+/// character and `[...]` a character class. Matching is case-sensitive.
+pub(crate) fn glob(pattern: &str, value: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let mut tokens = Vec::new();
+    let mut rest = pattern.as_slice();
+    while let Some((c, tail)) = rest.split_first() {
+        rest = tail;
+        tokens.push(match c {
+            '*' => Token::Any,
+            '?' => Token::One,
+            '[' => {
+                let negated = rest.first() == Some(&'^');
+                if negated {
+                    rest = &rest[1..];
+                }
+
+                // A `]` right after the `[` or `[^` is a member, and one that ends a range
+                // stands for itself. A class that is not closed matches nothing.
+                let mut ranges = Vec::new();
+                let mut first = true;
+                loop {
+                    match rest {
+                        [] => return false,
+                        [']', tail @ ..] if !first => {
+                            rest = tail;
+                            break;
+                        }
+                        [low, '-', high, tail @ ..] if *high != ']' => {
+                            ranges.push((*low, *high));
+                            rest = tail;
+                        }
+                        [member, tail @ ..] => {
+                            ranges.push((*member, *member));
+                            rest = tail;
+                        }
+                    }
+                    first = false;
+                }
+                Token::Class { negated, ranges }
+            }
+            c => Token::Char(*c),
+        });
+    }
+
+    wildcard_match(&tokens, value, false)
+}
+
+/// A part of a LIKE or GLOB pattern.
+enum Token {
+    /// Any sequence of characters, `%` or `*`.
+    Any,
+    /// Any single character, `_` or `?`.
+    One,
+    Char(char),
+    /// A GLOB character class: one of the characters in `ranges`, or if `negated` none.
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+/// Matches `value` against a pattern. Every token but [`Token::Any`] matches a single
+/// character, so only the position after the last `Any` needs remembering: when a match
+/// fails the pattern resumes there with one more character taken by that `Any`, as in
+/// SQLite's `patternCompare`. This takes time proportional to the product of the lengths,
+/// however many wildcards the pattern has.
+fn wildcard_match(tokens: &[Token], value: &str, nocase: bool) -> bool {
+    let value: Vec<char> = value.chars().collect();
+    let matches = |token: &Token, c: char| match token {
+        Token::Any => false,
+        Token::One => true,
+        Token::Char(p) if nocase => p.eq_ignore_ascii_case(&c),
+        Token::Char(p) => *p == c,
+        Token::Class { negated, ranges } => {
+            ranges.iter().any(|(low, high)| (*low..=*high).contains(&c)) != *negated
+        }
+    };
+
+    let (mut p, mut v) = (0, 0);
+    // The token after the last `Any` and the position in `value` it was tried from.
+    let mut resume = None;
+    while v < value.len() {
+        match tokens.get(p) {
+            Some(Token::Any) => {
+                p += 1;
+                resume = Some((p, v));
+                continue;
+            }
+            Some(token) if matches(token, value[v]) => {
+                p += 1;
+                v += 1;
+                continue;
+            }
+            _ => {}
+        }
+
+        let Some((after_any, from)) = resume else {
+            return false;
+        };
+        p = after_any;
+        v = from + 1;
+        resume = Some((after_any, v));
+    }
+
+    tokens[p..].iter().all(|token| matches!(token, Token::Any))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(sql: &str) -> SerialValue {
+        let scope = Scope::default();
+        let dialect = sqlparser::dialect::SQLiteDialect {};
+        let expr = sqlparser::parser::Parser::new(&dialect)
+            .try_with_sql(sql)
+            .unwrap()
+            .parse_expr()
+            .unwrap();
+
+        Expr::try_from(&expr)
+            .unwrap()
+            .bind(&scope)
+            .unwrap()
+            .eval(&[])
+            .unwrap()
+    }
+
+    #[test]
+    fn test_eval_arithmetic() {
+        assert_eq!(eval("1 + 2 * 3"), SerialValue::I64(7));
+        assert_eq!(eval("7 / 2"), SerialValue::I64(3));
+        assert_eq!(eval("7.0 / 2"), SerialValue::Float64(3.5));
+        assert_eq!(eval("7 % 0"), SerialValue::Null);
+        assert_eq!(eval("'12abc' + 1"), SerialValue::I64(13));
+        assert_eq!(
+            eval("9223372036854775807 + 1"),
+            SerialValue::Float64(9.223372036854776e18)
+        );
+        assert_eq!(eval("-(3)"), SerialValue::I64(-3));
+    }
+
+    #[test]
+    fn test_eval_three_valued_logic() {
+        assert_eq!(eval("NULL AND 0"), SerialValue::Zero);
+        assert_eq!(eval("NULL AND 1"), SerialValue::Null);
+        assert_eq!(eval("NULL OR 1"), SerialValue::One);
+        assert_eq!(eval("NOT NULL"), SerialValue::Null);
+        assert_eq!(eval("NULL = NULL"), SerialValue::Null);
+        assert_eq!(eval("NULL IS NULL"), SerialValue::One);
+        assert_eq!(eval("1 IS NOT NULL"), SerialValue::One);
+    }
+
+    #[test]
+    fn test_eval_in_and_between() {
+        assert_eq!(eval("2 IN (1, 2, 3)"), SerialValue::One);
+        assert_eq!(eval("4 IN (1, NULL)"), SerialValue::Null);
+        assert_eq!(eval("4 NOT IN (1, 2)"), SerialValue::One);
+        assert_eq!(eval("5 BETWEEN 1 AND 10"), SerialValue::One);
+        assert_eq!(eval("5 NOT BETWEEN 1 AND 10"), SerialValue::Zero);
+        assert_eq!(eval("'b' BETWEEN 'a' AND 'c'"), SerialValue::One);
+    }
+
+    #[test]
+    fn test_comparison_affinity() {
+        let mut scope = Scope::default();
+        scope.push(None, "t", Affinity::Text, Collation::Binary, false);
+        scope.push(None, "n", Affinity::Integer, Collation::Binary, false);
+        scope.push(None, "b", Affinity::Blob, Collation::Binary, false);
+        scope.push(None, "u", Affinity::Blob, Collation::Binary, false);
+        let row = [
+            SerialValue::String("7".to_string()),
+            SerialValue::I8(7),
+            SerialValue::String("7".to_string()),
+            SerialValue::I8(7),
+        ];
+
+        let eval = |sql: &str| {
+            let dialect = sqlparser::dialect::SQLiteDialect {};
+            let expr = sqlparser::parser::Parser::new(&dialect)
+                .try_with_sql(sql)
+                .unwrap()
+                .parse_expr()
+                .unwrap();
+            Expr::try_from(&expr)
+                .unwrap()
+                .bind(&scope)
+                .unwrap()
+                .eval(&row)
+                .unwrap()
+        };
+
+        assert_eq!(eval("t = 7"), SerialValue::One);
+        assert_eq!(eval("n = '7'"), SerialValue::One);
+        assert_eq!(eval("n = 7.0"), SerialValue::One);
+        assert_eq!(eval("t = n"), SerialValue::One);
+        assert_eq!(eval("b = 7"), SerialValue::Zero);
+        assert_eq!(eval("b = '7'"), SerialValue::One);
+        assert_eq!(eval("u = 7"), SerialValue::One);
+        assert_eq!(eval("u = '7'"), SerialValue::Zero);
+        assert_eq!(eval("t = u"), SerialValue::Zero);
+        assert_eq!(eval("u < t"), SerialValue::One);
+        assert_eq!(eval("u = n"), SerialValue::One);
+        assert_eq!(eval("'7' = 7"), SerialValue::Zero);
+        assert_eq!(eval("n IN ('6', '7')"), SerialValue::One);
+        assert_eq!(eval("t BETWEEN 5 AND 9"), SerialValue::One);
+    }
+
+    #[test]
+    fn test_like_and_glob() {
+        assert!(like("a%c", "ABBC", None));
+        assert!(like("_b_", "abc", None));
+        assert!(!like("a_c", "ac", None));
+        assert!(like("10\\%", "10%", Some('\\')));
+        assert!(!like("10\\%", "100", Some('\\')));
+
+        assert!(glob("a*c", "abbc"));
+        assert!(!glob("a*c", "ABBC"));
+        assert!(glob("[a-c]?", "bz"));
+        assert!(glob("[^a-c]*", "zebra"));
+        assert!(!glob("[^a-c]*", "apple"));
+        assert!(glob("[]x]", "]"));
+        assert!(glob("[a-]", "-"));
+        assert!(!glob("[abc", "a"));
+        assert!(like("%a%b", "xaxab", None));
+        assert!(!like("a%", "", None));
+        assert!(like("%", "", None));
+        assert!(!like("ab\\", "ab", Some('\\')));
+
+        // Many wildcards must not take exponential time.
+        let value = format!("{}b", "a".repeat(10_000));
+        assert!(!like("%a%a%a%a%a%a%a%a%c", &value, None));
+        assert!(like("%a%a%a%a%a%a%a%a%b", &value, None));
+        assert!(!glob("*a*a*a*a*a*a*a*a*c", &value));
+    }
+
+    #[test]
+    fn test_text_to_numeric() {
+        assert!(matches!(
+            text_to_numeric(" 42 apples"),
+            Numeric::Integer(42)
+        ));
+        assert!(matches!(text_to_numeric("1.5e3x"), Numeric::Real(num) if num == 1500.0));
+        assert!(matches!(text_to_numeric("abc"), Numeric::Integer(0)));
+    }
+}
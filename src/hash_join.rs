@@ -0,0 +1,531 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, VecDeque},
+    hash::{Hash, Hasher},
+    iter::Peekable,
+    mem,
+};
+
+use crate::{
+    column::{Collation, SerialValue},
+    error::Result,
+    expr::Expr,
+    query::Row,
+    temp::{size_of_values, RecordWriter, Records, TempFile},
+};
+
+/// How many bytes of rows a [`HashJoin`] keeps in its hash table before it partitions
+/// both inputs into temporary files.
+pub const DEFAULT_HASH_MEMORY: usize = 32 << 20;
+
+/// How many partitions a [`HashJoin`] that runs out of memory splits its inputs into.
+const PARTITIONS: usize = 16;
+
+/// How many times a partition whose build rows do not fit in memory is split again, by a
+/// differently seeded hash, before it is joined in blocks instead.
+const MAX_SPLITS: usize = 3;
+
+/// An equality join. The rows of the build input are put into a hash table by their key,
+/// then each row of the probe input is joined with the build rows that have an equal key
+/// and for which `condition` holds. Every build row is given, with [`HashJoin::build`],
+/// before the first probe row, with [`HashJoin::probe`].
+///
+/// Keys are hashed the way SQLite compares them: an integer equals a real of the same
+/// value, text is folded by the collation of its part of the key, and a key containing
+/// NULL equals nothing.
+///
+/// If the build rows do not fit in memory, both inputs are split into partitions by the
+/// hash of their keys and each pair of partitions is joined on its own once
+/// [`HashJoin::finish`] is called, which changes the order rows come out in. A partition
+/// still too large is split again, and one that splitting does not help, such as one
+/// holding many rows of a single key, is joined a block of build rows at a time, reading
+/// its probe rows once per block.
+pub struct HashJoin<'a> {
+    collations: &'a [Collation],
+    condition: Option<Expr>,
+    /// Whether the build row comes before the probe row in the joined rows.
+    build_first: bool,
+    /// For a LEFT join, the number of NULLs that stand in for the build row of probe rows
+    /// that match nothing.
+    null_extend: Option<usize>,
+    memory: usize,
+    table: HashMap<Vec<SerialValue>, Vec<Row>>,
+    table_bytes: usize,
+    /// Once the build rows outgrow memory, the partitions of the build and probe rows.
+    spill: Option<(Partitions, Option<Partitions>)>,
+    spilled: bool,
+    /// After [`HashJoin::finish`], the partitions not joined yet.
+    partitions: Option<Vec<Partition>>,
+    /// The probe rows of the partition being joined.
+    probe: Option<Records>,
+    /// The partition being joined a block of build rows at a time.
+    block: Option<Block>,
+    output: VecDeque<Row>,
+}
+
+impl<'a> HashJoin<'a> {
+    pub fn new(
+        collations: &'a [Collation],
+        condition: Option<Expr>,
+        build_first: bool,
+        null_extend: Option<usize>,
+        memory: usize,
+    ) -> Self {
+        Self {
+            collations,
+            condition,
+            build_first,
+            null_extend,
+            memory,
+            table: HashMap::new(),
+            table_bytes: 0,
+            spill: None,
+            spilled: false,
+            partitions: None,
+            probe: None,
+            block: None,
+            output: VecDeque::new(),
+        }
+    }
+
+    /// Adds a build row with the values of its key.
+    pub fn build(&mut self, key: Vec<SerialValue>, row: Row) -> Result<()> {
+        let key = self.fold(key);
+        // Nothing is equal to NULL, and build rows are never NULL-extended.
+        if key.contains(&SerialValue::Null) {
+            return Ok(());
+        }
+
+        match &mut self.spill {
+            Some((partitions, _)) => partitions.write(key, row)?,
+            None => {
+                self.table_bytes += size_of_values(&key) + size_of_values(&row);
+                self.table.entry(key).or_default().push(row);
+
+                if self.table_bytes > self.memory {
+                    let mut partitions = Partitions::create(0)?;
+                    for (key, rows) in mem::take(&mut self.table) {
+                        for row in rows {
+                            partitions.write(key.clone(), row)?;
+                        }
+                    }
+                    self.spill = Some((partitions, None));
+                    self.spilled = true;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Joins a probe row, with the values of its key, with the build rows. Unless the build
+    /// rows did not fit in memory, the joined rows can be read right away.
+    pub fn probe(&mut self, key: Vec<SerialValue>, row: Row) -> Result<()> {
+        let key = self.fold(key);
+        match &mut self.spill {
+            Some((_, probe_partitions)) => {
+                let partitions = match probe_partitions {
+                    Some(partitions) => partitions,
+                    None => probe_partitions.insert(Partitions::create(0)?),
+                };
+                partitions.write(key, row)
+            }
+            None => self.join_probe_row(key, row, false, true).map(|_| ()),
+        }
+    }
+
+    /// Ends the probe input, after which the partitions of spilled rows are joined.
+    pub fn finish(&mut self) -> Result<()> {
+        let partitions = match self.spill.take() {
+            Some((build, probe)) => {
+                let probe = match probe {
+                    Some(probe) => probe,
+                    None => Partitions::create(0)?,
+                };
+                Partition::pair(build, probe, 0)?
+            }
+            None => Vec::new(),
+        };
+        self.partitions = Some(partitions);
+        Ok(())
+    }
+
+    /// Whether [`HashJoin::finish`] was called.
+    pub fn is_finished(&self) -> bool {
+        self.partitions.is_some()
+    }
+
+    /// Whether the build rows did not fit in memory.
+    pub fn spilled(&self) -> bool {
+        self.spilled
+    }
+
+    /// The next joined row, or `None` once every row joined so far was returned. After
+    /// [`HashJoin::finish`], `None` means that the join is done.
+    pub fn next_row(&mut self) -> Result<Option<Row>> {
+        loop {
+            if let Some(row) = self.output.pop_front() {
+                return Ok(Some(row));
+            }
+
+            let nkeys = self.collations.len();
+            match self.probe.as_mut().and_then(Iterator::next) {
+                Some(values) => {
+                    let (key, row) = split_key(values?, nkeys);
+                    match &mut self.block {
+                        Some(block) => {
+                            let i = block.probed;
+                            block.probed += 1;
+                            if i == block.matched.len() {
+                                block.matched.push(false);
+                            }
+                            let (matched, last) = (block.matched[i], block.is_last());
+                            let matched = self.join_probe_row(key, row, matched, last)?;
+                            if let Some(block) = &mut self.block {
+                                block.matched[i] = matched;
+                            }
+                        }
+                        None => {
+                            self.join_probe_row(key, row, false, true)?;
+                        }
+                    }
+                }
+                None if self.next_partition()? => {}
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// Folds the text of a key by the collations of its parts.
+    fn fold(&self, key: Vec<SerialValue>) -> Vec<SerialValue> {
+        key.into_iter()
+            .zip(self.collations)
+            .map(|(value, collation)| collation.fold(value))
+            .collect()
+    }
+
+    /// Joins a probe row with the build rows of the same key, returning whether it matched
+    /// one of them or was `matched` already. For a LEFT join, a probe row that never
+    /// matched is NULL-extended once the `last` build rows were joined with it.
+    fn join_probe_row(
+        &mut self,
+        key: Vec<SerialValue>,
+        mut row: Row,
+        mut matched: bool,
+        last: bool,
+    ) -> Result<bool> {
+        let build_rows = match key.contains(&SerialValue::Null) {
+            true => None,
+            false => self.table.get(&key),
+        };
+        for build_row in build_rows.into_iter().flatten() {
+            let joined = match self.build_first {
+                true => [build_row.as_slice(), &row].concat(),
+                false => [row.as_slice(), build_row].concat(),
+            };
+            if self
+                .condition
+                .as_ref()
+                .map_or(Ok(true), |condition| condition.is_true(&joined))?
+            {
+                matched = true;
+                self.output.push_back(joined);
+            }
+        }
+
+        if let (false, true, Some(width)) = (matched, last, self.null_extend) {
+            row.resize(row.len() + width, SerialValue::Null);
+            self.output.push_back(row);
+        }
+
+        Ok(matched)
+    }
+
+    /// Loads the next block of build rows, or the build rows of the next partition, into
+    /// the hash table and starts on the probe rows to join with them. Returns false once
+    /// every partition was joined.
+    fn next_partition(&mut self) -> Result<bool> {
+        self.probe = None;
+        if let Some(mut block) = self.block.take() {
+            if !block.is_last() {
+                self.load(&mut block.build)?;
+                block.probed = 0;
+                self.probe = Some(block.probe.records()?);
+                self.block = Some(block);
+                return Ok(true);
+            }
+        }
+
+        while let Some(partition) = self.partitions.as_mut().and_then(Vec::pop) {
+            let mut build = partition.build.into_records()?.peekable();
+            self.load(&mut build)?;
+            if build.peek().is_none() {
+                self.probe = Some(partition.probe.into_records()?);
+                return Ok(true);
+            }
+
+            if partition.splits < MAX_SPLITS {
+                self.split(build, partition.probe, partition.splits + 1)?;
+                continue;
+            }
+
+            let block = Block {
+                build,
+                probe: partition.probe,
+                matched: Vec::new(),
+                probed: 0,
+            };
+            self.probe = Some(block.probe.records()?);
+            self.block = Some(block);
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+
+    /// Replaces the hash table with build rows until they outgrow memory or run out.
+    fn load(&mut self, build: &mut Peekable<Records>) -> Result<()> {
+        let nkeys = self.collations.len();
+        self.table.clear();
+        self.table_bytes = 0;
+        while self.table_bytes <= self.memory {
+            let Some(values) = build.next() else {
+                break;
+            };
+            let (key, row) = split_key(values?, nkeys);
+            self.table_bytes += size_of_values(&key) + size_of_values(&row);
+            self.table.entry(key).or_default().push(row);
+        }
+        Ok(())
+    }
+
+    /// Splits a partition, whose first build rows are in the hash table, into partitions
+    /// by a hash seeded with `splits`, to be joined in its place.
+    fn split(&mut self, build: Peekable<Records>, probe: TempFile, splits: usize) -> Result<()> {
+        let nkeys = self.collations.len();
+        let mut build_partitions = Partitions::create(splits)?;
+        for (key, rows) in mem::take(&mut self.table) {
+            for row in rows {
+                build_partitions.write(key.clone(), row)?;
+            }
+        }
+        self.table_bytes = 0;
+        for values in build {
+            let (key, row) = split_key(values?, nkeys);
+            build_partitions.write(key, row)?;
+        }
+
+        let mut probe_partitions = Partitions::create(splits)?;
+        for values in probe.into_records()? {
+            let (key, row) = split_key(values?, nkeys);
+            probe_partitions.write(key, row)?;
+        }
+
+        let partitions = Partition::pair(build_partitions, probe_partitions, splits)?;
+        self.partitions
+            .get_or_insert_with(Vec::new)
+            .extend(partitions);
+        Ok(())
+    }
+}
+
+/// The build and probe rows of one partition, left to join.
+struct Partition {
+    build: TempFile,
+    probe: TempFile,
+    /// How many times the rows were split before they ended up in the partition.
+    splits: usize,
+}
+
+impl Partition {
+    fn pair(build: Partitions, probe: Partitions, splits: usize) -> Result<Vec<Self>> {
+        Ok(build
+            .finish()?
+            .into_iter()
+            .zip(probe.finish()?)
+            .map(|(build, probe)| Self {
+                build,
+                probe,
+                splits,
+            })
+            .collect())
+    }
+}
+
+/// A partition joined a block of build rows at a time: the hash table holds one block
+/// while every probe row is read.
+struct Block {
+    /// The build rows of the blocks after the one in the hash table.
+    build: Peekable<Records>,
+    probe: TempFile,
+    /// Whether each probe row, by position, matched a build row of an earlier block.
+    matched: Vec<bool>,
+    /// How many probe rows were read for the block in the hash table.
+    probed: usize,
+}
+
+impl Block {
+    /// Whether the block in the hash table is the last one.
+    fn is_last(&mut self) -> bool {
+        self.build.peek().is_none()
+    }
+}
+
+fn split_key(mut values: Vec<SerialValue>, nkeys: usize) -> (Vec<SerialValue>, Row) {
+    let row = values.split_off(nkeys.min(values.len()));
+    (values, row)
+}
+
+/// Rows split into temporary files by the hash of their keys, seeded with `seed`.
+struct Partitions {
+    files: Vec<(TempFile, RecordWriter)>,
+    seed: usize,
+}
+
+impl Partitions {
+    fn create(seed: usize) -> Result<Self> {
+        let files = (0..PARTITIONS)
+            .map(|_| {
+                let file = TempFile::create()?;
+                let writer = file.writer()?;
+                Ok((file, writer))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { files, seed })
+    }
+
+    /// Writes a row, preceded by its key, to the partition of the key.
+    fn write(&mut self, mut key: Vec<SerialValue>, row: Row) -> Result<()> {
+        let mut hasher = DefaultHasher::new();
+        self.seed.hash(&mut hasher);
+        key.hash(&mut hasher);
+        let (_, writer) = &mut self.files[hasher.finish() as usize % PARTITIONS];
+
+        key.extend(row);
+        writer.write(&key)
+    }
+
+    fn finish(self) -> Result<Vec<TempFile>> {
+        self.files
+            .into_iter()
+            .map(|(file, mut writer)| {
+                writer.flush()?;
+                Ok(file)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// LEFT joins `probe` with `build` on their first values, compared with NOCASE,
+    /// returning whether the build rows spilled and the joined rows, sorted.
+    fn left_join(build: &[Row], probe: &[Row], memory: usize) -> (bool, Vec<Row>) {
+        let mut join = HashJoin::new(&[Collation::NoCase], None, false, Some(2), memory);
+        for row in build {
+            join.build(vec![row[0].clone()], row.clone()).unwrap();
+        }
+        let mut joined = Vec::new();
+        for row in probe {
+            join.probe(vec![row[0].clone()], row.clone()).unwrap();
+            while let Some(row) = join.next_row().unwrap() {
+                joined.push(row);
+            }
+        }
+        let spilled = join.spilled();
+        join.finish().unwrap();
+        while let Some(row) = join.next_row().unwrap() {
+            joined.push(row);
+        }
+        joined.sort();
+        (spilled, joined)
+    }
+
+    #[test]
+    fn test_hash_join_matches_like_sqlite_and_spills() {
+        let text = |txt: &str| SerialValue::String(txt.to_string());
+        let build: Vec<Row> = (0..200i64)
+            .map(|i| match i % 4 {
+                0 => vec![SerialValue::Float64(i as f64), SerialValue::I64(i)],
+                1 => vec![text(&format!("K{}", i)), SerialValue::I64(i)],
+                2 => vec![SerialValue::Null, SerialValue::I64(i)],
+                _ => vec![SerialValue::I8((i % 100) as i8), SerialValue::I64(i)],
+            })
+            .collect();
+        let probe: Vec<Row> = vec![
+            vec![SerialValue::I64(4)],
+            vec![text("k5")],
+            vec![SerialValue::Null],
+            vec![SerialValue::Float64(3.0)],
+            vec![SerialValue::Float64(4.5)],
+        ];
+
+        let expected = vec![
+            vec![SerialValue::Null, SerialValue::Null, SerialValue::Null],
+            vec![
+                SerialValue::Float64(3.0),
+                SerialValue::I8(3),
+                SerialValue::I64(3),
+            ],
+            vec![
+                SerialValue::Float64(3.0),
+                SerialValue::I8(3),
+                SerialValue::I64(103),
+            ],
+            vec![
+                SerialValue::I64(4),
+                SerialValue::Float64(4.0),
+                SerialValue::I64(4),
+            ],
+            vec![
+                SerialValue::Float64(4.5),
+                SerialValue::Null,
+                SerialValue::Null,
+            ],
+            vec![text("k5"), text("K5"), SerialValue::I64(5)],
+        ];
+        assert_eq!(
+            left_join(&build, &probe, DEFAULT_HASH_MEMORY),
+            (false, expected.clone())
+        );
+        assert_eq!(left_join(&build, &probe, 256), (true, expected));
+    }
+
+    #[test]
+    fn test_hash_join_skewed_key() {
+        // Most build rows share the key 7, which no split can spread over partitions, so
+        // its partition is joined in blocks.
+        let build: Vec<Row> = (0..600i64)
+            .map(|i| {
+                let key = if i < 500 { 7 } else { i - 500 };
+                vec![SerialValue::I64(key), SerialValue::I64(i)]
+            })
+            .collect();
+        let probe: Vec<Row> = [7, 8, 7, 1000]
+            .into_iter()
+            .map(|key| vec![SerialValue::I64(key)])
+            .collect();
+
+        let (spilled, joined) = left_join(&build, &probe, 1024);
+        assert!(spilled);
+        assert_eq!(joined, left_join(&build, &probe, DEFAULT_HASH_MEMORY).1);
+
+        let count = |key: i64| {
+            joined
+                .iter()
+                .filter(|row| row[0] == SerialValue::I64(key))
+                .count()
+        };
+        assert_eq!(count(7), 2 * 501);
+        assert_eq!(count(8), 1);
+        assert_eq!(
+            joined.last(),
+            Some(&vec![
+                SerialValue::I64(1000),
+                SerialValue::Null,
+                SerialValue::Null
+            ])
+        );
+    }
+}
@@ -0,0 +1,836 @@
+use std::{cmp::Ordering, collections::HashMap};
+
+use crate::{
+    btree::{compare_prefix, BTreeCursor, KeyOrder},
+    cell::local_payload_size,
+    column::{Affinity, Collation, SerialValue},
+    database::Database,
+    decode_varint,
+    error::Result,
+    page::PageType,
+    schema::{Index, Schema, Table},
+};
+
+/// The page holding the byte at offset 2^30 is never used, so that the file can be locked
+/// there on systems with mandatory locks.
+const PENDING_BYTE: usize = 0x4000_0000;
+
+/// The kinds of pages a pointer map entry describes, with the page that refers to them.
+const ROOT_PAGE: u8 = 1;
+const FREE_PAGE: u8 = 2;
+const FIRST_OVERFLOW: u8 = 3;
+const OVERFLOW: u8 = 4;
+const BTREE_PAGE: u8 = 5;
+
+/// Checks the database the way `PRAGMA integrity_check` does, returning its rows: one
+/// listing the problems found in the b-trees and the freelist, followed by one per problem
+/// found in the content of the tables and indexes, or just "ok". At most `max_errors`
+/// problems are reported. A `quick` check, as `PRAGMA quick_check` runs, does not compare
+/// the indexes with the rows of their tables.
+///
+/// The messages are those of sqlite3. Rows that cannot be decoded are reported, and index
+/// entries that cannot be decoded leave their rows missing from the index. The content
+/// checks stop at the first b-tree found to have problems that cannot be read, where
+/// sqlite3 fails with an error. CHECK
+/// constraints, WITHOUT ROWID tables and indexes on expressions and partial indexes are
+/// not checked against the rows of their tables.
+pub fn integrity_check(db: &Database, max_errors: usize, quick: bool) -> Result<Vec<String>> {
+    let schema = db.schema()?;
+    let objects = newest_first(&schema);
+    let mut checker = Checker::new(db, max_errors);
+
+    let mut roots = Vec::new();
+    for (table, indexes) in &objects {
+        roots.push(table.root_page);
+        roots.extend(indexes.iter().map(|index| index.root_page));
+    }
+    let trees = checker.btrees(&roots)?;
+
+    let mut rows = Vec::new();
+    if !checker.messages.is_empty() {
+        rows.push(format!(
+            "*** in database main ***\n{}",
+            checker.messages.join("\n")
+        ));
+    }
+    let mut content = Content {
+        db,
+        trees,
+        rows,
+        remaining: checker.remaining,
+    };
+    content.check(&objects, quick);
+
+    if content.rows.is_empty() {
+        content.rows.push("ok".to_string());
+    }
+    Ok(content.rows)
+}
+
+/// The tables with a b-tree and their indexes, newest first, the order sqlite3 checks a
+/// schema of fewer than ten tables in.
+fn newest_first(schema: &Schema) -> Vec<(&Table, Vec<&Index>)> {
+    schema
+        .tables
+        .iter()
+        .rev()
+        .filter(|table| table.root_page != 0)
+        .map(|table| {
+            let mut indexes: Vec<&Index> = schema.indexes(&table.name).collect();
+            indexes.reverse();
+            (table, indexes)
+        })
+        .collect()
+}
+
+/// The number of entries found in a b-tree, and whether no problems were found in it.
+#[derive(Debug, Clone, Copy)]
+struct Tree {
+    entries: usize,
+    intact: bool,
+}
+
+/// What the problems found are reported about, as sqlite3 prefixes their messages with
+/// the root page, page and cell being checked.
+#[derive(Debug, Clone, Copy)]
+enum Prefix {
+    None,
+    Freelist,
+    Page,
+    Cell,
+    /// Used instead of `Cell` for interior pages of databases with pointer maps.
+    RightChild,
+}
+
+/// Walks the b-trees and the freelist, keeping track of the pages they reach.
+struct Checker<'a> {
+    db: &'a Database,
+    usable_size: usize,
+    /// Whether each page, by number, was reached.
+    referenced: Vec<bool>,
+    messages: Vec<String>,
+    /// How many more problems may be reported.
+    remaining: usize,
+    prefix: Prefix,
+    /// The root page of the b-tree being checked.
+    root: usize,
+    page: u32,
+    cell: usize,
+    /// The entries found so far in the b-tree being checked.
+    entries: usize,
+    /// Whether the database has pointer map pages, which record the page that refers to
+    /// each page.
+    auto_vacuum: bool,
+    /// The page holding the byte at [`PENDING_BYTE`].
+    pending: usize,
+}
+
+impl<'a> Checker<'a> {
+    fn new(db: &'a Database, max_errors: usize) -> Self {
+        Self {
+            db,
+            usable_size: db.header.usable_size(),
+            referenced: vec![false; db.page_count() + 1],
+            messages: Vec::new(),
+            remaining: max_errors,
+            prefix: Prefix::None,
+            root: 0,
+            page: 0,
+            cell: 0,
+            entries: 0,
+            // Such databases record their largest root page.
+            auto_vacuum: db.header.largest_root_page != 0,
+            pending: PENDING_BYTE / db.page_size() + 1,
+        }
+    }
+
+    fn report(&mut self, message: String) {
+        if self.remaining == 0 {
+            return;
+        }
+        self.remaining -= 1;
+
+        let message = match self.prefix {
+            Prefix::None => message,
+            Prefix::Freelist => format!("Freelist: {}", message),
+            Prefix::Page => format!("Tree {} page {}: {}", self.root, self.page, message),
+            Prefix::Cell => format!(
+                "Tree {} page {} cell {}: {}",
+                self.root, self.page, self.cell, message
+            ),
+            Prefix::RightChild => format!(
+                "Tree {} page {} right child: {}",
+                self.root, self.page, message
+            ),
+        };
+        self.messages.push(message);
+    }
+
+    /// Checks the freelist and the b-trees rooted at `roots`, then that every page was
+    /// reached exactly once.
+    fn btrees(&mut self, roots: &[usize]) -> Result<HashMap<usize, Tree>> {
+        let npages = self.referenced.len() - 1;
+        if self.pending <= npages {
+            self.referenced[self.pending] = true;
+        }
+
+        let header = &self.db.header;
+        let (trunk, free_pages) = (header.first_freelist_trunk_page, header.freelist_count);
+        self.prefix = Prefix::Freelist;
+        self.list(true, trunk, free_pages)?;
+        self.prefix = Prefix::None;
+
+        let largest_root = self.db.header.largest_root_page;
+        if self.auto_vacuum {
+            let max = roots.iter().copied().max().unwrap_or_default();
+            if max != largest_root as usize {
+                self.report(format!(
+                    "max rootpage ({}) disagrees with header ({})",
+                    max, largest_root
+                ));
+            }
+        } else if self.db.header.incremental_vacuum != 0 {
+            self.report("incremental_vacuum enabled with a max rootpage of zero".to_string());
+        }
+
+        let mut trees = HashMap::new();
+        for &root in roots {
+            if self.remaining == 0 {
+                break;
+            }
+            let errors = self.messages.len();
+            if self.auto_vacuum && root > 1 {
+                self.pointer_map(root as u32, ROOT_PAGE, 0)?;
+            }
+            self.root = root;
+            self.entries = 0;
+            let mut max_key = i64::MAX;
+            self.tree_page(root as u32, &mut max_key)?;
+            trees.insert(
+                root,
+                Tree {
+                    entries: self.entries,
+                    intact: self.messages.len() == errors,
+                },
+            );
+        }
+
+        for page in 1..=npages {
+            if self.remaining == 0 {
+                break;
+            }
+            let pointer_map = self.auto_vacuum && self.pointer_map_page(page) == page;
+            match (self.referenced[page], pointer_map) {
+                (false, false) => self.report(format!("Page {}: never used", page)),
+                (true, true) => self.report(format!("Page {}: pointer map referenced", page)),
+                _ => {}
+            }
+        }
+
+        Ok(trees)
+    }
+
+    /// The pointer map page that holds the entry of `page`, or 0 for page 1.
+    fn pointer_map_page(&self, page: usize) -> usize {
+        if page < 2 {
+            return 0;
+        }
+        let per_page = self.usable_size / 5 + 1;
+        let map = (page - 2) / per_page * per_page + 2;
+        map + (map == self.pending) as usize
+    }
+
+    /// Checks that the pointer map entry of `page` describes it as a page of `kind`
+    /// referred to by `parent`.
+    fn pointer_map(&mut self, page: u32, kind: u8, parent: u32) -> Result<()> {
+        let map = self.pointer_map_page(page as usize);
+        let entry = match map {
+            _ if map == 0 || map >= page as usize || map >= self.referenced.len() => None,
+            _ => {
+                let data = self.db.raw_page(map)?;
+                let offset = 5 * (page as usize - map - 1);
+                Some((data[offset], u32_at(&data, offset + 1)))
+            }
+        };
+        match entry {
+            Some(found) if found == (kind, parent) => {}
+            Some((found_kind @ 1..=5, found_parent)) => self.report(format!(
+                "Bad ptr map entry key={} expected=({},{}) got=({},{})",
+                page, kind, parent, found_kind, found_parent
+            )),
+            _ => self.report(format!("Failed to read ptrmap key={}", page)),
+        }
+        Ok(())
+    }
+
+    /// Marks `page` as reached, returning false if it does not exist or was reached before.
+    fn reference(&mut self, page: u32) -> bool {
+        let Some(referenced) = self.referenced.get_mut(page as usize).filter(|_| page != 0) else {
+            self.report(format!("invalid page number {}", page));
+            return false;
+        };
+        if *referenced {
+            self.report(format!("2nd reference to page {}", page));
+            return false;
+        }
+        *referenced = true;
+        true
+    }
+
+    /// Follows a list of `expected` pages from `page`: the trunk pages of the freelist and
+    /// the leaf pages they list, or the pages of an overflow chain.
+    fn list(&mut self, freelist: bool, mut page: u32, expected: u32) -> Result<()> {
+        let errors = self.messages.len();
+        let mut missing = expected;
+        while page != 0 && self.remaining > 0 {
+            if !self.reference(page) {
+                break;
+            }
+            missing = missing.wrapping_sub(1);
+
+            let data = self.db.raw_page(page as usize)?;
+            let next = u32_at(&data, 0);
+            if freelist {
+                if self.auto_vacuum {
+                    self.pointer_map(page, FREE_PAGE, 0)?;
+                }
+                let leaves = u32_at(&data, 4);
+                if leaves as usize > self.usable_size / 4 - 2 {
+                    self.report(format!("freelist leaf count too big on page {}", page));
+                    missing = missing.wrapping_sub(1);
+                } else {
+                    for i in 0..leaves as usize {
+                        let leaf = u32_at(&data, 8 + i * 4);
+                        if self.auto_vacuum {
+                            self.pointer_map(leaf, FREE_PAGE, 0)?;
+                        }
+                        self.reference(leaf);
+                    }
+                    missing = missing.wrapping_sub(leaves);
+                }
+            } else if self.auto_vacuum && missing > 0 {
+                self.pointer_map(next, OVERFLOW, page)?;
+            }
+            page = next;
+        }
+
+        if missing != 0 && self.messages.len() == errors {
+            self.report(format!(
+                "{} is {} but should be {}",
+                if freelist {
+                    "size"
+                } else {
+                    "overflow list length"
+                },
+                expected.wrapping_sub(missing),
+                expected
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks the b-tree page `page` and the pages below it, returning the depth of the
+    /// subtree, 0 for a leaf. The rowids of a table b-tree must not exceed `max_key`, which
+    /// is lowered to the smallest rowid found.
+    fn tree_page(&mut self, page: u32, max_key: &mut i64) -> Result<i32> {
+        if page == 0 || self.remaining == 0 || !self.reference(page) {
+            return Ok(0);
+        }
+
+        let saved = (self.prefix, self.page, self.cell);
+        self.prefix = Prefix::Page;
+        self.page = page;
+        let depth = self.cells(page, max_key);
+        (self.prefix, self.page, self.cell) = saved;
+        Ok(depth? + 1)
+    }
+
+    fn cells(&mut self, page: u32, max_key: &mut i64) -> Result<i32> {
+        let data = self.db.raw_page(page as usize)?;
+        let usable = self.usable_size;
+        let header = if page == 1 { 100 } else { 0 };
+
+        let page_type = PageType::try_from(data[header])?;
+        let ncells = u16_at(&data, header + 3) as usize;
+        if page_type == PageType::PageError || ncells > (self.db.page_size() - 8) / 6 {
+            self.report("btreeInitPage() returns error code 11".to_string());
+            return Ok(-1);
+        }
+        let leaf = matches!(page_type, PageType::LeafTable | PageType::LeafIndex);
+        let table = matches!(page_type, PageType::LeafTable | PageType::InteriorTable);
+        let pointers = header + if leaf { 8 } else { 12 };
+        let content_start = match u16_at(&data, header + 5) {
+            0 => 65536,
+            start => start as usize,
+        };
+        if !free_space_is_valid(&data, header, pointers + 2 * ncells, content_start, usable) {
+            self.report("free space corruption".to_string());
+            return Ok(-1);
+        }
+
+        if leaf || !table {
+            self.entries += ncells;
+        }
+
+        self.prefix = Prefix::Cell;
+        let mut depth = -1;
+        let mut key_can_be_equal = true;
+        if !leaf {
+            let child = u32_at(&data, header + 8);
+            if self.auto_vacuum {
+                self.prefix = Prefix::RightChild;
+                self.pointer_map(child, BTREE_PAGE, page)?;
+            }
+            depth = self.tree_page(child, max_key)?;
+            key_can_be_equal = false;
+        }
+
+        // The bytes used by each cell, to check that no byte is used twice.
+        let mut spans = Vec::with_capacity(ncells);
+        let mut check_coverage = true;
+        for i in (0..ncells).rev() {
+            if self.remaining == 0 {
+                break;
+            }
+            self.cell = i;
+
+            let offset = u16_at(&data, pointers + 2 * i) as usize;
+            if offset < content_start || offset > usable - 4 {
+                self.report(format!(
+                    "Offset {} out of range {}..{}",
+                    offset,
+                    content_start,
+                    usable - 4
+                ));
+                check_coverage = false;
+                continue;
+            }
+            let Some(cell) = CellInfo::parse(&data, offset, page_type, usable)
+                .filter(|cell| offset + cell.size <= usable)
+            else {
+                self.report("Extends off end of page".to_string());
+                check_coverage = false;
+                continue;
+            };
+
+            if table {
+                let out_of_order = match key_can_be_equal {
+                    true => cell.key > *max_key,
+                    false => cell.key >= *max_key,
+                };
+                if out_of_order {
+                    self.report(format!("Rowid {} out of order", cell.key));
+                }
+                *max_key = cell.key;
+                key_can_be_equal = false;
+            }
+
+            if cell.payload > cell.local {
+                let pages = (cell.payload - cell.local + usable - 5) / (usable - 4);
+                let first = u32_at(&data, offset + cell.size - 4);
+                if self.auto_vacuum {
+                    self.pointer_map(first, FIRST_OVERFLOW, page)?;
+                }
+                self.list(false, first, pages as u32)?;
+            }
+
+            if !leaf {
+                let child = u32_at(&data, offset);
+                if self.auto_vacuum {
+                    self.pointer_map(child, BTREE_PAGE, page)?;
+                }
+                let child_depth = self.tree_page(child, max_key)?;
+                key_can_be_equal = false;
+                if child_depth != depth {
+                    self.report("Child page depth differs".to_string());
+                    depth = child_depth;
+                }
+            }
+            spans.push((offset, offset + cell.size - 1));
+        }
+
+        self.prefix = Prefix::None;
+        if check_coverage && self.remaining > 0 {
+            let mut free = u16_at(&data, header + 1) as usize;
+            while free > 0 {
+                let size = u16_at(&data, free + 2) as usize;
+                spans.push((free, free + size - 1));
+                free = u16_at(&data, free) as usize;
+            }
+            spans.sort_unstable();
+
+            // Bytes that belong to no cell or freeblock are fragments, which the header
+            // counts.
+            let mut fragmented = 0;
+            let mut end = content_start - 1;
+            let mut overlap = None;
+            for (i, &(start, span_end)) in spans.iter().enumerate() {
+                if end >= start {
+                    self.report(format!("Multiple uses for byte {} of page {}", start, page));
+                    overlap = Some(i);
+                    break;
+                }
+                fragmented += start - end - 1;
+                end = span_end;
+            }
+            fragmented += usable - end - 1;
+
+            let reported = data[header + 7] as usize;
+            let all_spans = overlap.is_none_or(|i| i + 1 == spans.len());
+            if all_spans && fragmented != reported {
+                self.report(format!(
+                    "Fragmentation of {} bytes reported as {} on page {}",
+                    fragmented, reported, page
+                ));
+            }
+        }
+
+        Ok(depth)
+    }
+}
+
+/// Whether the freeblocks of a b-tree page are in ascending order and within the page, and
+/// the free space they and the header describe lies between the end of the cell pointer
+/// array at `pointers_end` and the end of the page.
+fn free_space_is_valid(
+    data: &[u8],
+    header: usize,
+    pointers_end: usize,
+    content_start: usize,
+    usable: usize,
+) -> bool {
+    let mut free = data[header + 7] as usize + content_start;
+    let mut offset = u16_at(data, header + 1) as usize;
+    if offset > 0 {
+        if offset < content_start {
+            return false;
+        }
+        loop {
+            if offset > usable - 4 {
+                return false;
+            }
+            let next = u16_at(data, offset) as usize;
+            let size = u16_at(data, offset + 2) as usize;
+            free += size;
+            if next <= offset + size + 3 {
+                if next > 0 || offset + size > usable {
+                    return false;
+                }
+                break;
+            }
+            offset = next;
+        }
+    }
+
+    (pointers_end..=usable).contains(&free)
+}
+
+/// The sizes of a cell, read the way SQLite parses cells.
+struct CellInfo {
+    /// The bytes the cell takes up on the page, at least 4.
+    size: usize,
+    /// The rowid of a table b-tree cell.
+    key: i64,
+    payload: usize,
+    /// The bytes of the payload stored on the page.
+    local: usize,
+}
+
+impl CellInfo {
+    fn parse(data: &[u8], offset: usize, page_type: PageType, usable: usize) -> Option<Self> {
+        let varint = |at: usize| decode_varint(data.get(at..)?).ok();
+
+        if page_type == PageType::InteriorTable {
+            let (key, len) = varint(offset + 4)?;
+            return Some(Self {
+                size: 4 + len,
+                key,
+                payload: 0,
+                local: 0,
+            });
+        }
+
+        let mut end = offset + (page_type == PageType::InteriorIndex) as usize * 4;
+        let (payload, len) = varint(end)?;
+        end += len;
+        let mut key = payload;
+        if page_type == PageType::LeafTable {
+            let (rowid, len) = varint(end)?;
+            end += len;
+            key = rowid;
+        }
+
+        let payload = payload as u32 as usize;
+        let local = local_payload_size(&page_type, payload, usable);
+        let overflow = if local < payload { 4 } else { 0 };
+        Some(Self {
+            size: (end - offset + local + overflow).max(4),
+            key,
+            payload,
+            local,
+        })
+    }
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([data[offset], data[offset + 1]])
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+/// Checks the rows of the tables against their constraints and indexes.
+struct Content<'a> {
+    db: &'a Database,
+    trees: HashMap<usize, Tree>,
+    rows: Vec<String>,
+    /// How many more problems may be reported.
+    remaining: usize,
+}
+
+impl Content<'_> {
+    /// Adds a row, returning false once no more may be added.
+    fn report(&mut self, row: String) -> bool {
+        self.rows.push(row);
+        self.remaining = self.remaining.saturating_sub(1);
+        self.remaining > 0
+    }
+
+    fn check(&mut self, objects: &[(&Table, Vec<&Index>)], quick: bool) {
+        if self.remaining == 0 {
+            return;
+        }
+
+        for (table, indexes) in objects {
+            let Some(&tree) = self.trees.get(&table.root_page) else {
+                continue;
+            };
+            for index in indexes.iter().filter(|index| !index.partial) {
+                let entries = self.trees.get(&index.root_page).map(|tree| tree.entries);
+                if entries.is_some_and(|entries| entries != tree.entries)
+                    && !self.report(format!("wrong # of entries in index {}", index.name))
+                {
+                    return;
+                }
+            }
+        }
+
+        for (table, indexes) in objects.iter().filter(|(table, _)| !table.without_rowid) {
+            let intact = indexes
+                .iter()
+                .map(|index| index.root_page)
+                .chain([table.root_page])
+                .all(|root| self.trees.get(&root).is_some_and(|tree| tree.intact));
+            let indexes: Vec<(&Index, Vec<(usize, KeyOrder)>)> = indexes
+                .iter()
+                .filter(|index| !quick && !index.partial && !index.columns.is_empty())
+                .map(|index| (*index, index.key_columns(table)))
+                .filter(|(index, key)| key.len() == index.columns.len())
+                .collect();
+            if !self.rows(table, &indexes, intact) {
+                return;
+            }
+        }
+    }
+
+    /// Checks the rows of `table` and, for each row, that `indexes` hold it under the key
+    /// given by the columns and orders with it. Rows that cannot be decoded are reported,
+    /// unless the b-trees were found to have problems, which were reported already and at
+    /// which the check stops. Returns false once no more problems may be reported.
+    fn rows(
+        &mut self,
+        table: &Table,
+        indexes: &[(&Index, Vec<(usize, KeyOrder)>)],
+        intact: bool,
+    ) -> bool {
+        for (k, entry) in BTreeCursor::new(self.db, table.root_page)
+            .entries()
+            .enumerate()
+        {
+            let k = k + 1;
+            // The cursor moves on past a row whose record cannot be decoded, and stops
+            // if it cannot move.
+            let (rowid, record) = match entry {
+                Ok(entry) => entry,
+                Err(_) if !intact => return false,
+                Err(err) => {
+                    let message = format!("row {} of {} cannot be read: {}", k, table.name, err);
+                    if !self.report(message) {
+                        return false;
+                    }
+                    continue;
+                }
+            };
+            let value = |i: usize| match record.columns.get(i) {
+                _ if Some(i) == table.rowid_alias => SerialValue::I64(rowid),
+                Some(column) => column.data().clone(),
+                None => SerialValue::Null,
+            };
+
+            for (i, column) in table.columns.iter().enumerate() {
+                let Some(stored) = record.columns.get(i).map(|column| column.data()) else {
+                    continue;
+                };
+                if Some(i) == table.rowid_alias {
+                    continue;
+                }
+                let affinity = column.affinity();
+                let kind = match stored {
+                    SerialValue::Null if column.not_null => "NULL",
+                    SerialValue::Null | SerialValue::Blob(_) => continue,
+                    // Text that looks like a number is converted when it is stored in a
+                    // column with numeric affinity.
+                    SerialValue::String(_) if affinity.is_numeric() => {
+                        match stored.apply_affinity(Affinity::Numeric) {
+                            SerialValue::String(_) => continue,
+                            _ => "TEXT",
+                        }
+                    }
+                    SerialValue::String(_) => continue,
+                    _ if affinity == Affinity::Text => "NUMERIC",
+                    _ => continue,
+                };
+                let message = format!("{} value in {}.{}", kind, table.name, column.name);
+                if !self.report(message) {
+                    return false;
+                }
+            }
+
+            for (index, key_columns) in indexes {
+                let mut key: Vec<SerialValue> =
+                    key_columns.iter().map(|&(i, _)| value(i)).collect();
+                key.push(SerialValue::I64(rowid));
+                let order: Vec<KeyOrder> = key_columns.iter().map(|&(_, order)| order).collect();
+
+                // An entry that cannot be decoded holds no row, as in sqlite3.
+                let mut cursor = BTreeCursor::new(self.db, index.root_page);
+                let entry = match cursor.seek_key(&key, &order) {
+                    Ok(true) => cursor.record().ok(),
+                    Ok(false) | Err(_) => None,
+                };
+                // An entry missing columns is equal to every key it is a prefix of.
+                let Some(entry) = entry.filter(|entry| entry.columns.len() == key.len()) else {
+                    if !self.report(format!("row {} missing from index {}", k, index.name)) {
+                        return false;
+                    }
+                    continue;
+                };
+
+                // Values equal under other collations must still be stored as they are
+                // in the table.
+                let differs = entry
+                    .columns
+                    .iter()
+                    .zip(&key)
+                    .zip(&order)
+                    .filter(|(_, order)| order.collation != Collation::Binary)
+                    .any(|((stored, value), _)| {
+                        Collation::Binary.compare(stored.data(), value).is_ne()
+                    });
+                if differs
+                    && !self.report(format!("row {} values differ from index {}", k, index.name))
+                {
+                    return false;
+                }
+
+                // Keys with a NULL are never equal to another, so only the others must be
+                // unique.
+                let nkey = key_columns.len();
+                let has_null = key[..nkey]
+                    .iter()
+                    .any(|value| matches!(value, SerialValue::Null));
+                // A following entry that cannot be decoded is reported as missing from the
+                // index when its own row is checked.
+                if index.unique
+                    && !has_null
+                    && cursor.next().unwrap_or(false)
+                    && cursor.record().is_ok_and(|next| {
+                        compare_prefix(&next, &key[..nkey], &order) == Ordering::Equal
+                    })
+                    && !self.report(format!("non-unique entry in index {}", index.name))
+                {
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_integrity_check() {
+        let db = Database::open("sample.db").unwrap();
+        assert_eq!(integrity_check(&db, 100, false).unwrap(), vec!["ok"]);
+        assert_eq!(integrity_check(&db, 100, true).unwrap(), vec!["ok"]);
+    }
+
+    #[test]
+    fn test_integrity_check_corrupt() {
+        let mut bytes = std::fs::read("sample.db").unwrap();
+        // Claim a freelist page, fragment bytes on page 2 and move page 4's
+        // cell content area past its cells.
+        bytes[39] = 1;
+        bytes[4096 + 7] = 5;
+        bytes[3 * 4096 + 5..3 * 4096 + 7].copy_from_slice(&4000u16.to_be_bytes());
+        let path = std::env::temp_dir().join(format!("integrity-{}.db", std::process::id()));
+        std::fs::write(&path, bytes).unwrap();
+        let db = Database::open(&path).unwrap();
+
+        let messages = [
+            "Freelist: size is 0 but should be 1",
+            "Tree 4 page 4 cell 5: Offset 3864 out of range 4000..4092",
+            "Tree 4 page 4 cell 4: Offset 3910 out of range 4000..4092",
+            "Tree 4 page 4 cell 3: Offset 3947 out of range 4000..4092",
+            "Tree 4 page 4 cell 2: Offset 3999 out of range 4000..4092",
+            "Fragmentation of 0 bytes reported as 5 on page 2",
+        ];
+        let report =
+            |messages: &[&str]| vec![format!("*** in database main ***\n{}", messages.join("\n"))];
+        assert_eq!(integrity_check(&db, 100, false).unwrap(), report(&messages));
+        assert_eq!(
+            integrity_check(&db, 2, false).unwrap(),
+            report(&messages[..2])
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        // Point the entry of 'y' in sqlite_autoindex_p_1 at rowid 5 instead of 2.
+        let mut bytes = std::fs::read("indexed.db").unwrap();
+        assert_eq!(&bytes[110582..110587], b"\x03\x0f\x01y\x02");
+        bytes[110586] = 5;
+        std::fs::write(&path, bytes).unwrap();
+        let db = Database::open(&path).unwrap();
+        assert_eq!(
+            integrity_check(&db, 100, false).unwrap(),
+            ["row 2 missing from index sqlite_autoindex_p_1"]
+        );
+        assert_eq!(integrity_check(&db, 100, true).unwrap(), ["ok"]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_integrity_check_corrupt_record() {
+        // Replace the 'y' of its entry in sqlite_autoindex_p_1 with a byte that is not
+        // UTF-8, so that the entry cannot be decoded.
+        let mut bytes = std::fs::read("indexed.db").unwrap();
+        assert_eq!(&bytes[110582..110587], b"\x03\x0f\x01y\x02");
+        bytes[110585] = 0xff;
+        let path = std::env::temp_dir().join(format!("integrity-record-{}.db", std::process::id()));
+        std::fs::write(&path, bytes).unwrap();
+        let db = Database::open(&path).unwrap();
+        let report = integrity_check(&db, 100, false).unwrap();
+        assert!(report.contains(&"row 2 missing from index sqlite_autoindex_p_1".to_string()));
+        assert_eq!(integrity_check(&db, 1, false).unwrap().len(), 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,41 +1,115 @@
+pub mod aggregate;
+pub mod btree;
 pub mod cell;
+pub mod codegen;
 pub mod column;
+pub mod connection;
 pub mod database;
+pub mod de;
+pub mod error;
+pub mod expr;
+pub mod hash_join;
+pub mod integrity;
 pub mod page;
+pub mod pager;
+pub mod query;
 pub mod record;
+pub mod schema;
+pub mod sort;
 pub mod sql;
+pub mod stat;
+pub mod temp;
+pub mod vdbe;
 
-pub fn decode_varint(bytes: &[u8]) -> anyhow::Result<(i64, usize)> {
-    // if bytes.is_empty() || bytes.len() > 9 {
-    //     return Err(anyhow::Error::msg(format!(
-    //         "invalid varint: {:?}",
-    //         &bytes[0..9]
-    //     )));
-    // }
+pub use connection::{Connection, Row, Rows, Statement};
+pub use error::{Error, Result};
 
-    let mut result = 0;
-    let mut bytes_read = 0;
+use error::bail;
 
-    for (i, &byte) in bytes.iter().take(8).enumerate() {
-        bytes_read += 1;
+/// Decodes the SQLite varint at the start of `bytes`, returning its value and length.
+///
+/// A varint is 1 to 9 bytes long. The first eight bytes contribute their lower seven bits
+/// and have the high bit set if another byte follows; a ninth byte contributes all eight bits.
+pub fn decode_varint(bytes: &[u8]) -> Result<(i64, usize)> {
+    let mut result: u64 = 0;
 
-        let has_more = i != 8 && (byte & 0b10000000 != 0);
+    for (i, &byte) in bytes.iter().take(9).enumerate() {
+        if i == 8 {
+            result = (result << 8) | byte as u64;
+            return Ok((result as i64, 9));
+        }
+
+        result = (result << 7) | (byte & 0b01111111) as u64;
+        if byte & 0b10000000 == 0 {
+            return Ok((result as i64, i + 1));
+        }
+    }
+
+    bail!(Corrupt(0, 0), "truncated varint: {:02x?}", bytes)
+}
+
+/// The `len` bytes at `offset` of `bytes`, failing as corrupt if they run past its end.
+pub(crate) fn read_bytes(bytes: &[u8], offset: usize, len: usize) -> Result<&[u8]> {
+    match offset
+        .checked_add(len)
+        .and_then(|end| bytes.get(offset..end))
+    {
+        Some(bytes) => Ok(bytes),
+        None => bail!(
+            Corrupt(0, offset),
+            "{} bytes at offset {} run past the end of {} bytes",
+            len,
+            offset,
+            bytes.len()
+        ),
+    }
+}
+
+/// The `N` bytes at `offset` of `bytes`, as for [`read_bytes`].
+pub(crate) fn read_array<const N: usize>(bytes: &[u8], offset: usize) -> Result<[u8; N]> {
+    let mut array = [0; N];
+    array.copy_from_slice(read_bytes(bytes, offset, N)?);
+    Ok(array)
+}
+
+/// Decodes the varint at `offset` of `bytes`, as [`decode_varint`] does, placing errors at
+/// that offset.
+pub(crate) fn decode_varint_at(bytes: &[u8], offset: usize) -> Result<(i64, usize)> {
+    decode_varint(bytes.get(offset..).unwrap_or_default()).map_err(|err| err.at(0, offset))
+}
+
+/// Encodes `value` as a SQLite varint, the inverse of [`decode_varint`].
+pub fn encode_varint(value: i64) -> Vec<u8> {
+    let mut value = value as u64;
+
+    if value >> 56 != 0 {
+        let mut bytes = vec![0; 9];
+        bytes[8] = value as u8;
+        value >>= 8;
+        for byte in bytes[..8].iter_mut().rev() {
+            *byte = (value & 0b01111111) as u8 | 0b10000000;
+            value >>= 7;
+        }
+        return bytes;
+    }
 
-        if has_more {
-            result = (result << 7) | ((byte & 0b01111111) as i64);
-        } else {
-            result = (result << 7) | (byte as i64);
-            bytes_read = i + 1;
+    let mut bytes = Vec::with_capacity(9);
+    loop {
+        bytes.push((value & 0b01111111) as u8 | 0b10000000);
+        value >>= 7;
+        if value == 0 {
             break;
         }
     }
+    bytes[0] &= 0b01111111;
+    bytes.reverse();
 
-    Ok((result, bytes_read))
+    bytes
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::decode_varint;
+    use crate::{decode_varint, encode_varint};
 
     #[test]
     fn test_decode_varint() {
@@ -45,4 +119,47 @@ mod tests {
 
         assert_eq!(val, 129);
     }
+
+    #[test]
+    fn test_decode_varint_nine_bytes() {
+        let (val, len) = decode_varint(&[0xff; 9]).unwrap();
+        assert_eq!((val, len), (-1, 9));
+
+        let (val, len) =
+            decode_varint(&[0x81, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x00]).unwrap();
+        assert_eq!((val, len), (1 << 57, 9));
+    }
+
+    #[test]
+    fn test_decode_varint_truncated() {
+        assert!(decode_varint(&[]).is_err());
+        assert!(decode_varint(&[0x81, 0x82]).is_err());
+    }
+
+    #[test]
+    fn test_encode_varint_round_trip() {
+        let values = [
+            0,
+            1,
+            127,
+            128,
+            240,
+            16383,
+            16384,
+            (1 << 56) - 1,
+            1 << 56,
+            i64::MAX,
+            i64::MIN,
+            -1,
+        ];
+
+        for value in values {
+            let bytes = encode_varint(value);
+            assert_eq!(decode_varint(&bytes).unwrap(), (value, bytes.len()));
+        }
+
+        assert_eq!(encode_varint(129), vec![0x81, 0x01]);
+        assert_eq!(encode_varint((1 << 56) - 1).len(), 8);
+        assert_eq!(encode_varint(-1).len(), 9);
+    }
 }
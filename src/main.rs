@@ -1,12 +1,17 @@
-use std::{collections::HashSet, time::Instant};
+use std::io::{BufWriter, Write};
 
 use anyhow::{bail, Result};
-use sqlite_starter_rust::{column::SerialValue, database::Database, sql::Sql};
+use sqlite_starter_rust::{
+    btree::BTreeCursor,
+    column::{DisplayOptions, OutputMode, SerialValue},
+    sql::Explain,
+    Connection,
+};
 
 fn main() -> Result<()> {
-    let now = Instant::now();
     // Parse arguments
-    let args = std::env::args().collect::<Vec<_>>();
+    let mut args = std::env::args().collect::<Vec<_>>();
+    let options = parse_options(&mut args)?;
     match args.len() {
         0 | 1 => bail!("Missing <database path> and <command>"),
         2 => bail!("Missing <command>"),
@@ -14,180 +19,137 @@ fn main() -> Result<()> {
     }
 
     let file_path = &args[1];
-    let db = Database::read_file(file_path)?;
+    let conn = Connection::open(file_path)?;
+    let db = conn.database();
     // Parse command and act accordingly
     let command = &args[2];
     match command.as_str() {
         ".dbinfo" => {
-            println!("database page size: {}", db.page_size());
-
-            if let Some(first_page) = db.pages.get(0) {
-                println!("number of tables: {}", first_page.btree_header.ncells());
-            }
-        }
-        ".tables" => match db.pages.get(0) {
-            Some(first_page) => {
-                let mut tables = String::new();
-                for i in 0..first_page.btree_header.ncells() {
-                    if let Ok((_, Some(record))) = first_page.read_cell(i) {
-                        match record.columns[0].data() {
-                            SerialValue::String(ref str) => {
-                                if str != "table" {
-                                    continue;
-                                }
-                            }
-                            _ => {}
-                        }
-
-                        let tbl_name = match record.columns[2].data() {
-                            SerialValue::String(ref str) => {
-                                if str == "sqlite_sequence" {
-                                    continue;
-                                }
-                                &str
-                            }
-                            _ => "",
-                        };
-
-                        tables.push_str(&format!("{} ", tbl_name));
-                    };
+            let header = &db.header;
+
+            let (mut ntables, mut nindexes, mut ntriggers, mut nviews) = (0, 0, 0, 0);
+            let mut schema_size = 0;
+            for entry in BTreeCursor::new(db, 1).entries() {
+                let (_, record) = entry?;
+                if let Some(SerialValue::String(kind)) =
+                    record.columns.first().map(|column| column.data())
+                {
+                    match kind.as_str() {
+                        "table" => ntables += 1,
+                        "index" => nindexes += 1,
+                        "trigger" => ntriggers += 1,
+                        "view" => nviews += 1,
+                        _ => {}
+                    }
+                }
+                if let Some(SerialValue::String(sql)) =
+                    record.columns.get(4).map(|column| column.data())
+                {
+                    schema_size += sql.chars().count();
                 }
-                println!("{tables}");
             }
-            None => eprintln!("can not read first page"),
-        },
-        query if query.to_lowercase().starts_with("select count(*)") => {
-            let select_statement = Sql::from_str(query);
 
-            if let Some(first_page) = db.pages.get(0) {
-                for i in 0..first_page.btree_header.ncells() {
-                    if let Ok((_, Some(record))) = first_page.read_cell(i) {
-                        match record.columns[0].data() {
-                            SerialValue::String(ref str) => {
-                                if str != "table" {
-                                    continue;
-                                }
-                            }
-                            _ => {}
-                        }
-
-                        match record.columns[2].data() {
-                            SerialValue::String(str) => match str.as_str() {
-                                "sqlite_sequence" => {
-                                    continue;
-                                }
-                                t_name => {
-                                    if select_statement.tbl_name == t_name {
-                                        match record.columns[3].data() {
-                                            SerialValue::I8(num) => {
-                                                // eprintln!("num: {num}");
-                                                if let Some(page) = db.pages.get(*num as usize - 1)
-                                                {
-                                                    let cell_len = page.cell_offsets.len();
-                                                    println!("{:?}", cell_len);
-                                                }
-                                            }
-                                            _ => {}
-                                        }
-                                    }
-                                }
-                            },
+            let print_field = |name: &str, value: &dyn std::fmt::Display| {
+                println!("{:<20} {}", name, value);
+            };
+            print_field("database page size:", &header.page_size);
+            print_field("write format:", &header.write_version);
+            print_field("read format:", &header.read_version);
+            print_field("reserved bytes:", &header.reserved_space);
+            print_field("file change counter:", &header.file_change_counter);
+            print_field("database page count:", &header.database_size);
+            print_field("freelist page count:", &header.freelist_count);
+            print_field("schema cookie:", &header.schema_cookie);
+            print_field("schema format:", &header.schema_format);
+            print_field("default cache size:", &header.default_cache_size);
+            print_field("autovacuum top root:", &header.largest_root_page);
+            print_field("incremental vacuum:", &header.incremental_vacuum);
+            print_field("text encoding:", &header.text_encoding);
+            print_field("user version:", &header.user_version);
+            print_field("application id:", &header.application_id);
+            print_field("software version:", &header.sqlite_version_number);
+            print_field("number of tables:", &ntables);
+            print_field("number of indexes:", &nindexes);
+            print_field("number of triggers:", &ntriggers);
+            print_field("number of views:", &nviews);
+            print_field("schema size:", &schema_size);
+        }
+        ".tables" => {
+            let mut tables = String::new();
+            for entry in BTreeCursor::new(db, 1).entries() {
+                let (_, record) = entry?;
+                if let Some(SerialValue::String(str)) =
+                    record.columns.first().map(|column| column.data())
+                {
+                    if str != "table" {
+                        continue;
+                    }
+                }
 
-                            _ => {}
+                let tbl_name = match record.columns.get(2).map(|column| column.data()) {
+                    Some(SerialValue::String(str)) => {
+                        if str == "sqlite_sequence" {
+                            continue;
                         }
+                        str
                     }
-                }
+                    _ => "",
+                };
+
+                tables.push_str(&format!("{} ", tbl_name));
             }
+            println!("{tables}");
         }
-        query if query.to_lowercase().starts_with("select") => {
-            let select_statement = Sql::from_str(query);
-
-            if let Some(first_page) = db.pages.get(0) {
-                for i in (0..first_page.btree_header.ncells()).rev() {
-                    match first_page.read_cell(i)? {
-                        (_, Some(record)) => {
-                            let mut rowids = HashSet::new();
-
-                            match record.columns[0].data() {
-                                SerialValue::String(str) => match str.as_str() {
-                                    "index" => {
-                                        let index_statement =
-                                            Sql::from_str(&record.columns[4].data().display());
-                                        if let SerialValue::I8(num) = record.columns[3].data() {
-                                            db.read_index(
-                                                *num as usize,
-                                                &index_statement,
-                                                &select_statement,
-                                                &mut rowids,
-                                            );
-                                        }
-                                        continue;
-                                    }
-                                    _ => {}
-                                },
-                                _ => {}
-                            }
-
-                            let mut rowids: Vec<i64> = rowids.into_iter().collect();
-                            rowids.sort_unstable();
-
-                            match record.columns[2].data() {
-                                SerialValue::String(str) => match str.as_str() {
-                                    "sqlite_sequence" => {
-                                        continue;
-                                    }
-                                    t_name => {
-                                        if select_statement.tbl_name == t_name {
-                                            match record.columns[3].data() {
-                                                SerialValue::I8(num) => {
-                                                    let create_statement = Sql::from_str(
-                                                        &record.columns[4].data().display(),
-                                                    );
-
-                                                    let fields = select_statement
-                                                        .get_fields(&create_statement);
-
-                                                    let mut row_set = HashSet::new();
-                                                    let mut rowid_set = HashSet::new();
-
-                                                    if rowids.is_empty() {
-                                                        db.read_table(
-                                                            *num as usize,
-                                                            &select_statement,
-                                                            fields,
-                                                            &mut row_set,
-                                                            &mut rowid_set,
-                                                        );
-                                                    } else {
-                                                        db.read_ids_from_table(
-                                                            *num as usize,
-                                                            &select_statement,
-                                                            fields,
-                                                            &mut row_set,
-                                                            &mut rowid_set,
-                                                            &rowids,
-                                                        );
-                                                    }
-
-                                                    row_set
-                                                        .iter()
-                                                        .for_each(|str| println!("{str}"));
-                                                }
-                                                _ => {}
-                                            }
-                                        }
-                                    }
-                                },
-                                _ => {}
-                            }
-                        }
-                        _ => {}
+        query
+            if ["select", "explain", "pragma"]
+                .iter()
+                .any(|command| query.to_lowercase().starts_with(command)) =>
+        {
+            let stmt = conn.prepare(query)?;
+
+            let mut out = BufWriter::new(std::io::stdout().lock());
+            match stmt.explain() {
+                Some(Explain::QueryPlan) => {
+                    let lines = stmt.query_plan();
+                    // As in sqlite3, statements without a plan, such as PRAGMAs, show nothing.
+                    if !lines.is_empty() {
+                        writeln!(out, "QUERY PLAN")?;
+                    }
+                    for (i, line) in lines.iter().enumerate() {
+                        let branch = if i + 1 == lines.len() { "`--" } else { "|--" };
+                        writeln!(out, "{branch}{line}")?;
+                    }
+                }
+                Some(Explain::Program) => writeln!(out, "{}", stmt.program())?,
+                None => {
+                    // As in sqlite3, parameters that are not given a value are NULL.
+                    let params = vec![SerialValue::Null; stmt.parameter_count()];
+                    for row in stmt.query(params)? {
+                        options.write_row(&mut out, row?.values())?;
                     }
                 }
             }
+            out.flush()?;
         }
         _ => bail!("Missing or invalid command passed: {}", command),
     }
 
     Ok(())
 }
+
+/// Removes the sqlite3-style options that precede the database path from `args`.
+fn parse_options(args: &mut Vec<String>) -> Result<DisplayOptions> {
+    let mut options = DisplayOptions::default();
+
+    while args.len() > 1 && args[1].starts_with('-') {
+        let option = args.remove(1);
+        match option.trim_start_matches('-') {
+            "list" => options.mode = OutputMode::List,
+            "quote" => options.mode = OutputMode::Quote,
+            "nullvalue" if args.len() > 1 => options.null_value = args.remove(1),
+            _ => bail!("unknown option: {}", option),
+        }
+    }
+
+    Ok(options)
+}
@@ -1,167 +1,93 @@
-use anyhow::{bail, Context};
-
-use crate::{database::DbHeader, decode_varint, record::Record};
+use crate::{
+    database::DbHeader,
+    error::{bail, Error, Result},
+    read_array, read_bytes,
+};
 
 #[derive(Debug, Clone)]
 pub struct Page {
+    /// The number of the page, counting from 1.
+    pub number: usize,
     pub db_header: Option<DbHeader>,
     pub btree_header: BTreePageHeader,
     pub(crate) buffer: Vec<u8>,
     pub cell_offsets: Vec<u16>,
-    // pub cells: Vec<Cell>,
 }
 
 impl Page {
-    pub fn new(idx: usize, db_header: Option<DbHeader>, b_tree_page: &[u8]) -> Self {
-        // let mut db_header = None;
-        let btree_header;
-        let mut buffer = vec![];
-        buffer.extend_from_slice(b_tree_page);
-
-        if idx == 0 {
-            // db_header = Some(header.clone());
-            btree_header = BTreePageHeader::new(&b_tree_page[100..112]).unwrap();
-            buffer.drain(0..100);
-        } else {
-            btree_header = BTreePageHeader::new(&b_tree_page[0..12]).unwrap();
-        }
+    pub fn new(idx: usize, db_header: Option<DbHeader>, b_tree_page: &[u8]) -> Result<Self> {
+        // The b-tree header of page 1 follows the database header.
+        let header_start = if idx == 0 { 100 } else { 0 };
+        let btree_header =
+            BTreePageHeader::new(b_tree_page.get(header_start..).unwrap_or_default())
+                .map_err(|err| err.at(idx + 1, header_start))?;
 
         let header_size: usize = match btree_header.page_type {
             PageType::InteriorIndex | PageType::InteriorTable => 12,
             _ => 8,
         };
 
+        // Cells must lie between the cell pointer array and the end of the page.
         let ncells = btree_header.ncells as usize;
-        // let mut cells = Vec::new();
-        let mut cell_offsets = vec![0; ncells];
-        for i in 0..ncells {
-            let offset = header_size + i * 2;
-            let num = u16::from_be_bytes([buffer[offset], buffer[offset + 1]]);
-            cell_offsets[i] = num;
-
-            // let cell = Cell::from_bytes(&btree_header.page_type, num as usize, &b_tree_page)
-            //     .expect("construct a cell");
-            // cells.push(cell);
+        let pointers_start = header_start + header_size;
+        let pointers = read_bytes(b_tree_page, pointers_start, ncells * 2)
+            .map_err(|err| err.at(idx + 1, 0))?;
+        let mut cell_offsets = Vec::with_capacity(ncells);
+        for (i, pointer) in pointers.chunks_exact(2).enumerate() {
+            let offset = u16::from_be_bytes([pointer[0], pointer[1]]);
+            if (offset as usize) < pointers_start + ncells * 2
+                || offset as usize >= b_tree_page.len()
+            {
+                bail!(
+                    Corrupt(idx + 1, pointers_start + i * 2),
+                    "cell {} at offset {} is outside the cell content area",
+                    i,
+                    offset
+                );
+            }
+            cell_offsets.push(offset);
         }
 
-        Self {
+        Ok(Self {
+            number: idx + 1,
             db_header,
             btree_header,
             buffer: b_tree_page.to_vec(),
             cell_offsets,
-            // cells,
-        }
+        })
     }
 
     pub fn page_type(&self) -> &PageType {
         &self.btree_header.page_type
     }
 
-    pub fn read_cell(&self, i: u16) -> anyhow::Result<(Option<i64>, Option<Record>)> {
-        if i >= self.btree_header.ncells {
-            bail!("Cell index out of range");
-        }
-
-        let offset = self.cell_offsets[i as usize] as usize;
-
-        match self.btree_header.page_type {
-            PageType::LeafTable => {
-                let mut idx = offset;
-
-                let (npayload, bytes_read) = decode_varint(&self.buffer[idx..idx + 9])
-                    .context("decode varint for payload size")?;
-                idx += bytes_read;
-
-                let (rowid, bytes_read) = decode_varint(&self.buffer[idx..idx + 9])
-                    .context("decode varint for payload size")?;
-                idx += bytes_read;
-
-                // let end = if npayload as usize > self.buffer.len() {
-                //     self.buffer.len()
-                // } else {
-                //     idx + npayload as usize
-                // };
-                let end = idx + npayload as usize;
-                let payload = &self.buffer[idx..end];
-                let record = Record::new(payload).context("create new record")?;
-
-                Ok((Some(rowid), Some(record)))
-            }
-            PageType::LeafIndex => {
-                let mut idx = offset;
-
-                let (npayload, bytes_read) =
-                    decode_varint(&self.buffer[idx..]).context("decode varint for payload size")?;
-                idx += bytes_read;
-
-                let end = idx + npayload as usize;
-                let payload = &self.buffer[idx..end];
-                let record = Record::new(payload).context("create new record")?;
+    pub fn is_leaf(&self) -> bool {
+        matches!(self.page_type(), PageType::LeafIndex | PageType::LeafTable)
+    }
 
-                Ok((None, Some(record)))
-            }
-            PageType::InteriorTable => {
-                let mut idx = offset;
-
-                let _left_child_pointer = u32::from_be_bytes([
-                    self.buffer[idx],
-                    self.buffer[idx + 1],
-                    self.buffer[idx + 2],
-                    self.buffer[idx + 3],
-                ]);
-                idx += 4;
-
-                let (rowid, _bytes_read) = decode_varint(&self.buffer[idx..idx + 9])
-                    .context("decode varint for payload size")?;
-                // idx += bytes_read;
-
-                Ok((Some(rowid), None))
-            }
-            PageType::InteriorIndex => {
-                let mut idx = offset;
-
-                let _left_child_pointer = u32::from_be_bytes([
-                    self.buffer[idx],
-                    self.buffer[idx + 1],
-                    self.buffer[idx + 2],
-                    self.buffer[idx + 3],
-                ]);
-                idx += 4;
-
-                let (npayload, bytes_read) =
-                    decode_varint(&self.buffer[idx..]).context("decode varint for payload size")?;
-                idx += bytes_read;
-
-                let end = idx + npayload as usize;
-                let payload = &self.buffer[idx..end];
-                let record = Record::new(payload).context("create new record")?;
-
-                Ok((None, Some(record)))
-            }
-            PageType::PageError => {
-                bail!("can not read cell");
-            }
-        }
+    pub fn is_index(&self) -> bool {
+        matches!(
+            self.page_type(),
+            PageType::InteriorIndex | PageType::LeafIndex
+        )
     }
 
-    // pub fn read_page_idx(&self, i: u16) -> anyhow::Result<Option<Vec<usize>>> {
-    //     if i >= self.btree_header.ncells {
-    //         bail!("Cell index out of range");
-    //     }
+    /// Page number of the `i`-th child of an interior page, where `i == ncells` is the
+    /// right-most pointer.
+    pub fn child_page(&self, i: usize) -> Option<u32> {
+        if self.is_leaf() {
+            return None;
+        }
 
-    //     let offset = self.cell_offsets[i as usize] as usize;
+        if i == self.cell_offsets.len() {
+            return self.btree_header.right_most_pointer;
+        }
 
-    //     match self.btree_header.page_type {
-    //         PageType::InteriorTable  => {
-    //
+        let offset = *self.cell_offsets.get(i)? as usize;
+        let bytes = self.buffer.get(offset..offset + 4)?;
 
-    //             Ok(pointer as usize)
-    //         }
-    //         PageType::InteriorIndex => {
-    //         }
-    //         _ => todo!(),
-    //     }
-    // }
+        Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -177,25 +103,28 @@ pub struct BTreePageHeader {
 }
 
 impl BTreePageHeader {
-    pub fn new(header: &[u8]) -> anyhow::Result<Self> {
-        let page_type = PageType::try_from(u8::from_be_bytes([header[0]]))?;
+    pub fn new(header: &[u8]) -> Result<Self> {
+        let page_type = PageType::try_from(read_array::<1>(header, 0)?[0])?;
+        if page_type == PageType::PageError {
+            bail!(Corrupt(0, 0), "invalid b-tree page type: {}", header[0]);
+        }
 
+        let [_, freeblock_0, freeblock_1, ncells_0, ncells_1, start_0, start_1, nfragmented] =
+            read_array(header, 0)?;
         let right_most_pointer = if matches!(page_type, PageType::InteriorTable)
             || matches!(page_type, PageType::InteriorIndex)
         {
-            Some(u32::from_be_bytes([
-                header[8], header[9], header[10], header[11],
-            ]))
+            Some(u32::from_be_bytes(read_array(header, 8)?))
         } else {
             None
         };
 
         Ok(Self {
             page_type,
-            freeblock_offset: u16::from_be_bytes([header[1], header[2]]),
-            ncells: u16::from_be_bytes([header[3], header[4]]),
-            cells_start: u16::from_be_bytes([header[5], header[6]]),
-            nfragemented_free: u8::from_be_bytes([header[7]]),
+            freeblock_offset: u16::from_be_bytes([freeblock_0, freeblock_1]),
+            ncells: u16::from_be_bytes([ncells_0, ncells_1]),
+            cells_start: u16::from_be_bytes([start_0, start_1]),
+            nfragemented_free: nfragmented,
             right_most_pointer,
         })
     }
@@ -206,7 +135,7 @@ impl BTreePageHeader {
 }
 
 #[repr(u8)]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PageType {
     /// A value of 2 (0x02) means the page is an interior index b-tree page
     InteriorIndex = 2,
@@ -225,7 +154,7 @@ pub enum PageType {
 }
 
 impl TryFrom<u8> for PageType {
-    type Error = anyhow::Error;
+    type Error = Error;
 
     fn try_from(value: u8) -> Result<Self, Self::Error> {
         match value {
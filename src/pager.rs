@@ -0,0 +1,268 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::File,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    database::DbHeader,
+    error::{bail, Result},
+    page::Page,
+};
+
+/// Number of pages kept in memory when no explicit cache size is given.
+pub const DEFAULT_CACHE_SIZE: usize = 2000;
+
+/// Reads b-tree pages from the database file on demand and keeps the most
+/// recently used ones in a bounded cache.
+#[derive(Debug)]
+pub struct Pager {
+    file: File,
+    header: DbHeader,
+    npages: usize,
+    cache: Mutex<PageCache>,
+}
+
+impl Pager {
+    pub fn open(path: impl AsRef<Path>, cache_size: usize) -> Result<Self> {
+        let file = File::open(path)?;
+
+        let mut raw_header = [0; 100];
+        read_exact_at(&file, &mut raw_header, 0)?;
+        let header = DbHeader::new(&raw_header)?;
+
+        let file_len = file.metadata()?.len() as usize;
+        if !file_len.is_multiple_of(header.page_size()) {
+            bail!(
+                Corrupt(0, file_len),
+                "file size {} is not a multiple of the page size {}",
+                file_len,
+                header.page_size()
+            );
+        }
+
+        Ok(Self {
+            file,
+            npages: header
+                .valid_database_size()
+                .unwrap_or(file_len / header.page_size()),
+            header,
+            cache: Mutex::new(PageCache::new(cache_size)),
+        })
+    }
+
+    pub fn header(&self) -> &DbHeader {
+        &self.header
+    }
+
+    pub fn page_size(&self) -> usize {
+        self.header.page_size()
+    }
+
+    /// Number of pages in the database file.
+    pub fn npages(&self) -> usize {
+        self.npages
+    }
+
+    /// Returns page `page_num`, counting from 1 as SQLite does.
+    pub fn get(&self, page_num: usize) -> Result<Arc<Page>> {
+        if let Some(page) = self.cache.lock().unwrap().get(page_num) {
+            return Ok(page);
+        }
+
+        let buffer = self.read_raw(page_num)?;
+        let db_header = (page_num == 1).then(|| self.header.clone());
+        let page = Arc::new(Page::new(page_num - 1, db_header, &buffer)?);
+
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(page_num, Arc::clone(&page));
+
+        Ok(page)
+    }
+
+    /// Reads the `len` payload bytes stored on the overflow page chain starting at `first_page`.
+    pub fn read_overflow(&self, first_page: u32, len: usize) -> Result<Vec<u8>> {
+        let usable_size = self.header.usable_size();
+        // A chain cannot be longer than the file, so a larger length is rejected before
+        // anything is read.
+        let capacity = self.npages.saturating_mul(usable_size - 4);
+        if len > capacity {
+            bail!(
+                Corrupt(0, 0),
+                "overflow payload of {} bytes exceeds the {} bytes the file can hold",
+                len,
+                capacity
+            );
+        }
+
+        // Grown page by page, as the length is only trusted as far as the chain goes.
+        let mut payload = Vec::new();
+        let mut page = 0;
+        let mut next_page = first_page as usize;
+        let mut visited = HashSet::new();
+
+        while payload.len() < len {
+            if next_page == 0 {
+                bail!(
+                    Corrupt(page, 0),
+                    "overflow chain ended after {} of {} bytes",
+                    payload.len(),
+                    len
+                );
+            }
+            if !visited.insert(next_page) {
+                bail!(
+                    Corrupt(page, 0),
+                    "overflow chain starting at page {} is cyclic",
+                    first_page
+                );
+            }
+            page = next_page;
+
+            let buffer = self.read_raw(next_page)?;
+            next_page = u32::from_be_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as usize;
+
+            let nbytes = (len - payload.len()).min(usable_size - 4);
+            payload.extend_from_slice(&buffer[4..4 + nbytes]);
+        }
+
+        Ok(payload)
+    }
+
+    /// Reads a page that is not a b-tree page, such as an overflow page, bypassing the cache.
+    pub(crate) fn read_raw(&self, page_num: usize) -> Result<Vec<u8>> {
+        if page_num == 0 || page_num > self.npages {
+            bail!(
+                Corrupt(0, 0),
+                "page {} out of range (1..={})",
+                page_num,
+                self.npages
+            );
+        }
+
+        let page_size = self.page_size();
+        let mut buffer = vec![0; page_size];
+        read_exact_at(&self.file, &mut buffer, ((page_num - 1) * page_size) as u64)?;
+
+        Ok(buffer)
+    }
+}
+
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+    use std::os::unix::fs::FileExt;
+
+    file.read_exact_at(buf, offset)
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> std::io::Result<()> {
+    use std::os::windows::fs::FileExt;
+
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset)? {
+            0 => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            n => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Least-recently-used page cache.
+#[derive(Debug)]
+struct PageCache {
+    capacity: usize,
+    tick: u64,
+    pages: HashMap<usize, (Arc<Page>, u64)>,
+    /// Last access tick of every cached page, oldest first.
+    recency: BTreeMap<u64, usize>,
+}
+
+impl PageCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            tick: 0,
+            pages: HashMap::new(),
+            recency: BTreeMap::new(),
+        }
+    }
+
+    fn get(&mut self, page_num: usize) -> Option<Arc<Page>> {
+        self.tick += 1;
+        let (page, last_used) = self.pages.get_mut(&page_num)?;
+        self.recency.remove(last_used);
+        *last_used = self.tick;
+        self.recency.insert(self.tick, page_num);
+
+        Some(Arc::clone(page))
+    }
+
+    fn insert(&mut self, page_num: usize, page: Arc<Page>) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        self.tick += 1;
+        if let Some((_, last_used)) = self.pages.insert(page_num, (page, self.tick)) {
+            self.recency.remove(&last_used);
+        }
+        self.recency.insert(self.tick, page_num);
+
+        while self.pages.len() > self.capacity {
+            if let Some((_, oldest)) = self.recency.pop_first() {
+                self.pages.remove(&oldest);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page_cache_evicts_least_recently_used() {
+        let mut buffer = vec![0; 512];
+        buffer[0] = 13;
+        let page = Arc::new(Page::new(1, None, &buffer).unwrap());
+        let mut cache = PageCache::new(2);
+
+        cache.insert(1, Arc::clone(&page));
+        cache.insert(2, Arc::clone(&page));
+        assert!(cache.get(1).is_some());
+        cache.insert(3, Arc::clone(&page));
+
+        assert!(cache.get(1).is_some());
+        assert!(cache.get(2).is_none());
+        assert!(cache.get(3).is_some());
+    }
+
+    #[test]
+    fn test_pager_reads_sample_db() {
+        let pager = Pager::open("sample.db", DEFAULT_CACHE_SIZE).unwrap();
+
+        assert_eq!(pager.page_size(), 4096);
+        assert_eq!(pager.npages(), 4);
+        assert_eq!(pager.get(1).unwrap().btree_header.ncells(), 3);
+        assert!(pager.get(5).is_err());
+    }
+
+    #[test]
+    fn test_overflow_longer_than_file() {
+        let pager = Pager::open("sample.db", DEFAULT_CACHE_SIZE).unwrap();
+
+        // Four pages of 4092 usable bytes after their next-page pointers.
+        let err = pager.read_overflow(2, 4 * 4092 + 1).unwrap_err();
+        assert!(matches!(err, crate::error::Error::Corrupt { .. }));
+        let err = pager.read_overflow(2, usize::MAX).unwrap_err();
+        assert!(matches!(err, crate::error::Error::Corrupt { .. }));
+    }
+}
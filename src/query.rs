@@ -0,0 +1,2198 @@
+use std::{collections::HashMap, mem};
+
+use crate::{
+    aggregate::AggregateFunc,
+    btree::KeyOrder,
+    codegen,
+    column::{Affinity, Collation, SerialValue},
+    database::Database,
+    error::{bail, Result},
+    expr::{comparison_collation, BinaryOp, Expr, Scope},
+    schema::{Index, Schema, Table},
+    sort::SortKey,
+    sql::{JoinConstraint, Pragma, ResultColumn, Sql},
+    stat::Statistics,
+    vdbe::Program,
+};
+
+/// A row of a query result, or of one of the intermediate steps that produce it.
+pub type Row = Vec<SerialValue>;
+
+/// The rows a [`Query`] produces, read from the database as they are pulled.
+pub type Rows<'a> = Box<dyn Iterator<Item = Result<Row>> + 'a>;
+
+/// A SELECT statement, or a PRAGMA that returns rows, resolved against the schema and
+/// ready to run.
+#[derive(Debug, Clone)]
+pub struct Query {
+    /// The names of the result columns, as sqlite3 reports them.
+    pub column_names: Vec<String>,
+    plan: Plan,
+    program: Program,
+    /// The names the tables are referred to by, in the order they are joined.
+    table_names: Vec<String>,
+}
+
+/// The steps that produce the rows of a query, each reading the rows of its input. The
+/// plan is compiled into the [`Program`] that runs the query.
+#[derive(Debug, Clone)]
+pub(crate) enum Plan {
+    /// A single empty row, for a SELECT without FROM.
+    Row,
+    /// The rows of a table whose rowid lies between `lower` and `upper`, in rowid order or,
+    /// if `reverse`, in descending rowid order. Without bounds, every row of the table.
+    Scan {
+        table: Table,
+        reverse: bool,
+        lower: Option<Bound>,
+        upper: Option<Bound>,
+    },
+    /// The row of a table with the rowid `rowid`, if there is one.
+    RowidSeek {
+        table: Table,
+        rowid: Expr,
+    },
+    /// The rows of a table whose leading indexed columns equal `key` and whose next indexed
+    /// column lies between `lower` and `upper`, in index order or, if `reverse`, in reverse
+    /// index order. `order` tells how the entries are ordered by each indexed column.
+    /// Without a key or bounds, every row of the table.
+    IndexSeek {
+        table: Table,
+        index: Box<Index>,
+        reverse: bool,
+        order: Vec<KeyOrder>,
+        key: Vec<Expr>,
+        lower: Option<Bound>,
+        upper: Option<Bound>,
+        /// If the index holds every column the query reads, the
+        /// [`Index::column_positions`] to build rows from its records with.
+        covering: Option<Vec<Option<usize>>>,
+    },
+    /// Each row of `outer` followed by each row of `inner` for which `condition` holds.
+    /// The lookups of `inner` are evaluated against the row of `outer`. A LEFT join also
+    /// returns the rows of `outer` that match nothing, followed by NULLs.
+    NestedLoop {
+        outer: Box<Plan>,
+        inner: Box<Plan>,
+        condition: Option<Expr>,
+        left: bool,
+    },
+    /// The same rows as [`Plan::NestedLoop`], for a join whose inner rows can only be
+    /// found by a full scan: the rows whose `inner_keys` equal the `outer_keys` of the outer
+    /// row, compared with `collations`, are found in a hash table built from `inner` or, if
+    /// `build_outer`, from `outer`. The inner keys are bound to the rows of `inner` alone.
+    HashJoin {
+        outer: Box<Plan>,
+        inner: Box<Plan>,
+        outer_keys: Vec<Expr>,
+        inner_keys: Vec<Expr>,
+        collations: Vec<Collation>,
+        condition: Option<Expr>,
+        left: bool,
+        inner_width: usize,
+        build_outer: bool,
+    },
+    Filter {
+        input: Box<Plan>,
+        predicate: Expr,
+    },
+    /// One row per group of input rows: the row bare columns are taken from, followed
+    /// by the results of `aggregates`.
+    Aggregate {
+        input: Box<Plan>,
+        input_width: usize,
+        group_by: Vec<Expr>,
+        aggregates: Vec<Expr>,
+    },
+    /// The input rows whose values of `columns` differ, under `collations`, from those of
+    /// every row before them, for SELECT DISTINCT. If `ordered`, the input puts rows with
+    /// equal values next to each other, so only the last row needs to be remembered.
+    Distinct {
+        input: Box<Plan>,
+        columns: Vec<Expr>,
+        collations: Vec<Collation>,
+        ordered: bool,
+    },
+    Sort {
+        input: Box<Plan>,
+        keys: Vec<SortKey>,
+    },
+    Project {
+        input: Box<Plan>,
+        columns: Vec<Expr>,
+    },
+    /// At most `limit` rows of the input after skipping `offset` rows. Both are constant
+    /// expressions, evaluated each time the query runs.
+    Limit {
+        input: Box<Plan>,
+        limit: Expr,
+        offset: Option<Expr>,
+    },
+    /// The rows of `PRAGMA integrity_check`, or of `PRAGMA quick_check` if `quick`.
+    IntegrityCheck {
+        quick: bool,
+        max_errors: usize,
+    },
+}
+
+impl Query {
+    pub fn prepare(db: &Database, sql: &Sql) -> Result<Self> {
+        let schema = db.schema()?;
+
+        if let Some(Pragma::IntegrityCheck { quick, max_errors }) = sql.pragma {
+            let plan = Plan::IntegrityCheck { quick, max_errors };
+            let name = if quick {
+                "quick_check"
+            } else {
+                "integrity_check"
+            };
+            return Ok(Self {
+                column_names: vec![name.to_string()],
+                program: codegen::compile(&plan)?,
+                plan,
+                table_names: Vec::new(),
+            });
+        }
+
+        // The tables are laid out one after the other in the rows they are joined into.
+        let mut scope = Scope::default();
+        let mut sources = Vec::new();
+        for from in &sql.from {
+            let Some(table) = schema.table(&from.name) else {
+                bail!(Sql, "no such table: {}", from.name);
+            };
+            let start = scope.columns().len();
+            let table_scope = table.scope(from.alias.as_deref());
+
+            let using = match &from.constraint {
+                JoinConstraint::Using(names) => names.clone(),
+                JoinConstraint::Natural => table_scope
+                    .columns()
+                    .iter()
+                    .filter(|column| !column.hidden && scope.resolve(None, &column.name).is_ok())
+                    .map(|column| column.name.clone())
+                    .collect(),
+                _ => Vec::new(),
+            };
+            let mut condition = match &from.constraint {
+                JoinConstraint::On(expr) => vec![expr.clone()],
+                _ => Vec::new(),
+            };
+            let mut shadowed = Vec::new();
+            for name in &using {
+                let (Ok(left), Ok(right)) =
+                    (scope.resolve(None, name), table_scope.resolve(None, name))
+                else {
+                    bail!(
+                        Sql,
+                        "cannot join using column {} - column not present in both tables",
+                        name
+                    );
+                };
+                let qualified = |table: &Option<String>| Expr::Identifier {
+                    table: table.clone(),
+                    name: name.clone(),
+                };
+                condition.push(Expr::Binary {
+                    left: Box::new(qualified(&scope.columns()[left].table)),
+                    op: BinaryOp::Eq,
+                    right: Box::new(qualified(&table_scope.columns()[right].table)),
+                });
+                shadowed.push(start + right);
+            }
+
+            let width = table_scope.columns().len();
+            scope.extend(table_scope);
+            for i in shadowed {
+                scope.qualify_only(i);
+            }
+            let condition = condition
+                .iter()
+                .map(|expr| expr.bind(&scope))
+                .collect::<Result<Vec<_>>>()?;
+
+            sources.push(Source {
+                table,
+                start,
+                width,
+                left: from.left,
+                cross: from.cross,
+                name: from.alias.clone().unwrap_or_else(|| from.name.clone()),
+                condition,
+            });
+        }
+
+        let result_columns = sql.result_columns()?;
+        let mut column_names = Vec::new();
+        let mut columns = Vec::new();
+        for column in &result_columns {
+            match column {
+                ResultColumn::Wildcard => {
+                    if sources.is_empty() {
+                        bail!(Sql, "no tables specified");
+                    }
+                    for (i, column) in scope.columns().iter().enumerate() {
+                        if !column.hidden && !column.qualified_only {
+                            column_names.push(column.name.clone());
+                            columns.push(Expr::Column(i));
+                        }
+                    }
+                }
+                ResultColumn::QualifiedWildcard(table) => {
+                    let before = columns.len();
+                    for (i, column) in scope.columns().iter().enumerate() {
+                        let in_table = column
+                            .table
+                            .as_ref()
+                            .is_some_and(|name| name.eq_ignore_ascii_case(table));
+                        if in_table && !column.hidden {
+                            column_names.push(column.name.clone());
+                            columns.push(Expr::Column(i));
+                        }
+                    }
+                    if columns.len() == before {
+                        bail!(Sql, "no such table: {}", table);
+                    }
+                }
+                ResultColumn::Expr { expr, name } => {
+                    column_names.push(name.clone());
+                    columns.push(expr.bind(&scope)?);
+                }
+            }
+        }
+
+        let mut predicate = sql
+            .selection
+            .as_ref()
+            .map(|selection| substitute_aliases(selection, &scope, &result_columns).bind(&scope))
+            .transpose()?;
+        if let Some(func) = predicate.as_ref().and_then(first_aggregate) {
+            bail!(Sql, "misuse of aggregate: {}()", func.name());
+        }
+
+        let mut having = sql
+            .having
+            .as_ref()
+            .map(|having| substitute_aliases(having, &scope, &result_columns).bind(&scope))
+            .transpose()?;
+
+        let mut sort = sql
+            .order_by
+            .iter()
+            .enumerate()
+            .map(|(i, term)| {
+                let expr =
+                    resolve_term("ORDER BY", i, &term.expr, &scope, &result_columns, &columns)?;
+                Ok(SortKey {
+                    collation: expr.collation().unwrap_or_default(),
+                    expr,
+                    descending: term.descending,
+                    nulls_first: term.nulls_first.unwrap_or(!term.descending),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut group_by = sql
+            .group_by
+            .iter()
+            .enumerate()
+            .map(|(i, term)| resolve_term("GROUP BY", i, term, &scope, &result_columns, &columns))
+            .collect::<Result<Vec<_>>>()?;
+
+        // As in sqlite3, only GROUP BY and aggregates among the result columns allow a
+        // HAVING clause; an aggregate in HAVING alone does not.
+        let aggregate_columns = columns.iter().any(Expr::contains_aggregate);
+        if having.is_some() && sql.group_by.is_empty() && !aggregate_columns {
+            bail!(Sql, "HAVING clause on a non-aggregate query");
+        }
+        let is_aggregate = !sql.group_by.is_empty()
+            || aggregate_columns
+            || sort.iter().any(|key| key.expr.contains_aggregate());
+
+        // The columns the query reads, which an index must hold to be read without the table.
+        let mut used: Vec<usize> = columns
+            .iter()
+            .chain(&predicate)
+            .chain(&having)
+            .chain(&group_by)
+            .chain(sort.iter().map(|key| &key.expr))
+            .chain(sources.iter().flat_map(|source| &source.condition))
+            .flat_map(Expr::referenced_columns)
+            .collect();
+
+        let planner = Planner::new(db, &schema, sources.iter().map(|source| source.table))?;
+        let terms: Vec<&Expr> = predicate.as_ref().map(Expr::conjuncts).unwrap_or_default();
+        let order = join_order(&planner, &sources, &terms, &used);
+        if order.iter().enumerate().any(|(k, &i)| k != i) {
+            // Lay the tables out in the order they are joined in. Join conditions become
+            // part of the WHERE clause, to be checked once the tables they read are joined.
+            let mut reordered = Vec::new();
+            let mut starts = vec![0; sources.len()];
+            let mut start = 0;
+            for &i in &order {
+                reordered.push(Source {
+                    start,
+                    condition: Vec::new(),
+                    ..sources[i].clone()
+                });
+                starts[i] = start;
+                start += sources[i].width;
+            }
+            let place = |i: usize| {
+                let k = sources
+                    .iter()
+                    .position(|source| i < source.start + source.width)
+                    .expect("every column belongs to a table");
+                starts[k] + i - sources[k].start
+            };
+
+            let conditions = sources.iter().flat_map(|source| &source.condition);
+            predicate = conjunction(
+                predicate
+                    .iter()
+                    .chain(conditions)
+                    .map(|expr| expr.map_columns(&place)),
+            );
+            having = having.map(|having| having.map_columns(&place));
+            columns = columns
+                .iter()
+                .map(|column| column.map_columns(&place))
+                .collect();
+            group_by = group_by
+                .iter()
+                .map(|expr| expr.map_columns(&place))
+                .collect();
+            for key in &mut sort {
+                key.expr = key.expr.map_columns(&place);
+            }
+            used = used.into_iter().map(place).collect();
+            sources = reordered;
+        }
+
+        // Each term of the WHERE clause is checked as soon as the tables it reads are joined.
+        let terms: Vec<&Expr> = predicate.as_ref().map(Expr::conjuncts).unwrap_or_default();
+        let level = |term: &Expr| {
+            let end = term
+                .referenced_columns()
+                .into_iter()
+                .max()
+                .map_or(0, |i| i + 1);
+            sources
+                .iter()
+                .position(|source| end <= source.start + source.width)
+                .unwrap_or(0)
+        };
+
+        let mut plan = match sources.is_empty() {
+            true => Some(Plan::Row),
+            false => None,
+        };
+        let mut provided_order = None;
+        let mut hashed = false;
+        // Roughly how many rows `plan` produces.
+        let mut rows = 1.0;
+        for (k, source) in sources.iter().enumerate() {
+            let level_terms: Vec<&Expr> = terms
+                .iter()
+                .copied()
+                .filter(|term| level(term) == k)
+                .collect();
+
+            // A LEFT JOIN may only look up the rows its own condition asks for.
+            let mut lookup_terms: Vec<&Expr> =
+                source.condition.iter().flat_map(Expr::conjuncts).collect();
+            if !source.left {
+                lookup_terms.extend(&level_terms);
+            }
+            // Rows that come out of the first table or an index on it in the requested
+            // order need no sort; joining keeps that order.
+            let first_table_order = sort.iter().all(|key| {
+                key.expr
+                    .referenced_columns()
+                    .iter()
+                    .all(|&i| i < source.width)
+            });
+            let order = match k == 0 && !is_aggregate && first_table_order {
+                true => sort.as_slice(),
+                false => &[],
+            };
+            let (mut access, cost) = access_path(
+                &planner,
+                source.table,
+                source.start,
+                &lookup_terms,
+                &used,
+                order,
+            );
+            if k == 0
+                && !is_aggregate
+                && first_table_order
+                && provide_order(&mut access, &sort, source.table)
+            {
+                provided_order = Some(mem::take(&mut sort));
+            }
+
+            let hash_keys: Vec<_> = lookup_terms
+                .iter()
+                .filter_map(|term| hash_key(term, source.start, source.start + source.width))
+                .collect();
+            let condition = conjunction(source.condition.iter().cloned());
+            let mut joined = match plan {
+                None => access,
+                Some(outer) if is_full_scan(&access) && !hash_keys.is_empty() => {
+                    // Only the inner rows can be NULL-extended, so a LEFT join builds from them.
+                    let build_outer = !source.left && rows < cost.rows;
+                    let (outer_keys, (inner_keys, collations)) = hash_keys
+                        .into_iter()
+                        .map(|(outer, inner, collation)| (outer, (inner, collation)))
+                        .unzip();
+                    hashed = true;
+                    Plan::HashJoin {
+                        outer: Box::new(outer),
+                        inner: Box::new(access),
+                        outer_keys,
+                        inner_keys,
+                        collations,
+                        condition,
+                        left: source.left,
+                        inner_width: source.width,
+                        build_outer,
+                    }
+                }
+                Some(outer) => Plan::NestedLoop {
+                    outer: Box::new(outer),
+                    inner: Box::new(access),
+                    condition,
+                    left: source.left,
+                },
+            };
+            rows = match source.left {
+                true => rows * cost.rows.max(1.0),
+                false => rows * cost.rows,
+            };
+            if let Some(predicate) = conjunction(level_terms.into_iter().cloned()) {
+                joined = Plan::Filter {
+                    input: Box::new(joined),
+                    predicate,
+                };
+            }
+            plan = Some(joined);
+        }
+        let mut plan = plan.expect("at least one table or a single row");
+        // A hash join may return rows in another order than the first table's.
+        if let (true, Some(keys)) = (hashed, provided_order) {
+            sort = keys;
+        }
+        if sources.is_empty() {
+            if let Some(predicate) = predicate {
+                plan = Plan::Filter {
+                    input: Box::new(plan),
+                    predicate,
+                };
+            }
+        }
+
+        if is_aggregate {
+            let input_width = scope.columns().len();
+            let mut aggregates = Vec::new();
+            columns = columns
+                .iter()
+                .map(|column| column.extract_aggregates(input_width, &mut aggregates))
+                .collect::<Result<_>>()?;
+            let having = having
+                .map(|having| having.extract_aggregates(input_width, &mut aggregates))
+                .transpose()?;
+            for key in &mut sort {
+                key.expr = key.expr.extract_aggregates(input_width, &mut aggregates)?;
+            }
+
+            plan = Plan::Aggregate {
+                input: Box::new(plan),
+                input_width,
+                group_by,
+                aggregates,
+            };
+            if let Some(predicate) = having {
+                plan = Plan::Filter {
+                    input: Box::new(plan),
+                    predicate,
+                };
+            }
+        }
+
+        if sql.distinct {
+            let collations: Vec<Collation> = columns
+                .iter()
+                .map(|column| column.collation().unwrap_or_default())
+                .collect();
+            let distinct = match sources.as_slice() {
+                [source] if !is_aggregate => {
+                    distinct_rows(&plan, &schema, source.table, &columns, &collations)
+                }
+                _ => DistinctRows::Unordered,
+            };
+            if distinct != DistinctRows::Unique {
+                plan = Plan::Distinct {
+                    input: Box::new(plan),
+                    columns: columns.clone(),
+                    collations,
+                    ordered: distinct == DistinctRows::Ordered,
+                };
+            }
+        }
+
+        if !sort.is_empty() {
+            plan = Plan::Sort {
+                input: Box::new(plan),
+                keys: sort,
+            };
+        }
+
+        plan = Plan::Project {
+            input: Box::new(plan),
+            columns,
+        };
+
+        if let Some(limit) = &sql.limit {
+            // LIMIT and OFFSET cannot refer to columns.
+            let no_columns = Scope::default();
+            plan = Plan::Limit {
+                input: Box::new(plan),
+                limit: limit.bind(&no_columns)?,
+                offset: sql
+                    .offset
+                    .as_ref()
+                    .map(|offset| offset.bind(&no_columns))
+                    .transpose()?,
+            };
+        }
+
+        Ok(Self {
+            column_names,
+            program: codegen::compile(&plan)?,
+            plan,
+            table_names: sources.into_iter().map(|source| source.name).collect(),
+        })
+    }
+
+    /// Describes the steps taken to run the query, one line each, the way SQLite's
+    /// EXPLAIN QUERY PLAN does.
+    pub fn query_plan(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        self.plan.explain(&self.table_names, &mut 0, &mut lines);
+        lines
+    }
+
+    /// Runs the query, reading from `db`, with `parameters` bound to its parameters by
+    /// index from 1.
+    pub fn rows<'a>(&'a self, db: &'a Database, parameters: Vec<SerialValue>) -> Result<Rows<'a>> {
+        Ok(Box::new(self.program.run(db, parameters)))
+    }
+
+    /// The program the query is compiled into, listed by EXPLAIN.
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+}
+
+/// One end of the range of values an index seek reads.
+#[derive(Debug, Clone)]
+pub(crate) struct Bound {
+    pub(crate) value: Expr,
+    pub(crate) inclusive: bool,
+}
+
+/// A table of the FROM clause, placed in the joined rows.
+#[derive(Clone)]
+struct Source<'s> {
+    table: &'s Table,
+    /// The position of the table's first column in the joined rows.
+    start: usize,
+    width: usize,
+    left: bool,
+    cross: bool,
+    /// The alias of the table, or else its name as written.
+    name: String,
+    /// The join condition, as terms to be ANDed together.
+    condition: Vec<Expr>,
+}
+
+/// How many tables a join may have for the planner to consider the orders to join them
+/// in. Joins of more tables are made in the order they are written.
+const MAX_REORDERED_TABLES: usize = 8;
+
+/// The number of rows SQLite assumes a table has when ANALYZE has not counted them.
+const DEFAULT_TABLE_ROWS: f64 = 1_048_576.0;
+
+/// What a plan is estimated to cost: how much work it does, in units of about reading
+/// a small row, and how many rows it produces.
+#[derive(Debug, Clone, Copy, Default)]
+struct Cost {
+    work: f64,
+    rows: f64,
+}
+
+/// What the planner knows about the tables of a query besides their definitions.
+struct Planner<'a> {
+    schema: &'a Schema,
+    stats: Statistics,
+    /// The number of rows of each table, by lowercase name.
+    table_rows: HashMap<String, f64>,
+}
+
+impl<'a> Planner<'a> {
+    /// Counts the rows of `tables` as of the last ANALYZE or, if they were not analyzed,
+    /// assumes [`DEFAULT_TABLE_ROWS`], as SQLite does.
+    fn new<'t>(
+        db: &Database,
+        schema: &'a Schema,
+        tables: impl IntoIterator<Item = &'t Table>,
+    ) -> Result<Self> {
+        let stats = Statistics::read(db, schema)?;
+        let mut table_rows = HashMap::new();
+        for table in tables {
+            let rows = match stats.table_rows(&table.name) {
+                Some(rows) => rows as f64,
+                None => DEFAULT_TABLE_ROWS,
+            };
+            table_rows.insert(table.name.to_ascii_lowercase(), rows.max(1.0));
+        }
+
+        Ok(Self {
+            schema,
+            stats,
+            table_rows,
+        })
+    }
+
+    fn table_rows(&self, table: &Table) -> f64 {
+        self.table_rows
+            .get(&table.name.to_ascii_lowercase())
+            .copied()
+            .unwrap_or(1.0)
+    }
+
+    /// Roughly how many entries of `index`, whose [`Index::key_columns`] are `columns`,
+    /// a seek for `key` reads, narrowed down to the range between `lower` and `upper`
+    /// of the next column. Without statistics, SQLite guesses that 10 entries share a
+    /// value of the first column, 9 of the first two and so on.
+    fn index_rows(
+        &self,
+        index: &Index,
+        columns: &[(usize, KeyOrder)],
+        key: &[Expr],
+        lower: &Option<Bound>,
+        upper: &Option<Bound>,
+        rows: f64,
+    ) -> f64 {
+        let stats = self.stats.index(&index.name);
+        let constant = |expr: &Expr| match expr.is_constant() {
+            true => expr.eval(&[]).ok(),
+            false => None,
+        };
+
+        let mut matched = match key.len() {
+            0 => rows,
+            n if index.unique && n == index.columns.len() => 1.0,
+            n => {
+                let sampled = match (stats, key, columns.first()) {
+                    (Some(stats), [value], Some((_, order))) => constant(value)
+                        .and_then(|value| stats.sampled_equal(&value, order.collation)),
+                    _ => None,
+                };
+                sampled.or_else(|| stats?.average_equal(n)).unwrap_or(
+                    [10.0, 9.0, 8.0, 7.0, 6.0]
+                        .get(n - 1)
+                        .copied()
+                        .unwrap_or(5.0),
+                )
+            }
+        };
+
+        // The samples tell how many entries of an ascending first column lie in a range.
+        let sampled_range = match (stats, key, columns.first()) {
+            (Some(stats), [], Some((_, order))) if !order.descending => {
+                let before = |bound: &Option<Bound>, upper: bool| match bound {
+                    Some(bound) => {
+                        let value = constant(&bound.value)?;
+                        let or_equal = bound.inclusive == upper;
+                        stats.sampled_before(&value, order.collation, or_equal)
+                    }
+                    None if upper => Some(rows),
+                    None => Some(0.0),
+                };
+                match (before(lower, false), before(upper, true)) {
+                    (Some(start), Some(end)) if lower.is_some() || upper.is_some() => {
+                        Some(end - start)
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        match sampled_range {
+            Some(range) => matched = range,
+            None => matched *= range_selectivity(lower, upper),
+        }
+
+        matched.clamp(1.0, rows)
+    }
+}
+
+impl Plan {
+    /// Adds the lines of EXPLAIN QUERY PLAN that describe this plan to `lines`. The tables
+    /// read are called by `names`, in the order they are joined, from `names[next]` on.
+    fn explain(&self, names: &[String], next: &mut usize, lines: &mut Vec<String>) {
+        let name = names.get(*next).map_or("?", String::as_str);
+        let mut next_name = || {
+            *next += 1;
+            name
+        };
+        match self {
+            Plan::Row => lines.push("SCAN CONSTANT ROW".to_string()),
+            Plan::Scan {
+                lower: None,
+                upper: None,
+                ..
+            } => lines.push(format!("SCAN {}", next_name())),
+            Plan::Scan { lower, upper, .. } => lines.push(format!(
+                "SEARCH {} USING INTEGER PRIMARY KEY ({})",
+                next_name(),
+                seek_constraints(&["rowid"], 0, lower, upper)
+            )),
+            Plan::RowidSeek { .. } => lines.push(format!(
+                "SEARCH {} USING INTEGER PRIMARY KEY (rowid=?)",
+                next_name()
+            )),
+            Plan::IndexSeek {
+                table,
+                index,
+                key,
+                lower,
+                upper,
+                covering,
+                ..
+            } => {
+                let kind = match covering {
+                    Some(_) => "COVERING INDEX",
+                    None => "INDEX",
+                };
+                let columns: Vec<&str> = index
+                    .column_positions(table)
+                    .into_iter()
+                    .map(|position| match position {
+                        Some(i) => table.column_name(i),
+                        None => "<expr>",
+                    })
+                    .collect();
+                lines.push(match (key.is_empty(), lower, upper) {
+                    (true, None, None) => {
+                        format!("SCAN {} USING {} {}", next_name(), kind, index.name)
+                    }
+                    _ => format!(
+                        "SEARCH {} USING {} {} ({})",
+                        next_name(),
+                        kind,
+                        index.name,
+                        seek_constraints(&columns, key.len(), lower, upper)
+                    ),
+                });
+            }
+            Plan::NestedLoop {
+                outer, inner, left, ..
+            } => {
+                outer.explain(names, next, lines);
+                inner.explain(names, next, lines);
+                if let (true, Some(line)) = (*left, lines.last_mut()) {
+                    line.push_str(" LEFT-JOIN");
+                }
+            }
+            Plan::HashJoin {
+                outer,
+                inner,
+                inner_keys,
+                left,
+                ..
+            } => {
+                outer.explain(names, next, lines);
+                let name = names.get(*next).map_or("?", String::as_str);
+                let mut inner_lines = Vec::new();
+                inner.explain(names, next, &mut inner_lines);
+                if let (true, Some(line)) = (*left, inner_lines.last_mut()) {
+                    line.push_str(" LEFT-JOIN");
+                }
+                lines.extend(inner_lines);
+
+                let columns: Vec<&str> = inner_keys
+                    .iter()
+                    .map(|key| {
+                        let key = match key {
+                            Expr::ApplyAffinity { expr, .. } => expr,
+                            key => key,
+                        };
+                        (column_position(key), inner.table())
+                    })
+                    .map(|position| match position {
+                        (Some(i), Some(table)) => table.column_name(i),
+                        _ => "<expr>",
+                    })
+                    .collect();
+                lines.push(format!(
+                    "USE HASH TABLE FOR JOIN ON {} ({})",
+                    name,
+                    seek_constraints(&columns, columns.len(), &None, &None)
+                ));
+            }
+            Plan::Filter { input, .. }
+            | Plan::Project { input, .. }
+            | Plan::Limit { input, .. } => input.explain(names, next, lines),
+            Plan::Aggregate {
+                input, group_by, ..
+            } => {
+                input.explain(names, next, lines);
+                if !group_by.is_empty() {
+                    lines.push("USE TEMP B-TREE FOR GROUP BY".to_string());
+                }
+            }
+            Plan::Distinct { input, ordered, .. } => {
+                input.explain(names, next, lines);
+                if !ordered {
+                    lines.push("USE TEMP B-TREE FOR DISTINCT".to_string());
+                }
+            }
+            Plan::Sort { input, .. } => {
+                input.explain(names, next, lines);
+                lines.push("USE TEMP B-TREE FOR ORDER BY".to_string());
+            }
+            Plan::IntegrityCheck { .. } => {}
+        }
+    }
+
+    /// The table this plan reads, if it reads a single one.
+    fn table(&self) -> Option<&Table> {
+        match self {
+            Plan::Scan { table, .. }
+            | Plan::RowidSeek { table, .. }
+            | Plan::IndexSeek { table, .. } => Some(table),
+            Plan::Filter { input, .. } => input.table(),
+            _ => None,
+        }
+    }
+}
+
+/// The constraints a seek puts on the leading `columns` of an index the way EXPLAIN QUERY
+/// PLAN lists them: the first `nequal` are fixed and the next one may have bounds.
+fn seek_constraints(
+    columns: &[&str],
+    nequal: usize,
+    lower: &Option<Bound>,
+    upper: &Option<Bound>,
+) -> String {
+    let mut constraints: Vec<String> = columns[..nequal]
+        .iter()
+        .map(|column| format!("{}=?", column))
+        .collect();
+    if let Some(column) = columns.get(nequal) {
+        if lower.is_some() {
+            constraints.push(format!("{}>?", column));
+        }
+        if upper.is_some() {
+            constraints.push(format!("{}<?", column));
+        }
+    }
+    constraints.join(" AND ")
+}
+
+/// Resolves the `i`-th term of an ORDER BY or GROUP BY `clause`. An integer constant `k`
+/// stands for the k-th result column, and other terms may refer to the aliases of result
+/// columns. In ORDER BY, an alias takes precedence over a column of the same name.
+///
+/// See <https://www.sqlite.org/lang_select.html#the_order_by_clause>.
+fn resolve_term(
+    clause: &str,
+    i: usize,
+    term: &Expr,
+    scope: &Scope,
+    result_columns: &[ResultColumn],
+    columns: &[Expr],
+) -> Result<Expr> {
+    let is_alias = match term {
+        Expr::Identifier { table: None, name } if clause == "ORDER BY" => {
+            result_columns.iter().any(|column| {
+                matches!(column, ResultColumn::Expr { name: alias, .. } if alias.eq_ignore_ascii_case(name))
+            })
+        }
+        _ => false,
+    };
+
+    let term = match (is_alias, term) {
+        // Against an empty scope, the name can only be taken as the alias.
+        (true, _) => substitute_aliases(term, &Scope::default(), result_columns).bind(scope)?,
+        (_, Expr::Literal(value)) => match value.as_integer() {
+            Some(k) if k >= 1 && k as usize <= columns.len() => columns[k as usize - 1].clone(),
+            Some(_) => bail!(
+                Sql,
+                "{} {} term out of range - should be between 1 and {}",
+                ordinal(i + 1),
+                clause,
+                columns.len()
+            ),
+            None => term.clone(),
+        },
+        (_, term) => substitute_aliases(term, scope, result_columns).bind(scope)?,
+    };
+
+    if clause == "GROUP BY" && term.contains_aggregate() {
+        bail!(
+            Sql,
+            "aggregate functions are not allowed in the GROUP BY clause"
+        );
+    }
+
+    Ok(term)
+}
+
+/// "1st", "2nd", "3rd", "4th", ..., as sqlite3 numbers terms in error messages.
+fn ordinal(n: usize) -> String {
+    let suffix = match (n % 100, n % 10) {
+        (11..=13, _) => "th",
+        (_, 1) => "st",
+        (_, 2) => "nd",
+        (_, 3) => "rd",
+        _ => "th",
+    };
+    format!("{}{}", n, suffix)
+}
+
+/// Replaces the names that are not columns of `scope` but are aliases of result columns
+/// with the aliased expressions.
+fn substitute_aliases(expr: &Expr, scope: &Scope, result_columns: &[ResultColumn]) -> Expr {
+    match expr {
+        Expr::Identifier { table: None, name } if scope.resolve(None, name).is_err() => {
+            result_columns
+                .iter()
+                .find_map(|column| match column {
+                    ResultColumn::Expr { expr, name: alias }
+                        if alias.eq_ignore_ascii_case(name) =>
+                    {
+                        Some(expr.clone())
+                    }
+                    _ => None,
+                })
+                .unwrap_or_else(|| expr.clone())
+        }
+        expr => expr
+            .map_children(|child| Ok(substitute_aliases(child, scope, result_columns)))
+            .expect("substituting aliases cannot fail"),
+    }
+}
+
+/// The function of the first aggregate call in `expr`, if any.
+fn first_aggregate(expr: &Expr) -> Option<AggregateFunc> {
+    let mut aggregates = Vec::new();
+    expr.extract_aggregates(0, &mut aggregates).ok()?;
+    match aggregates.first() {
+        Some(Expr::Aggregate { func, .. }) => Some(*func),
+        _ => None,
+    }
+}
+
+/// Chooses how to read `table`, whose columns start at position `start` of the joined
+/// rows, by estimating the cost of each way to: a rowid lookup or range, seeks on the
+/// indexes whose leading columns `terms` fix or limit to a range, scans of the indexes
+/// that hold every column the query reads (`used`) and a full scan. If the rows are to
+/// be sorted by `order`, the cost of sorting is added to the ways that do not read them
+/// in that order.
+///
+/// The estimates follow those of SQLite's query planner, see
+/// <https://www.sqlite.org/optoverview.html#cost_based_optimization>.
+fn access_path(
+    planner: &Planner,
+    table: &Table,
+    start: usize,
+    terms: &[&Expr],
+    used: &[usize],
+    order: &[SortKey],
+) -> (Plan, Cost) {
+    let rows = planner.table_rows(table);
+    let seek = rows.log2().max(1.0);
+    // Reading an entry is cheaper the smaller the entries of the b-tree are.
+    let table_size = (4 * table.estimated_row_size()) as f64;
+    let read = |size: usize| {
+        let size = (4 * size) as f64;
+        2f64.powf((1.0 + 15.0 * size.log2() / table_size.log2()) / 10.0)
+    };
+    let lookup = read(table.estimated_row_size());
+
+    // Each candidate, with its work, the rows it reads and how many of `terms`, and of
+    // those how many equalities, it uses to find them.
+    let mut candidates = Vec::new();
+    let mut rowid_positions = vec![start + table.columns.len()];
+    rowid_positions.extend(table.rowid_alias.map(|i| start + i));
+    for &position in &rowid_positions {
+        if let Some(rowid) = terms
+            .iter()
+            .find_map(|term| equality_term(term, position, start, None))
+        {
+            let plan = Plan::RowidSeek {
+                table: table.clone(),
+                rowid: rowid.clone(),
+            };
+            candidates.push((plan, seek + lookup, 1.0, 1, 1));
+        }
+
+        let order = KeyOrder::default();
+        let (lower, upper) = range_bounds(terms, table, start, position - start, order);
+        if lower.is_some() || upper.is_some() {
+            let matched = rows * range_selectivity(&lower, &upper);
+            let nterms = range_terms(terms, table, start, position - start, order);
+            let plan = Plan::Scan {
+                table: table.clone(),
+                reverse: false,
+                lower,
+                upper,
+            };
+            candidates.push((plan, seek + matched * lookup, matched, nterms, 0));
+        }
+    }
+
+    let plan = Plan::Scan {
+        table: table.clone(),
+        reverse: false,
+        lower: None,
+        upper: None,
+    };
+    candidates.push((plan, rows * lookup, rows, 0, 0));
+
+    for index in usable_indexes(planner.schema, table) {
+        let columns = index.key_columns(table);
+        let mut key = Vec::new();
+        for &(position, order) in &columns {
+            match terms.iter().find_map(|term| {
+                equality_term(term, start + position, start, Some(order.collation))
+            }) {
+                Some(value) => key.push(value.clone()),
+                None => break,
+            }
+        }
+        let (lower, upper, nrange) = match columns.get(key.len()) {
+            Some(&(position, order)) => {
+                let (lower, upper) = range_bounds(terms, table, start, position, order);
+                let nrange = range_terms(terms, table, start, position, order);
+                (lower, upper, nrange)
+            }
+            None => (None, None, 0),
+        };
+        let covering = covered_columns(index, table, start, used);
+
+        // An index that neither narrows down the rows nor holds every column read is only
+        // worth scanning for the order of its entries.
+        let full = key.is_empty() && lower.is_none() && upper.is_none();
+        if full
+            && covering.is_none()
+            && (order.is_empty() || index_order(index, 0, order, table).is_none())
+        {
+            continue;
+        }
+
+        let matched = planner.index_rows(index, &columns, &key, &lower, &upper, rows);
+        let mut work = matched * read(index.estimated_row_size(table));
+        if !full {
+            work += seek;
+        }
+        if covering.is_none() {
+            work += matched * lookup;
+        }
+        let (nterms, nequal) = (key.len() + nrange, key.len());
+        let plan = Plan::IndexSeek {
+            table: table.clone(),
+            index: Box::new(index.clone()),
+            reverse: false,
+            order: columns.iter().map(|&(_, order)| order).collect(),
+            key,
+            lower,
+            upper,
+            covering,
+        };
+        candidates.push((plan, work, matched, nterms, nequal));
+    }
+
+    // The terms a candidate does not use are checked against each row it reads, which
+    // SQLite guesses lets through most rows, but only a quarter if one of them is an
+    // equality.
+    let equalities = terms
+        .iter()
+        .filter(|term| {
+            matches!(
+                term,
+                Expr::Binary {
+                    op: BinaryOp::Eq,
+                    ..
+                }
+            )
+        })
+        .count();
+    candidates
+        .into_iter()
+        .map(|(mut plan, work, matched, nterms, nequal)| {
+            let unused = terms.len().saturating_sub(nterms);
+            let mut rows = matched * 0.93f64.powi(unused as i32);
+            if equalities > nequal {
+                rows /= 4.0;
+            }
+
+            let mut cost = Cost { work, rows };
+            if !order.is_empty() && !provide_order(&mut plan, order, table) {
+                cost.work += rows * rows.log2().max(1.0);
+            }
+            (plan, cost)
+        })
+        .min_by(|(_, left), (_, right)| {
+            left.work
+                .total_cmp(&right.work)
+                .then(left.rows.total_cmp(&right.rows))
+        })
+        .expect("a full scan is always possible")
+}
+
+/// The fraction of the rows that SQLite guesses a range with the given bounds holds:
+/// a quarter for an open range and a 64th for a closed one.
+fn range_selectivity(lower: &Option<Bound>, upper: &Option<Bound>) -> f64 {
+    match (lower, upper) {
+        (Some(_), Some(_)) => 1.0 / 64.0,
+        (Some(_), None) | (None, Some(_)) => 0.25,
+        (None, None) => 1.0,
+    }
+}
+
+/// How many of `terms` put a bound on the column at `position`, see [`range_bounds`].
+fn range_terms(
+    terms: &[&Expr],
+    table: &Table,
+    start: usize,
+    position: usize,
+    order: KeyOrder,
+) -> usize {
+    terms
+        .iter()
+        .filter(|&&term| {
+            let (lower, upper) = range_bounds(&[term], table, start, position, order);
+            lower.is_some() || upper.is_some()
+        })
+        .count()
+}
+
+/// The order to join `sources` in that is estimated to cost the least, as indexes into
+/// `sources`, given the terms of the WHERE clause and the columns the query reads
+/// (`used`). Like SQLite, only inner joins without CROSS JOIN are reordered.
+fn join_order(
+    planner: &Planner,
+    sources: &[Source],
+    terms: &[&Expr],
+    used: &[usize],
+) -> Vec<usize> {
+    let n = sources.len();
+    let written = (0..n).collect();
+    if !(2..=MAX_REORDERED_TABLES).contains(&n)
+        || sources.iter().any(|source| source.left || source.cross)
+    {
+        return written;
+    }
+
+    // The conditions of inner joins can be checked in any order.
+    let terms: Vec<&Expr> = terms
+        .iter()
+        .copied()
+        .chain(sources.iter().flat_map(|source| &source.condition))
+        .flat_map(Expr::conjuncts)
+        .collect();
+    let source_of = |i: usize| {
+        sources
+            .iter()
+            .position(|source| i < source.start + source.width)
+            .unwrap_or(n)
+    };
+
+    // The cheapest order found to join each set of tables, and its cost. Sets are
+    // extended one table at a time, so the sets come after their subsets.
+    let mut best: Vec<Option<(Vec<usize>, Cost)>> = vec![None; 1 << n];
+    best[0] = Some((Vec::new(), Cost::default()));
+    for set in 0..best.len() {
+        let Some((joined, cost)) = best[set].clone() else {
+            continue;
+        };
+
+        for next in (0..n).filter(|&next| set & (1 << next) == 0) {
+            // Lay the joined tables out one after the other, and the rest after them all.
+            let mut starts = vec![None; n];
+            let mut end = 0;
+            for &i in joined.iter().chain([&next]) {
+                starts[i] = Some(end);
+                end += sources[i].width;
+            }
+            let place = |i: usize| {
+                let k = source_of(i);
+                match starts.get(k).copied().flatten() {
+                    Some(start) => start + i - sources[k].start,
+                    None => usize::MAX / 2 + i,
+                }
+            };
+
+            let start = end - sources[next].width;
+            let placed: Vec<Expr> = terms
+                .iter()
+                .filter(|term| {
+                    let columns = term.referenced_columns();
+                    let level = columns.iter().map(|&i| source_of(i)).collect::<Vec<_>>();
+                    match set {
+                        0 => level.iter().all(|&k| k == next),
+                        _ => {
+                            level.contains(&next)
+                                && level.iter().all(|&k| k == next || set & (1 << k) != 0)
+                        }
+                    }
+                })
+                .map(|term| term.map_columns(&place))
+                .collect();
+            let placed: Vec<&Expr> = placed.iter().collect();
+            let used: Vec<usize> = used.iter().map(|&i| place(i)).collect();
+
+            let table = sources[next].table;
+            let (access, step) = access_path(planner, table, start, &placed, &used, &[]);
+            let hashed = set != 0
+                && is_full_scan(&access)
+                && placed
+                    .iter()
+                    .any(|term| hash_key(term, start, end).is_some());
+            // Building the hash table is costed as SQLite costs building the automatic
+            // index it would use instead: 7 N log2 N for the N rows that pass the terms
+            // on the table alone.
+            let work = match hashed {
+                true => {
+                    let local: Vec<&Expr> = placed
+                        .iter()
+                        .copied()
+                        .filter(|term| {
+                            term.referenced_columns()
+                                .iter()
+                                .all(|i| (start..end).contains(i))
+                        })
+                        .collect();
+                    let (_, build) = access_path(planner, table, start, &local, &used, &[]);
+                    step.work + 7.0 * build.rows * build.rows.log2().max(1.0) + cost.rows
+                }
+                false => step.work * cost.rows.max(1.0),
+            };
+            let total = Cost {
+                work: cost.work + work,
+                rows: cost.rows.max(1.0) * step.rows,
+            };
+
+            let extended = set | (1 << next);
+            if best[extended]
+                .as_ref()
+                .is_none_or(|(_, cost)| total.work < cost.work)
+            {
+                let mut order = joined.clone();
+                order.push(next);
+                best[extended] = Some((order, total));
+            }
+        }
+    }
+
+    best.pop().flatten().map_or(written, |(order, _)| order)
+}
+
+/// Whether `plan` reads every row of its table.
+fn is_full_scan(plan: &Plan) -> bool {
+    match plan {
+        Plan::Scan { lower, upper, .. } => lower.is_none() && upper.is_none(),
+        Plan::IndexSeek {
+            key, lower, upper, ..
+        } => key.is_empty() && lower.is_none() && upper.is_none(),
+        _ => false,
+    }
+}
+
+/// The [`Index::column_positions`] of `index` if it holds every column of `table`, whose
+/// columns start at `start`, that the query reads (`used`). Rowids are in every index.
+fn covered_columns(
+    index: &Index,
+    table: &Table,
+    start: usize,
+    used: &[usize],
+) -> Option<Vec<Option<usize>>> {
+    let positions = index.column_positions(table);
+    let rowid = table.columns.len();
+    let covers = used
+        .iter()
+        .filter(|&&i| (start..=start + rowid).contains(&i))
+        .all(|&i| {
+            let i = i - start;
+            i == rowid || Some(i) == table.rowid_alias || positions.contains(&Some(i))
+        });
+
+    covers.then_some(positions)
+}
+
+/// The indexes on `table` that hold every row of it.
+fn usable_indexes<'a>(schema: &'a Schema, table: &'a Table) -> impl Iterator<Item = &'a Index> {
+    schema.indexes(&table.name).filter(|index| !index.partial)
+}
+
+/// The position of the column that `expr` reads, if it is a column, with or without a
+/// collating sequence.
+fn column_position(expr: &Expr) -> Option<usize> {
+    match expr {
+        Expr::Column(i) => Some(*i),
+        Expr::Collate { expr, .. } => column_position(expr),
+        _ => None,
+    }
+}
+
+/// Whether `expr` only reads columns before position `available`.
+fn is_available(expr: &Expr, available: usize) -> bool {
+    !expr.contains_aggregate() && expr.referenced_columns().iter().all(|&i| i < available)
+}
+
+/// The expression that `term` requires the column at `position` to be equal to, if it
+/// only reads columns before position `available` and, if given, the comparison uses
+/// `collation`.
+fn equality_term(
+    term: &Expr,
+    position: usize,
+    available: usize,
+    collation: Option<Collation>,
+) -> Option<&Expr> {
+    let Expr::Binary {
+        left,
+        op: BinaryOp::Eq,
+        right,
+    } = term
+    else {
+        return None;
+    };
+    if collation.is_some_and(|collation| comparison_collation(left, right) != collation) {
+        return None;
+    }
+
+    match (left.as_ref(), right.as_ref()) {
+        (column, value) | (value, column)
+            if column_position(column) == Some(position) && is_available(value, available) =>
+        {
+            Some(value)
+        }
+        _ => None,
+    }
+}
+
+/// The lower and upper bounds that `terms` put on the indexed column at `position` of
+/// `table`, whose columns start at `start`, if the index entries are ordered by the
+/// collation the terms compare with. Comparisons, BETWEEN and a LIKE pattern that starts
+/// with a fixed prefix give bounds; the terms are still checked against each row.
+fn range_bounds(
+    terms: &[&Expr],
+    table: &Table,
+    start: usize,
+    position: usize,
+    order: KeyOrder,
+) -> (Option<Bound>, Option<Bound>) {
+    let is_column = |expr: &Expr| column_position(expr) == Some(start + position);
+    let bound = |value: &Expr, inclusive| {
+        Some(Bound {
+            value: value.clone(),
+            inclusive,
+        })
+    };
+
+    let (mut lower, mut upper) = (None, None);
+    for term in terms {
+        let (low, high) = match term {
+            Expr::Binary { left, op, right }
+                if comparison_collation(left, right) == order.collation =>
+            {
+                let (op, value) = if is_column(left) && is_available(right, start) {
+                    (*op, right.as_ref())
+                } else if is_column(right) && is_available(left, start) {
+                    match op {
+                        BinaryOp::Lt => (BinaryOp::Gt, left.as_ref()),
+                        BinaryOp::LtEq => (BinaryOp::GtEq, left.as_ref()),
+                        BinaryOp::Gt => (BinaryOp::Lt, left.as_ref()),
+                        BinaryOp::GtEq => (BinaryOp::LtEq, left.as_ref()),
+                        _ => continue,
+                    }
+                } else {
+                    continue;
+                };
+                match op {
+                    BinaryOp::Gt => (bound(value, false), None),
+                    BinaryOp::GtEq => (bound(value, true), None),
+                    BinaryOp::Lt => (None, bound(value, false)),
+                    BinaryOp::LtEq => (None, bound(value, true)),
+                    _ => continue,
+                }
+            }
+            Expr::Between {
+                expr,
+                low,
+                high,
+                negated: false,
+            } if is_column(expr)
+                && is_available(low, start)
+                && is_available(high, start)
+                && comparison_collation(expr, low) == order.collation
+                && comparison_collation(expr, high) == order.collation =>
+            {
+                (bound(low, true), bound(high, true))
+            }
+            // LIKE ignores the case of ASCII letters, so its prefix is a range of a
+            // NOCASE index on text. See <https://www.sqlite.org/optoverview.html#like_opt>.
+            Expr::Like {
+                expr,
+                pattern,
+                escape: None,
+                negated: false,
+            } if is_column(expr)
+                && order.collation == Collation::NoCase
+                && table
+                    .columns
+                    .get(position)
+                    .is_some_and(|column| column.affinity() == Affinity::Text) =>
+            {
+                like_bounds(pattern)
+            }
+            _ => continue,
+        };
+
+        if lower.is_none() {
+            lower = low;
+        }
+        if upper.is_none() {
+            upper = high;
+        }
+    }
+
+    (lower, upper)
+}
+
+/// The range of text that matches a constant LIKE pattern with a fixed prefix: from the
+/// prefix up to the prefix with its last character incremented.
+fn like_bounds(pattern: &Expr) -> (Option<Bound>, Option<Bound>) {
+    if !pattern.is_constant() {
+        return (None, None);
+    }
+    let Ok(SerialValue::String(pattern)) = pattern.eval(&[]) else {
+        return (None, None);
+    };
+    let prefix = match pattern.find(['%', '_']) {
+        Some(end) => &pattern[..end],
+        None => &pattern,
+    };
+    if prefix.is_empty() {
+        return (None, None);
+    }
+
+    let text = |txt: String| Expr::Literal(SerialValue::String(txt));
+    let lower = Bound {
+        value: text(prefix.to_string()),
+        inclusive: true,
+    };
+    let mut end = prefix.to_ascii_lowercase().into_bytes();
+    let upper = match end.last_mut() {
+        Some(last) if *last < 0x7f => {
+            *last += 1;
+            String::from_utf8(end).ok().map(|end| Bound {
+                value: text(end),
+                inclusive: false,
+            })
+        }
+        _ => None,
+    };
+
+    (Some(lower), upper)
+}
+
+/// The outer key, the inner key and the collation of `term` if it is an equality between
+/// an expression of the table whose columns are at positions `start..end` and an
+/// expression of the tables before it. The inner key is rebased onto the table's own rows.
+fn hash_key(term: &Expr, start: usize, end: usize) -> Option<(Expr, Expr, Collation)> {
+    let Expr::Binary {
+        left,
+        op: BinaryOp::Eq,
+        right,
+    } = term
+    else {
+        return None;
+    };
+
+    // Whether the expression reads only the table, or only the tables before it.
+    let side = |expr: &Expr| {
+        let columns = expr.referenced_columns();
+        if expr.contains_aggregate() || columns.is_empty() {
+            None
+        } else if columns.iter().all(|&i| i < start) {
+            Some(false)
+        } else if columns.iter().all(|&i| (start..end).contains(&i)) {
+            Some(true)
+        } else {
+            None
+        }
+    };
+    let collation = comparison_collation(left, right);
+    let (outer, inner) = match (side(left)?, side(right)?) {
+        (false, true) => (left, right),
+        (true, false) => (right, left),
+        _ => return None,
+    };
+
+    Some((
+        outer.as_ref().clone(),
+        inner.map_columns(&|i| i - start),
+        collation,
+    ))
+}
+
+/// ANDs `terms` together, or returns `None` if there are none.
+fn conjunction(terms: impl IntoIterator<Item = Expr>) -> Option<Expr> {
+    terms.into_iter().reduce(|left, right| Expr::Binary {
+        left: Box::new(left),
+        op: BinaryOp::And,
+        right: Box::new(right),
+    })
+}
+
+/// Whether `plan`, a way to read `table`, returns rows in the order of `keys`, possibly
+/// after switching it to scan backwards.
+fn provide_order(plan: &mut Plan, keys: &[SortKey], table: &Table) -> bool {
+    let Some(first) = keys.first() else {
+        return true;
+    };
+    let is_rowid = |key: &SortKey| match key.expr {
+        Expr::Column(i) => i == table.columns.len() || Some(i) == table.rowid_alias,
+        _ => false,
+    };
+
+    match plan {
+        // At most one row.
+        Plan::RowidSeek { .. } => true,
+        Plan::Scan { reverse, .. } if is_rowid(first) => {
+            *reverse = first.descending;
+            true
+        }
+        Plan::IndexSeek {
+            index,
+            key,
+            reverse,
+            ..
+        } => match index_order(index, key.len(), keys, table) {
+            Some(backward) => {
+                *reverse = backward;
+                true
+            }
+            None => false,
+        },
+        _ => false,
+    }
+}
+
+/// How SELECT DISTINCT tells apart the rows of `plan`, which reads `table` alone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DistinctRows {
+    /// No two rows can be equal.
+    Unique,
+    /// Equal rows come next to each other.
+    Ordered,
+    Unordered,
+}
+
+/// How the rows of `plan`, which reads `table` alone, can be told apart by their values of
+/// `columns`, compared with `collations`. Rows are unique if the columns include the rowid,
+/// or every column of a UNIQUE index on NOT NULL columns with the same collations. They
+/// come ordered from an index whose columns after those fixed by the seek are the
+/// distinct columns.
+fn distinct_rows(
+    plan: &Plan,
+    schema: &Schema,
+    table: &Table,
+    columns: &[Expr],
+    collations: &[Collation],
+) -> DistinctRows {
+    let Some(positions) = columns
+        .iter()
+        .map(column_position)
+        .collect::<Option<Vec<usize>>>()
+    else {
+        return DistinctRows::Unordered;
+    };
+    let has_column = |position: usize, collation: Collation| {
+        positions
+            .iter()
+            .zip(collations)
+            .any(|(&i, &c)| i == position && c == collation)
+    };
+
+    if positions
+        .iter()
+        .any(|&i| i == table.columns.len() || Some(i) == table.rowid_alias)
+    {
+        return DistinctRows::Unique;
+    }
+    let unique = schema.indexes(&table.name).any(|index| {
+        let key_columns = index.key_columns(table);
+        index.unique
+            && !index.partial
+            && key_columns.len() == index.columns.len()
+            && key_columns.iter().all(|&(position, order)| {
+                table.columns[position].not_null && has_column(position, order.collation)
+            })
+    });
+    if unique {
+        return DistinctRows::Unique;
+    }
+
+    let mut access = plan;
+    while let Plan::Filter { input, .. } = access {
+        access = input;
+    }
+    let Plan::IndexSeek { index, key, .. } = access else {
+        return DistinctRows::Unordered;
+    };
+    let key_columns = index.key_columns(table);
+    let nequal = key.len();
+    let fixed = |position| {
+        key_columns[..nequal]
+            .iter()
+            .any(|&(fixed, _)| fixed == position)
+    };
+    let free = positions.iter().filter(|&&i| !fixed(i)).count();
+    let ordered = key_columns.len() >= nequal + free
+        && key_columns[nequal..nequal + free]
+            .iter()
+            .all(|&(position, order)| has_column(position, order.collation))
+        && positions.iter().all(|&i| {
+            fixed(i)
+                || key_columns[nequal..nequal + free]
+                    .iter()
+                    .any(|&(p, _)| p == i)
+        });
+    match ordered {
+        true => DistinctRows::Ordered,
+        false => DistinctRows::Unordered,
+    }
+}
+
+/// Whether the entries of `index` whose first `nequal` columns are fixed come in the
+/// order of `keys`, either as they are or all reversed. Returns whether the entries must be
+/// read backwards. Entries are ordered by their columns, then by ascending rowid.
+fn index_order(index: &Index, nequal: usize, keys: &[SortKey], table: &Table) -> Option<bool> {
+    let columns = index.key_columns(table);
+    let is_fixed = |position| {
+        columns[..nequal]
+            .iter()
+            .any(|&(fixed, _)| fixed == position)
+    };
+
+    let mut next = nequal;
+    // The first key not fixed by the seek decides which way the entries are read.
+    let mut reverse = None;
+    for key in keys {
+        let position = column_position(&key.expr)?;
+
+        if is_fixed(position) {
+            continue;
+        }
+        match columns.get(next) {
+            Some(&(column, order)) if column == position => {
+                let backward = *reverse.get_or_insert(key.descending != order.descending);
+                let descending = order.descending != backward;
+                // NULLs are the smallest values.
+                if key.descending != descending
+                    || key.nulls_first == descending
+                    || key.collation != order.collation
+                {
+                    return None;
+                }
+                next += 1;
+            }
+            // The rowid orders entries whose columns are all equal.
+            _ => {
+                let backward = *reverse.get_or_insert(key.descending);
+                let is_rowid =
+                    position == table.columns.len() || Some(position) == table.rowid_alias;
+                return (next == index.columns.len() && key.descending == backward && is_rowid)
+                    .then_some(backward);
+            }
+        }
+    }
+
+    Some(reverse.unwrap_or(false))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::Error, sql::Explain};
+
+    fn query(sql: &str) -> Vec<Row> {
+        query_in("sample.db", sql)
+    }
+
+    fn query_in(path: &str, sql: &str) -> Vec<Row> {
+        let db = Database::open(path).unwrap();
+        let query = Query::prepare(&db, &Sql::from_str(sql).unwrap()).unwrap();
+        let rows = query
+            .rows(&db, Vec::new())
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        rows
+    }
+
+    fn plan_in(path: &str, sql: &str) -> Vec<String> {
+        let db = Database::open(path).unwrap();
+        Query::prepare(&db, &Sql::from_str(sql).unwrap())
+            .unwrap()
+            .query_plan()
+    }
+
+    #[test]
+    fn test_aggregate_query() {
+        assert_eq!(
+            query("SELECT count(*), count(DISTINCT color) FROM apples"),
+            vec![vec![SerialValue::I64(4), SerialValue::I64(4)]]
+        );
+        assert_eq!(
+            query("SELECT count(*) FROM apples WHERE 0"),
+            vec![vec![SerialValue::I64(0)]]
+        );
+        assert!(query("SELECT count(*) FROM apples WHERE 0 GROUP BY color").is_empty());
+        assert_eq!(
+            query("SELECT count(*) FROM apples HAVING count(*) > 3"),
+            vec![vec![SerialValue::I64(4)]]
+        );
+
+        let db = Database::open("sample.db").unwrap();
+        for sql in [
+            "SELECT name FROM apples HAVING 1",
+            "SELECT name FROM apples HAVING count(*) > 0",
+        ] {
+            let err = Query::prepare(&db, &Sql::from_str(sql).unwrap()).unwrap_err();
+            assert!(matches!(err, Error::Sql(message)
+                if message == "HAVING clause on a non-aggregate query"));
+        }
+    }
+
+    #[test]
+    fn test_group_by() {
+        let rows = query(
+            "SELECT description LIKE '%great%' AS great, count(*), max(name) FROM oranges GROUP BY 1 HAVING great",
+        );
+        assert_eq!(
+            rows,
+            vec![vec![
+                SerialValue::I64(1),
+                SerialValue::I64(3),
+                SerialValue::String("Tangerine".to_string()),
+            ]]
+        );
+    }
+
+    #[test]
+    fn test_order_by() {
+        let names = |rows: Vec<Row>| -> Vec<String> {
+            rows.into_iter().map(|row| row[0].display()).collect()
+        };
+
+        assert_eq!(
+            names(query("SELECT name FROM apples ORDER BY color DESC")),
+            ["Golden Delicious", "Fuji", "Granny Smith", "Honeycrisp"]
+        );
+        assert_eq!(
+            names(query("SELECT name, id AS n FROM oranges ORDER BY n DESC")),
+            [
+                "Navel Orange",
+                "Valencia Orange",
+                "Clementine",
+                "Tangerine",
+                "Tangelo",
+                "Mandarin"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_distinct() {
+        let values = |rows: Vec<Row>| -> Vec<String> {
+            rows.into_iter().map(|row| row[0].display()).collect()
+        };
+
+        // Rows come in the order they are first found, unless sorted.
+        assert_eq!(
+            values(query(
+                "SELECT DISTINCT description LIKE '%great%' FROM oranges"
+            )),
+            ["1", "0"]
+        );
+        assert_eq!(
+            values(query(
+                "SELECT DISTINCT id % 3 AS r FROM oranges ORDER BY r DESC LIMIT 2"
+            )),
+            ["2", "1"]
+        );
+        assert_eq!(
+            query("SELECT DISTINCT o.name FROM apples, oranges AS o").len(),
+            6
+        );
+        assert_eq!(query("SELECT DISTINCT id, name FROM apples").len(), 4);
+    }
+
+    #[test]
+    fn test_duplicate_rows() {
+        // indexed.db holds each (a, b) pair of t 20 times, with b from 0 to 49 and a the
+        // letter b % 10 places after 'a'.
+        let counts = |sql: &str| {
+            let mut counts = std::collections::BTreeMap::new();
+            for row in query_in("indexed.db", sql) {
+                let row: Vec<String> = row.iter().map(SerialValue::display).collect();
+                *counts.entry(row.join("|")).or_insert(0) += 1;
+            }
+            counts.into_iter().collect::<Vec<(String, usize)>>()
+        };
+        let every_b: Vec<(String, usize)> = (0..50).map(|b| (b.to_string(), 20)).collect();
+
+        // From the table, from a covering index, from an index seek and sorted.
+        let mut by_b = counts("SELECT b FROM t WHERE id > 0");
+        by_b.sort_by_key(|(b, _)| b.parse::<i64>().unwrap());
+        assert_eq!(by_b, every_b);
+        let mut every_pair: Vec<(String, usize)> = (0..50u8)
+            .map(|b| (format!("{}|{}", (b'a' + b % 10) as char, b), 20))
+            .collect();
+        every_pair.sort();
+        assert_eq!(counts("SELECT a, b FROM t"), every_pair);
+        assert_eq!(
+            counts("SELECT a, b FROM t WHERE a = 'c' AND b = 22"),
+            [("c|22".to_string(), 20)]
+        );
+        assert_eq!(
+            query_in("indexed.db", "SELECT b FROM t ORDER BY b").len(),
+            1000
+        );
+    }
+
+    #[test]
+    fn test_rowid_range() {
+        let ids = |rows: Vec<Row>| -> Vec<String> {
+            rows.into_iter().map(|row| row[0].display()).collect()
+        };
+
+        assert_eq!(
+            ids(query(
+                "SELECT id FROM oranges WHERE id > 2.5 AND id <= 5 ORDER BY id DESC"
+            )),
+            ["5", "4", "3"]
+        );
+        assert_eq!(
+            ids(query("SELECT id FROM oranges WHERE rowid < '3'")),
+            ["1", "2"]
+        );
+        assert!(query("SELECT id FROM oranges WHERE id > 'x'").is_empty());
+    }
+
+    #[test]
+    fn test_left_join() {
+        let text = |txt: &str| SerialValue::String(txt.to_string());
+
+        assert_eq!(
+            query("SELECT a.name, o.name FROM apples AS a LEFT JOIN oranges o ON o.id = a.id + 3"),
+            vec![
+                vec![text("Granny Smith"), text("Clementine")],
+                vec![text("Fuji"), text("Valencia Orange")],
+                vec![text("Honeycrisp"), text("Navel Orange")],
+                vec![text("Golden Delicious"), SerialValue::Null],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reordered_join() {
+        let text = |txt: &str| SerialValue::String(txt.to_string());
+
+        // Joined from the rowid range of oranges, whatever the order written.
+        for sql in [
+            "SELECT o.name, a.name FROM apples a JOIN oranges o ON o.id = a.id WHERE o.id > 2 ORDER BY 1",
+            "SELECT o.name, a.name FROM oranges o, apples a WHERE o.id = a.id AND o.id > 2 ORDER BY 1",
+        ] {
+            assert_eq!(
+                query(sql),
+                vec![
+                    vec![text("Clementine"), text("Golden Delicious")],
+                    vec![text("Tangerine"), text("Honeycrisp")],
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn test_query_plan() {
+        let db = Database::open("sample.db").unwrap();
+        let sql = Sql::from_str(
+            "EXPLAIN QUERY PLAN SELECT a.name, o.name FROM apples AS a \
+             LEFT JOIN oranges o ON o.id = a.id + 3 ORDER BY 1",
+        )
+        .unwrap();
+        assert_eq!(sql.explain, Some(Explain::QueryPlan));
+
+        assert_eq!(
+            Query::prepare(&db, &sql).unwrap().query_plan(),
+            [
+                "SCAN a",
+                "SEARCH o USING INTEGER PRIMARY KEY (rowid=?) LEFT-JOIN",
+                "USE TEMP B-TREE FOR ORDER BY",
+            ]
+        );
+
+        // Rows that include the rowid are all distinct.
+        let plan = |sql| {
+            Query::prepare(&db, &Sql::from_str(sql).unwrap())
+                .unwrap()
+                .query_plan()
+        };
+        assert_eq!(
+            plan("SELECT DISTINCT name FROM apples"),
+            ["SCAN apples", "USE TEMP B-TREE FOR DISTINCT"]
+        );
+        assert_eq!(
+            plan("SELECT DISTINCT id, name FROM apples"),
+            ["SCAN apples"]
+        );
+
+        // Without sqlite_stat1 every table is taken to be large, so a rowid lookup beats
+        // hashing the other table, whichever order the tables are written in.
+        for sql in [
+            "SELECT a.name FROM apples a JOIN oranges o ON a.id = o.id + 1",
+            "SELECT a.name FROM oranges o JOIN apples a ON a.id = o.id + 1",
+        ] {
+            assert_eq!(
+                plan(sql),
+                ["SCAN o", "SEARCH a USING INTEGER PRIMARY KEY (rowid=?)"]
+            );
+        }
+    }
+
+    #[test]
+    fn test_index_seek() {
+        // indexed.db: t(id INTEGER PRIMARY KEY, a, b, c COLLATE NOCASE, d) with indexes
+        // t_ab(a, b), t_c(c) and t_d(d DESC). Row i has a = 'a' + i % 10 and b = i % 50.
+        let ids = |ids: Vec<i64>| -> Vec<Row> {
+            ids.into_iter()
+                .map(|id| vec![SerialValue::I64(id)])
+                .collect()
+        };
+
+        let sql = "SELECT id FROM t WHERE a = 'e' AND b = 24";
+        assert_eq!(
+            plan_in("indexed.db", sql),
+            ["SEARCH t USING COVERING INDEX t_ab (a=? AND b=?)"]
+        );
+        assert_eq!(
+            query_in("indexed.db", sql),
+            ids((0..20).map(|k| 24 + 50 * k).collect())
+        );
+
+        // A range of the column after the equality prefix.
+        let sql = "SELECT id FROM t WHERE a = 'e' AND b >= 10 AND b < 30";
+        assert_eq!(
+            plan_in("indexed.db", sql),
+            ["SEARCH t USING COVERING INDEX t_ab (a=? AND b>? AND b<?)"]
+        );
+        assert_eq!(
+            query_in("indexed.db", sql),
+            ids((0..20)
+                .map(|k| 14 + 50 * k)
+                .chain((0..20).map(|k| 24 + 50 * k))
+                .collect())
+        );
+        assert_eq!(
+            query_in(
+                "indexed.db",
+                "SELECT id FROM t WHERE a = 'e' AND b BETWEEN 10 AND 20"
+            ),
+            ids((0..20).map(|k| 14 + 50 * k).collect())
+        );
+
+        // A range of the first column.
+        let sql = "SELECT count(*), min(b), max(b) FROM t WHERE a > 'h'";
+        assert_eq!(
+            plan_in("indexed.db", sql),
+            ["SEARCH t USING COVERING INDEX t_ab (a>?)"]
+        );
+        assert_eq!(
+            query_in("indexed.db", sql),
+            [[200, 8, 49].map(SerialValue::I64)]
+        );
+
+        // The prefix of a LIKE pattern is a range of the NOCASE index.
+        let sql = "SELECT count(*) FROM t WHERE c LIKE 'APRICOT%'";
+        assert_eq!(
+            plan_in("indexed.db", sql),
+            ["SEARCH t USING COVERING INDEX t_c (c>? AND c<?)"]
+        );
+        assert_eq!(query_in("indexed.db", sql), [[SerialValue::I64(334)]]);
+        assert_eq!(
+            query_in("indexed.db", "SELECT count(*) FROM t WHERE c LIKE 'Ban%6'"),
+            [[SerialValue::I64(47)]]
+        );
+
+        // The bounds of a range of a DESC column swap.
+        let sql = "SELECT id, d FROM t WHERE d >= 'd0500' AND d < 'd0503'";
+        assert_eq!(
+            plan_in("indexed.db", sql),
+            ["SEARCH t USING COVERING INDEX t_d (d>? AND d<?)"]
+        );
+        assert_eq!(
+            query_in("indexed.db", sql),
+            [(786, "d0502"), (643, "d0501"), (500, "d0500")]
+                .map(|(id, d)| vec![SerialValue::I64(id), SerialValue::String(d.into())])
+        );
+    }
+
+    #[test]
+    fn test_covering_index() {
+        let db = Database::open("indexed.db").unwrap();
+        let prepare = |sql| Query::prepare(&db, &Sql::from_str(sql).unwrap()).unwrap();
+
+        // The index holds a, b and the rowid, so the table is never opened.
+        let query = prepare("SELECT id, a FROM t WHERE a = 'j'");
+        assert_eq!(
+            query.query_plan(),
+            ["SEARCH t USING COVERING INDEX t_ab (a=?)"]
+        );
+        assert_eq!(query.program().to_string().matches("OpenRead").count(), 1);
+        let rows = query
+            .rows(&db, Vec::new())
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(
+            rows,
+            // In index order: by b, then by rowid.
+            (9..50)
+                .step_by(10)
+                .flat_map(|b| (0..20).map(move |k| b + 50 * k))
+                .map(|id| vec![SerialValue::I64(id), SerialValue::String("j".into())])
+                .collect::<Vec<_>>()
+        );
+
+        let query = prepare("SELECT a, b FROM t");
+        assert_eq!(query.query_plan(), ["SCAN t USING COVERING INDEX t_ab"]);
+        let rows = query
+            .rows(&db, Vec::new())
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(rows.len(), 1000);
+        assert!(rows.is_sorted());
+
+        // d is not in the index, so each entry looks up its row.
+        let query = prepare("SELECT id, d FROM t WHERE a = 'e' AND b = 24");
+        assert_eq!(
+            query.query_plan(),
+            ["SEARCH t USING INDEX t_ab (a=? AND b=?)"]
+        );
+        assert_eq!(query.program().to_string().matches("OpenRead").count(), 2);
+        let rows = query
+            .rows(&db, Vec::new())
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(rows.len(), 20);
+        assert_eq!(
+            rows[..3],
+            [(24, "d0168"), (74, "d0518"), (124, "d0868")]
+                .map(|(id, d)| vec![SerialValue::I64(id), SerialValue::String(d.into())])
+        );
+
+        // p(id INTEGER PRIMARY KEY, t TEXT UNIQUE) is indexed by sqlite_autoindex_p_1.
+        let query = prepare("SELECT * FROM p WHERE t = 'y'");
+        assert_eq!(
+            query.query_plan(),
+            ["SEARCH p USING COVERING INDEX sqlite_autoindex_p_1 (t=?)"]
+        );
+        let rows = query
+            .rows(&db, Vec::new())
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(
+            rows,
+            [vec![SerialValue::I64(2), SerialValue::String("y".into())]]
+        );
+    }
+
+    #[test]
+    fn test_reverse_index_scan() {
+        assert_eq!(
+            plan_in("indexed.db", "SELECT id FROM t ORDER BY a DESC, b DESC"),
+            ["SCAN t USING COVERING INDEX t_ab"]
+        );
+
+        let sql = "SELECT id FROM t WHERE a = 'c' AND b > 40 ORDER BY b DESC, id DESC";
+        assert_eq!(
+            plan_in("indexed.db", sql),
+            ["SEARCH t USING COVERING INDEX t_ab (a=? AND b>?)"]
+        );
+        assert_eq!(
+            query_in("indexed.db", sql),
+            [
+                992, 942, 892, 842, 792, 742, 692, 642, 592, 542, 492, 442, 392, 342, 292, 242,
+                192, 142, 92, 42
+            ]
+            .map(|id| vec![SerialValue::I64(id)])
+        );
+
+        // t_d is descending, so ORDER BY d DESC reads it forwards and ORDER BY d backwards.
+        let sql = "SELECT d FROM t WHERE d > 'd0990' ORDER BY d DESC";
+        assert_eq!(
+            plan_in("indexed.db", sql),
+            ["SEARCH t USING COVERING INDEX t_d (d>?)"]
+        );
+        assert_eq!(
+            query_in("indexed.db", sql),
+            (991..1000)
+                .rev()
+                .map(|i| vec![SerialValue::String(format!("d{i:04}"))])
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            query_in("indexed.db", "SELECT d FROM t WHERE d < 'd0003' ORDER BY d"),
+            (0..3)
+                .map(|i| vec![SerialValue::String(format!("d{i:04}"))])
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_program() {
+        let db = Database::open("sample.db").unwrap();
+        let sql = Sql::from_str("EXPLAIN SELECT name FROM apples WHERE id > 2").unwrap();
+        assert_eq!(sql.explain, Some(Explain::Program));
+
+        let query = Query::prepare(&db, &sql).unwrap();
+        let listing = query.program().to_string();
+        assert!(listing.starts_with("addr  opcode         p1    p2    p3    p4 "));
+        assert!(listing
+            .contains("\n0     Init           0     13    0                    0   Start at 13"));
+        assert!(listing
+            .contains("\n4     SeekGT         0     12    1     1              0   key=r[1]"));
+        assert!(listing
+            .contains("\n10      ResultRow      4     1     0                    0   output=r[4]"));
+        assert!(listing.contains("\n11    Next           0     5     0 "));
+
+        let rows = query
+            .rows(&db, Vec::new())
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(rows.len(), 2);
+    }
+}
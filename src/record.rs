@@ -1,8 +1,8 @@
-use anyhow::Context;
-
 use crate::{
     column::{Column, SerialType, SerialValue},
-    decode_varint,
+    decode_varint_at, encode_varint,
+    error::{bail, err, Result},
+    read_array, read_bytes,
 };
 
 #[derive(Debug, Clone)]
@@ -11,74 +11,60 @@ pub struct Record {
 }
 
 impl Record {
-    pub fn new(data: &[u8]) -> anyhow::Result<Self> {
-        let (header_length, hl_size) = decode_varint(&data[0..]).context("read record header")?;
+    /// Decodes a record. Errors are placed at their offset in `data`.
+    pub fn new(data: &[u8]) -> Result<Self> {
+        let (header_length, hl_size) = decode_varint_at(data, 0)?;
+        if header_length < hl_size as i64 || header_length as usize > data.len() {
+            bail!(
+                Corrupt(0, 0),
+                "record header of {} bytes does not fit in {} bytes",
+                header_length,
+                data.len()
+            );
+        }
         let header_length = header_length as usize;
         let mut header_index = hl_size;
         let mut data_index = header_length;
 
         let mut columns = Vec::new();
         while header_index < header_length {
-            let (int, len) = decode_varint(&data[header_index..]).context("read serial type")?;
+            let (int, len) = decode_varint_at(&data[..header_length], header_index)?;
+            let serial_type = SerialType::read(int).map_err(|err| err.at(0, header_index))?;
             header_index += len;
 
-            let serial_type = SerialType::read(int)?;
-
             let value = match &serial_type {
                 SerialType::Null => SerialValue::Null,
-                SerialType::I8 => SerialValue::I8(i8::from_be_bytes([data[data_index]])),
+                SerialType::I8 => SerialValue::I8(i8::from_be_bytes(read_array(data, data_index)?)),
                 SerialType::I16 => {
-                    SerialValue::I16(i16::from_be_bytes([data[data_index], data[data_index + 1]]))
+                    SerialValue::I16(i16::from_be_bytes(read_array(data, data_index)?))
+                }
+                // Reading the bytes into the top of a wider integer and shifting them down
+                // extends the sign.
+                SerialType::I24 => {
+                    let [a, b, c] = read_array(data, data_index)?;
+                    SerialValue::I24(i32::from_be_bytes([a, b, c, 0]) >> 8)
+                }
+                SerialType::I32 => {
+                    SerialValue::I32(i32::from_be_bytes(read_array(data, data_index)?))
+                }
+                SerialType::I48 => {
+                    let [a, b, c, d, e, f] = read_array(data, data_index)?;
+                    SerialValue::I48(i64::from_be_bytes([a, b, c, d, e, f, 0, 0]) >> 16)
+                }
+                SerialType::I64 => {
+                    SerialValue::I64(i64::from_be_bytes(read_array(data, data_index)?))
+                }
+                SerialType::Float64 => {
+                    SerialValue::Float64(f64::from_be_bytes(read_array(data, data_index)?))
                 }
-                SerialType::I24 => SerialValue::I24(i32::from_be_bytes([
-                    0,
-                    data[data_index],
-                    data[data_index + 1],
-                    data[data_index + 2],
-                ])),
-                SerialType::I32 => SerialValue::I32(i32::from_be_bytes([
-                    data[data_index],
-                    data[data_index + 1],
-                    data[data_index + 2],
-                    data[data_index + 3],
-                ])),
-                SerialType::I48 => SerialValue::I48(i64::from_be_bytes([
-                    0,
-                    0,
-                    data[data_index],
-                    data[data_index + 1],
-                    data[data_index + 2],
-                    data[data_index + 3],
-                    data[data_index + 4],
-                    data[data_index + 5],
-                ])),
-                SerialType::I64 => SerialValue::I64(i64::from_be_bytes([
-                    data[data_index],
-                    data[data_index + 1],
-                    data[data_index + 2],
-                    data[data_index + 3],
-                    data[data_index + 4],
-                    data[data_index + 5],
-                    data[data_index + 6],
-                    data[data_index + 7],
-                ])),
-                SerialType::Float64 => SerialValue::Float64(f64::from_be_bytes([
-                    data[data_index],
-                    data[data_index + 1],
-                    data[data_index + 2],
-                    data[data_index + 3],
-                    data[data_index + 4],
-                    data[data_index + 5],
-                    data[data_index + 6],
-                    data[data_index + 7],
-                ])),
                 SerialType::Zero => SerialValue::Zero,
                 SerialType::One => SerialValue::One,
                 SerialType::Blob(len) => {
-                    SerialValue::Blob((data[data_index..data_index + len]).to_vec())
+                    SerialValue::Blob(read_bytes(data, data_index, *len)?.to_vec())
                 }
                 SerialType::String(len) => {
-                    let val = String::from_utf8(data[data_index..(data_index + len)].to_vec())?;
+                    let val = String::from_utf8(read_bytes(data, data_index, *len)?.to_vec())
+                        .map_err(|_| err!(Corrupt(0, data_index), "text is not valid UTF-8"))?;
                     SerialValue::String(val)
                 }
             };
@@ -92,3 +78,74 @@ impl Record {
         Ok(Self { columns })
     }
 }
+
+/// Encodes `values` in the record format, so that [`Record::new`] reads them back.
+/// Integers are always stored in eight bytes.
+///
+/// See <https://www.sqlite.org/fileformat.html#record_format>.
+pub fn encode_record(values: &[SerialValue]) -> Vec<u8> {
+    let mut header = Vec::new();
+    let mut body = Vec::new();
+    for value in values {
+        let serial_type = match value {
+            SerialValue::Null => 0,
+            SerialValue::Zero => 8,
+            SerialValue::One => 9,
+            SerialValue::Float64(num) => {
+                body.extend_from_slice(&num.to_be_bytes());
+                7
+            }
+            SerialValue::Blob(blob) => {
+                body.extend_from_slice(blob);
+                12 + 2 * blob.len() as i64
+            }
+            SerialValue::String(txt) => {
+                body.extend_from_slice(txt.as_bytes());
+                13 + 2 * txt.len() as i64
+            }
+            value => {
+                body.extend_from_slice(&value.as_integer().unwrap_or_default().to_be_bytes());
+                6
+            }
+        };
+        header.extend(encode_varint(serial_type));
+    }
+
+    // The header size includes the varint that stores it.
+    let mut header_size = header.len() + 1;
+    while encode_varint(header_size as i64).len() + header.len() > header_size {
+        header_size += 1;
+    }
+
+    let mut record = encode_varint(header_size as i64);
+    record.extend(header);
+    record.extend(body);
+    record
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+
+    #[test]
+    fn test_sign_extension() {
+        // Header of 3 bytes: serial types 3 (24-bit) and 5 (48-bit).
+        let data = [
+            3, 3, 5, 0xfe, 0xee, 0x90, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe,
+        ];
+        let record = Record::new(&data).unwrap();
+        assert_eq!(record.columns[0].data(), &SerialValue::I24(-70000));
+        assert_eq!(record.columns[1].data(), &SerialValue::I48(-2));
+    }
+
+    #[test]
+    fn test_truncated() {
+        // A 10-byte string after a 2-byte header, with only 3 bytes present.
+        let err = Record::new(&[2, 33, b'a', b'b', b'c']).unwrap_err();
+        assert!(matches!(err, Error::Corrupt { offset: 2, .. }), "{err}");
+
+        // A header longer than the record.
+        assert!(Record::new(&[20, 1]).is_err());
+    }
+}
@@ -0,0 +1,659 @@
+use sqlparser::{
+    dialect::SQLiteDialect,
+    tokenizer::{Token, Tokenizer},
+};
+
+use crate::{
+    btree::{BTreeCursor, KeyOrder},
+    column::{Affinity, Collation, SerialValue},
+    database::Database,
+    error::{bail, err, Result},
+    expr::Scope,
+    record::Record,
+    sql::{IndexedColumn, Sql},
+};
+
+/// The tables and indexes described by the schema table on page 1.
+///
+/// See <https://www.sqlite.org/fileformat.html#storage_of_the_sql_database_schema>.
+#[derive(Debug, Clone)]
+pub struct Schema {
+    pub tables: Vec<Table>,
+    pub indexes: Vec<Index>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Table {
+    pub name: String,
+    pub root_page: usize,
+    pub columns: Vec<TableColumn>,
+    /// The column declared as INTEGER PRIMARY KEY, which is an alias for the rowid.
+    pub rowid_alias: Option<usize>,
+    pub without_rowid: bool,
+    /// The key columns of each UNIQUE and PRIMARY KEY constraint that SQLite enforces
+    /// with an index of its own, where the `n`-th is `sqlite_autoindex_<table>_<n>`.
+    pub unique_keys: Vec<Vec<IndexedColumn>>,
+    pub sql: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct TableColumn {
+    pub name: String,
+    /// The declared type of the column, or an empty string if it has none.
+    pub decl_type: String,
+    /// The collating sequence given by a COLLATE constraint, used to compare the values.
+    pub collation: Collation,
+    /// Whether the column has a NOT NULL constraint.
+    pub not_null: bool,
+}
+
+impl TableColumn {
+    pub fn affinity(&self) -> Affinity {
+        Affinity::from_decl_type(&self.decl_type)
+    }
+
+    /// Roughly how large the values of the column are, in units of 4 bytes, judged from
+    /// the declared type the way SQLite's query planner does: text and blobs take the
+    /// length the type gives, or else 20 bytes, and other values 4 bytes.
+    pub fn estimated_size(&self) -> usize {
+        if self.decl_type.is_empty() || self.affinity().is_numeric() {
+            return 1;
+        }
+
+        let length = self.decl_type.split_once('(').and_then(|(_, length)| {
+            let digits = length.trim_start();
+            let end = digits
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(digits.len());
+            digits[..end].parse::<usize>().ok()
+        });
+        (length.unwrap_or(16) / 4 + 1).min(255)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Index {
+    pub name: String,
+    pub table_name: String,
+    pub root_page: usize,
+    /// The indexed columns and expressions, in key order.
+    pub columns: Vec<IndexedColumn>,
+    /// Whether the index has a WHERE clause, and so only holds the rows it selects.
+    pub partial: bool,
+    /// Whether no two entries of the index have the same values.
+    pub unique: bool,
+    /// `None` for the indexes SQLite creates for UNIQUE and PRIMARY KEY constraints.
+    pub sql: Option<String>,
+}
+
+impl Index {
+    /// The position in the rows of `table` of each indexed column, and how the entries
+    /// are ordered by it: by the index's COLLATE clause or else the column's collation.
+    /// Stops at the first indexed expression.
+    pub fn key_columns(&self, table: &Table) -> Vec<(usize, KeyOrder)> {
+        self.columns
+            .iter()
+            .zip(self.column_positions(table))
+            .map_while(|(column, position)| {
+                let position = position?;
+                let declared = table.columns.get(position).map(|column| column.collation);
+                let order = KeyOrder {
+                    collation: column.collation.or(declared).unwrap_or_default(),
+                    descending: column.descending,
+                };
+                Some((position, order))
+            })
+            .collect()
+    }
+
+    /// Roughly how large the entries of the index are, see [`TableColumn::estimated_size`].
+    pub fn estimated_row_size(&self, table: &Table) -> usize {
+        let columns: usize = self
+            .column_positions(table)
+            .into_iter()
+            .map(|position| {
+                position
+                    .and_then(|i| table.columns.get(i))
+                    .map_or(1, TableColumn::estimated_size)
+            })
+            .sum();
+        columns + 1
+    }
+
+    /// The position in the rows of `table` of the value in each column of the index
+    /// records, except the trailing rowid. `None` for an indexed expression.
+    pub fn column_positions(&self, table: &Table) -> Vec<Option<usize>> {
+        let scope = table.scope(None);
+        self.columns
+            .iter()
+            .map(|column| scope.resolve(None, column.name.as_ref()?).ok())
+            .collect()
+    }
+}
+
+impl Schema {
+    pub fn read(db: &Database) -> Result<Self> {
+        let mut tables = vec![Table::schema_table()];
+        let mut indexes = Vec::new();
+
+        for entry in BTreeCursor::new(db, 1).entries() {
+            let (_, record) = entry?;
+            let text = |i: usize| match record.columns.get(i).map(|column| column.data()) {
+                Some(SerialValue::String(txt)) => Some(txt.clone()),
+                _ => None,
+            };
+            let (Some(kind), Some(name), Some(table_name)) = (text(0), text(1), text(2)) else {
+                bail!(Corrupt(1, 0), "malformed schema entry");
+            };
+            let root_page = record
+                .columns
+                .get(3)
+                .and_then(|column| column.data().as_integer())
+                .unwrap_or_default() as usize;
+
+            match kind.as_str() {
+                "table" => {
+                    let sql =
+                        text(4).ok_or_else(|| err!(Corrupt(1, 0), "table {} without sql", name))?;
+                    tables.push(Table::from_sql(name, root_page, sql)?);
+                }
+                "index" => {
+                    let sql = text(4);
+                    // Indexes without SQL enforce UNIQUE and PRIMARY KEY constraints.
+                    let (columns, partial, unique) = match &sql {
+                        Some(sql) => {
+                            let sql = Sql::from_str(sql)?;
+                            let columns = sql.index_column.unwrap_or_default();
+                            (columns, sql.partial_index, sql.unique_index)
+                        }
+                        None => (Vec::new(), false, true),
+                    };
+                    indexes.push(Index {
+                        name,
+                        table_name,
+                        root_page,
+                        columns,
+                        partial,
+                        unique,
+                        sql,
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        for index in indexes.iter_mut().filter(|index| index.sql.is_none()) {
+            let Some(table) = tables
+                .iter()
+                .find(|table| table.name.eq_ignore_ascii_case(&index.table_name))
+            else {
+                continue;
+            };
+            let prefix = format!("sqlite_autoindex_{}_", table.name);
+            let n = index
+                .name
+                .get(prefix.len()..)
+                .filter(|_| index.name[..prefix.len()].eq_ignore_ascii_case(&prefix))
+                .and_then(|n| n.parse::<usize>().ok());
+            if let Some(key) = n.and_then(|n| table.unique_keys.get(n.checked_sub(1)?)) {
+                index.columns = key.clone();
+            }
+        }
+
+        Ok(Self { tables, indexes })
+    }
+
+    /// Looks up a table by name, ignoring case.
+    pub fn table(&self, name: &str) -> Option<&Table> {
+        let name = match name.to_ascii_lowercase().as_str() {
+            "sqlite_master" | "sqlite_temp_master" | "sqlite_temp_schema" => "sqlite_schema",
+            _ => name,
+        };
+
+        self.tables
+            .iter()
+            .find(|table| table.name.eq_ignore_ascii_case(name))
+    }
+
+    /// The indexes on the table called `table_name`.
+    pub fn indexes<'a>(&'a self, table_name: &'a str) -> impl Iterator<Item = &'a Index> + 'a {
+        self.indexes
+            .iter()
+            .filter(move |index| index.table_name.eq_ignore_ascii_case(table_name))
+    }
+}
+
+/// Words that end the type name of a column definition and start its constraints.
+const COLUMN_CONSTRAINTS: [&str; 11] = [
+    "CONSTRAINT",
+    "PRIMARY",
+    "NOT",
+    "NULL",
+    "UNIQUE",
+    "CHECK",
+    "DEFAULT",
+    "COLLATE",
+    "REFERENCES",
+    "GENERATED",
+    "AS",
+];
+
+/// Words that start a table constraint rather than a column definition.
+const TABLE_CONSTRAINTS: [&str; 5] = ["CONSTRAINT", "PRIMARY", "UNIQUE", "CHECK", "FOREIGN"];
+
+fn is_word(token: &Token, words: &[&str]) -> bool {
+    matches!(token, Token::Word(word) if word.quote_style.is_none()
+        && words.iter().any(|w| word.value.eq_ignore_ascii_case(w)))
+}
+
+fn name_of(token: &Token) -> Option<String> {
+    match token {
+        Token::Word(word) => Some(word.value.clone()),
+        Token::SingleQuotedString(name) | Token::DoubleQuotedString(name) => Some(name.clone()),
+        _ => None,
+    }
+}
+
+/// Reads the parenthesized column list of a table constraint, such as
+/// `(a COLLATE nocase, b DESC)`, at the start of `tokens`.
+fn key_columns(tokens: &[&Token]) -> Result<Vec<IndexedColumn>> {
+    let end = tokens
+        .iter()
+        .position(|token| **token == Token::RParen)
+        .unwrap_or(tokens.len());
+    let mut columns = Vec::new();
+    for column in tokens
+        .get(1..end)
+        .unwrap_or_default()
+        .split(|token| **token == Token::Comma)
+    {
+        let Some(name) = column.first().and_then(|token| name_of(token)) else {
+            bail!(Corrupt(1, 0), "malformed key column");
+        };
+        let collation = match column.iter().position(|token| is_word(token, &["COLLATE"])) {
+            Some(at) => match column.get(at + 1).and_then(|token| name_of(token)) {
+                Some(name) => Some(Collation::from_name(&name)?),
+                None => bail!(Corrupt(1, 0), "malformed key column {}", name),
+            },
+            None => None,
+        };
+        columns.push(IndexedColumn {
+            name: Some(name),
+            collation,
+            descending: column.last().is_some_and(|token| is_word(token, &["DESC"])),
+        });
+    }
+
+    Ok(columns)
+}
+
+impl Table {
+    /// The schema table itself, which is not described by a row of its own.
+    fn schema_table() -> Self {
+        Self::from_sql(
+            "sqlite_schema".to_string(),
+            1,
+            "CREATE TABLE sqlite_schema(type text, name text, tbl_name text, rootpage integer, sql text)"
+                .to_string(),
+        )
+        .expect("schema table definition is valid")
+    }
+
+    /// Reads the column definitions of a CREATE TABLE statement.
+    ///
+    /// Column types in SQLite are arbitrary sequences of names and may be omitted, which
+    /// sqlparser does not accept, so the column list is split up by hand.
+    pub fn from_sql(name: String, root_page: usize, sql: String) -> Result<Self> {
+        let tokens: Vec<Token> = Tokenizer::new(&SQLiteDialect {}, &sql)
+            .tokenize()
+            .map_err(|err| err!(Corrupt(1, 0), "tokenize schema of {}: {}", name, err))?
+            .into_iter()
+            .filter(|token| !matches!(token, Token::Whitespace(_)))
+            .collect();
+
+        let Some(start) = tokens.iter().position(|token| *token == Token::LParen) else {
+            bail!(Unsupported, "table definition: {}", sql);
+        };
+
+        // Split the column list on top-level commas.
+        let mut definitions = vec![Vec::new()];
+        let mut depth = 0;
+        let mut end = tokens.len();
+        for (i, token) in tokens.iter().enumerate().skip(start + 1) {
+            match token {
+                Token::LParen => depth += 1,
+                Token::RParen if depth == 0 => {
+                    end = i;
+                    break;
+                }
+                Token::RParen => depth -= 1,
+                Token::Comma if depth == 0 => {
+                    definitions.push(Vec::new());
+                    continue;
+                }
+                _ => {}
+            }
+            if let Some(definition) = definitions.last_mut() {
+                definition.push(token);
+            }
+        }
+
+        let without_rowid = tokens[end..]
+            .windows(2)
+            .any(|pair| is_word(&pair[0], &["WITHOUT"]) && is_word(&pair[1], &["ROWID"]));
+
+        let mut columns = Vec::new();
+        // The UNIQUE and PRIMARY KEY constraints in order, and which is the primary key.
+        let mut keys = Vec::new();
+        let mut primary_key = None;
+        let mut descending_key = false;
+        for definition in definitions {
+            let Some(first) = definition.first() else {
+                bail!(Corrupt(1, 0), "malformed table definition: {}", sql);
+            };
+
+            if is_word(first, &TABLE_CONSTRAINTS) {
+                if let Some(at) = definition
+                    .windows(2)
+                    .position(|pair| is_word(pair[0], &["PRIMARY"]) && is_word(pair[1], &["KEY"]))
+                {
+                    primary_key = Some(keys.len());
+                    keys.push(key_columns(&definition[at + 2..])?);
+                } else if let Some(at) = definition
+                    .iter()
+                    .position(|token| is_word(token, &["UNIQUE"]))
+                {
+                    keys.push(key_columns(&definition[at + 1..])?);
+                }
+                continue;
+            }
+
+            let name =
+                name_of(first).ok_or_else(|| err!(Corrupt(1, 0), "malformed column: {}", sql))?;
+            let type_len = definition[1..]
+                .iter()
+                .position(|token| is_word(token, &COLUMN_CONSTRAINTS))
+                .unwrap_or(definition.len() - 1);
+            let decl_type = definition[1..1 + type_len]
+                .iter()
+                .map(|token| token.to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+                .replace(" (", "(")
+                .replace("( ", "(")
+                .replace(" )", ")")
+                .replace(" ,", ",");
+
+            let constraints = &definition[1 + type_len..];
+            for (at, token) in constraints.iter().enumerate() {
+                let primary = is_word(token, &["PRIMARY"])
+                    && constraints
+                        .get(at + 1)
+                        .is_some_and(|token| is_word(token, &["KEY"]));
+                if !primary && !is_word(token, &["UNIQUE"]) {
+                    continue;
+                }
+                let descending = primary
+                    && constraints
+                        .get(at + 2)
+                        .is_some_and(|token| is_word(token, &["DESC"]));
+                if primary {
+                    primary_key = Some(keys.len());
+                    descending_key = descending;
+                }
+                // The column's own collation orders the index.
+                keys.push(vec![IndexedColumn {
+                    name: Some(name.clone()),
+                    collation: None,
+                    descending,
+                }]);
+            }
+            let collation = match constraints
+                .iter()
+                .position(|token| is_word(token, &["COLLATE"]))
+                .and_then(|at| constraints.get(at + 1))
+                .and_then(|token| name_of(token))
+            {
+                Some(name) => Collation::from_name(&name)?,
+                None => Collation::Binary,
+            };
+
+            let not_null = constraints
+                .windows(2)
+                .any(|pair| is_word(pair[0], &["NOT"]) && is_word(pair[1], &["NULL"]));
+
+            columns.push(TableColumn {
+                name,
+                decl_type,
+                collation,
+                not_null,
+            });
+        }
+
+        // Only a single-column INTEGER PRIMARY KEY aliases the rowid, and a column
+        // constraint declared DESC does not.
+        // See <https://www.sqlite.org/lang_createtable.html#rowid>.
+        let rowid_alias = match primary_key.map(|i| keys[i].as_slice()) {
+            Some([key]) if !without_rowid && !descending_key => columns.iter().position(|column| {
+                key.name
+                    .as_ref()
+                    .is_some_and(|name| column.name.eq_ignore_ascii_case(name))
+                    && column.decl_type.eq_ignore_ascii_case("INTEGER")
+            }),
+            _ => None,
+        };
+
+        // Every other constraint gets an index, unless an earlier one has the same
+        // columns and collations.
+        let collation = |key: &IndexedColumn| {
+            let declared = columns.iter().find(|column| {
+                key.name
+                    .as_ref()
+                    .is_some_and(|name| column.name.eq_ignore_ascii_case(name))
+            });
+            key.collation
+                .or(declared.map(|column| column.collation))
+                .unwrap_or_default()
+        };
+        let same = |a: &[IndexedColumn], b: &[IndexedColumn]| {
+            a.len() == b.len()
+                && a.iter().zip(b).all(|(a, b)| {
+                    matches!((&a.name, &b.name), (Some(x), Some(y)) if x.eq_ignore_ascii_case(y))
+                        && collation(a) == collation(b)
+                })
+        };
+        let mut unique_keys: Vec<Vec<IndexedColumn>> = Vec::new();
+        for (i, key) in keys.into_iter().enumerate() {
+            let is_alias = rowid_alias.is_some() && Some(i) == primary_key;
+            if !is_alias && !unique_keys.iter().any(|earlier| same(earlier, &key)) {
+                unique_keys.push(key);
+            }
+        }
+
+        Ok(Self {
+            name,
+            root_page,
+            columns,
+            rowid_alias,
+            without_rowid,
+            unique_keys,
+            sql,
+        })
+    }
+
+    /// The names rows of this table can be referred to by: its columns, followed by the
+    /// hidden rowid column. `alias` replaces the table name for qualified references.
+    pub fn scope(&self, alias: Option<&str>) -> Scope {
+        let table = alias.unwrap_or(&self.name);
+
+        let mut scope = Scope::default();
+        for column in &self.columns {
+            scope.push(
+                Some(table),
+                &column.name,
+                column.affinity(),
+                column.collation,
+                false,
+            );
+        }
+        scope.push(
+            Some(table),
+            "rowid",
+            Affinity::Integer,
+            Collation::Binary,
+            true,
+        );
+
+        scope
+    }
+
+    /// The name of the column at position `i` of the rows of the table, see
+    /// [`Table::scope`].
+    pub fn column_name(&self, i: usize) -> &str {
+        self.columns
+            .get(i)
+            .map_or("rowid", |column| column.name.as_str())
+    }
+
+    /// Roughly how large the rows of the table are, see [`TableColumn::estimated_size`].
+    pub fn estimated_row_size(&self) -> usize {
+        let columns: usize = self.columns.iter().map(TableColumn::estimated_size).sum();
+        columns + self.rowid_alias.is_none() as usize
+    }
+
+    /// Expands a stored record into a row laid out as described by [`Table::scope`].
+    pub fn row(&self, rowid: i64, record: &Record) -> Vec<SerialValue> {
+        let mut row: Vec<SerialValue> = (0..self.columns.len())
+            .map(|i| match record.columns.get(i) {
+                // The rowid alias is stored as NULL in the record.
+                _ if Some(i) == self.rowid_alias => SerialValue::I64(rowid),
+                Some(column) => self.value(i, column.data()),
+                // Columns added by ALTER TABLE are missing from older records.
+                None => SerialValue::Null,
+            })
+            .collect();
+        row.push(SerialValue::I64(rowid));
+
+        row
+    }
+
+    /// Builds a row like [`Table::row`] from an index record alone, given the
+    /// [`Index::column_positions`] of the index. Columns the index does not hold are NULL.
+    pub fn row_from_index(
+        &self,
+        positions: &[Option<usize>],
+        rowid: i64,
+        record: &Record,
+    ) -> Vec<SerialValue> {
+        let mut row = vec![SerialValue::Null; self.columns.len() + 1];
+        for (position, column) in positions.iter().zip(&record.columns) {
+            if let Some(i) = *position {
+                row[i] = self.value(i, column.data());
+            }
+        }
+        if let Some(i) = self.rowid_alias {
+            row[i] = SerialValue::I64(rowid);
+        }
+        row[self.columns.len()] = SerialValue::I64(rowid);
+
+        row
+    }
+
+    /// The value of the `i`-th column as stored in a record.
+    fn value(&self, i: usize, stored: &SerialValue) -> SerialValue {
+        match self.columns.get(i) {
+            // Reals without a fractional part may be stored as integers.
+            Some(column) if column.affinity() == Affinity::Real => {
+                stored.apply_affinity(Affinity::Real)
+            }
+            _ => stored.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table_from_sql() {
+        let table = Table::from_sql(
+            "t".to_string(),
+            2,
+            "CREATE TABLE t (id integer primary key autoincrement, \"full name\" varchar(255) not null, seq, price DECIMAL(10, 2), UNIQUE(seq))"
+                .to_string(),
+        )
+        .unwrap();
+
+        let columns: Vec<_> = table
+            .columns
+            .iter()
+            .map(|column| (column.name.as_str(), column.decl_type.as_str()))
+            .collect();
+        assert_eq!(
+            columns,
+            vec![
+                ("id", "integer"),
+                ("full name", "varchar(255)"),
+                ("seq", ""),
+                ("price", "DECIMAL(10, 2)"),
+            ]
+        );
+        assert_eq!(table.rowid_alias, Some(0));
+        assert!(!table.without_rowid);
+        assert_eq!(table.unique_keys.len(), 1);
+
+        let table = Table::from_sql(
+            "t".to_string(),
+            2,
+            "CREATE TABLE t (a INT PRIMARY KEY, b TEXT COLLATE nocase)".to_string(),
+        )
+        .unwrap();
+        assert_eq!(table.rowid_alias, None);
+        assert_eq!(table.columns[1].collation, Collation::NoCase);
+
+        // UNIQUE(p) repeats the primary key but not UNIQUE (q), which compares with
+        // another collation.
+        let table = Table::from_sql(
+            "t".to_string(),
+            2,
+            "CREATE TABLE t (p INT UNIQUE, q, r, PRIMARY KEY(q COLLATE nocase, r DESC), UNIQUE(p), CONSTRAINT u UNIQUE (q))"
+                .to_string(),
+        )
+        .unwrap();
+        let column = |name: &str, collation, descending| IndexedColumn {
+            name: Some(name.to_string()),
+            collation,
+            descending,
+        };
+        assert_eq!(
+            table.unique_keys,
+            vec![
+                vec![column("p", None, false)],
+                vec![
+                    column("q", Some(Collation::NoCase), false),
+                    column("r", None, true)
+                ],
+                vec![column("q", None, false)],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_schema() {
+        let db = Database::open("sample.db").unwrap();
+        let schema = Schema::read(&db).unwrap();
+
+        let apples = schema.table("APPLES").unwrap();
+        assert_eq!(apples.root_page, 2);
+        assert_eq!(apples.rowid_alias, Some(0));
+        assert!(schema.table("sqlite_master").is_some());
+
+        let db = Database::open("indexed.db").unwrap();
+        let schema = Schema::read(&db).unwrap();
+        let index = schema.indexes("p").next().unwrap();
+        assert_eq!(index.name, "sqlite_autoindex_p_1");
+        assert_eq!(index.columns, schema.table("p").unwrap().unique_keys[0]);
+        assert_eq!(index.columns[0].name.as_deref(), Some("t"));
+    }
+}
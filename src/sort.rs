@@ -0,0 +1,325 @@
+use std::{cmp::Ordering, collections::BinaryHeap, mem};
+
+use crate::{
+    column::{Collation, SerialValue},
+    error::Result,
+    expr::Expr,
+    temp::{size_of_values, Records, TempFile},
+};
+
+/// How many bytes of rows a [`Sorter`] keeps in memory before it spills them to a
+/// temporary file.
+pub const DEFAULT_SORT_MEMORY: usize = 32 << 20;
+
+/// How many runs are merged at once. More runs are first merged in passes into fewer,
+/// longer runs, so that only this many temporary files are open at a time.
+const MERGE_FAN_IN: usize = 16;
+
+/// A term of an ORDER BY clause, bound to the rows being sorted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SortKey {
+    pub expr: Expr,
+    pub descending: bool,
+    pub nulls_first: bool,
+    pub collation: Collation,
+}
+
+/// Compares the key values of two rows.
+pub fn compare_keys(keys: &[SortKey], left: &[SerialValue], right: &[SerialValue]) -> Ordering {
+    for (key, (left, right)) in keys.iter().zip(left.iter().zip(right)) {
+        let ordering = match (left, right) {
+            (SerialValue::Null, SerialValue::Null) => Ordering::Equal,
+            (SerialValue::Null, _) if key.nulls_first => Ordering::Less,
+            (SerialValue::Null, _) => Ordering::Greater,
+            (_, SerialValue::Null) if key.nulls_first => Ordering::Greater,
+            (_, SerialValue::Null) => Ordering::Less,
+            (left, right) if key.descending => key.collation.compare(left, right).reverse(),
+            (left, right) => key.collation.compare(left, right),
+        };
+        if ordering.is_ne() {
+            return ordering;
+        }
+    }
+
+    Ordering::Equal
+}
+
+/// An external merge sort. Rows are sorted in memory until they exceed the memory
+/// budget, then written to a temporary file as a sorted run; the runs are merged, at most
+/// [`MERGE_FAN_IN`] at a time, when the rows are read back. Rows with equal keys keep the
+/// order they were added in.
+#[derive(Debug)]
+pub struct Sorter<'a> {
+    keys: &'a [SortKey],
+    memory: usize,
+    /// The key values and the row of each buffered row.
+    buffer: Vec<(Vec<SerialValue>, Vec<SerialValue>)>,
+    buffered_bytes: usize,
+    runs: Vec<TempFile>,
+}
+
+impl<'a> Sorter<'a> {
+    /// Creates a sorter that keeps about `memory` bytes of rows in memory.
+    pub fn new(keys: &'a [SortKey], memory: usize) -> Self {
+        Self {
+            keys,
+            memory,
+            buffer: Vec::new(),
+            buffered_bytes: 0,
+            runs: Vec::new(),
+        }
+    }
+
+    /// Adds a row, given the values of the sort keys for it.
+    pub fn push(&mut self, key: Vec<SerialValue>, row: Vec<SerialValue>) -> Result<()> {
+        self.buffered_bytes += size_of_values(&key) + size_of_values(&row);
+        self.buffer.push((key, row));
+
+        if self.buffered_bytes > self.memory {
+            self.spill()?;
+        }
+
+        Ok(())
+    }
+
+    /// The number of sorted runs written to temporary files so far.
+    pub fn spilled_runs(&self) -> usize {
+        self.runs.len()
+    }
+
+    /// Sorts the buffered rows and writes them to a new run.
+    fn spill(&mut self) -> Result<()> {
+        self.sort_buffer();
+
+        let rows = mem::take(&mut self.buffer).into_iter().map(Ok);
+        self.runs.push(write_run(rows)?);
+        self.buffered_bytes = 0;
+
+        Ok(())
+    }
+
+    fn sort_buffer(&mut self) {
+        let keys = self.keys;
+        self.buffer
+            .sort_by(|(left, _), (right, _)| compare_keys(keys, left, right));
+    }
+
+    /// Returns the rows in sorted order.
+    pub fn finish(mut self) -> Result<Sorted<'a>> {
+        if self.runs.is_empty() {
+            self.sort_buffer();
+            return Ok(Sorted::Memory(self.buffer.into_iter()));
+        }
+
+        if !self.buffer.is_empty() {
+            self.spill()?;
+        }
+
+        // Merging consecutive runs keeps the earlier rows of equal keys in earlier runs.
+        let mut runs = mem::take(&mut self.runs);
+        while runs.len() > MERGE_FAN_IN {
+            let mut merged = Vec::with_capacity(runs.len().div_ceil(MERGE_FAN_IN));
+            let mut runs_left = runs.into_iter();
+            loop {
+                let group: Vec<TempFile> = runs_left.by_ref().take(MERGE_FAN_IN).collect();
+                if group.is_empty() {
+                    break;
+                }
+                merged.push(write_run(Merge::new(self.keys, group)?)?);
+            }
+            runs = merged;
+        }
+
+        Ok(Sorted::Merge(Merge::new(self.keys, runs)?))
+    }
+}
+
+/// Writes sorted rows, with the values of their sort keys first, to a new run.
+fn write_run(
+    rows: impl Iterator<Item = Result<(Vec<SerialValue>, Vec<SerialValue>)>>,
+) -> Result<TempFile> {
+    let run = TempFile::create()?;
+    let mut out = run.writer()?;
+    for row in rows {
+        let (mut values, row) = row?;
+        values.extend(row);
+        out.write(&values)?;
+    }
+    out.flush()?;
+
+    Ok(run)
+}
+
+/// The rows of a [`Sorter`], in order.
+#[derive(Debug)]
+pub enum Sorted<'a> {
+    Memory(std::vec::IntoIter<(Vec<SerialValue>, Vec<SerialValue>)>),
+    Merge(Merge<'a>),
+}
+
+impl Iterator for Sorted<'_> {
+    type Item = Result<Vec<SerialValue>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Sorted::Memory(rows) => rows.next().map(|(_, row)| Ok(row)),
+            Sorted::Merge(merge) => merge.next().map(|row| row.map(|(_, row)| row)),
+        }
+    }
+}
+
+/// Sorted runs being merged into one, yielding the key values and the row of each row.
+#[derive(Debug)]
+pub struct Merge<'a> {
+    keys: &'a [SortKey],
+    runs: Vec<Records>,
+    /// The smallest row not returned yet of each run that has rows left.
+    heads: BinaryHeap<Head<'a>>,
+}
+
+impl<'a> Merge<'a> {
+    fn new(keys: &'a [SortKey], files: Vec<TempFile>) -> Result<Self> {
+        let mut merge = Self {
+            keys,
+            runs: Vec::with_capacity(files.len()),
+            heads: BinaryHeap::with_capacity(files.len()),
+        };
+        for file in files {
+            merge.runs.push(file.into_records()?);
+            merge.advance(merge.runs.len() - 1)?;
+        }
+
+        Ok(merge)
+    }
+
+    /// Reads the next row of run `run` into the heads, if it has one.
+    fn advance(&mut self, run: usize) -> Result<()> {
+        if let Some(mut values) = self.runs[run].next().transpose()? {
+            let row = values.split_off(self.keys.len().min(values.len()));
+            self.heads.push(Head {
+                keys: self.keys,
+                key: values,
+                row,
+                run,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+impl Iterator for Merge<'_> {
+    type Item = Result<(Vec<SerialValue>, Vec<SerialValue>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let head = self.heads.pop()?;
+        if let Err(err) = self.advance(head.run) {
+            return Some(Err(err));
+        }
+        Some(Ok((head.key, head.row)))
+    }
+}
+
+/// The smallest row not returned yet of a run being merged. Heads are ordered so that the
+/// largest is the row to return next: the one with the smallest key and, among equal keys,
+/// the one from the earliest run, which holds the earlier rows.
+#[derive(Debug)]
+struct Head<'a> {
+    keys: &'a [SortKey],
+    key: Vec<SerialValue>,
+    row: Vec<SerialValue>,
+    run: usize,
+}
+
+impl Ord for Head<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare_keys(self.keys, &other.key, &self.key).then(other.run.cmp(&self.run))
+    }
+}
+
+impl PartialOrd for Head<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Head<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Head<'_> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sort_spills_and_merges() {
+        let keys = [
+            SortKey {
+                expr: Expr::Column(0),
+                descending: true,
+                nulls_first: false,
+                collation: Collation::NoCase,
+            },
+            SortKey {
+                expr: Expr::Column(1),
+                descending: false,
+                nulls_first: true,
+                collation: Collation::Binary,
+            },
+        ];
+        let names = ["b", "A", "a", "B", "c"];
+
+        let mut sorter = Sorter::new(&keys, 256);
+        let mut expected = Vec::new();
+        for i in 0..100i64 {
+            let name = match i % 7 {
+                0 => SerialValue::Null,
+                _ => SerialValue::String(names[i as usize % names.len()].to_string()),
+            };
+            let key = vec![name, SerialValue::I64(-i / 3)];
+            expected.push((key.clone(), vec![SerialValue::I64(i)]));
+            sorter.push(key, vec![SerialValue::I64(i)]).unwrap();
+        }
+        assert!(sorter.spilled_runs() > 1);
+
+        expected.sort_by(|(left, _), (right, _)| compare_keys(&keys, left, right));
+        let expected: Vec<_> = expected.into_iter().map(|(_, row)| row).collect();
+        let sorted = sorter
+            .finish()
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(sorted, expected);
+    }
+
+    #[test]
+    fn test_sort_merges_runs_in_passes() {
+        let keys = [SortKey {
+            expr: Expr::Column(0),
+            descending: false,
+            nulls_first: true,
+            collation: Collation::Binary,
+        }];
+
+        // Enough runs for two merge passes, with many rows of equal keys across them.
+        let mut sorter = Sorter::new(&keys, 256);
+        for i in 0..2000i64 {
+            let key = vec![SerialValue::I64((i * 7919) % 13)];
+            sorter.push(key, vec![SerialValue::I64(i)]).unwrap();
+        }
+        assert!(sorter.spilled_runs() > MERGE_FAN_IN * MERGE_FAN_IN);
+
+        let mut expected: Vec<i64> = (0..2000).collect();
+        expected.sort_by_key(|i| (i * 7919) % 13);
+        let sorted: Vec<i64> = sorter
+            .finish()
+            .unwrap()
+            .map(|row| row.unwrap()[0].as_integer().unwrap())
+            .collect();
+        assert_eq!(sorted, expected);
+    }
+}
@@ -1,265 +1,701 @@
-use std::collections::{HashMap, HashSet};
+use std::any::TypeId;
 
 use sqlparser::{
-    ast::{Expr, SelectItem, SetExpr, Statement, TableFactor, Value},
-    dialect::GenericDialect,
-    parser::Parser,
+    ast::{
+        self, BinaryOperator, JoinOperator, SelectItem, SetExpr, Statement, TableFactor,
+        UnaryOperator, Value,
+    },
+    dialect::{Dialect, SQLiteDialect},
+    keywords::Keyword,
+    parser::{Parser, ParserError},
+    tokenizer::{Token, Tokenizer, Whitespace, Word},
 };
 
-use crate::{column::SerialValue, record::Record};
+use crate::{
+    column::Collation,
+    error::{bail, err, Result},
+    expr::Expr,
+};
 
 #[derive(Debug)]
 pub struct Sql {
+    /// Whether the statement is prefixed with EXPLAIN, and how.
+    pub explain: Option<Explain>,
     pub index_name: Option<Vec<String>>,
-    pub field_name: Vec<String>,
-    pub selection: HashMap<String, String>,
+    /// The result columns of a SELECT statement.
+    pub projection: Vec<SelectItem>,
+    /// Whether it is a SELECT DISTINCT statement, which returns each row only once.
+    pub distinct: bool,
+    /// The WHERE clause of a SELECT statement.
+    pub selection: Option<Expr>,
+    pub group_by: Vec<Expr>,
+    pub having: Option<Expr>,
+    pub order_by: Vec<OrderingTerm>,
+    pub limit: Option<Expr>,
+    pub offset: Option<Expr>,
+    /// The tables of the FROM clause, in the order they are joined.
+    pub from: Vec<FromTable>,
     pub tbl_name: String,
-    pub index_column: Option<Vec<String>>,
+    pub index_column: Option<Vec<IndexedColumn>>,
+    /// Whether a CREATE INDEX statement has a WHERE clause.
+    pub partial_index: bool,
+    /// Whether a CREATE INDEX statement is CREATE UNIQUE INDEX.
+    pub unique_index: bool,
+    /// The parameters of the statement by index, from 1, with the names they are written
+    /// with. Parameters written `?` have no name.
+    pub parameters: Vec<Option<String>>,
+    /// The PRAGMA the statement runs, if it is one.
+    pub pragma: Option<Pragma>,
+}
+
+/// A PRAGMA statement that returns rows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pragma {
+    /// `PRAGMA integrity_check(N)` or `PRAGMA quick_check(N)`: report at most `max_errors`
+    /// problems found in the database. A quick check does not compare indexes with their
+    /// tables.
+    IntegrityCheck { quick: bool, max_errors: usize },
+}
+
+/// What an EXPLAIN prefix asks to be shown instead of the results of a statement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Explain {
+    /// `EXPLAIN`: the program the statement is compiled into.
+    Program,
+    /// `EXPLAIN QUERY PLAN`: the steps taken to run the statement.
+    QueryPlan,
+}
+
+/// A table in the FROM clause, and how it is joined to the tables before it.
+#[derive(Debug, Clone)]
+pub struct FromTable {
+    pub name: String,
+    pub alias: Option<String>,
+    /// A LEFT JOIN, which keeps the rows that match no row of this table.
+    pub left: bool,
+    /// A CROSS JOIN, whose tables SQLite never joins in another order than written.
+    pub cross: bool,
+    pub constraint: JoinConstraint,
+}
+
+/// The condition that joins a table to the tables before it.
+#[derive(Debug, Clone)]
+pub enum JoinConstraint {
+    /// The first table, a comma or CROSS join, or a join without a condition.
+    None,
+    On(Expr),
+    Using(Vec<String>),
+    Natural,
+}
+
+/// A column of a CREATE INDEX statement.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexedColumn {
+    /// `None` for an indexed expression.
+    pub name: Option<String>,
+    /// The collating sequence given by a COLLATE clause, if any.
+    pub collation: Option<Collation>,
+    pub descending: bool,
+}
+
+/// A term of an ORDER BY clause.
+#[derive(Debug, Clone)]
+pub struct OrderingTerm {
+    pub expr: Expr,
+    pub descending: bool,
+    /// `NULLS FIRST` or `NULLS LAST`, if given. NULLs sort first by default.
+    pub nulls_first: Option<bool>,
+}
+
+/// A column of a SELECT statement's result.
+#[derive(Debug, Clone)]
+pub enum ResultColumn {
+    /// `*`
+    Wildcard,
+    /// `table.*`
+    QualifiedWildcard(String),
+    /// An expression, and the name of the column in the result.
+    Expr { expr: Expr, name: String },
 }
 
 impl Sql {
-    pub fn from_str(query: &str) -> Self {
-        let dialect = GenericDialect {};
-        let query = Parser::parse_sql(&dialect, query).expect("parse select statement");
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(query: &str) -> Result<Self> {
+        let mut tokens = Tokenizer::new(&SqliteDialect, query)
+            .tokenize()
+            .map_err(|err| err!(Syntax, "{}", err))?;
+        let explain = take_explain(&mut tokens);
+        let parameters = number_parameters(&mut tokens)?;
+        rewrite_limit_comma(&mut tokens);
+        let query = Parser::new(&SqliteDialect)
+            .with_tokens(tokens)
+            .parse_statements()
+            .map_err(|err| err!(Syntax, "{}", err))?;
 
         let mut index_name = None;
-        let mut field_name = Vec::new();
+        let mut projection = Vec::new();
         let mut tbl_name = String::new();
-        let mut selection = HashMap::new();
+        let mut selection = None;
+        let mut group_by = Vec::new();
+        let mut having = None;
+        let mut order_by = Vec::new();
+        let mut limit = None;
+        let mut offset = None;
+        let mut from = Vec::new();
         let mut index_column = None;
+        let mut partial_index = false;
+        let mut unique_index = false;
+        let mut distinct = false;
+        let mut pragma = None;
 
-        while field_name.is_empty() && tbl_name.is_empty() {
-            match &query[0] {
-                Statement::Query(select) => match *select.body.clone() {
-                    SetExpr::Select(select) => {
-                        for proj in select.projection {
-                            match &proj {
-                                SelectItem::UnnamedExpr(expr) => match expr {
-                                    Expr::Identifier(ident) => {
-                                        field_name.push(ident.value.to_string());
-                                    }
-                                    _ => {}
-                                },
-                                _ => todo!(),
-                            }
-                        }
-                        if let Some(expr) = &select.selection {
-                            let mut key = String::new();
-                            let mut value = String::new();
-                            match expr {
-                                Expr::BinaryOp { left, op: _, right } => {
-                                    if let Expr::Identifier(ident) = *left.clone() {
-                                        key = ident.value;
-                                    }
-                                    if let Expr::Value(val) = *right.clone() {
-                                        match val {
-                                            Value::SingleQuotedString(txt) => {
-                                                value = txt.to_string();
-                                            }
-                                            _ => {}
-                                        }
-                                    }
-                                }
-                                _ => {}
-                            }
+        let statement = match query.as_slice() {
+            [statement] => statement,
+            [] => bail!(Syntax, "empty statement"),
+            _ => bail!(Sql, "only one statement may be run at a time"),
+        };
 
-                            selection.insert(key, value);
-                        }
-                        match &select.from[0].relation {
-                            TableFactor::Table {
-                                name,
-                                alias: _,
-                                args: _,
-                                with_hints: _,
-                                version: _,
-                                partitions: _,
-                            } => {
-                                tbl_name = name.0[0].value.to_string();
-                            }
-                            _ => {}
+        match statement {
+            Statement::Query(query) => match query.body.as_ref() {
+                SetExpr::Select(select) => {
+                    order_by = query
+                        .order_by
+                        .iter()
+                        .map(|term| {
+                            Ok(OrderingTerm {
+                                expr: Expr::try_from(&term.expr)?,
+                                descending: term.asc == Some(false),
+                                nulls_first: term.nulls_first,
+                            })
+                        })
+                        .collect::<Result<_>>()?;
+                    limit = query.limit.as_ref().map(Expr::try_from).transpose()?;
+                    offset = query
+                        .offset
+                        .as_ref()
+                        .map(|offset| Expr::try_from(&offset.value))
+                        .transpose()?;
+                    projection = select.projection.clone();
+                    distinct = match &select.distinct {
+                        None => false,
+                        Some(ast::Distinct::Distinct) => true,
+                        Some(ast::Distinct::On(_)) => bail!(Unsupported, "DISTINCT ON"),
+                    };
+                    if let Some(expr) = &select.selection {
+                        selection = Some(Expr::try_from(expr)?);
+                    }
+                    match &select.group_by {
+                        ast::GroupByExpr::Expressions(exprs) => {
+                            group_by =
+                                exprs.iter().map(Expr::try_from).collect::<Result<_, _>>()?;
                         }
+                        ast::GroupByExpr::All => bail!(Unsupported, "GROUP BY ALL"),
                     }
-                    _ => todo!(),
-                },
-                Statement::CreateTable { name, columns, .. } => {
-                    field_name = columns
-                        .iter()
-                        .map(|column_def| column_def.name.value.clone())
-                        .collect();
-                    tbl_name = name.0[0].value.to_string();
-                }
-                Statement::CreateIndex {
-                    name,
-                    table_name,
-                    columns,
-                    ..
-                } => {
-                    if let Some(indexes) = name {
-                        let names: Vec<String> =
-                            indexes.0.iter().map(|index| index.value.clone()).collect();
-                        index_name = Some(names);
+                    if let Some(expr) = &select.having {
+                        having = Some(Expr::try_from(expr)?);
                     }
-                    tbl_name = table_name.0[0].value.to_string();
-
-                    let mut idx_columns = Vec::new();
-                    for column in columns.iter() {
-                        if let Expr::Identifier(ident) = &column.expr {
-                            idx_columns.push(ident.value.clone());
+                    for table in &select.from {
+                        from.push(from_table(
+                            &table.relation,
+                            false,
+                            false,
+                            JoinConstraint::None,
+                        )?);
+                        for join in &table.joins {
+                            let (left, cross, constraint) = match &join.join_operator {
+                                JoinOperator::Inner(constraint) => (false, false, constraint),
+                                JoinOperator::LeftOuter(constraint) => (true, false, constraint),
+                                JoinOperator::CrossJoin => {
+                                    (false, true, &ast::JoinConstraint::None)
+                                }
+                                operator => bail!(Unsupported, "join: {:?}", operator),
+                            };
+                            let constraint = match constraint {
+                                ast::JoinConstraint::On(expr) => {
+                                    JoinConstraint::On(Expr::try_from(expr)?)
+                                }
+                                ast::JoinConstraint::Using(columns) => JoinConstraint::Using(
+                                    columns.iter().map(|column| column.value.clone()).collect(),
+                                ),
+                                ast::JoinConstraint::Natural => JoinConstraint::Natural,
+                                ast::JoinConstraint::None => JoinConstraint::None,
+                            };
+                            from.push(from_table(&join.relation, left, cross, constraint)?);
                         }
                     }
-                    index_column = Some(idx_columns);
+                    if let Some(first) = from.first() {
+                        tbl_name = first.name.clone();
+                    }
+                }
+                body => bail!(Unsupported, "query: {}", body),
+            },
+            Statement::CreateTable { name, .. } => {
+                tbl_name = name.0[0].value.to_string();
+            }
+            Statement::CreateIndex {
+                name,
+                table_name,
+                columns,
+                predicate,
+                unique,
+                ..
+            } => {
+                partial_index = predicate.is_some();
+                unique_index = *unique;
+                if let Some(indexes) = name {
+                    let names: Vec<String> =
+                        indexes.0.iter().map(|index| index.value.clone()).collect();
+                    index_name = Some(names);
+                }
+                tbl_name = table_name.0[0].value.to_string();
+
+                let mut idx_columns = Vec::new();
+                for column in columns.iter() {
+                    let (expr, collation) = match &column.expr {
+                        ast::Expr::Collate { expr, collation } => match collation.0.last() {
+                            Some(name) => (expr.as_ref(), Some(Collation::from_name(&name.value)?)),
+                            None => bail!(Unsupported, "collation: {}", collation),
+                        },
+                        expr => (expr, None),
+                    };
+                    idx_columns.push(IndexedColumn {
+                        name: match expr {
+                            ast::Expr::Identifier(ident) => Some(ident.value.clone()),
+                            _ => None,
+                        },
+                        collation,
+                        descending: column.asc == Some(false),
+                    });
                 }
-                _ => todo!(),
+                index_column = Some(idx_columns);
             }
+            Statement::Pragma { name, value, .. } => {
+                let (schema, name) = match name.0.as_slice() {
+                    [name] => (None, name),
+                    [schema, name] => (Some(schema), name),
+                    _ => bail!(Syntax, "malformed PRAGMA: {}", statement),
+                };
+                if let Some(schema) =
+                    schema.filter(|schema| !schema.value.eq_ignore_ascii_case("main"))
+                {
+                    bail!(Sql, "unknown database {}", schema.value);
+                }
+                pragma = Some(match name.value.to_ascii_lowercase().as_str() {
+                    check @ ("integrity_check" | "quick_check") => Pragma::IntegrityCheck {
+                        quick: check == "quick_check",
+                        max_errors: pragma_max_errors(value.as_ref()),
+                    },
+                    _ => bail!(Unsupported, "PRAGMA {}", name.value),
+                });
+            }
+            statement => bail!(Unsupported, "statement: {}", statement),
         }
 
-        Self {
+        Ok(Self {
+            explain,
             index_name,
-            field_name,
+            projection,
+            distinct,
             selection,
+            group_by,
+            having,
+            order_by,
+            limit,
+            offset,
+            from,
             tbl_name,
             index_column,
-        }
+            partial_index,
+            unique_index,
+            parameters,
+            pragma,
+        })
     }
 
-    pub fn get_fields(&self, create_statement: &Sql) -> Vec<(usize, String)> {
-        self.field_name
-            .clone()
-            .into_iter()
-            .enumerate()
-            .map(|(_i, select_field)| {
-                let index = if let Some(index) = create_statement
-                    .field_name
-                    .iter()
-                    .position(|x| x.as_str() == select_field.as_str())
-                {
-                    index
-                } else {
-                    0
-                };
-
-                (index, select_field)
+    /// The result columns of the SELECT statement, named the way sqlite3 names them.
+    pub fn result_columns(&self) -> Result<Vec<ResultColumn>> {
+        self.projection
+            .iter()
+            .map(|item| {
+                Ok(match item {
+                    SelectItem::Wildcard(_) => ResultColumn::Wildcard,
+                    SelectItem::QualifiedWildcard(name, _) => match name.0.last() {
+                        Some(table) => ResultColumn::QualifiedWildcard(table.value.clone()),
+                        None => bail!(Unsupported, "result column: {}", item),
+                    },
+                    SelectItem::UnnamedExpr(expr) => ResultColumn::Expr {
+                        expr: Expr::try_from(expr)?,
+                        name: match expr {
+                            ast::Expr::Identifier(ident) => ident.value.clone(),
+                            ast::Expr::CompoundIdentifier(idents) => idents
+                                .last()
+                                .map(|ident| ident.value.clone())
+                                .unwrap_or_default(),
+                            expr => expr.to_string(),
+                        },
+                    },
+                    SelectItem::ExprWithAlias { expr, alias } => ResultColumn::Expr {
+                        expr: Expr::try_from(expr)?,
+                        name: alias.value.clone(),
+                    },
+                })
             })
             .collect()
     }
+}
 
-    pub fn print_rows(
-        &self,
-        record: &Record,
-        rowid: &Option<i64>,
-        fields: &Vec<(usize, String)>,
-        row_set: &mut HashSet<String>,
-        _rowid_set: &mut HashSet<i64>,
-    ) {
-        let mut values = Vec::new();
-
-        for (_key, value) in self.selection.iter() {
-            for (column_i, column) in record.columns.iter().enumerate() {
-                if column_i == 0 && *column.data() != SerialValue::Null {
-                    break;
-                }
+fn from_table(
+    relation: &TableFactor,
+    left: bool,
+    cross: bool,
+    constraint: JoinConstraint,
+) -> Result<FromTable> {
+    let TableFactor::Table { name, alias, .. } = relation else {
+        bail!(Unsupported, "table: {}", relation);
+    };
+    let Some(table_name) = name.0.last() else {
+        bail!(Unsupported, "table: {}", relation);
+    };
 
-                if let SerialValue::String(candidate_value) = column.data() {
-                    if candidate_value == value {
-                        let rows: Vec<String> = fields
-                            .iter()
-                            .map(|(i, _field)| {
-                                if *i == 0 {
-                                    String::new()
-                                } else {
-                                    record.columns[*i].data().display()
-                                }
-                            })
-                            .collect();
+    Ok(FromTable {
+        name: table_name.value.clone(),
+        alias: alias.as_ref().map(|alias| alias.name.value.clone()),
+        left,
+        cross,
+        constraint,
+    })
+}
 
-                        let con_row = if fields[0].0 == 0 {
-                            format!("{}{}", rowid.unwrap(), rows.join("|"))
-                        } else {
-                            rows.join("|")
-                        };
+/// Removes an `EXPLAIN` or `EXPLAIN QUERY PLAN` prefix from the start of `tokens`, since
+/// sqlparser does not parse the latter and parses the former into another statement.
+fn take_explain(tokens: &mut Vec<Token>) -> Option<Explain> {
+    let words: Vec<(usize, &Token)> = tokens
+        .iter()
+        .enumerate()
+        .filter(|(_, token)| !matches!(token, Token::Whitespace(_)))
+        .take(3)
+        .collect();
+    match words.as_slice() {
+        [(_, explain), (_, query), (plan, word)]
+            if is_word(explain, "explain") && is_word(query, "query") && is_word(word, "plan") =>
+        {
+            tokens.drain(..=*plan);
+            Some(Explain::QueryPlan)
+        }
+        [(at, explain), ..] if is_word(explain, "explain") => {
+            tokens.drain(..=*at);
+            Some(Explain::Program)
+        }
+        _ => None,
+    }
+}
 
-                        if !values.contains(&con_row) {
-                            values.push(con_row)
-                        }
-                        break;
-                    }
-                }
+/// The most errors an integrity check reports when not told otherwise.
+const DEFAULT_MAX_ERRORS: usize = 100;
+
+/// The limit on errors given to an integrity check, which as in SQLite falls back to the
+/// default unless it is a positive integer.
+fn pragma_max_errors(value: Option<&Value>) -> usize {
+    match value {
+        Some(Value::Number(num, _)) => match num.parse::<i32>() {
+            Ok(max_errors) if max_errors > 0 => max_errors as usize,
+            _ => DEFAULT_MAX_ERRORS,
+        },
+        _ => DEFAULT_MAX_ERRORS,
+    }
+}
+
+/// The largest parameter index SQLite accepts by default, SQLITE_MAX_VARIABLE_NUMBER.
+const MAX_PARAMETER: usize = 32766;
+
+/// Rewrites each parameter in `tokens` as `?NNN`, numbered the way SQLite numbers them,
+/// and returns the names of the parameters by index.
+///
+/// `?NNN` has index NNN. `?` and the first occurrence of `:AAAA`, `@AAAA` or `$AAAA`
+/// take the index after the largest one so far; later occurrences of a name share its
+/// index. sqlparser parses `$AAAA` as an identifier, since SQLite's dialect lets
+/// identifiers start with `$`.
+fn number_parameters(tokens: &mut Vec<Token>) -> Result<Vec<Option<String>>> {
+    let mut parameters: Vec<Option<String>> = Vec::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        let (name, len) = match (&tokens[i], tokens.get(i + 1)) {
+            (Token::Placeholder(name), _) => (name.clone(), 1),
+            (Token::Colon | Token::AtSign, Some(Token::Word(word)))
+                if word.quote_style.is_none() =>
+            {
+                (format!("{}{}", tokens[i], word.value), 2)
             }
-        }
+            (Token::Colon | Token::AtSign, Some(Token::Number(num, false))) => {
+                (format!("{}{}", tokens[i], num), 2)
+            }
+            (Token::Word(word), _) if word.quote_style.is_none() && word.value.starts_with('$') => {
+                (word.value.clone(), 1)
+            }
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
 
-        if !values.is_empty() {
-            // if rowid_set.insert(rowid) {
-            row_set.insert(values.join("|"));
-            // }
+        let index = match name.strip_prefix('?') {
+            Some("") => parameters.len() + 1,
+            Some(num) => match num.parse() {
+                Ok(index @ 1..=MAX_PARAMETER) => index,
+                _ => bail!(
+                    Sql,
+                    "variable number must be between ?1 and ?{}",
+                    MAX_PARAMETER
+                ),
+            },
+            None => match parameters.iter().position(|p| p.as_ref() == Some(&name)) {
+                Some(at) => at + 1,
+                None => parameters.len() + 1,
+            },
+        };
+        if parameters.len() < index {
+            parameters.resize(index, None);
         }
+        if name != "?" && parameters[index - 1].is_none() {
+            parameters[index - 1] = Some(name);
+        }
+
+        tokens.splice(i..i + len, [Token::Placeholder(format!("?{}", index))]);
+        i += 1;
     }
 
-    pub fn print_rows_by_rowid(
-        &self,
-        record: &Record,
-        rowid: &Option<i64>,
-        fields: &Vec<(usize, String)>,
-        row_set: &mut HashSet<String>,
-        _rowid_set: &mut HashSet<i64>,
-    ) {
-        let mut values = Vec::new();
-
-        for (column_i, column) in record.columns.iter().enumerate() {
-            if column_i == 0 && *column.data() != SerialValue::Null {
-                break;
-            }
+    Ok(parameters)
+}
 
-            let rows: Vec<String> = fields
-                .iter()
-                .map(|(i, _field)| {
-                    if *i == 0 {
-                        String::new()
-                    } else {
-                        record.columns[*i].data().display()
-                    }
-                })
-                .collect();
+/// Rewrites SQLite's `LIMIT offset, count` as `LIMIT count OFFSET offset`, which
+/// sqlparser only accepts for other dialects.
+fn rewrite_limit_comma(tokens: &mut Vec<Token>) {
+    let is_keyword =
+        |token: &Token, keyword| matches!(token, Token::Word(word) if word.keyword == keyword);
 
-            let con_row = if fields[0].0 == 0 {
-                format!("{}{}", rowid.unwrap(), rows.join("|"))
-            } else {
-                rows.join("|")
-            };
+    let mut start = 0;
+    while let Some(at) = tokens[start..]
+        .iter()
+        .position(|token| is_keyword(token, Keyword::LIMIT))
+    {
+        let limit = start + at;
+        start = limit + 1;
 
-            if !values.contains(&con_row) {
-                values.push(con_row)
+        // The LIMIT clause runs to the end of the statement or the enclosing parentheses.
+        let mut depth = 0;
+        let mut comma = None;
+        let mut end = tokens.len();
+        for (i, token) in tokens.iter().enumerate().skip(limit + 1) {
+            match token {
+                Token::LParen => depth += 1,
+                Token::RParen if depth == 0 => {
+                    end = i;
+                    break;
+                }
+                Token::RParen => depth -= 1,
+                Token::SemiColon if depth == 0 => {
+                    end = i;
+                    break;
+                }
+                Token::Comma if depth == 0 && comma.is_none() => comma = Some(i),
+                token if depth == 0 && is_keyword(token, Keyword::OFFSET) => {
+                    comma = None;
+                    break;
+                }
+                _ => {}
             }
         }
+        let Some(comma) = comma else {
+            continue;
+        };
+
+        let offset_expr = tokens[limit + 1..comma].to_vec();
+        let mut rewritten = tokens[comma + 1..end].to_vec();
+        rewritten.push(Token::Whitespace(Whitespace::Space));
+        rewritten.push(Token::Word(Word {
+            value: "OFFSET".to_string(),
+            quote_style: None,
+            keyword: Keyword::OFFSET,
+        }));
+        rewritten.extend(offset_expr);
+        tokens.splice(limit + 1..end, rewritten);
+    }
+}
+
+/// SQLite's dialect, extended with the `[NOT] GLOB` operator that sqlparser does not parse.
+#[derive(Debug)]
+struct SqliteDialect;
+
+/// Binding power of GLOB, the same as LIKE.
+const GLOB_PREC: u8 = 19;
+
+/// Whether `token` is the unquoted word `word`, in any case.
+fn is_word(token: &Token, word: &str) -> bool {
+    matches!(token, Token::Word(w) if w.quote_style.is_none() && w.value.eq_ignore_ascii_case(word))
+}
+
+fn is_glob(token: &Token) -> bool {
+    is_word(token, "glob")
+}
+
+fn is_not(token: &Token) -> bool {
+    is_word(token, "not")
+}
+
+impl Dialect for SqliteDialect {
+    // Parse as SQLite, so that the parser's SQLite-specific rules apply.
+    fn dialect(&self) -> TypeId {
+        TypeId::of::<SQLiteDialect>()
+    }
+
+    fn is_delimited_identifier_start(&self, ch: char) -> bool {
+        SQLiteDialect {}.is_delimited_identifier_start(ch)
+    }
+
+    fn is_identifier_start(&self, ch: char) -> bool {
+        SQLiteDialect {}.is_identifier_start(ch)
+    }
 
-        if !values.is_empty() {
-            // if rowid_set.insert(rowid) {
-            row_set.insert(values.join("|"));
-            // }
+    fn is_identifier_part(&self, ch: char) -> bool {
+        SQLiteDialect {}.is_identifier_part(ch)
+    }
+
+    fn supports_filter_during_aggregation(&self) -> bool {
+        true
+    }
+
+    fn supports_in_empty_list(&self) -> bool {
+        true
+    }
+
+    fn parse_statement(&self, parser: &mut Parser) -> Option<Result<Statement, ParserError>> {
+        SQLiteDialect {}.parse_statement(parser)
+    }
+
+    fn get_next_precedence(&self, parser: &Parser) -> Option<Result<u8, ParserError>> {
+        let token = parser.peek_token().token;
+        if is_glob(&token) || (is_not(&token) && is_glob(&parser.peek_nth_token(1).token)) {
+            return Some(Ok(GLOB_PREC));
         }
+
+        None
     }
 
-    pub fn print_row_id(
+    fn parse_infix(
         &self,
-        record: Option<Record>,
-        select_statement: &Sql,
-        rowids: &mut HashSet<i64>,
-    ) {
-        if let Some(record) = record {
-            for (_key, value) in select_statement.selection.iter() {
-                if let SerialValue::String(country) = record.columns[0].data() {
-                    if value == country {
-                        let num = match record.columns[1].data() {
-                            SerialValue::I8(num) => *num as i64,
-                            SerialValue::I16(num) => *num as i64,
-                            SerialValue::I24(num) => *num as i64,
-                            SerialValue::I32(num) => *num as i64,
-                            _ => todo!(),
-                        };
-
-                        rowids.insert(num);
-                    }
-                }
-            }
+        parser: &mut Parser,
+        expr: &ast::Expr,
+        _precedence: u8,
+    ) -> Option<Result<ast::Expr, ParserError>> {
+        let negated = is_not(&parser.peek_token().token);
+        if !is_glob(&parser.peek_nth_token(negated as usize).token) {
+            return None;
         }
+
+        for _ in 0..=negated as usize {
+            parser.next_token();
+        }
+
+        let glob = parser
+            .parse_subexpr(GLOB_PREC)
+            .map(|pattern| ast::Expr::BinaryOp {
+                left: Box::new(expr.clone()),
+                op: BinaryOperator::Custom("GLOB".to_string()),
+                right: Box::new(pattern),
+            });
+
+        Some(glob.map(|glob| match negated {
+            true => ast::Expr::UnaryOp {
+                op: UnaryOperator::Not,
+                expr: Box::new(ast::Expr::Nested(Box::new(glob))),
+            },
+            false => glob,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::column::SerialValue;
+
+    #[test]
+    fn test_parse_where_clause() {
+        let sql = Sql::from_str(
+            "SELECT name FROM apples WHERE color = 'Red' AND NOT name GLOB 'G*' OR id IN (1, 2)",
+        )
+        .unwrap();
+
+        assert_eq!(sql.tbl_name, "apples");
+        let Some(Expr::Binary { left, .. }) = sql.selection else {
+            panic!("expected an OR expression");
+        };
+        assert!(matches!(*left, Expr::Binary { .. }));
+    }
+
+    #[test]
+    fn test_parse_limit() {
+        let sql = Sql::from_str("SELECT * FROM apples LIMIT 1 + 1, 3").unwrap();
+        assert_eq!(sql.limit, Some(Expr::Literal(SerialValue::I64(3))));
+        assert!(matches!(sql.offset, Some(Expr::Binary { .. })));
+
+        let sql = Sql::from_str("SELECT * FROM apples LIMIT 3 OFFSET 2").unwrap();
+        assert_eq!(sql.limit, Some(Expr::Literal(SerialValue::I64(3))));
+        assert_eq!(sql.offset, Some(Expr::Literal(SerialValue::I64(2))));
+    }
+
+    #[test]
+    fn test_parse_single_statement() {
+        assert_eq!(
+            Sql::from_str("SELECT * FROM apples;").unwrap().tbl_name,
+            "apples"
+        );
+        assert!(Sql::from_str("SELECT 1; SELECT 2").is_err());
+        assert!(Sql::from_str("SELECT 1;;").is_ok());
+    }
+
+    #[test]
+    fn test_parse_parameters() {
+        let sql = Sql::from_str(
+            "SELECT ? FROM apples WHERE id = ?3 AND name = :name OR color = $c OR name = :name LIMIT @n, ?",
+        )
+        .unwrap();
+        let name = |name: &str| Some(name.to_string());
+        assert_eq!(
+            sql.parameters,
+            [
+                None,
+                None,
+                name("?3"),
+                name(":name"),
+                name("$c"),
+                name("@n"),
+                None
+            ]
+        );
+        assert_eq!(sql.limit, Some(Expr::Parameter(7)));
+        assert_eq!(sql.offset, Some(Expr::Parameter(6)));
+
+        assert!(Sql::from_str("SELECT ?0").is_err());
+        assert!(Sql::from_str("SELECT ?32767").is_err());
+    }
+
+    #[test]
+    fn test_parse_index_columns() {
+        let sql =
+            Sql::from_str("CREATE INDEX i ON t (a, b COLLATE nocase DESC, lower(c), d)").unwrap();
+        let column = |name: Option<&str>, collation, descending| IndexedColumn {
+            name: name.map(str::to_string),
+            collation,
+            descending,
+        };
+        assert_eq!(
+            sql.index_column.unwrap(),
+            vec![
+                column(Some("a"), None, false),
+                column(Some("b"), Some(Collation::NoCase), true),
+                column(None, None, false),
+                column(Some("d"), None, false),
+            ]
+        );
     }
 }
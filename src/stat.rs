@@ -0,0 +1,218 @@
+use std::{cmp::Ordering, collections::HashMap};
+
+use crate::{
+    btree::BTreeCursor,
+    column::{Collation, SerialValue},
+    database::Database,
+    error::Result,
+    record::Record,
+    schema::{Schema, Table},
+};
+
+/// What ANALYZE found out about the tables and indexes of a database, read from the
+/// `sqlite_stat1` and `sqlite_stat4` tables. Empty if ANALYZE was never run.
+///
+/// See <https://www.sqlite.org/fileformat.html#the_sqlite_stat1_table>.
+#[derive(Debug, Clone, Default)]
+pub struct Statistics {
+    /// The number of rows of each analyzed table, by lowercase name.
+    table_rows: HashMap<String, u64>,
+    /// The statistics of each analyzed index, by lowercase name.
+    indexes: HashMap<String, IndexStatistics>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct IndexStatistics {
+    /// How many entries the index has, followed by how many entries on average have the
+    /// same values in the first 1, 2, ... columns.
+    pub rows: Vec<u64>,
+    /// Entries sampled from the index, in index order.
+    pub samples: Vec<Sample>,
+}
+
+/// An index entry sampled by ANALYZE.
+///
+/// See <https://www.sqlite.org/fileformat.html#the_sqlite_stat4_table>.
+#[derive(Debug, Clone)]
+pub struct Sample {
+    /// The values of the entry, ending with its rowid.
+    pub key: Vec<SerialValue>,
+    /// How many entries have the same values as the sample in the first 1, 2, ... columns.
+    pub equal: Vec<u64>,
+    /// How many entries come before the first one with the same values as the sample in
+    /// the first 1, 2, ... columns.
+    pub less: Vec<u64>,
+}
+
+impl Statistics {
+    pub fn read(db: &Database, schema: &Schema) -> Result<Self> {
+        let mut stats = Self::default();
+
+        if let Some(table) = schema.table("sqlite_stat1") {
+            for row in rows(db, table) {
+                let row = row?;
+                let (Some(SerialValue::String(tbl)), Some(SerialValue::String(stat))) =
+                    (row.first(), row.get(2))
+                else {
+                    continue;
+                };
+                let numbers = parse_integers(stat);
+                let Some(&table_rows) = numbers.first() else {
+                    continue;
+                };
+
+                stats
+                    .table_rows
+                    .insert(tbl.to_ascii_lowercase(), table_rows);
+                if let Some(SerialValue::String(idx)) = row.get(1) {
+                    let index = stats.indexes.entry(idx.to_ascii_lowercase()).or_default();
+                    index.rows = numbers;
+                }
+            }
+        }
+
+        if let Some(table) = schema.table("sqlite_stat4") {
+            for row in rows(db, table) {
+                let row = row?;
+                let (
+                    Some(SerialValue::String(idx)),
+                    Some(SerialValue::String(neq)),
+                    Some(SerialValue::String(nlt)),
+                    Some(SerialValue::Blob(sample)),
+                ) = (row.get(1), row.get(2), row.get(3), row.get(5))
+                else {
+                    continue;
+                };
+                let key = Record::new(sample)?
+                    .columns
+                    .iter()
+                    .map(|column| column.data().clone())
+                    .collect();
+
+                let index = stats.indexes.entry(idx.to_ascii_lowercase()).or_default();
+                index.samples.push(Sample {
+                    key,
+                    equal: parse_integers(neq),
+                    less: parse_integers(nlt),
+                });
+            }
+        }
+
+        Ok(stats)
+    }
+
+    /// How many rows the table called `name` had when it was analyzed.
+    pub fn table_rows(&self, name: &str) -> Option<u64> {
+        self.table_rows.get(&name.to_ascii_lowercase()).copied()
+    }
+
+    /// The statistics of the index called `name`, if it was analyzed.
+    pub fn index(&self, name: &str) -> Option<&IndexStatistics> {
+        self.indexes.get(&name.to_ascii_lowercase())
+    }
+}
+
+impl IndexStatistics {
+    /// How many entries on average have the same values in the first `columns` columns.
+    pub fn average_equal(&self, columns: usize) -> Option<f64> {
+        self.rows.get(columns).map(|&rows| rows as f64)
+    }
+
+    /// How many entries have `value` in their first column, if an entry with that value
+    /// was sampled.
+    pub fn sampled_equal(&self, value: &SerialValue, collation: Collation) -> Option<f64> {
+        self.samples.iter().find_map(|sample| {
+            let first = sample.key.first()?;
+            match collation.compare(first, value) {
+                Ordering::Equal => sample.equal.first().map(|&equal| equal as f64),
+                _ => None,
+            }
+        })
+    }
+
+    /// Roughly how many entries have a first column less than `value`, or less than or
+    /// equal to it if `or_equal`, judged from the samples of an index whose first column
+    /// is in ascending order.
+    pub fn sampled_before(
+        &self,
+        value: &SerialValue,
+        collation: Collation,
+        or_equal: bool,
+    ) -> Option<f64> {
+        if self.samples.is_empty() {
+            return None;
+        }
+
+        // Between two samples, assume the value lies halfway.
+        let mut before = 0;
+        for sample in &self.samples {
+            let (Some(first), Some(&less), Some(&equal)) = (
+                sample.key.first(),
+                sample.less.first(),
+                sample.equal.first(),
+            ) else {
+                continue;
+            };
+            match collation.compare(first, value) {
+                Ordering::Less => before = less + equal,
+                Ordering::Equal if or_equal => return Some((less + equal) as f64),
+                Ordering::Equal => return Some(less as f64),
+                Ordering::Greater => return Some((before + less) as f64 / 2.0),
+            }
+        }
+        let rows = self.rows.first().copied().unwrap_or(before);
+        Some((before + rows.max(before)) as f64 / 2.0)
+    }
+}
+
+fn rows<'a>(
+    db: &'a Database,
+    table: &'a Table,
+) -> impl Iterator<Item = Result<Vec<SerialValue>>> + 'a {
+    BTreeCursor::new(db, table.root_page)
+        .entries()
+        .map(|entry| entry.map(|(rowid, record)| table.row(rowid, &record)))
+}
+
+/// The integers a statistic starts with. Keywords such as `unordered` may follow them.
+fn parse_integers(stat: &str) -> Vec<u64> {
+    stat.split_ascii_whitespace()
+        .map_while(|n| n.parse().ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sampled_estimates() {
+        let sample = |value: i64, less: u64, equal: u64| Sample {
+            key: vec![SerialValue::I64(value), SerialValue::I64(1)],
+            equal: vec![equal, 1],
+            less: vec![less, less],
+        };
+        let stats = IndexStatistics {
+            rows: parse_integers("1000 10 1 unordered"),
+            samples: vec![sample(1, 0, 900), sample(50, 950, 2), sample(90, 990, 2)],
+        };
+
+        assert_eq!(stats.rows, [1000, 10, 1]);
+        assert_eq!(stats.average_equal(1), Some(10.0));
+        let one = SerialValue::I64(1);
+        assert_eq!(stats.sampled_equal(&one, Collation::Binary), Some(900.0));
+        assert_eq!(
+            stats.sampled_equal(&SerialValue::I64(2), Collation::Binary),
+            None
+        );
+
+        let before = |value: i64, or_equal| {
+            stats.sampled_before(&SerialValue::I64(value), Collation::Binary, or_equal)
+        };
+        assert_eq!(before(1, false), Some(0.0));
+        assert_eq!(before(1, true), Some(900.0));
+        assert_eq!(before(50, true), Some(952.0));
+        assert_eq!(before(70, false), Some(971.0));
+        assert_eq!(before(100, false), Some(996.0));
+    }
+}
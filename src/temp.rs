@@ -0,0 +1,139 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufReader, BufWriter, ErrorKind, Read, Seek, Write},
+    path::PathBuf,
+    sync::atomic::{self, AtomicUsize},
+};
+
+use crate::{
+    column::SerialValue,
+    error::Result,
+    record::{encode_record, Record},
+};
+
+/// A file in the temporary directory, removed when dropped. Operators that run out of
+/// memory write rows to it as length-prefixed records and read them back in order.
+#[derive(Debug)]
+pub struct TempFile {
+    path: PathBuf,
+    file: File,
+}
+
+impl TempFile {
+    pub fn create() -> Result<Self> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let path = std::env::temp_dir().join(format!(
+            "sqlite-temp-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, atomic::Ordering::Relaxed)
+        ));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+
+        Ok(Self { path, file })
+    }
+
+    /// Appends rows to the file.
+    pub fn writer(&self) -> Result<RecordWriter> {
+        Ok(RecordWriter {
+            out: BufWriter::new(self.file.try_clone()?),
+        })
+    }
+
+    /// Reads the rows written to the file from the start. The writers must have been
+    /// flushed.
+    pub fn into_records(self) -> Result<Records> {
+        let mut records = self.records()?;
+        records._file = Some(self);
+        Ok(records)
+    }
+
+    /// Reads the rows written to the file from the start, leaving the file to be read
+    /// again. The writers must have been flushed, and only one reader may be in use at a
+    /// time.
+    pub fn records(&self) -> Result<Records> {
+        let mut reader = self.file.try_clone()?;
+        reader.rewind()?;
+        Ok(Records {
+            reader: BufReader::new(reader),
+            _file: None,
+        })
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Writes rows to a [`TempFile`].
+#[derive(Debug)]
+pub struct RecordWriter {
+    out: BufWriter<File>,
+}
+
+impl RecordWriter {
+    pub fn write(&mut self, values: &[SerialValue]) -> Result<()> {
+        let record = encode_record(values);
+        self.out.write_all(&(record.len() as u32).to_be_bytes())?;
+        self.out.write_all(&record)?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        Ok(self.out.flush()?)
+    }
+}
+
+/// The rows of a [`TempFile`], in the order they were written.
+#[derive(Debug)]
+pub struct Records {
+    reader: BufReader<File>,
+    /// The file, if the rows own it, removed once they are read.
+    _file: Option<TempFile>,
+}
+
+impl Iterator for Records {
+    type Item = Result<Vec<SerialValue>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut len = [0; 4];
+        match self.reader.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => return None,
+            Err(err) => return Some(Err(err.into())),
+        }
+
+        let mut record = vec![0; u32::from_be_bytes(len) as usize];
+        if let Err(err) = self.reader.read_exact(&mut record) {
+            return Some(Err(err.into()));
+        }
+        Some(Record::new(&record).map(|record| {
+            record
+                .columns
+                .iter()
+                .map(|column| column.data().clone())
+                .collect()
+        }))
+    }
+}
+
+/// Roughly how much memory the values take up.
+pub fn size_of_values(values: &[SerialValue]) -> usize {
+    values
+        .iter()
+        .map(|value| {
+            std::mem::size_of::<SerialValue>()
+                + match value {
+                    SerialValue::String(txt) => txt.len(),
+                    SerialValue::Blob(blob) => blob.len(),
+                    _ => 0,
+                }
+        })
+        .sum()
+}
@@ -0,0 +1,2155 @@
+use std::{collections::BTreeSet, fmt};
+
+use crate::{
+    aggregate::{AggregateFunc, Groups},
+    btree::{compare_prefix, BTreeCursor, KeyOrder},
+    column::{Affinity, Collated, Collation, SerialValue},
+    database::Database,
+    error::{bail, Result},
+    expr::{binary, compare, from_truth, glob, like, to_text, truth, BinaryOp, Expr},
+    hash_join::{HashJoin, DEFAULT_HASH_MEMORY},
+    integrity::integrity_check,
+    query::Row,
+    record::Record,
+    sort::{SortKey, Sorted, Sorter, DEFAULT_SORT_MEMORY},
+};
+
+/// A query compiled for a register machine modelled on SQLite's virtual database engine.
+/// The program reads b-trees through numbered cursors, keeps values in numbered registers
+/// and returns a row each time it reaches a [`Insn::ResultRow`].
+///
+/// See <https://www.sqlite.org/opcode.html>.
+#[derive(Debug, Clone, Default)]
+pub struct Program {
+    pub insns: Vec<Insn>,
+    /// The number of registers. They are numbered from 1, as in SQLite.
+    pub registers: usize,
+    pub cursors: usize,
+}
+
+/// An instruction of a [`Program`]. Jumps go to the instruction at address `target`.
+///
+/// The instructions are those of SQLite where it has them, with the same meaning. Hash
+/// joins and the grouping of aggregate queries, which SQLite does differently, have
+/// instructions of their own.
+#[derive(Debug, Clone)]
+pub enum Insn {
+    /// The first instruction of every program.
+    Init {
+        target: usize,
+    },
+    Goto {
+        target: usize,
+    },
+    Halt,
+    /// Sets up the coroutine whose code starts at `start`, keeping where it is to be
+    /// resumed in `reg`, and jumps to `target`, past its code.
+    InitCoroutine {
+        reg: usize,
+        target: usize,
+        start: usize,
+    },
+    /// Switches between a coroutine and its caller. When the coroutine resumed by this
+    /// instruction ends, the caller continues at `target`.
+    Yield {
+        reg: usize,
+        target: usize,
+    },
+    /// Ends a coroutine, returning to the caller that resumed it.
+    EndCoroutine {
+        reg: usize,
+    },
+
+    Integer {
+        value: i64,
+        dest: usize,
+    },
+    Real {
+        value: f64,
+        dest: usize,
+    },
+    String8 {
+        value: String,
+        dest: usize,
+    },
+    Blob {
+        value: Vec<u8>,
+        dest: usize,
+    },
+    Null {
+        dest: usize,
+    },
+    /// Loads the value bound to the parameter with index `index`, from 1.
+    Variable {
+        index: usize,
+        dest: usize,
+    },
+    /// Copies a register into another.
+    SCopy {
+        src: usize,
+        dest: usize,
+    },
+
+    /// Opens a cursor on the b-tree rooted at `root_page`, whose records have `columns`
+    /// columns. Index cursors compare keys in the `key` order of the index.
+    OpenRead {
+        cursor: usize,
+        root_page: usize,
+        columns: usize,
+        key: Option<Vec<KeyOrder>>,
+        name: String,
+    },
+    /// Moves to the first entry, or jumps if there is none.
+    Rewind {
+        cursor: usize,
+        target: usize,
+    },
+    /// Moves to the last entry, or jumps if there is none.
+    Last {
+        cursor: usize,
+        target: usize,
+    },
+    /// Moves to the next entry and jumps, unless there is none.
+    Next {
+        cursor: usize,
+        target: usize,
+    },
+    /// Moves to the previous entry and jumps, unless there is none.
+    Prev {
+        cursor: usize,
+        target: usize,
+    },
+    /// Moves a table cursor to the row with the rowid in `rowid`, or jumps if there is
+    /// none.
+    SeekRowid {
+        cursor: usize,
+        target: usize,
+        rowid: usize,
+    },
+    /// Moves to the first entry at or after (`op` is `>=`), after (`>`), at or before
+    /// (`<=`) or before (`<`) the key in the `nkey` registers from `key`, or jumps if
+    /// there is none. The key of a table cursor is a rowid.
+    Seek {
+        op: BinaryOp,
+        cursor: usize,
+        target: usize,
+        key: usize,
+        nkey: usize,
+    },
+    /// Jumps if the leading columns of the current index entry compare with the key in
+    /// the `nkey` registers from `key` as `op` says.
+    IdxCompare {
+        op: BinaryOp,
+        cursor: usize,
+        target: usize,
+        key: usize,
+        nkey: usize,
+    },
+    /// Reads a column of the current entry, NULL if the record does not have it.
+    Column {
+        cursor: usize,
+        column: usize,
+        dest: usize,
+    },
+    Rowid {
+        cursor: usize,
+        dest: usize,
+    },
+    /// Reads the rowid at the end of the current index entry.
+    IdxRowid {
+        cursor: usize,
+        dest: usize,
+    },
+    /// Points a cursor at a row of NULLs, which it leaves at the next move.
+    NullRow {
+        cursor: usize,
+    },
+    /// Turns an integer into a real, as read from a column with REAL affinity.
+    RealAffinity {
+        reg: usize,
+    },
+    Affinity {
+        reg: usize,
+        affinity: Affinity,
+    },
+    /// Converts a register to an integer, failing if it cannot be one.
+    MustBeInt {
+        reg: usize,
+    },
+
+    /// `+`, `-`, `*`, `/`, `%` or `||`.
+    Arithmetic {
+        op: BinaryOp,
+        lhs: usize,
+        rhs: usize,
+        dest: usize,
+    },
+    /// Jumps if `lhs op rhs` holds under `collation`, or if either is NULL and
+    /// `jump_if_null`.
+    Compare {
+        op: BinaryOp,
+        lhs: usize,
+        rhs: usize,
+        target: usize,
+        collation: Collation,
+        jump_if_null: bool,
+    },
+    /// Sets `dest` to NULL if `lhs` or `rhs` is NULL, and to 0 otherwise.
+    ZeroOrNull {
+        lhs: usize,
+        dest: usize,
+        rhs: usize,
+    },
+    And {
+        lhs: usize,
+        rhs: usize,
+        dest: usize,
+    },
+    Or {
+        lhs: usize,
+        rhs: usize,
+        dest: usize,
+    },
+    Not {
+        src: usize,
+        dest: usize,
+    },
+    /// Jumps if the register is false, or NULL and `jump_if_null`.
+    IfNot {
+        reg: usize,
+        target: usize,
+        jump_if_null: bool,
+    },
+    IsNull {
+        reg: usize,
+        target: usize,
+    },
+    NotNull {
+        reg: usize,
+        target: usize,
+    },
+    /// Jumps if the register holds a positive integer, after subtracting `decrement`.
+    IfPos {
+        reg: usize,
+        target: usize,
+        decrement: i64,
+    },
+    /// Decrements the register, and jumps if it became zero.
+    DecrJumpZero {
+        reg: usize,
+        target: usize,
+    },
+    Function {
+        func: Function,
+        args: usize,
+        nargs: usize,
+        dest: usize,
+    },
+    /// Returns the `n` registers from `start` as a row of the result.
+    ResultRow {
+        start: usize,
+        n: usize,
+    },
+
+    /// Opens a set of rows, told apart by comparing their values with `collations`. If
+    /// `last_only`, for rows that come with equal rows next to each other, the set only
+    /// keeps the row added last.
+    OpenEphemeral {
+        cursor: usize,
+        collations: Vec<Collation>,
+        last_only: bool,
+    },
+    /// Jumps if the row in the `nkey` registers from `key` is in the set.
+    Found {
+        cursor: usize,
+        key: usize,
+        nkey: usize,
+        target: usize,
+    },
+    /// Adds the row in the `nkey` registers from `key` to the set.
+    IdxInsert {
+        cursor: usize,
+        key: usize,
+        nkey: usize,
+    },
+
+    /// Opens a sorter, which orders the rows put into it by `keys`.
+    SorterOpen {
+        cursor: usize,
+        keys: Vec<SortKey>,
+    },
+    SorterInsert {
+        cursor: usize,
+        key: usize,
+        nkey: usize,
+        row: usize,
+        n: usize,
+    },
+    /// Sorts the rows and moves to the first, or jumps if there is none.
+    SorterSort {
+        cursor: usize,
+        target: usize,
+    },
+    SorterNext {
+        cursor: usize,
+        target: usize,
+    },
+
+    /// Opens a [`HashJoin`] on keys compared with `collations`.
+    HashOpen {
+        cursor: usize,
+        collations: Vec<Collation>,
+        condition: Option<Expr>,
+        build_first: bool,
+        null_extend: Option<usize>,
+    },
+    HashBuild {
+        cursor: usize,
+        key: usize,
+        nkey: usize,
+        row: usize,
+        n: usize,
+    },
+    HashProbe {
+        cursor: usize,
+        key: usize,
+        nkey: usize,
+        row: usize,
+        n: usize,
+    },
+    /// Ends the probe rows, so that the rows of spilled partitions are joined.
+    HashFinish {
+        cursor: usize,
+    },
+    /// Moves to the next joined row. Jumps to `target` if more probe rows are needed, and
+    /// to `end` once the join is finished.
+    HashNext {
+        cursor: usize,
+        target: usize,
+        end: usize,
+    },
+
+    /// Opens the [`Groups`] of an aggregate query whose input rows have `width` columns,
+    /// with the collations of its GROUP BY terms.
+    AggOpen {
+        cursor: usize,
+        calls: Vec<(AggregateFunc, bool, Collation)>,
+        collations: Vec<Collation>,
+        width: usize,
+        grouped: bool,
+    },
+    /// Starts on an input row of the group with the key in the `nkey` registers from `key`.
+    AggGroup {
+        cursor: usize,
+        key: usize,
+        nkey: usize,
+    },
+    AggStep {
+        cursor: usize,
+        call: usize,
+        args: usize,
+        nargs: usize,
+    },
+    /// Offers the input row in the `n` registers from `row` for the bare columns.
+    AggRow {
+        cursor: usize,
+        row: usize,
+        n: usize,
+    },
+    /// Finishes the groups and moves to the first, or jumps if there is none.
+    AggFinal {
+        cursor: usize,
+        target: usize,
+    },
+
+    /// Checks the integrity of the database, as [`integrity_check`] does, and moves to the
+    /// first row of the result, reporting at most `max_errors` problems.
+    IntegrityCk {
+        cursor: usize,
+        max_errors: usize,
+        quick: bool,
+        target: usize,
+    },
+}
+
+/// The scalar functions an [`Insn::Function`] calls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Function {
+    /// `like(pattern, value)`, with the ESCAPE character of the LIKE operator.
+    Like { escape: Option<char> },
+    /// `glob(pattern, value)`.
+    Glob,
+}
+
+impl Program {
+    /// Starts running the program against `db`.
+    /// The instructions as the rows EXPLAIN returns: addr, opcode, p1, p2, p3, p4, p5 and
+    /// comment, with an empty p4 or comment as NULL.
+    pub fn rows(&self) -> Vec<Row> {
+        let text = |txt: String| match txt.is_empty() {
+            true => SerialValue::Null,
+            false => SerialValue::String(txt),
+        };
+        self.insns
+            .iter()
+            .enumerate()
+            .map(|(addr, insn)| {
+                let (opcode, [p1, p2, p3], p4, p5, comment) = insn.explain();
+                vec![
+                    SerialValue::I64(addr as i64),
+                    SerialValue::String(opcode.to_string()),
+                    SerialValue::I64(p1),
+                    SerialValue::I64(p2),
+                    SerialValue::I64(p3),
+                    text(p4),
+                    SerialValue::I64(p5 as i64),
+                    text(comment),
+                ]
+            })
+            .collect()
+    }
+
+    /// Starts running the program against `db`, with `parameters` bound to the parameters
+    /// by index from 1. Parameters without a value are NULL.
+    pub fn run<'a>(&'a self, db: &'a Database, parameters: Vec<SerialValue>) -> Vm<'a> {
+        Vm {
+            db,
+            program: self,
+            pc: 0,
+            registers: vec![SerialValue::Null; self.registers + 1],
+            cursors: (0..self.cursors).map(|_| None).collect(),
+            parameters,
+            halted: false,
+        }
+    }
+
+    /// How far sqlite3 indents each instruction in an EXPLAIN listing: the body of a
+    /// loop is indented from the instruction a Next jumps back to, or a Goto jumps back
+    /// to if that starts a loop.
+    fn indents(&self) -> Vec<usize> {
+        let mut indents = vec![0; self.insns.len()];
+        for (addr, insn) in self.insns.iter().enumerate() {
+            let (opcode, [p1, p2, _], ..) = insn.explain();
+            let Ok(target) = usize::try_from(p2) else {
+                continue;
+            };
+            let starts_loop = |target: usize| {
+                self.insns.get(target).is_some_and(|insn| {
+                    let opcode = insn.explain().0;
+                    ["Yield", "SeekLT", "SeekGT", "Rewind", "HashNext"].contains(&opcode)
+                })
+            };
+            let is_loop = match opcode {
+                "Next" | "Prev" | "SorterNext" => target > 0,
+                "Goto" => target < addr && (starts_loop(target) || p1 != 0),
+                _ => false,
+            };
+            if is_loop {
+                for indent in &mut indents[target.min(addr)..addr] {
+                    *indent += 2;
+                }
+            }
+        }
+        indents
+    }
+}
+
+/// Lists the instructions the way sqlite3 shows the result of EXPLAIN.
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "addr  opcode         p1    p2    p3    p4             p5  comment      "
+        )?;
+        write!(
+            f,
+            "----  -------------  ----  ----  ----  -------------  --  -------------"
+        )?;
+        for ((addr, insn), indent) in self.insns.iter().enumerate().zip(self.indents()) {
+            let (opcode, [p1, p2, p3], p4, p5, comment) = insn.explain();
+            write!(
+                f,
+                "\n{:<4}  {:indent$}{:<13}  {:<4}  {:<4}  {:<4}  {:<13}  {:<2}  {}",
+                addr,
+                "",
+                opcode,
+                p1,
+                p2,
+                p3,
+                p4,
+                p5,
+                comment,
+                indent = indent
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Insn {
+    /// The columns of the instruction in an EXPLAIN listing: its opcode, operands P1 to P5
+    /// and a comment.
+    fn explain(&self) -> (&'static str, [i64; 3], String, u16, String) {
+        let r = |reg: &usize| *reg as i64;
+        let range = |start: usize, n: usize| match n {
+            0 => String::new(),
+            1 => format!("r[{}]", start),
+            _ => format!("r[{}..{}]", start, (start + n).saturating_sub(1)),
+        };
+        let op = |p1: i64, p2: i64, p3: i64| [p1, p2, p3];
+
+        match self {
+            Insn::Init { target } => (
+                "Init",
+                op(0, r(target), 0),
+                String::new(),
+                0,
+                format!("Start at {}", target),
+            ),
+            Insn::Goto { target } => ("Goto", op(0, r(target), 0), String::new(), 0, String::new()),
+            Insn::Halt => ("Halt", op(0, 0, 0), String::new(), 0, String::new()),
+            Insn::InitCoroutine { reg, target, start } => (
+                "InitCoroutine",
+                op(r(reg), r(target), r(start)),
+                String::new(),
+                0,
+                String::new(),
+            ),
+            Insn::Yield { reg, target } => (
+                "Yield",
+                op(r(reg), r(target), 0),
+                String::new(),
+                0,
+                String::new(),
+            ),
+            Insn::EndCoroutine { reg } => (
+                "EndCoroutine",
+                op(r(reg), 0, 0),
+                String::new(),
+                0,
+                String::new(),
+            ),
+            Insn::Integer { value, dest } => match i32::try_from(*value) {
+                Ok(_) => (
+                    "Integer",
+                    op(*value, r(dest), 0),
+                    String::new(),
+                    0,
+                    format!("r[{}]={}", dest, value),
+                ),
+                Err(_) => (
+                    "Int64",
+                    op(0, r(dest), 0),
+                    value.to_string(),
+                    0,
+                    format!("r[{}]={}", dest, value),
+                ),
+            },
+            Insn::Real { value, dest } => {
+                let value = SerialValue::Float64(*value).display();
+                (
+                    "Real",
+                    op(0, r(dest), 0),
+                    value.clone(),
+                    0,
+                    format!("r[{}]={}", dest, value),
+                )
+            }
+            Insn::String8 { value, dest } => (
+                "String8",
+                op(0, r(dest), 0),
+                value.clone(),
+                0,
+                format!("r[{}]='{}'", dest, value),
+            ),
+            Insn::Blob { value, dest } => (
+                "Blob",
+                op(value.len() as i64, r(dest), 0),
+                "(blob)".to_string(),
+                0,
+                format!("r[{}]= (len={})", dest, value.len()),
+            ),
+            Insn::Null { dest } => (
+                "Null",
+                op(0, r(dest), 0),
+                String::new(),
+                0,
+                format!("r[{}]=NULL", dest),
+            ),
+            Insn::Variable { index, dest } => (
+                "Variable",
+                op(*index as i64, r(dest), 0),
+                String::new(),
+                0,
+                format!("r[{}]=parameter({})", dest, index),
+            ),
+            Insn::SCopy { src, dest } => (
+                "SCopy",
+                op(r(src), r(dest), 0),
+                String::new(),
+                0,
+                format!("r[{}]=r[{}]", dest, src),
+            ),
+            Insn::OpenRead {
+                cursor,
+                root_page,
+                columns,
+                key,
+                name,
+            } => (
+                "OpenRead",
+                op(r(cursor), r(root_page), 0),
+                match key {
+                    Some(key) => key_info(key.iter().copied().chain([KeyOrder::default()])),
+                    None => columns.to_string(),
+                },
+                0,
+                format!("root={} iDb=0; {}", root_page, name),
+            ),
+            Insn::Rewind { cursor, target } => (
+                "Rewind",
+                op(r(cursor), r(target), 0),
+                String::new(),
+                0,
+                String::new(),
+            ),
+            Insn::Last { cursor, target } => (
+                "Last",
+                op(r(cursor), r(target), 0),
+                String::new(),
+                0,
+                String::new(),
+            ),
+            Insn::Next { cursor, target } => (
+                "Next",
+                op(r(cursor), r(target), 0),
+                String::new(),
+                0,
+                String::new(),
+            ),
+            Insn::Prev { cursor, target } => (
+                "Prev",
+                op(r(cursor), r(target), 0),
+                String::new(),
+                0,
+                String::new(),
+            ),
+            Insn::SeekRowid {
+                cursor,
+                target,
+                rowid,
+            } => (
+                "SeekRowid",
+                op(r(cursor), r(target), r(rowid)),
+                String::new(),
+                0,
+                format!("intkey=r[{}]", rowid),
+            ),
+            Insn::Seek {
+                op: seek,
+                cursor,
+                target,
+                key,
+                nkey,
+            } => (
+                match seek {
+                    BinaryOp::GtEq => "SeekGE",
+                    BinaryOp::Gt => "SeekGT",
+                    BinaryOp::LtEq => "SeekLE",
+                    _ => "SeekLT",
+                },
+                op(r(cursor), r(target), r(key)),
+                nkey.to_string(),
+                0,
+                format!("key={}", range(*key, *nkey)),
+            ),
+            Insn::IdxCompare {
+                op: compare,
+                cursor,
+                target,
+                key,
+                nkey,
+            } => (
+                match compare {
+                    BinaryOp::GtEq => "IdxGE",
+                    BinaryOp::Gt => "IdxGT",
+                    BinaryOp::LtEq => "IdxLE",
+                    _ => "IdxLT",
+                },
+                op(r(cursor), r(target), r(key)),
+                nkey.to_string(),
+                0,
+                format!("key={}", range(*key, *nkey)),
+            ),
+            Insn::Column {
+                cursor,
+                column,
+                dest,
+            } => (
+                "Column",
+                op(r(cursor), r(column), r(dest)),
+                String::new(),
+                0,
+                format!("r[{}]= cursor {} column {}", dest, cursor, column),
+            ),
+            Insn::Rowid { cursor, dest } => (
+                "Rowid",
+                op(r(cursor), r(dest), 0),
+                String::new(),
+                0,
+                format!("r[{}]=rowid", dest),
+            ),
+            Insn::IdxRowid { cursor, dest } => (
+                "IdxRowid",
+                op(r(cursor), r(dest), 0),
+                String::new(),
+                0,
+                format!("r[{}]=rowid", dest),
+            ),
+            Insn::NullRow { cursor } => (
+                "NullRow",
+                op(r(cursor), 0, 0),
+                String::new(),
+                0,
+                String::new(),
+            ),
+            Insn::RealAffinity { reg } => (
+                "RealAffinity",
+                op(r(reg), 0, 0),
+                String::new(),
+                0,
+                String::new(),
+            ),
+            Insn::Affinity { reg, affinity } => (
+                "Affinity",
+                op(r(reg), 1, 0),
+                match affinity {
+                    Affinity::Blob => "A",
+                    Affinity::Text => "B",
+                    Affinity::Numeric => "C",
+                    Affinity::Integer => "D",
+                    Affinity::Real => "E",
+                }
+                .to_string(),
+                0,
+                format!("affinity(r[{}])", reg),
+            ),
+            Insn::MustBeInt { reg } => (
+                "MustBeInt",
+                op(r(reg), 0, 0),
+                String::new(),
+                0,
+                String::new(),
+            ),
+            Insn::Arithmetic {
+                op: arithmetic,
+                lhs,
+                rhs,
+                dest,
+            } => {
+                let (opcode, symbol) = match arithmetic {
+                    BinaryOp::Add => ("Add", "+"),
+                    BinaryOp::Subtract => ("Subtract", "-"),
+                    BinaryOp::Multiply => ("Multiply", "*"),
+                    BinaryOp::Divide => ("Divide", "/"),
+                    BinaryOp::Modulo => ("Remainder", "%"),
+                    _ => ("Concat", "||"),
+                };
+                (
+                    opcode,
+                    op(r(rhs), r(lhs), r(dest)),
+                    String::new(),
+                    0,
+                    format!("r[{}]=r[{}]{}r[{}]", dest, lhs, symbol, rhs),
+                )
+            }
+            Insn::Compare {
+                op: comparison,
+                lhs,
+                rhs,
+                target,
+                collation,
+                jump_if_null,
+            } => {
+                let (opcode, symbol) = match comparison {
+                    BinaryOp::Eq => ("Eq", "=="),
+                    BinaryOp::NotEq => ("Ne", "!="),
+                    BinaryOp::Lt => ("Lt", "<"),
+                    BinaryOp::LtEq => ("Le", "<="),
+                    BinaryOp::Gt => ("Gt", ">"),
+                    _ => ("Ge", ">="),
+                };
+                (
+                    opcode,
+                    op(r(rhs), r(target), r(lhs)),
+                    format!("({})", collation_name(*collation)),
+                    if *jump_if_null { 16 } else { 0 },
+                    format!("if r[{}]{}r[{}] goto {}", lhs, symbol, rhs, target),
+                )
+            }
+            Insn::ZeroOrNull { lhs, dest, rhs } => (
+                "ZeroOrNull",
+                op(r(lhs), r(dest), r(rhs)),
+                String::new(),
+                0,
+                String::new(),
+            ),
+            Insn::And { lhs, rhs, dest } => (
+                "And",
+                op(r(lhs), r(rhs), r(dest)),
+                String::new(),
+                0,
+                format!("r[{}]=(r[{}] && r[{}])", dest, lhs, rhs),
+            ),
+            Insn::Or { lhs, rhs, dest } => (
+                "Or",
+                op(r(lhs), r(rhs), r(dest)),
+                String::new(),
+                0,
+                format!("r[{}]=(r[{}] || r[{}])", dest, lhs, rhs),
+            ),
+            Insn::Not { src, dest } => (
+                "Not",
+                op(r(src), r(dest), 0),
+                String::new(),
+                0,
+                format!("r[{}]=!r[{}]", dest, src),
+            ),
+            Insn::IfNot {
+                reg,
+                target,
+                jump_if_null,
+            } => (
+                "IfNot",
+                op(r(reg), r(target), *jump_if_null as i64),
+                String::new(),
+                0,
+                String::new(),
+            ),
+            Insn::IsNull { reg, target } => (
+                "IsNull",
+                op(r(reg), r(target), 0),
+                String::new(),
+                0,
+                format!("if r[{}]==NULL goto {}", reg, target),
+            ),
+            Insn::NotNull { reg, target } => (
+                "NotNull",
+                op(r(reg), r(target), 0),
+                String::new(),
+                0,
+                format!("if r[{}]!=NULL goto {}", reg, target),
+            ),
+            Insn::IfPos {
+                reg,
+                target,
+                decrement,
+            } => (
+                "IfPos",
+                op(r(reg), r(target), *decrement),
+                String::new(),
+                0,
+                format!(
+                    "if r[{}]>0 then r[{}]-={}, goto {}",
+                    reg, reg, decrement, target
+                ),
+            ),
+            Insn::DecrJumpZero { reg, target } => (
+                "DecrJumpZero",
+                op(r(reg), r(target), 0),
+                String::new(),
+                0,
+                format!("if (--r[{}])==0 goto {}", reg, target),
+            ),
+            Insn::Function {
+                func,
+                args,
+                nargs,
+                dest,
+            } => {
+                let name = match func {
+                    Function::Like { .. } => "like",
+                    Function::Glob => "glob",
+                };
+                (
+                    "Function",
+                    op(0, r(args), r(dest)),
+                    format!("{}({})", name, nargs),
+                    0,
+                    format!("r[{}]=func({})", dest, range(*args, *nargs)),
+                )
+            }
+            Insn::ResultRow { start, n } => (
+                "ResultRow",
+                op(r(start), r(n), 0),
+                String::new(),
+                0,
+                format!("output={}", range(*start, *n)),
+            ),
+            Insn::OpenEphemeral {
+                cursor,
+                collations,
+                last_only,
+            } => (
+                "OpenEphemeral",
+                op(r(cursor), collations.len() as i64, 0),
+                key_info(collations.iter().map(|&collation| KeyOrder {
+                    collation,
+                    descending: false,
+                })),
+                *last_only as u16,
+                String::new(),
+            ),
+            Insn::Found {
+                cursor,
+                key,
+                nkey,
+                target,
+            } => (
+                "Found",
+                op(r(cursor), r(target), r(key)),
+                nkey.to_string(),
+                0,
+                format!("key={}", range(*key, *nkey)),
+            ),
+            Insn::IdxInsert { cursor, key, nkey } => (
+                "IdxInsert",
+                op(r(cursor), r(key), 0),
+                nkey.to_string(),
+                0,
+                format!("key={}", range(*key, *nkey)),
+            ),
+            Insn::SorterOpen { cursor, keys } => (
+                "SorterOpen",
+                op(r(cursor), keys.len() as i64, 0),
+                key_info(keys.iter().map(|key| KeyOrder {
+                    collation: key.collation,
+                    descending: key.descending,
+                })),
+                0,
+                String::new(),
+            ),
+            Insn::SorterInsert {
+                cursor,
+                key,
+                nkey,
+                row,
+                n,
+            } => (
+                "SorterInsert",
+                op(r(cursor), r(key), r(row)),
+                String::new(),
+                0,
+                format!("key={} data={}", range(*key, *nkey), range(*row, *n)),
+            ),
+            Insn::SorterSort { cursor, target } => (
+                "SorterSort",
+                op(r(cursor), r(target), 0),
+                String::new(),
+                0,
+                String::new(),
+            ),
+            Insn::SorterNext { cursor, target } => (
+                "SorterNext",
+                op(r(cursor), r(target), 0),
+                String::new(),
+                0,
+                String::new(),
+            ),
+            Insn::HashOpen {
+                cursor,
+                collations,
+                null_extend,
+                ..
+            } => (
+                "HashOpen",
+                op(r(cursor), collations.len() as i64, 0),
+                key_info(collations.iter().map(|&collation| KeyOrder {
+                    collation,
+                    descending: false,
+                })),
+                null_extend.is_some() as u16,
+                String::new(),
+            ),
+            Insn::HashBuild {
+                cursor,
+                key,
+                nkey,
+                row,
+                n,
+            } => (
+                "HashBuild",
+                op(r(cursor), r(key), r(row)),
+                String::new(),
+                0,
+                format!("key={} data={}", range(*key, *nkey), range(*row, *n)),
+            ),
+            Insn::HashProbe {
+                cursor,
+                key,
+                nkey,
+                row,
+                n,
+            } => (
+                "HashProbe",
+                op(r(cursor), r(key), r(row)),
+                String::new(),
+                0,
+                format!("key={} data={}", range(*key, *nkey), range(*row, *n)),
+            ),
+            Insn::HashFinish { cursor } => (
+                "HashFinish",
+                op(r(cursor), 0, 0),
+                String::new(),
+                0,
+                String::new(),
+            ),
+            Insn::HashNext {
+                cursor,
+                target,
+                end,
+            } => (
+                "HashNext",
+                op(r(cursor), r(target), r(end)),
+                String::new(),
+                0,
+                String::new(),
+            ),
+            Insn::AggOpen {
+                cursor,
+                calls,
+                width,
+                ..
+            } => (
+                "AggOpen",
+                op(r(cursor), calls.len() as i64, r(width)),
+                String::new(),
+                0,
+                String::new(),
+            ),
+            Insn::AggGroup { cursor, key, nkey } => (
+                "AggGroup",
+                op(r(cursor), r(key), r(nkey)),
+                String::new(),
+                0,
+                format!("key={}", range(*key, *nkey)),
+            ),
+            Insn::AggStep {
+                cursor,
+                call,
+                args,
+                nargs,
+            } => (
+                "AggStep",
+                op(r(cursor), r(args), r(call)),
+                String::new(),
+                *nargs as u16,
+                format!("step({})", range(*args, *nargs)),
+            ),
+            Insn::AggRow { cursor, row, n } => (
+                "AggRow",
+                op(r(cursor), r(row), r(n)),
+                String::new(),
+                0,
+                format!("data={}", range(*row, *n)),
+            ),
+            Insn::AggFinal { cursor, target } => (
+                "AggFinal",
+                op(r(cursor), r(target), 0),
+                String::new(),
+                0,
+                String::new(),
+            ),
+            Insn::IntegrityCk {
+                cursor,
+                max_errors,
+                quick,
+                target,
+            } => (
+                "IntegrityCk",
+                op(r(cursor), r(target), r(max_errors)),
+                String::new(),
+                *quick as u16,
+                String::new(),
+            ),
+        }
+    }
+
+    /// The jump targets of the instruction, for the code generator to resolve.
+    pub(crate) fn targets_mut(&mut self) -> Vec<&mut usize> {
+        match self {
+            Insn::Init { target }
+            | Insn::Goto { target }
+            | Insn::Yield { target, .. }
+            | Insn::Rewind { target, .. }
+            | Insn::Last { target, .. }
+            | Insn::Next { target, .. }
+            | Insn::Prev { target, .. }
+            | Insn::SeekRowid { target, .. }
+            | Insn::Seek { target, .. }
+            | Insn::IdxCompare { target, .. }
+            | Insn::Found { target, .. }
+            | Insn::Compare { target, .. }
+            | Insn::IfNot { target, .. }
+            | Insn::IsNull { target, .. }
+            | Insn::NotNull { target, .. }
+            | Insn::IfPos { target, .. }
+            | Insn::DecrJumpZero { target, .. }
+            | Insn::SorterSort { target, .. }
+            | Insn::SorterNext { target, .. }
+            | Insn::AggFinal { target, .. }
+            | Insn::IntegrityCk { target, .. } => vec![target],
+            Insn::InitCoroutine { target, start, .. } => vec![target, start],
+            Insn::HashNext { target, end, .. } => vec![target, end],
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// How SQLite shows the order of keys: `k(n,...)` with the collation of each column,
+/// preceded by `-` if it is descending. An index shows BINARY as nothing.
+fn key_info(orders: impl Iterator<Item = KeyOrder>) -> String {
+    let columns: Vec<String> = orders
+        .map(|order| {
+            let sign = if order.descending { "-" } else { "" };
+            let name = match order.collation {
+                Collation::Binary => "",
+                collation => collation_name(collation),
+            };
+            format!(",{}{}", sign, name)
+        })
+        .collect();
+    format!("k({}{})", columns.len(), columns.concat())
+}
+
+fn collation_name(collation: Collation) -> &'static str {
+    match collation {
+        Collation::Binary => "BINARY",
+        Collation::NoCase => "NOCASE",
+        Collation::RTrim => "RTRIM",
+    }
+}
+
+/// A running [`Program`], which returns the rows of the query as they are produced.
+pub struct Vm<'a> {
+    db: &'a Database,
+    program: &'a Program,
+    /// The address of the next instruction to run.
+    pc: usize,
+    registers: Vec<SerialValue>,
+    cursors: Vec<Option<Cursor<'a>>>,
+    /// The values bound to the parameters, by index from 1.
+    parameters: Vec<SerialValue>,
+    halted: bool,
+}
+
+enum Cursor<'a> {
+    BTree {
+        cursor: BTreeCursor<'a>,
+        /// The order of the keys of an index, or `None` for a table.
+        key: Option<&'a [KeyOrder]>,
+        /// The record of the current entry, once a column was read from it.
+        record: Option<Record>,
+        null_row: bool,
+    },
+    /// The rows of an ephemeral set.
+    Set {
+        rows: BTreeSet<Vec<Collated>>,
+        collations: Vec<Collation>,
+        last_only: bool,
+    },
+    Sorter {
+        sorter: Option<Sorter<'a>>,
+        sorted: Option<Sorted<'a>>,
+        row: Option<Row>,
+    },
+    Hash {
+        join: Box<HashJoin<'a>>,
+        row: Option<Row>,
+    },
+    Groups {
+        groups: Option<Groups>,
+        width: usize,
+        grouped: bool,
+        rows: std::vec::IntoIter<Row>,
+        row: Option<Row>,
+    },
+    /// The rows of an integrity check.
+    Rows {
+        rows: std::vec::IntoIter<Row>,
+        row: Option<Row>,
+    },
+}
+
+impl Iterator for Vm<'_> {
+    type Item = Result<Row>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.halted {
+            return None;
+        }
+
+        let result = self.step().transpose();
+        if !matches!(result, Some(Ok(_))) {
+            self.halted = true;
+        }
+        result
+    }
+}
+
+impl<'a> Vm<'a> {
+    /// Runs the program until it produces a row or halts.
+    fn step(&mut self) -> Result<Option<Row>> {
+        let program = self.program;
+        loop {
+            let addr = self.pc;
+            let Some(insn) = program.insns.get(addr) else {
+                bail!(Sql, "program ran past its end at {}", addr);
+            };
+            self.pc += 1;
+
+            match insn {
+                Insn::Init { target } | Insn::Goto { target } => self.pc = *target,
+                Insn::Halt => return Ok(None),
+                Insn::InitCoroutine { reg, target, start } => {
+                    self.registers[*reg] = SerialValue::I64(*start as i64 - 1);
+                    self.pc = *target;
+                }
+                Insn::Yield { reg, .. } => {
+                    let resume = self.address(*reg)?;
+                    self.registers[*reg] = SerialValue::I64(addr as i64);
+                    self.pc = resume + 1;
+                }
+                Insn::EndCoroutine { reg } => {
+                    let caller = self.address(*reg)?;
+                    match program.insns.get(caller) {
+                        Some(Insn::Yield { target, .. }) => self.pc = *target,
+                        _ => bail!(Sql, "coroutine in r[{}] was not resumed by a Yield", reg),
+                    }
+                }
+
+                Insn::Integer { value, dest } => self.registers[*dest] = SerialValue::I64(*value),
+                Insn::Real { value, dest } => self.registers[*dest] = SerialValue::Float64(*value),
+                Insn::String8 { value, dest } => {
+                    self.registers[*dest] = SerialValue::String(value.clone())
+                }
+                Insn::Blob { value, dest } => {
+                    self.registers[*dest] = SerialValue::Blob(value.clone())
+                }
+                Insn::Null { dest } => self.registers[*dest] = SerialValue::Null,
+                Insn::Variable { index, dest } => {
+                    self.registers[*dest] = self
+                        .parameters
+                        .get(index - 1)
+                        .cloned()
+                        .unwrap_or(SerialValue::Null)
+                }
+                Insn::SCopy { src, dest } => self.registers[*dest] = self.registers[*src].clone(),
+
+                Insn::OpenRead {
+                    cursor,
+                    root_page,
+                    key,
+                    ..
+                } => {
+                    self.cursors[*cursor] = Some(Cursor::BTree {
+                        cursor: BTreeCursor::new(self.db, *root_page),
+                        key: key.as_deref(),
+                        record: None,
+                        null_row: false,
+                    });
+                }
+                Insn::Rewind { cursor, target } | Insn::Last { cursor, target } => {
+                    let last = matches!(insn, Insn::Last { .. });
+                    let (btree, ..) = self.btree(*cursor)?;
+                    let found = match last {
+                        true => btree.last()?,
+                        false => btree.first()?,
+                    };
+                    if !found {
+                        self.pc = *target;
+                    }
+                }
+                Insn::Next { cursor, target } | Insn::Prev { cursor, target } => {
+                    let prev = matches!(insn, Insn::Prev { .. });
+                    let moved = match self.cursor(*cursor)? {
+                        Cursor::BTree {
+                            cursor,
+                            record,
+                            null_row,
+                            ..
+                        } => {
+                            *record = None;
+                            match (*null_row, cursor.is_valid(), prev) {
+                                (false, true, true) => cursor.prev()?,
+                                (false, true, false) => cursor.next()?,
+                                _ => false,
+                            }
+                        }
+                        Cursor::Groups { rows, row, .. } | Cursor::Rows { rows, row } => {
+                            *row = rows.next();
+                            row.is_some()
+                        }
+                        _ => bail!(Sql, "cursor {} cannot be moved with Next", cursor),
+                    };
+                    if moved {
+                        self.pc = *target;
+                    }
+                }
+                Insn::SeekRowid {
+                    cursor,
+                    target,
+                    rowid,
+                } => {
+                    // Only integers can be equal to a rowid.
+                    let rowid = self.registers[*rowid].apply_affinity(Affinity::Integer);
+                    let (btree, ..) = self.btree(*cursor)?;
+                    let found = match rowid.as_integer() {
+                        Some(rowid) => btree.seek_rowid(rowid)?,
+                        None => false,
+                    };
+                    if !found {
+                        self.pc = *target;
+                    }
+                }
+                Insn::Seek {
+                    op,
+                    cursor,
+                    target,
+                    key,
+                    nkey,
+                } => {
+                    let key = self.registers[*key..*key + *nkey].to_vec();
+                    if !self.seek(*cursor, *op, &key)? {
+                        self.pc = *target;
+                    }
+                }
+                Insn::IdxCompare {
+                    op,
+                    cursor,
+                    target,
+                    key,
+                    nkey,
+                } => {
+                    let key = self.registers[*key..*key + *nkey].to_vec();
+                    let order = match self.cursor(*cursor)? {
+                        Cursor::BTree {
+                            key: Some(order), ..
+                        } => *order,
+                        _ => bail!(Sql, "cursor {} is not an index cursor", cursor),
+                    };
+                    let Some(record) = self.entry(*cursor)? else {
+                        bail!(Sql, "cursor {} is not on an index entry", cursor);
+                    };
+                    let ordering = compare_prefix(record, &key, order);
+                    let jump = match op {
+                        BinaryOp::Gt => ordering.is_gt(),
+                        BinaryOp::GtEq => ordering.is_ge(),
+                        BinaryOp::Lt => ordering.is_lt(),
+                        _ => ordering.is_le(),
+                    };
+                    if jump {
+                        self.pc = *target;
+                    }
+                }
+                Insn::Column {
+                    cursor,
+                    column,
+                    dest,
+                } => self.registers[*dest] = self.column(*cursor, *column)?,
+                Insn::Rowid { cursor, dest } | Insn::IdxRowid { cursor, dest } => {
+                    self.registers[*dest] = match self.cursor(*cursor)? {
+                        Cursor::BTree { null_row: true, .. } => SerialValue::Null,
+                        Cursor::BTree { cursor, .. } if cursor.is_valid() => {
+                            SerialValue::I64(cursor.rowid()?)
+                        }
+                        _ => SerialValue::Null,
+                    }
+                }
+                Insn::NullRow { cursor } => {
+                    if let Cursor::BTree {
+                        record, null_row, ..
+                    } = self.cursor(*cursor)?
+                    {
+                        *record = None;
+                        *null_row = true;
+                    }
+                }
+                Insn::RealAffinity { reg } => {
+                    self.registers[*reg] = self.registers[*reg].apply_affinity(Affinity::Real)
+                }
+                Insn::Affinity { reg, affinity } => {
+                    self.registers[*reg] = self.registers[*reg].apply_affinity(*affinity)
+                }
+                Insn::MustBeInt { reg } => {
+                    match self.registers[*reg]
+                        .apply_affinity(Affinity::Integer)
+                        .as_integer()
+                    {
+                        Some(num) => self.registers[*reg] = SerialValue::I64(num),
+                        None => bail!(Sql, "datatype mismatch"),
+                    }
+                }
+
+                Insn::Arithmetic { op, lhs, rhs, dest } => {
+                    self.registers[*dest] = binary(
+                        *op,
+                        &self.registers[*lhs],
+                        &self.registers[*rhs],
+                        Collation::Binary,
+                    )
+                }
+                Insn::Compare {
+                    op,
+                    lhs,
+                    rhs,
+                    target,
+                    collation,
+                    jump_if_null,
+                } => {
+                    let jump =
+                        match compare(&self.registers[*lhs], &self.registers[*rhs], *collation) {
+                            None => *jump_if_null,
+                            Some(ordering) => match op {
+                                BinaryOp::Eq => ordering.is_eq(),
+                                BinaryOp::NotEq => ordering.is_ne(),
+                                BinaryOp::Lt => ordering.is_lt(),
+                                BinaryOp::LtEq => ordering.is_le(),
+                                BinaryOp::Gt => ordering.is_gt(),
+                                _ => ordering.is_ge(),
+                            },
+                        };
+                    if jump {
+                        self.pc = *target;
+                    }
+                }
+                Insn::ZeroOrNull { lhs, dest, rhs } => {
+                    let is_null = |reg: &usize| self.registers[*reg] == SerialValue::Null;
+                    self.registers[*dest] = match is_null(lhs) || is_null(rhs) {
+                        true => SerialValue::Null,
+                        false => SerialValue::Zero,
+                    };
+                }
+                Insn::And { lhs, rhs, dest } | Insn::Or { lhs, rhs, dest } => {
+                    let and = matches!(insn, Insn::And { .. });
+                    let (lhs, rhs) = (truth(&self.registers[*lhs]), truth(&self.registers[*rhs]));
+                    self.registers[*dest] = from_truth(match (lhs, rhs) {
+                        (Some(lhs), Some(rhs)) if and => Some(lhs && rhs),
+                        (Some(lhs), Some(rhs)) => Some(lhs || rhs),
+                        // NULL decides the result unless the other operand does.
+                        (Some(known), None) | (None, Some(known)) if known != and => Some(known),
+                        _ => None,
+                    });
+                }
+                Insn::Not { src, dest } => {
+                    self.registers[*dest] =
+                        from_truth(truth(&self.registers[*src]).map(|truth| !truth))
+                }
+                Insn::IfNot {
+                    reg,
+                    target,
+                    jump_if_null,
+                } => {
+                    if truth(&self.registers[*reg]).map_or(*jump_if_null, |truth| !truth) {
+                        self.pc = *target;
+                    }
+                }
+                Insn::IsNull { reg, target } | Insn::NotNull { reg, target } => {
+                    let is_null = self.registers[*reg] == SerialValue::Null;
+                    if is_null == matches!(insn, Insn::IsNull { .. }) {
+                        self.pc = *target;
+                    }
+                }
+                Insn::IfPos {
+                    reg,
+                    target,
+                    decrement,
+                } => {
+                    if let Some(num) = self.registers[*reg].as_integer().filter(|&num| num > 0) {
+                        self.registers[*reg] = SerialValue::I64(num - decrement);
+                        self.pc = *target;
+                    }
+                }
+                Insn::DecrJumpZero { reg, target } => {
+                    if let Some(num) = self.registers[*reg].as_integer() {
+                        let num = num.saturating_sub(1);
+                        self.registers[*reg] = SerialValue::I64(num);
+                        if num == 0 {
+                            self.pc = *target;
+                        }
+                    }
+                }
+                Insn::Function {
+                    func,
+                    args,
+                    nargs,
+                    dest,
+                } => {
+                    let args = &self.registers[*args..*args + *nargs];
+                    let texts = (
+                        args.first().and_then(to_text),
+                        args.get(1).and_then(to_text),
+                    );
+                    self.registers[*dest] = match (func, texts) {
+                        (Function::Like { escape }, (Some(pattern), Some(value))) => {
+                            from_truth(Some(like(&pattern, &value, *escape)))
+                        }
+                        (Function::Glob, (Some(pattern), Some(value))) => {
+                            from_truth(Some(glob(&pattern, &value)))
+                        }
+                        _ => SerialValue::Null,
+                    };
+                }
+                Insn::ResultRow { start, n } => {
+                    return Ok(Some(self.registers[*start..*start + *n].to_vec()))
+                }
+
+                Insn::OpenEphemeral {
+                    cursor,
+                    collations,
+                    last_only,
+                } => {
+                    self.cursors[*cursor] = Some(Cursor::Set {
+                        rows: BTreeSet::new(),
+                        collations: collations.clone(),
+                        last_only: *last_only,
+                    });
+                }
+                Insn::Found {
+                    cursor,
+                    key,
+                    nkey,
+                    target,
+                } => {
+                    let key = self.registers[*key..*key + *nkey].to_vec();
+                    let (rows, key) = self.set(*cursor, key)?;
+                    if rows.contains(&key) {
+                        self.pc = *target;
+                    }
+                }
+                Insn::IdxInsert { cursor, key, nkey } => {
+                    let key = self.registers[*key..*key + *nkey].to_vec();
+                    if let Cursor::Set {
+                        rows,
+                        last_only: true,
+                        ..
+                    } = self.cursor(*cursor)?
+                    {
+                        rows.clear();
+                    }
+                    let (rows, key) = self.set(*cursor, key)?;
+                    rows.insert(key);
+                }
+                Insn::SorterOpen { cursor, keys } => {
+                    self.cursors[*cursor] = Some(Cursor::Sorter {
+                        sorter: Some(Sorter::new(keys, DEFAULT_SORT_MEMORY)),
+                        sorted: None,
+                        row: None,
+                    });
+                }
+                Insn::SorterInsert {
+                    cursor,
+                    key,
+                    nkey,
+                    row,
+                    n,
+                } => {
+                    let key = self.registers[*key..*key + *nkey].to_vec();
+                    let row = self.registers[*row..*row + *n].to_vec();
+                    match self.cursor(*cursor)? {
+                        Cursor::Sorter {
+                            sorter: Some(sorter),
+                            ..
+                        } => sorter.push(key, row)?,
+                        _ => bail!(Sql, "cursor {} is not an open sorter", cursor),
+                    }
+                }
+                Insn::SorterSort { cursor, target } | Insn::SorterNext { cursor, target } => {
+                    let Cursor::Sorter {
+                        sorter,
+                        sorted,
+                        row,
+                    } = self.cursor(*cursor)?
+                    else {
+                        bail!(Sql, "cursor {} is not a sorter", cursor);
+                    };
+                    if let Some(sorter) = sorter.take() {
+                        *sorted = Some(sorter.finish()?);
+                    }
+                    *row = match sorted {
+                        Some(sorted) => sorted.next().transpose()?,
+                        None => None,
+                    };
+                    // SorterSort jumps when there are no rows, SorterNext while there are.
+                    if row.is_some() == matches!(insn, Insn::SorterNext { .. }) {
+                        self.pc = *target;
+                    }
+                }
+
+                Insn::HashOpen {
+                    cursor,
+                    collations,
+                    condition,
+                    build_first,
+                    null_extend,
+                } => {
+                    self.cursors[*cursor] = Some(Cursor::Hash {
+                        join: Box::new(HashJoin::new(
+                            collations,
+                            condition
+                                .as_ref()
+                                .map(|condition| condition.bind_parameters(&self.parameters)),
+                            *build_first,
+                            *null_extend,
+                            DEFAULT_HASH_MEMORY,
+                        )),
+                        row: None,
+                    });
+                }
+                Insn::HashBuild {
+                    cursor,
+                    key,
+                    nkey,
+                    row,
+                    n,
+                }
+                | Insn::HashProbe {
+                    cursor,
+                    key,
+                    nkey,
+                    row,
+                    n,
+                } => {
+                    let key = self.registers[*key..*key + *nkey].to_vec();
+                    let row = self.registers[*row..*row + *n].to_vec();
+                    let join = self.hash_join(*cursor)?;
+                    match insn {
+                        Insn::HashBuild { .. } => join.build(key, row)?,
+                        _ => join.probe(key, row)?,
+                    }
+                }
+                Insn::HashFinish { cursor } => self.hash_join(*cursor)?.finish()?,
+                Insn::HashNext {
+                    cursor,
+                    target,
+                    end,
+                } => {
+                    let Cursor::Hash { join, row } = self.cursor(*cursor)? else {
+                        bail!(Sql, "cursor {} is not a hash join", cursor);
+                    };
+                    *row = join.next_row()?;
+                    match (&row, join.is_finished()) {
+                        (Some(_), _) => {}
+                        (None, false) => self.pc = *target,
+                        (None, true) => self.pc = *end,
+                    }
+                }
+
+                Insn::AggOpen {
+                    cursor,
+                    calls,
+                    collations,
+                    width,
+                    grouped,
+                } => {
+                    self.cursors[*cursor] = Some(Cursor::Groups {
+                        groups: Some(Groups::new(calls.clone(), collations.clone())),
+                        width: *width,
+                        grouped: *grouped,
+                        rows: Vec::new().into_iter(),
+                        row: None,
+                    });
+                }
+                Insn::AggGroup { cursor, key, nkey } => {
+                    let key = self.registers[*key..*key + *nkey].to_vec();
+                    self.groups(*cursor)?.select(key);
+                }
+                Insn::AggStep {
+                    cursor,
+                    call,
+                    args,
+                    nargs,
+                } => {
+                    let args = self.registers[*args..*args + *nargs].to_vec();
+                    self.groups(*cursor)?.step(*call, &args)?;
+                }
+                Insn::AggRow { cursor, row, n } => {
+                    let row = self.registers[*row..*row + *n].to_vec();
+                    self.groups(*cursor)?.set_row(row);
+                }
+                Insn::AggFinal { cursor, target } => {
+                    let Cursor::Groups {
+                        groups,
+                        width,
+                        grouped,
+                        rows,
+                        row,
+                    } = self.cursor(*cursor)?
+                    else {
+                        bail!(Sql, "cursor {} is not an aggregate", cursor);
+                    };
+                    if let Some(groups) = groups.take() {
+                        *rows = groups.finish(*width, *grouped)?.into_iter();
+                    }
+                    *row = rows.next();
+                    if row.is_none() {
+                        self.pc = *target;
+                    }
+                }
+
+                Insn::IntegrityCk {
+                    cursor,
+                    max_errors,
+                    quick,
+                    target,
+                } => {
+                    let rows: Vec<Row> = integrity_check(self.db, *max_errors, *quick)?
+                        .into_iter()
+                        .map(|message| vec![SerialValue::String(message)])
+                        .collect();
+                    let mut rows = rows.into_iter();
+                    let row = rows.next();
+                    if row.is_none() {
+                        self.pc = *target;
+                    }
+                    self.cursors[*cursor] = Some(Cursor::Rows { rows, row });
+                }
+            }
+        }
+    }
+
+    /// The address of the instruction kept in a register by a coroutine.
+    fn address(&self, reg: usize) -> Result<usize> {
+        match self.registers[reg].as_integer() {
+            Some(addr) if addr >= 0 => Ok(addr as usize),
+            _ => bail!(Sql, "r[{}] does not hold an address", reg),
+        }
+    }
+
+    fn cursor(&mut self, cursor: usize) -> Result<&mut Cursor<'a>> {
+        match self.cursors.get_mut(cursor) {
+            Some(Some(cursor)) => Ok(cursor),
+            _ => bail!(Sql, "cursor {} is not open", cursor),
+        }
+    }
+
+    /// The rows of an ephemeral set, and `key` made comparable with them.
+    fn set(
+        &mut self,
+        cursor: usize,
+        key: Vec<SerialValue>,
+    ) -> Result<(&mut BTreeSet<Vec<Collated>>, Vec<Collated>)> {
+        let Cursor::Set {
+            rows, collations, ..
+        } = self.cursor(cursor)?
+        else {
+            bail!(Sql, "cursor {} is not an ephemeral set", cursor);
+        };
+        let key = key
+            .into_iter()
+            .zip(collations.iter())
+            .map(|(value, &collation)| Collated(value, collation))
+            .collect();
+        Ok((rows, key))
+    }
+
+    /// The b-tree cursor, about to be moved, and the order of its keys if it is an index.
+    fn btree(&mut self, cursor: usize) -> Result<(&mut BTreeCursor<'a>, Option<&'a [KeyOrder]>)> {
+        match self.cursor(cursor)? {
+            Cursor::BTree {
+                cursor,
+                key,
+                record,
+                null_row,
+            } => {
+                *record = None;
+                *null_row = false;
+                Ok((cursor, *key))
+            }
+            _ => bail!(Sql, "cursor {} is not a b-tree cursor", cursor),
+        }
+    }
+
+    /// The record of the current entry of a b-tree cursor, or `None` if the cursor is on a
+    /// row of NULLs or past its last entry.
+    fn entry(&mut self, cursor: usize) -> Result<Option<&Record>> {
+        match self.cursor(cursor)? {
+            Cursor::BTree { null_row: true, .. } => Ok(None),
+            Cursor::BTree { cursor, .. } if !cursor.is_valid() => Ok(None),
+            Cursor::BTree { cursor, record, .. } => Ok(Some(match record {
+                Some(record) => record,
+                None => record.insert(cursor.record()?),
+            })),
+            _ => bail!(Sql, "cursor {} is not a b-tree cursor", cursor),
+        }
+    }
+
+    fn hash_join(&mut self, cursor: usize) -> Result<&mut HashJoin<'a>> {
+        match self.cursor(cursor)? {
+            Cursor::Hash { join, .. } => Ok(join),
+            _ => bail!(Sql, "cursor {} is not a hash join", cursor),
+        }
+    }
+
+    fn groups(&mut self, cursor: usize) -> Result<&mut Groups> {
+        match self.cursor(cursor)? {
+            Cursor::Groups {
+                groups: Some(groups),
+                ..
+            } => Ok(groups),
+            _ => bail!(Sql, "cursor {} is not an open aggregate", cursor),
+        }
+    }
+
+    /// Moves a cursor for [`Insn::Seek`], returning whether it found an entry.
+    fn seek(&mut self, cursor: usize, op: BinaryOp, key: &[SerialValue]) -> Result<bool> {
+        let (btree, order) = self.btree(cursor)?;
+        let forward = matches!(op, BinaryOp::Gt | BinaryOp::GtEq);
+        let inclusive = matches!(op, BinaryOp::GtEq | BinaryOp::LtEq);
+
+        let Some(order) = order else {
+            let Some(value) = key.first() else {
+                bail!(Sql, "seek on table cursor {} without a rowid", cursor);
+            };
+            let Some(rowid) = rowid_bound(value.clone(), inclusive, !forward) else {
+                return Ok(false);
+            };
+            let found = btree.seek_rowid(rowid)?;
+            return Ok(match forward {
+                true => btree.is_valid(),
+                // Step back from the first rowid after the bound to the last one within it.
+                false if !btree.is_valid() => btree.last()?,
+                false if !found => btree.prev()?,
+                false => true,
+            });
+        };
+
+        // Find the first entry past the bound, then step back from it to the last one
+        // within the bound.
+        let past = match forward {
+            true => !inclusive,
+            false => inclusive,
+        };
+        let found = match past {
+            true => btree.seek_past_key(key, order)?,
+            false => {
+                btree.seek_key(key, order)?;
+                btree.is_valid()
+            }
+        };
+        Ok(match forward {
+            true => found,
+            false if found => btree.prev()?,
+            false => btree.last()?,
+        })
+    }
+
+    /// Reads a column of the current entry or row of a cursor.
+    fn column(&mut self, cursor: usize, column: usize) -> Result<SerialValue> {
+        let row = match self.cursor(cursor)? {
+            Cursor::BTree { .. } => {
+                return Ok(match self.entry(cursor)? {
+                    Some(record) => record
+                        .columns
+                        .get(column)
+                        .map_or(SerialValue::Null, |column| column.data().clone()),
+                    None => SerialValue::Null,
+                })
+            }
+            Cursor::Sorter { row, .. }
+            | Cursor::Hash { row, .. }
+            | Cursor::Groups { row, .. }
+            | Cursor::Rows { row, .. } => row,
+            Cursor::Set { .. } => bail!(Sql, "cursor {} has no current row", cursor),
+        };
+        Ok(row
+            .as_ref()
+            .and_then(|row| row.get(column))
+            .cloned()
+            .unwrap_or(SerialValue::Null))
+    }
+}
+
+/// The first (or, for an upper bound, last) rowid that lies on the inside of a bound of
+/// `value`, or `None` if no rowid does. Rowids are integers, which come before all text and
+/// blobs and are compared with reals by value.
+fn rowid_bound(value: SerialValue, inclusive: bool, is_upper: bool) -> Option<i64> {
+    const LIMIT: f64 = 9223372036854775808.0;
+
+    match (value.apply_affinity(Affinity::Integer), is_upper) {
+        (SerialValue::Null, _) => None,
+        (SerialValue::Float64(real), false) => {
+            let first = match real.ceil() {
+                ceil if ceil == real && !inclusive => ceil + 1.0,
+                ceil => ceil,
+            };
+            match first {
+                first if first >= LIMIT => None,
+                first if first < -LIMIT => Some(i64::MIN),
+                first => Some(first as i64),
+            }
+        }
+        (SerialValue::Float64(real), true) => {
+            let last = match real.floor() {
+                floor if floor == real && !inclusive => floor - 1.0,
+                floor => floor,
+            };
+            match last {
+                last if last < -LIMIT => None,
+                last if last >= LIMIT => Some(i64::MAX),
+                last => Some(last as i64),
+            }
+        }
+        (SerialValue::String(_) | SerialValue::Blob(_), false) => None,
+        (SerialValue::String(_) | SerialValue::Blob(_), true) => Some(i64::MAX),
+        (value, false) => {
+            let rowid = value.as_integer()?;
+            match inclusive {
+                true => Some(rowid),
+                false => rowid.checked_add(1),
+            }
+        }
+        (value, true) => {
+            let rowid = value.as_integer()?;
+            match inclusive {
+                true => Some(rowid),
+                false => rowid.checked_sub(1),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs `setup` and then `jump`, built with the address it jumps to. Returns whether
+    /// it jumped, and registers 2 and 3 after it. `setup` may go to address 1 to halt.
+    fn jumps(
+        db: &Database,
+        setup: Vec<Insn>,
+        jump: impl FnOnce(usize) -> Insn,
+    ) -> (bool, Vec<SerialValue>) {
+        let mut insns = vec![Insn::Init { target: 2 }, Insn::Halt];
+        insns.extend(setup);
+        let at = insns.len();
+        insns.extend([
+            jump(at + 3),
+            Insn::Integer { value: 0, dest: 1 },
+            Insn::Goto { target: at + 4 },
+            Insn::Integer { value: 1, dest: 1 },
+            Insn::ResultRow { start: 1, n: 3 },
+            Insn::Halt,
+        ]);
+        let program = Program {
+            insns,
+            registers: 8,
+            cursors: 1,
+        };
+
+        let rows = program
+            .run(db, Vec::new())
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        let [row] = rows.as_slice() else {
+            panic!("expected one row, got {:?}", rows);
+        };
+        (row[0] == SerialValue::I64(1), row[1..].to_vec())
+    }
+
+    fn text(value: &str, dest: usize) -> Insn {
+        Insn::String8 {
+            value: value.to_string(),
+            dest,
+        }
+    }
+
+    fn integer(value: i64, dest: usize) -> Insn {
+        Insn::Integer { value, dest }
+    }
+
+    #[test]
+    fn test_compare() {
+        let db = Database::open("sample.db").unwrap();
+        let compare = |setup: Vec<Insn>, op, collation, jump_if_null| {
+            let jump = |target| Insn::Compare {
+                op,
+                lhs: 2,
+                rhs: 3,
+                target,
+                collation,
+                jump_if_null,
+            };
+            jumps(&db, setup, jump).0
+        };
+
+        // Text is greater than any number, unless affinity turned it into one.
+        let nine = || vec![text("9", 2), integer(10, 3)];
+        assert!(compare(nine(), BinaryOp::Gt, Collation::Binary, false));
+        let mut setup = nine();
+        setup.push(Insn::Affinity {
+            reg: 2,
+            affinity: Affinity::Integer,
+        });
+        assert!(!compare(
+            setup.clone(),
+            BinaryOp::Gt,
+            Collation::Binary,
+            false
+        ));
+        assert!(compare(setup, BinaryOp::Lt, Collation::Binary, false));
+
+        let abc = || vec![text("abc", 2), text("ABC ", 3)];
+        assert!(compare(abc(), BinaryOp::Gt, Collation::Binary, false));
+        assert!(!compare(abc(), BinaryOp::Eq, Collation::NoCase, false));
+        let abc = || vec![text("abc", 2), text("ABC", 3)];
+        assert!(compare(abc(), BinaryOp::Eq, Collation::NoCase, false));
+        assert!(compare(abc(), BinaryOp::NotEq, Collation::RTrim, false));
+
+        let null = || vec![Insn::Null { dest: 2 }, integer(1, 3)];
+        assert!(!compare(null(), BinaryOp::NotEq, Collation::Binary, false));
+        assert!(compare(null(), BinaryOp::NotEq, Collation::Binary, true));
+    }
+
+    #[test]
+    fn test_idx_compare() {
+        // indexed.db: t_ab(a, b) has root page 3 and t_c(c COLLATE NOCASE) root page 4.
+        let db = Database::open("indexed.db").unwrap();
+        let idx_compare = |root_page, collation, seek: Vec<Insn>, nkey, key: Vec<Insn>, op| {
+            let mut setup = vec![Insn::OpenRead {
+                cursor: 0,
+                root_page,
+                columns: 3,
+                key: Some(vec![
+                    KeyOrder {
+                        collation,
+                        descending: false
+                    };
+                    2
+                ]),
+                name: String::new(),
+            }];
+            setup.extend(seek);
+            setup.push(Insn::Seek {
+                op: BinaryOp::GtEq,
+                cursor: 0,
+                target: 1,
+                key: 4,
+                nkey,
+            });
+            setup.extend(key);
+            let jump = |target| Insn::IdxCompare {
+                op,
+                cursor: 0,
+                target,
+                key: 2,
+                nkey,
+            };
+            jumps(&db, setup, jump).0
+        };
+
+        // On the first entry of ('e', 24), which is followed by its rowid 24.
+        let ab = |op, a: &str, b| {
+            let seek = vec![text("e", 4), integer(24, 5)];
+            idx_compare(
+                3,
+                Collation::Binary,
+                seek,
+                2,
+                vec![text(a, 2), integer(b, 3)],
+                op,
+            )
+        };
+        assert!(!ab(BinaryOp::Gt, "e", 24));
+        assert!(ab(BinaryOp::GtEq, "e", 24));
+        assert!(!ab(BinaryOp::Lt, "e", 24));
+        assert!(ab(BinaryOp::LtEq, "e", 24));
+        assert!(ab(BinaryOp::Gt, "e", 23));
+        assert!(ab(BinaryOp::Lt, "e", 25));
+        assert!(ab(BinaryOp::Lt, "f", 0));
+
+        // Only the leading columns are compared, under the collation of the index.
+        let c = |op, c: &str| {
+            let seek = vec![text("apple1", 4)];
+            idx_compare(4, Collation::NoCase, seek, 1, vec![text(c, 2)], op)
+        };
+        assert!(!c(BinaryOp::Gt, "APPLE1"));
+        assert!(c(BinaryOp::GtEq, "APPLE1"));
+        assert!(c(BinaryOp::Lt, "APPLE2"));
+    }
+
+    #[test]
+    fn test_if_pos_and_decr_jump_zero() {
+        let db = Database::open("sample.db").unwrap();
+        let if_pos = |value: Insn, decrement| {
+            jumps(&db, vec![value], |target| Insn::IfPos {
+                reg: 2,
+                target,
+                decrement,
+            })
+        };
+        let decr_jump_zero = |value: Insn| {
+            jumps(&db, vec![value], |target| Insn::DecrJumpZero {
+                reg: 2,
+                target,
+            })
+        };
+        let value = |value| vec![SerialValue::I64(value), SerialValue::Null];
+
+        // An OFFSET counts down while it skips rows.
+        assert_eq!(if_pos(integer(2, 2), 1), (true, value(1)));
+        assert_eq!(if_pos(integer(0, 2), 1), (false, value(0)));
+        assert_eq!(if_pos(integer(-1, 2), 1), (false, value(-1)));
+        assert_eq!(if_pos(integer(1, 2), 0), (true, value(1)));
+        assert_eq!(
+            if_pos(Insn::Null { dest: 2 }, 1),
+            (false, vec![SerialValue::Null; 2])
+        );
+
+        // A LIMIT stops once it reaches zero.
+        assert_eq!(decr_jump_zero(integer(2, 2)), (false, value(1)));
+        assert_eq!(decr_jump_zero(integer(1, 2)), (true, value(0)));
+    }
+
+    #[test]
+    fn test_null_row() {
+        // indexed.db: p(id INTEGER PRIMARY KEY, t TEXT UNIQUE) has root page 26 and holds
+        // (1, 'x'), (2, 'y') and (3, 'z').
+        let db = Database::open("indexed.db").unwrap();
+        let first_row = |null_row: bool| {
+            let mut setup = vec![
+                Insn::OpenRead {
+                    cursor: 0,
+                    root_page: 26,
+                    columns: 2,
+                    key: None,
+                    name: "p".to_string(),
+                },
+                Insn::Rewind {
+                    cursor: 0,
+                    target: 1,
+                },
+            ];
+            setup.extend(null_row.then_some(Insn::NullRow { cursor: 0 }));
+            setup.extend([
+                Insn::Column {
+                    cursor: 0,
+                    column: 1,
+                    dest: 2,
+                },
+                Insn::Rowid { cursor: 0, dest: 3 },
+            ]);
+            jumps(&db, setup, |target| Insn::Next { cursor: 0, target })
+        };
+
+        assert_eq!(
+            first_row(false),
+            (
+                true,
+                vec![SerialValue::String("x".into()), SerialValue::I64(1)]
+            )
+        );
+        // The row of NULLs a LEFT JOIN outputs when nothing matches has no next row.
+        assert_eq!(first_row(true), (false, vec![SerialValue::Null; 2]));
+    }
+}